@@ -0,0 +1,32 @@
+// Bakes the lava shader screen-blended with the cloud shader onto an
+// equirectangular map: cargo run --example blend_shaders
+use std::f32::consts::PI;
+use nalgebra_glm::Mat4;
+
+use fourth_laboratory_computer_graphics::{Uniforms, create_noise};
+use fourth_laboratory_computer_graphics::color::Color;
+use fourth_laboratory_computer_graphics::export::save_png;
+use fourth_laboratory_computer_graphics::fragment::Fragment;
+use fourth_laboratory_computer_graphics::math::from_lat_lon;
+use fourth_laboratory_computer_graphics::shaders::{ShaderType, blend_shaders};
+
+const OUTPUT_PATH: &str = "blend_shaders.png";
+
+fn main() {
+    let (width, height) = (512, 256);
+    let uniforms = Uniforms::new(Mat4::identity(), Mat4::identity(), Mat4::identity(), Mat4::identity(), 120, create_noise());
+    let screen = |lava: Color, clouds: Color| lava.blend_screen(&clouds);
+
+    let mut pixels = Vec::with_capacity(width * height);
+    for y in 0..height {
+        for x in 0..width {
+            let latitude = (0.5 - (y as f32 + 0.5) / height as f32) * PI;
+            let longitude = ((x as f32 + 0.5) / width as f32 - 0.5) * 2.0 * PI;
+            let fragment = Fragment::at(from_lat_lon(latitude, longitude), 0.0, 1.0);
+            pixels.push(blend_shaders(ShaderType::Lava, ShaderType::Cloud, screen, &fragment, &uniforms).to_hex());
+        }
+    }
+
+    save_png(OUTPUT_PATH, width, height, &pixels).expect("Failed to save the image");
+    println!("Saved {}", OUTPUT_PATH);
+}
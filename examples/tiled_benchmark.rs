@@ -0,0 +1,55 @@
+// Compares the serial and tiled parallel rasterizers on a scene of many
+// planets: cargo run --release --example tiled_benchmark
+use std::time::Instant;
+use nalgebra_glm::Vec3;
+
+use fourth_laboratory_computer_graphics::{
+    Uniforms, Handedness, render_mesh, create_noise, create_model_matrix,
+    create_view_matrix, create_perspective_matrix, create_viewport_matrix,
+};
+use fourth_laboratory_computer_graphics::framebuffer::Framebuffer;
+use fourth_laboratory_computer_graphics::geometry::generate_sphere;
+use fourth_laboratory_computer_graphics::tiled::render_mesh_tiled;
+
+fn main() {
+    let (width, height) = (800, 600);
+    let frames = 5;
+    let threads = std::thread::available_parallelism().map_or(4, |threads| threads.get());
+
+    let sphere = generate_sphere(4);
+    let mut uniforms = Uniforms::new(
+        create_model_matrix(Vec3::new(0.0, 0.0, 0.0), 1.0, Vec3::new(0.0, 0.0, 0.0)),
+        create_view_matrix(Vec3::new(0.0, 0.0, 12.0), Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0)),
+        create_perspective_matrix(width as f32, height as f32, Handedness::Right),
+        create_viewport_matrix(width as f32, height as f32),
+        0,
+        create_noise(),
+    );
+    let planets: Vec<_> = (0..9)
+        .map(|i| create_model_matrix(Vec3::new((i % 3) as f32 * 3.0 - 3.0, (i / 3) as f32 * 3.0 - 3.0, 0.0), 1.2, Vec3::new(0.0, 0.0, 0.0)))
+        .collect();
+
+    let mut framebuffer = Framebuffer::new(width, height);
+    let mut time_with = |parallel: bool| {
+        let start = Instant::now();
+        for _ in 0..frames {
+            framebuffer.clear();
+            for &model in &planets {
+                uniforms.model_matrix = model;
+                if parallel {
+                    render_mesh_tiled(&mut framebuffer, &uniforms, &sphere, 3, threads);
+                } else {
+                    render_mesh(&mut framebuffer, &uniforms, &sphere, 3);
+                }
+            }
+        }
+        (start.elapsed().as_secs_f64() * 1000.0 / frames as f64, framebuffer.buffer.clone())
+    };
+
+    let (serial_ms, serial_image) = time_with(false);
+    let (tiled_ms, tiled_image) = time_with(true);
+
+    println!("serial: {:.1} ms/frame", serial_ms);
+    println!("tiled ({} threads): {:.1} ms/frame, {:.2}x", threads, tiled_ms, serial_ms / tiled_ms);
+    println!("identical output: {}", serial_image == tiled_image);
+}
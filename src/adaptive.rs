@@ -0,0 +1,58 @@
+use std::time::Duration;
+
+// Bounds of `AdaptiveResolution::scale`, per axis: never below a quarter of
+// the output size (1/16 of the pixels), never above the output size
+pub const MIN_RESOLUTION_SCALE: f32 = 0.25;
+pub const MAX_RESOLUTION_SCALE: f32 = 1.0;
+
+// Frames slower than the budget by this factor shrink the next one, frames
+// faster than `GROW_BELOW` of it let it grow back. The gap keeps the scale
+// from oscillating when the cost sits right on the budget.
+const SHRINK_ABOVE: f32 = 1.1;
+const GROW_BELOW: f32 = 0.7;
+
+// Picks the internal render resolution from the last frame's cost, so heavy
+// shaders stay interactive: render at `internal_size`, upscale with
+// `RenderTarget::resized`, then feed the frame time to `update`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AdaptiveResolution {
+    pub scale: f32,
+}
+
+impl Default for AdaptiveResolution {
+    fn default() -> Self {
+        AdaptiveResolution { scale: MAX_RESOLUTION_SCALE }
+    }
+}
+
+impl AdaptiveResolution {
+    pub fn new() -> Self {
+        AdaptiveResolution::default()
+    }
+
+    // Adjusts the scale for the next frame. Rendering cost grows with the pixel
+    // count, so the correction is the square root of the time ratio, damped.
+    pub fn update(&mut self, frame_time: Duration, target_frame_ms: f32) {
+        let frame_ms = frame_time.as_secs_f32() * 1000.0;
+        if target_frame_ms <= 0.0 || frame_ms <= 0.0 {
+            return;
+        }
+
+        let ratio = frame_ms / target_frame_ms;
+        if !(GROW_BELOW..=SHRINK_ABOVE).contains(&ratio) {
+            let correction = (1.0 / ratio).sqrt().clamp(0.8, 1.1);
+            self.scale = (self.scale * correction).clamp(MIN_RESOLUTION_SCALE, MAX_RESOLUTION_SCALE);
+        }
+    }
+
+    // Back to full resolution, e.g. when adaptive mode is switched off
+    pub fn reset(&mut self) {
+        self.scale = MAX_RESOLUTION_SCALE;
+    }
+
+    // Size to render at for an output of `width` x `height`, at least 1x1
+    pub fn internal_size(&self, width: usize, height: usize) -> (usize, usize) {
+        let scaled = |size: usize| ((size as f32 * self.scale).round() as usize).clamp(1, size.max(1));
+        (scaled(width), scaled(height))
+    }
+}
@@ -0,0 +1,152 @@
+use nalgebra_glm::{Vec2, Vec3};
+use crate::Uniforms;
+use crate::color::Color;
+use crate::framebuffer::Framebuffer;
+use crate::shaders::environment_radiance;
+
+// Angular size of a starfield cell: about one pixel at 800x600 with the
+// default field of view, so each star is a single point
+const STAR_CELLS_PER_UNIT: f32 = 500.0;
+// Share of the cells holding a star
+const STAR_DENSITY: f32 = 0.0015;
+
+// What the clear stage paints behind the scene. Gradients run over the pixel's
+// `screen_uv`; the starfield and environment follow the view direction, so
+// they turn with the camera. `Solid` is the framebuffer's plain clear.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BackgroundMode {
+    Solid(Color),
+    // From the first color at the top of the screen to the second at the bottom
+    VerticalGradient(Color, Color),
+    // From the first color at the center to the second at the corners
+    Radial(Color, Color),
+    // Fixed white points of random brightness on black
+    Starfield,
+    // The uniforms' cubemap or HDR panorama (see `shaders::environment_radiance`),
+    // scaled by `Uniforms::exposure` like the shaded scene in front of it
+    Environment,
+}
+
+impl Default for BackgroundMode {
+    fn default() -> Self {
+        BackgroundMode::Solid(Color::black())
+    }
+}
+
+// Center of pixel (x, y) in [0, 1]^2, (0, 0) being the top-left corner
+pub fn screen_uv(x: usize, y: usize, width: usize, height: usize) -> Vec2 {
+    Vec2::new((x as f32 + 0.5) / width as f32, (y as f32 + 0.5) / height as f32)
+}
+
+// Clears the framebuffer and fills it with `mode`. The background keeps
+// infinite depth, so the scene drawn next covers it and antialiased edges
+// blend into it.
+pub fn clear_with_background(framebuffer: &mut Framebuffer, uniforms: &Uniforms, mode: BackgroundMode) {
+    if let BackgroundMode::Solid(color) = mode {
+        framebuffer.set_background_color(color.to_hex());
+        framebuffer.clear();
+        return;
+    }
+
+    framebuffer.clear();
+    let (width, height) = (framebuffer.width, framebuffer.height);
+    let aspect = width as f32 / height.max(1) as f32;
+    for y in 0..height {
+        for x in 0..width {
+            let uv = screen_uv(x, y, width, height);
+            let color = match mode {
+                BackgroundMode::Solid(color) => color,
+                BackgroundMode::VerticalGradient(top, bottom) => top.lerp(&bottom, uv.y),
+                BackgroundMode::Radial(center, edge) => {
+                    // Round on screen whatever the aspect ratio
+                    let offset = Vec2::new((uv.x - 0.5) * aspect, uv.y - 0.5);
+                    let corner = Vec2::new(0.5 * aspect, 0.5).magnitude();
+                    center.lerp(&edge, (offset.magnitude() / corner).min(1.0))
+                }
+                BackgroundMode::Starfield => star(view_direction(uniforms, x, y)),
+                BackgroundMode::Environment => {
+                    Color::from_vec3(environment_radiance(uniforms, &view_direction(uniforms, x, y)) * uniforms.exposure)
+                }
+            };
+            framebuffer.buffer[y * width + x] = color.to_hex();
+        }
+    }
+}
+
+fn view_direction(uniforms: &Uniforms, x: usize, y: usize) -> Vec3 {
+    let far = uniforms.unproject(Vec2::new(x as f32 + 0.5, y as f32 + 0.5), uniforms.depth_range().1);
+    far - uniforms.camera_position
+}
+
+// The star of the direction's cell, if it has one: the unit sphere is cut by a
+// fixed 3D grid and each cell is hashed, so every star stays put in the sky
+fn star(direction: Vec3) -> Color {
+    let Some(direction) = direction.try_normalize(f32::EPSILON) else {
+        return Color::black();
+    };
+    let cell = (direction * STAR_CELLS_PER_UNIT).map(|coordinate| coordinate.floor());
+    let hash = hash_cell(cell.x as i32, cell.y as i32, cell.z as i32);
+    let chance = (hash & 0xFFFF) as f32 / 65536.0;
+    if chance >= STAR_DENSITY {
+        return Color::black();
+    }
+
+    // Most stars are faint, a few are bright
+    let brightness = ((hash >> 16) & 0xFF) as f32 / 255.0;
+    Color::from_vec3(Vec3::repeat(0.2 + 0.8 * brightness * brightness))
+}
+
+// Integer mix of three cell coordinates into well-spread bits
+fn hash_cell(x: i32, y: i32, z: i32) -> u32 {
+    let mut h = (x as u32).wrapping_mul(0x8DA6_B343)
+        ^ (y as u32).wrapping_mul(0xD816_3841)
+        ^ (z as u32).wrapping_mul(0xCB1A_B31F);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x2C1B_3C6D);
+    h ^= h >> 12;
+    h = h.wrapping_mul(0x297A_2D39);
+    h ^ (h >> 15)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use nalgebra_glm::Mat4;
+    use crate::texture::EquirectEnvironment;
+    use crate::{create_noise, create_view_matrix, create_viewport_matrix_with_depth_range, DEFAULT_DEPTH_RANGE};
+
+    #[test]
+    fn environment_background_follows_each_pixels_ray_for_any_depth_range() {
+        // Brighter towards +X, so the ray direction shows in the color
+        let data = (0..8 * 4).map(|i| Vec3::new((i % 8) as f32 / 8.0, 0.5, 0.25)).collect();
+        let environment = Arc::new(EquirectEnvironment::new(8, 4, data));
+        let draw = |depth_range: (f32, f32), exposure: f32| {
+            let view = create_view_matrix(Vec3::new(0.0, 0.0, 5.0), Vec3::zeros(), Vec3::new(0.0, 1.0, 0.0));
+            let viewport = create_viewport_matrix_with_depth_range(16.0, 16.0, depth_range);
+            let mut uniforms = Uniforms::new(Mat4::identity(), view, Mat4::identity(), viewport, 0, create_noise());
+            uniforms.camera_position = Vec3::new(0.0, 0.0, 5.0);
+            // Parallel rays: only the far end of each one gives its direction
+            uniforms.orthographic(-2.0, 2.0, -2.0, 2.0, 0.1, 100.0);
+            uniforms.hdr_environment = Some(environment.clone());
+            uniforms.exposure = exposure;
+            let mut framebuffer = Framebuffer::new(16, 16);
+            clear_with_background(&mut framebuffer, &uniforms, BackgroundMode::Environment);
+            assert!(framebuffer.zbuffer.iter().all(|depth| depth.is_infinite()));
+            framebuffer.buffer
+        };
+
+        let default = draw(DEFAULT_DEPTH_RANGE, 1.0);
+        assert_eq!(draw((0.0, 1.0), 1.0), default);
+        assert_eq!(draw((0.25, 4.0), 1.0), default);
+        assert!(default.iter().any(|&pixel| pixel != default[0]));
+
+        // Exposure scales the radiance like it does the scene
+        let doubled = draw(DEFAULT_DEPTH_RANGE, 2.0);
+        for (&bright, &plain) in doubled.iter().zip(&default) {
+            let (bright, plain) = (Color::from_hex(bright), Color::from_hex(plain));
+            assert_eq!(bright.g(), 255);
+            assert!((bright.b() as i32 - 2 * plain.b() as i32).abs() <= 1 && plain.g() == 128, "{:?} {:?}", bright, plain);
+        }
+    }
+}
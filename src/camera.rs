@@ -1,11 +1,48 @@
 
 use nalgebra_glm::{Vec3, rotate_vec3};
 use std::f32::consts::PI;
+use crate::{FIELD_OF_VIEW, NEAR_PLANE};
+
+// World axis that points up. Blender and most CAD tools export Z-up models,
+// which lie on their side under the default Y-up orbit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UpAxis {
+  #[default]
+  Y,
+  Z,
+}
+
+impl UpAxis {
+  pub fn vector(self) -> Vec3 {
+    match self {
+      UpAxis::Y => Vec3::new(0.0, 1.0, 0.0),
+      UpAxis::Z => Vec3::new(0.0, 0.0, 1.0),
+    }
+  }
+
+  // Rotates a world vector into the Y-up frame the orbit math is written in
+  // (-90 degrees about X for Z-up, so +Z becomes +Y and +Y becomes -Z)
+  fn world_to_y_up(self, vector: Vec3) -> Vec3 {
+    match self {
+      UpAxis::Y => vector,
+      UpAxis::Z => Vec3::new(vector.x, vector.z, -vector.y),
+    }
+  }
+
+  fn y_up_to_world(self, vector: Vec3) -> Vec3 {
+    match self {
+      UpAxis::Y => vector,
+      UpAxis::Z => Vec3::new(vector.x, -vector.z, vector.y),
+    }
+  }
+}
 
 pub struct Camera {
   pub eye: Vec3,
   pub center: Vec3,
   pub up: Vec3,
+  // Axis the camera orbits around and turns about; see `with_up_axis`
+  pub up_axis: UpAxis,
   pub has_changed: bool
 }
 
@@ -15,10 +52,23 @@ impl Camera {
       eye,
       center,
       up,
+      up_axis: UpAxis::Y,
       has_changed: true,
     }
   }
 
+  // Switches to another up axis, carrying the current framing over: the eye
+  // is rotated around the center the same way the world is, so the default
+  // view from +Z of a Y-up scene becomes a front view from -Y of a Z-up one.
+  pub fn with_up_axis(mut self, up_axis: UpAxis) -> Self {
+    let offset = self.up_axis.world_to_y_up(self.eye - self.center);
+    self.eye = self.center + up_axis.y_up_to_world(offset);
+    self.up = up_axis.y_up_to_world(self.up_axis.world_to_y_up(self.up));
+    self.up_axis = up_axis;
+    self.has_changed = true;
+    self
+  }
+
   pub fn basis_change(&self, vector: &Vec3) -> Vec3 {
     let forward = (self.center - self.eye).normalize();
     let right = forward.cross(&self.up).normalize();
@@ -33,7 +83,7 @@ impl Camera {
   }
 
   pub fn orbit(&mut self, delta_yaw: f32, delta_pitch: f32) {
-    let radius_vector = self.eye - self.center;
+    let radius_vector = self.up_axis.world_to_y_up(self.eye - self.center);
     let radius = radius_vector.magnitude();
 
     let current_yaw = radius_vector.z.atan2(radius_vector.x);
@@ -44,16 +94,36 @@ impl Camera {
     let new_yaw = (current_yaw + delta_yaw) % (2.0 * PI);
     let new_pitch = (current_pitch + delta_pitch).clamp(-PI / 2.0 + 0.1, PI / 2.0 - 0.1);
 
-    let new_eye = self.center + Vec3::new(
+    let new_eye = self.center + self.up_axis.y_up_to_world(Vec3::new(
       radius * new_yaw.cos() * new_pitch.cos(),
       -radius * new_pitch.sin(),
       radius * new_yaw.sin() * new_pitch.cos()
-    );
+    ));
 
     self.eye = new_eye;
     self.has_changed = true;
   }
 
+  // Looks at a bounding sphere (see `Mesh::bounding_sphere`) from just far
+  // enough away that it fits the vertical field of view of
+  // `create_perspective_matrix`, keeping the current viewing direction.
+  // Call it after loading a model of unknown size.
+  pub fn frame(&mut self, center: Vec3, radius: f32) {
+    self.frame_with_fov(center, radius, FIELD_OF_VIEW);
+  }
+
+  // `frame` for a projection with another vertical field of view, in radians
+  pub fn frame_with_fov(&mut self, center: Vec3, radius: f32, fov: f32) {
+    let direction = (self.eye - self.center).try_normalize(f32::EPSILON)
+      .unwrap_or(self.up_axis.y_up_to_world(Vec3::new(0.0, 0.0, 1.0)));
+    // The sphere touches the view cone when sin(fov / 2) = radius / distance
+    let distance = (radius / (fov * 0.5).sin()).max(radius + NEAR_PLANE);
+
+    self.center = center;
+    self.eye = center + direction * distance;
+    self.has_changed = true;
+  }
+
   pub fn zoom(&mut self, delta: f32) {
     let direction = (self.center - self.eye).normalize();
     self.eye += direction * delta;
@@ -67,7 +137,7 @@ impl Camera {
     let angle_x = direction.x * 0.05; // Adjust this factor to control rotation speed
     let angle_y = direction.y * 0.05;
 
-    let rotated = rotate_vec3(&radius_vector, angle_x, &Vec3::new(0.0, 1.0, 0.0));
+    let rotated = rotate_vec3(&radius_vector, angle_x, &self.up_axis.vector());
 
     let right = rotated.cross(&self.up).normalize();
     let final_rotated = rotate_vec3(&rotated, angle_y, &right);
@@ -85,3 +155,83 @@ impl Camera {
     }
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use nalgebra_glm::Mat4;
+  use crate::{Uniforms, Handedness, create_view_matrix, create_perspective_matrix, create_viewport_matrix, create_noise};
+
+  // Screen position (Y down) of a world point seen through the camera
+  fn project(camera: &Camera, point: Vec3) -> Vec3 {
+    let uniforms = Uniforms::new(
+      Mat4::identity(),
+      create_view_matrix(camera.eye, camera.center, camera.up),
+      create_perspective_matrix(64.0, 64.0, Handedness::Right),
+      create_viewport_matrix(64.0, 64.0),
+      0,
+      create_noise(),
+    );
+    uniforms.project(point).unwrap()
+  }
+
+  #[test]
+  fn z_up_models_render_upright() {
+    let y_up = Camera::new(Vec3::new(0.0, 0.0, 5.0), Vec3::zeros(), Vec3::new(0.0, 1.0, 0.0));
+    let mut z_up = Camera::new(Vec3::new(0.0, 0.0, 5.0), Vec3::zeros(), Vec3::new(0.0, 1.0, 0.0)).with_up_axis(UpAxis::Z);
+    assert!((z_up.eye - Vec3::new(0.0, -5.0, 0.0)).magnitude() < 1e-5);
+    assert!((z_up.up - UpAxis::Z.vector()).magnitude() < 1e-5);
+
+    // The top of each model is straight above the middle of the screen
+    let (top, middle) = (project(&z_up, Vec3::new(0.0, 0.0, 1.0)), project(&z_up, Vec3::zeros()));
+    assert!(top.y < middle.y - 5.0 && (top.x - middle.x).abs() < 1e-3);
+    assert!((top - project(&y_up, Vec3::new(0.0, 1.0, 0.0))).magnitude() < 1e-3);
+
+    // Orbiting turns about the up axis, so the model stays upright
+    for _ in 0..7 {
+      z_up.orbit(0.4, 0.1);
+      let (top, middle) = (project(&z_up, Vec3::new(0.0, 0.0, 1.0)), project(&z_up, Vec3::zeros()));
+      assert!(top.y < middle.y - 3.0 && (top.x - middle.x).abs() < 1e-3);
+      assert!((z_up.eye.magnitude() - 5.0).abs() < 1e-3);
+    }
+  }
+
+  #[test]
+  fn y_up_is_the_default() {
+    let camera = Camera::new(Vec3::new(0.0, 0.0, 5.0), Vec3::zeros(), Vec3::new(0.0, 1.0, 0.0));
+    assert_eq!(camera.up_axis, UpAxis::default());
+    assert_eq!(UpAxis::default(), UpAxis::Y);
+    let unchanged = Camera::new(Vec3::new(1.0, 2.0, 5.0), Vec3::zeros(), Vec3::new(0.0, 1.0, 0.0)).with_up_axis(UpAxis::Y);
+    assert_eq!(unchanged.eye, Vec3::new(1.0, 2.0, 5.0));
+  }
+
+  #[test]
+  fn framing_distance_grows_with_the_radius_and_shrinks_with_the_field_of_view() {
+    let framed_distance = |radius: f32, fov: f32| {
+      let mut camera = Camera::new(Vec3::new(3.0, 4.0, 0.0), Vec3::zeros(), Vec3::new(0.0, 1.0, 0.0));
+      let center = Vec3::new(10.0, 0.0, -2.0);
+      camera.frame_with_fov(center, radius, fov);
+      assert_eq!(camera.center, center);
+      // The viewing direction is kept
+      assert!(((camera.eye - center).normalize() - Vec3::new(0.6, 0.8, 0.0)).magnitude() < 1e-5);
+      (camera.eye - center).magnitude()
+    };
+
+    let base = framed_distance(1.0, FIELD_OF_VIEW);
+    assert!((base - 1.0 / (FIELD_OF_VIEW * 0.5).sin()).abs() < 1e-4);
+    assert!((framed_distance(4.0, FIELD_OF_VIEW) - 4.0 * base).abs() < 1e-3);
+    assert!(framed_distance(1.0, FIELD_OF_VIEW * 2.0) < base);
+    assert!(framed_distance(1.0, FIELD_OF_VIEW * 0.5) > base);
+    // Never inside the sphere, whatever the field of view
+    assert!(framed_distance(1.0, PI * 1.5) >= 1.0 + NEAR_PLANE);
+  }
+
+  #[test]
+  fn a_framed_sphere_fits_the_screen() {
+    let mut camera = Camera::new(Vec3::new(0.0, 0.0, 5.0), Vec3::zeros(), Vec3::new(0.0, 1.0, 0.0));
+    camera.frame(Vec3::new(0.0, 0.0, 0.0), 20.0);
+    // Top of the sphere's silhouette: inside the screen, but not by much
+    let edge = project(&camera, Vec3::new(0.0, 20.0 * (FIELD_OF_VIEW * 0.5).cos(), 20.0 * (FIELD_OF_VIEW * 0.5).sin()));
+    assert!(edge.y > -0.5 && edge.y < 2.0, "{}", edge.y);
+  }
+}
@@ -0,0 +1,43 @@
+// Drives `Uniforms::time` in the render loop, with the controls needed to
+// inspect one exact moment of an animated shader: pause, single-step in
+// either direction and jump to any frame. Time counts frames, as before.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct AnimationClock {
+    time: u32,
+    paused: bool,
+}
+
+impl AnimationClock {
+    pub fn new() -> Self {
+        AnimationClock::default()
+    }
+
+    pub fn time(&self) -> u32 {
+        self.time
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    // Call once per frame: moves on by one unless paused
+    pub fn tick(&mut self) -> u32 {
+        if !self.paused {
+            self.time = self.time.wrapping_add(1);
+        }
+        self.time
+    }
+
+    pub fn set_time(&mut self, time: u32) {
+        self.time = time;
+    }
+
+    // Moves by `delta` frames (negative goes back, stopping at 0), paused or not
+    pub fn step_time(&mut self, delta: i64) {
+        self.time = (self.time as i64 + delta).clamp(0, u32::MAX as i64) as u32;
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+    }
+}
@@ -1,29 +1,192 @@
 use std::fmt;
+use nalgebra_glm::{Vec3, Vec4};
 
-#[derive(Debug, Clone, Copy)]
+// 8-bit RGB plus alpha. Alpha defaults to opaque and only matters to the
+// compositing helpers; the framebuffer itself stores opaque 0xRRGGBB pixels.
+// Colors are straight (not premultiplied) unless produced by `premultiply`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Color {
     r: u8,
     g: u8,
     b: u8,
+    a: u8,
 }
 
 impl Color {
     pub const fn new(r: u8, g: u8, b: u8) -> Self {
-        Color { r, g, b }
+        Color { r, g, b, a: 255 }
+    }
+
+    pub const fn new_rgba(r: u8, g: u8, b: u8, a: u8) -> Self {
+        Color { r, g, b, a }
     }
 
     pub const fn from_hex(hex: u32) -> Self {
         let r = ((hex >> 16) & 0xFF) as u8;
         let g = ((hex >> 8) & 0xFF) as u8;
         let b = (hex & 0xFF) as u8;
-        Color { r, g, b }
+        Color { r, g, b, a: 255 }
     }
 
+    // Hue in degrees (wrapped to [0, 360)), saturation and value in [0, 1]
+    pub fn from_hsv(h: f32, s: f32, v: f32) -> Self {
+        let h = h.rem_euclid(360.0);
+        let s = s.clamp(0.0, 1.0);
+        let v = v.clamp(0.0, 1.0);
+
+        let c = v * s;
+        let x = c * (1.0 - ((h / 60.0) % 2.0 - 1.0).abs());
+        let m = v - c;
+
+        let (r, g, b) = match (h / 60.0) as u32 {
+            0 => (c, x, 0.0),
+            1 => (x, c, 0.0),
+            2 => (0.0, c, x),
+            3 => (0.0, x, c),
+            4 => (x, 0.0, c),
+            _ => (c, 0.0, x),
+        };
+
+        Color {
+            r: ((r + m) * 255.0).round() as u8,
+            g: ((g + m) * 255.0).round() as u8,
+            b: ((b + m) * 255.0).round() as u8,
+            a: 255,
+        }
+    }
+
+    // Blackbody color of a star, using Tanner Helland's curve fit of the CIE 1964
+    // color matching data (about 2000K orange, 6500K white, 10000K blue-white).
+    // Temperatures are clamped to 1000K..40000K, the range of the fit.
+    pub fn from_temperature(kelvin: f32) -> Self {
+        let t = kelvin.clamp(1000.0, 40000.0) / 100.0;
+
+        let r = if t <= 66.0 {
+            255.0
+        } else {
+            329.698_73 * (t - 60.0).powf(-0.133_204_76)
+        };
+
+        let g = if t <= 66.0 {
+            99.470_8 * t.ln() - 161.119_57
+        } else {
+            288.122_16 * (t - 60.0).powf(-0.075_514_85)
+        };
+
+        let b = if t >= 66.0 {
+            255.0
+        } else if t <= 19.0 {
+            0.0
+        } else {
+            138.517_73 * (t - 10.0).ln() - 305.044_8
+        };
+
+        Color {
+            r: r.clamp(0.0, 255.0).round() as u8,
+            g: g.clamp(0.0, 255.0).round() as u8,
+            b: b.clamp(0.0, 255.0).round() as u8,
+            a: 255,
+        }
+    }
+
+    // Conspicuous debug color for pixels whose inputs were NaN or infinite
+    pub const NON_FINITE: Color = Color::new(255, 0, 255);
+
     pub const fn black() -> Self {
-        Color { r: 0, g: 0, b: 0 }
+        Color { r: 0, g: 0, b: 0, a: 255 }
+    }
+
+    pub const fn r(self) -> u8 {
+        self.r
+    }
+
+    pub const fn g(self) -> u8 {
+        self.g
+    }
+
+    pub const fn b(self) -> u8 {
+        self.b
+    }
+
+    pub const fn a(self) -> u8 {
+        self.a
+    }
+
+    pub const fn with_alpha(self, a: u8) -> Self {
+        Color { a, ..self }
+    }
+
+    // Scales RGB by alpha. Compositing in premultiplied space keeps a soft edge
+    // between an opaque and a transparent region from picking up the
+    // transparent pixels' (usually black) color as a dark fringe.
+    pub fn premultiply(&self) -> Color {
+        let scale = |channel: u8| ((channel as u16 * self.a as u16 + 127) / 255) as u8;
+        Color { r: scale(self.r), g: scale(self.g), b: scale(self.b), a: self.a }
+    }
+
+    // Inverse of `premultiply`; fully transparent colors come back as transparent black
+    pub fn unpremultiply(&self) -> Color {
+        if self.a == 0 {
+            return Color { r: 0, g: 0, b: 0, a: 0 };
+        }
+        let scale = |channel: u8| ((channel as u32 * 255 + self.a as u32 / 2) / self.a as u32).min(255) as u8;
+        Color { r: scale(self.r), g: scale(self.g), b: scale(self.b), a: self.a }
+    }
+
+    // Porter-Duff "over" with both colors premultiplied: self drawn on top of `below`
+    pub fn over(&self, below: &Color) -> Color {
+        let remaining = 255 - self.a as u16;
+        let mix = |top: u8, bottom: u8| (top as u16 + (bottom as u16 * remaining + 127) / 255).min(255) as u8;
+        Color {
+            r: mix(self.r, below.r),
+            g: mix(self.g, below.g),
+            b: mix(self.b, below.b),
+            a: mix(self.a, below.a),
+        }
+    }
+
+    pub const fn channel(self, channel: Channel) -> u8 {
+        match channel {
+            Channel::R => self.r,
+            Channel::G => self.g,
+            Channel::B => self.b,
+            Channel::A => self.a,
+        }
+    }
+
+    // Applies `f` to the red, green and blue channels; alpha is kept
+    pub fn map(self, f: impl Fn(u8) -> u8) -> Color {
+        Color { r: f(self.r), g: f(self.g), b: f(self.b), a: self.a }
+    }
+
+    // Rebuilds the color from any of its channels, e.g. `swizzle(B, G, R)` swaps
+    // red and blue and `swizzle(R, R, R)` is a grayscale view of the red channel
+    pub const fn swizzle(self, r: Channel, g: Channel, b: Channel) -> Color {
+        Color { r: self.channel(r), g: self.channel(g), b: self.channel(b), a: self.a }
+    }
+
+    // Channels as floats in [0, 1], for doing lighting math in vectors
+    pub fn to_vec3(self) -> Vec3 {
+        Vec3::new(self.r as f32, self.g as f32, self.b as f32) / 255.0
+    }
+
+    // Inverse of `to_vec3`: channels are clamped to [0, 1] and rounded; opaque
+    pub fn from_vec3(color: Vec3) -> Self {
+        let channel = |value: f32| (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+        Color::new(channel(color.x), channel(color.y), channel(color.z))
+    }
+
+    // Same as `to_vec3` with alpha in w
+    pub fn to_vec4(self) -> Vec4 {
+        Vec4::new(self.r as f32, self.g as f32, self.b as f32, self.a as f32) / 255.0
+    }
+
+    pub fn from_vec4(color: Vec4) -> Self {
+        let channel = |value: f32| (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+        Color::new_rgba(channel(color.x), channel(color.y), channel(color.z), channel(color.w))
     }
 
-    pub fn to_hex(&self) -> u32 {
+    pub fn to_hex(self) -> u32 {
         ((self.r as u32) << 16) | ((self.g as u32) << 8) | (self.b as u32)
     }
 
@@ -34,13 +197,49 @@ impl Color {
             r: (self.r as f32 + (other.r as f32 - self.r as f32) * t).round() as u8,
             g: (self.g as f32 + (other.g as f32 - self.g as f32) * t).round() as u8,
             b: (self.b as f32 + (other.b as f32 - self.b as f32) * t).round() as u8,
+            a: (self.a as f32 + (other.a as f32 - self.a as f32) * t).round() as u8,
         }
     }
 
+    // Relative luminance in [0, 1] with Rec. 709 weights (applied to the stored
+    // values directly, without linearizing)
+    pub fn luminance(&self) -> f32 {
+        (0.2126 * self.r as f32 + 0.7152 * self.g as f32 + 0.0722 * self.b as f32) / 255.0
+    }
+
+    // Channel-wise sum of RGB; alpha is kept. `+` saturates like
+    // `add_with(.., Saturate)` but sums the alphas as well.
+    pub fn add_with(self, other: Color, mode: OverflowMode) -> Color {
+        let add = |a: u8, b: u8| match mode {
+            OverflowMode::Saturate => a.saturating_add(b),
+            OverflowMode::Wrap => a.wrapping_add(b),
+        };
+        Color { r: add(self.r, other.r), g: add(self.g, other.g), b: add(self.b, other.b), a: self.a }
+    }
+
+    // RGB times `factor`, rounded; alpha is kept. Wrapping takes the result
+    // modulo 256, so brightening past white cycles through the channel again.
+    pub fn scale_with(self, factor: f32, mode: OverflowMode) -> Color {
+        let scale = |value: u8| {
+            let scaled = (value as f32 * factor).round();
+            match mode {
+                OverflowMode::Saturate => scaled.clamp(0.0, 255.0) as u8,
+                OverflowMode::Wrap => (scaled as i64).rem_euclid(256) as u8,
+            }
+        };
+        Color { r: scale(self.r), g: scale(self.g), b: scale(self.b), a: self.a }
+    }
+
     pub fn is_black(&self) -> bool {
         self.r == 0 && self.g == 0 && self.b == 0 
     }
 
+    // No channel above `threshold`, for colors that went through float math
+    // and came back as (1, 0, 0) where exact black was meant. Alpha is ignored.
+    pub fn is_near_black(&self, threshold: u8) -> bool {
+        self.r <= threshold && self.g <= threshold && self.b <= threshold
+    }
+
     pub fn blend_normal(&self, blend: &Color) -> Color {
         if blend.is_black() { *self } else { *blend }
       }
@@ -61,15 +260,174 @@ impl Color {
         )
     }
     
+    // Inverted multiply of the inverted colors: always at least as bright as
+    // either one, like projecting both pictures onto the same screen
+    pub fn blend_screen(&self, blend: &Color) -> Color {
+        let screen = |a: u8, b: u8| 255 - ((255 - a as u16) * (255 - b as u16) / 255) as u8;
+        Color::new(screen(self.r, blend.r), screen(self.g, blend.g), screen(self.b, blend.b))
+    }
+
     pub fn blend_subtract(&self, blend: &Color) -> Color {
-        let r = (self.r as i16 - blend.r as i16).max(0).min(255) as u8;
-        let g = (self.g as i16 - blend.g as i16).max(0).min(255) as u8;
-        let b = (self.b as i16 - blend.b as i16).max(0).min(255) as u8;
+        let r = (self.r as i16 - blend.r as i16).clamp(0, 255) as u8;
+        let g = (self.g as i16 - blend.g as i16).clamp(0, 255) as u8;
+        let b = (self.b as i16 - blend.b as i16).clamp(0, 255) as u8;
 
         Color::new(r, g, b)
     }
 }
 
+// What channel arithmetic does past 0 or 255. Every operator and blend on
+// `Color` saturates; wrapping is opt-in through `add_with` and `scale_with`
+// for glitchy or banded artistic looks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverflowMode {
+    #[default]
+    Saturate,
+    Wrap,
+}
+
+// Names a channel for `Color::channel` and `Color::swizzle`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    R,
+    G,
+    B,
+    A,
+}
+
+// 16 bits per channel, for exports that must survive further grading
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Color16 {
+    pub r: u16,
+    pub g: u16,
+    pub b: u16,
+}
+
+impl Color16 {
+    pub const fn new(r: u16, g: u16, b: u16) -> Self {
+        Color16 { r, g, b }
+    }
+
+    // Linear radiance in [0, 1] per channel; larger values clip at full white
+    pub fn from_radiance(radiance: Vec3) -> Self {
+        let channel = |value: f32| (value.clamp(0.0, 1.0) * 65535.0).round() as u16;
+        Color16::new(channel(radiance.x), channel(radiance.y), channel(radiance.z))
+    }
+}
+
+impl From<Color> for Color16 {
+    // 0xAB becomes 0xABAB, so 255 maps exactly to 65535
+    fn from(color: Color) -> Self {
+        Color16::new(color.r as u16 * 257, color.g as u16 * 257, color.b as u16 * 257)
+    }
+}
+
+// Curve mapping exposed linear radiance onto displayable [0, 1] channels.
+// The pipeline feeds it the shaders' float output (see
+// `shaders::fragment_radiance`), so highlights where lighting adds up past 1
+// are compressed instead of clipped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ToneMapper {
+    // Clips at 1, so exposure is a plain brightness multiplier
+    #[default]
+    None,
+    // x / (1 + x): never clips, but flattens highlights and desaturates little
+    Reinhard,
+    // Narkowicz's fit of the ACES filmic curve: punchier contrast, highlights
+    // roll off towards white
+    AcesFilmic,
+}
+
+impl ToneMapper {
+    pub fn apply(self, radiance: Vec3, exposure: f32) -> Vec3 {
+        let exposed = radiance * exposure;
+        match self {
+            ToneMapper::None => exposed,
+            ToneMapper::Reinhard => exposed.map(|x| {
+                let x = x.max(0.0);
+                x / (1.0 + x)
+            }),
+            ToneMapper::AcesFilmic => exposed.map(|x| {
+                let x = x.max(0.0);
+                (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14)
+            }),
+        }
+        .map(|x| if x.is_nan() { 0.0 } else { x.clamp(0.0, 1.0) })
+    }
+}
+
+// sRGB transfer function (IEC 61966-2-1) on a channel in [0, 1]: gamma-encoded
+// 8-bit values, as stored in PNGs, to linear light and back
+pub fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+pub fn linear_to_srgb(value: f32) -> f32 {
+    if value <= 0.003_130_8 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+// Exponent of the plain power-law display curve, `EncodeMode::default()`
+pub const DEFAULT_GAMMA: f32 = 2.2;
+
+// Transfer curve between linear light and the values sent to the display.
+// `Gamma(g)` is a pure power law (encoded = linear^(1/g)); `Srgb` is the exact
+// piecewise sRGB curve: its linear toe encodes the darkest tones darker than
+// gamma 2.2 (1% linear becomes 0.10 instead of 0.12), while above about 15%
+// the two stay within one percent of each other.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EncodeMode {
+    Gamma(f32),
+    Srgb,
+}
+
+impl Default for EncodeMode {
+    fn default() -> Self {
+        EncodeMode::Gamma(DEFAULT_GAMMA)
+    }
+}
+
+impl EncodeMode {
+    // Linear channel in [0, 1] to its display value. A gamma that is not
+    // positive and finite leaves values as they are.
+    pub fn encode(self, linear: f32) -> f32 {
+        match self {
+            EncodeMode::Gamma(gamma) if gamma > 0.0 && gamma.is_finite() => linear.max(0.0).powf(1.0 / gamma),
+            EncodeMode::Gamma(_) => linear,
+            EncodeMode::Srgb => linear_to_srgb(linear),
+        }
+    }
+
+    // Inverse of `encode`
+    pub fn decode(self, encoded: f32) -> f32 {
+        match self {
+            EncodeMode::Gamma(gamma) if gamma > 0.0 && gamma.is_finite() => encoded.max(0.0).powf(gamma),
+            EncodeMode::Gamma(_) => encoded,
+            EncodeMode::Srgb => srgb_to_linear(encoded),
+        }
+    }
+}
+
+// Nearest palette entry by squared Euclidean distance in RGB.
+// An empty palette leaves the color untouched.
+pub fn quantize_to_palette(color: Color, palette: &[Color]) -> Color {
+    let distance = |other: &Color| {
+        let dr = color.r as i32 - other.r as i32;
+        let dg = color.g as i32 - other.g as i32;
+        let db = color.b as i32 - other.b as i32;
+        dr * dr + dg * dg + db * db
+    };
+
+    palette.iter().copied().min_by_key(distance).unwrap_or(color)
+}
+
 use std::ops::Add;
 
 impl Add for Color {
@@ -80,6 +438,7 @@ impl Add for Color {
             r: self.r.saturating_add(other.r),
             g: self.g.saturating_add(other.g),
             b: self.b.saturating_add(other.b),
+            a: self.a.saturating_add(other.a),
         }
     }
 }
@@ -94,12 +453,256 @@ impl Mul<f32> for Color {
             r: (self.r as f32 * scalar).clamp(0.0, 255.0) as u8,
             g: (self.g as f32 * scalar).clamp(0.0, 255.0) as u8,
             b: (self.b as f32 * scalar).clamp(0.0, 255.0) as u8,
+            a: self.a,
         }
     }
 }
 
 impl fmt::Display for Color {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Color(r: {}, g: {}, b: {})", self.r, self.g, self.b)
+        write!(f, "Color(r: {}, g: {}, b: {}, a: {})", self.r, self.g, self.b, self.a)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn addition_saturates_instead_of_wrapping() {
+        let (a, b) = (Color::new_rgba(200, 10, 255, 200), Color::new_rgba(100, 20, 1, 100));
+        assert_eq!(a + b, Color::new_rgba(255, 30, 255, 255));
+        assert_eq!(a.add_with(b, OverflowMode::Saturate), Color::new_rgba(255, 30, 255, 200));
+        assert_eq!(a.add_with(b, OverflowMode::default()), a.add_with(b, OverflowMode::Saturate));
+        assert_eq!(a.blend_add(&b), Color::new(255, 30, 255));
+    }
+
+    #[test]
+    fn wrapping_is_modulo_256() {
+        let (a, b) = (Color::new_rgba(200, 10, 255, 200), Color::new_rgba(100, 20, 1, 100));
+        assert_eq!(a.add_with(b, OverflowMode::Wrap), Color::new_rgba(44, 30, 0, 200));
+        assert_eq!(Color::new(200, 100, 0).scale_with(1.5, OverflowMode::Wrap), Color::new(44, 150, 0));
+        assert_eq!(Color::new(10, 0, 0).scale_with(-1.0, OverflowMode::Wrap), Color::new(246, 0, 0));
+    }
+
+    #[test]
+    fn scaling_saturates_at_both_ends() {
+        let color = Color::new_rgba(200, 100, 0, 77);
+        assert_eq!(color.scale_with(1.5, OverflowMode::Saturate), Color::new_rgba(255, 150, 0, 77));
+        assert_eq!(color.scale_with(-2.0, OverflowMode::Saturate), Color::new_rgba(0, 0, 0, 77));
+        assert_eq!(color * 1.5, Color::new_rgba(255, 150, 0, 77));
+        assert_eq!(color * -2.0, Color::new_rgba(0, 0, 0, 77));
+        assert_eq!(color * f32::NAN, Color::new_rgba(0, 0, 0, 77));
+    }
+
+    #[test]
+    fn blends_stay_in_range() {
+        let (light, dark) = (Color::new(200, 255, 30), Color::new(100, 255, 60));
+        assert_eq!(light.blend_subtract(&dark), Color::new(100, 0, 0));
+        assert_eq!(light.blend_multiply(&dark), Color::new(78, 255, 7));
+        let screen = light.blend_screen(&dark);
+        assert_eq!(screen.g(), 255);
+        assert!(screen.r() >= 200 && screen.b() >= 60);
+    }
+
+    const TONE_MAPPERS: [ToneMapper; 3] = [ToneMapper::None, ToneMapper::Reinhard, ToneMapper::AcesFilmic];
+
+    fn gray(value: f32) -> Vec3 {
+        Vec3::repeat(value)
+    }
+
+    #[test]
+    fn tone_curves_map_black_to_black() {
+        for mapper in TONE_MAPPERS {
+            assert!(mapper.apply(gray(0.0), 1.0).x.abs() < 1e-6, "{:?}", mapper);
+            assert!(mapper.apply(gray(0.7), 0.0).x.abs() < 1e-6, "{:?}", mapper);
+        }
+    }
+
+    #[test]
+    fn tone_curves_are_monotonic_and_bounded() {
+        for mapper in TONE_MAPPERS {
+            let mut previous = 0.0;
+            for step in 0..=400 {
+                let mapped = mapper.apply(gray(step as f32 * 0.05), 1.0).x;
+                assert!((0.0..=1.0).contains(&mapped), "{:?} gave {}", mapper, mapped);
+                assert!(mapped >= previous, "{:?} decreased at {}", mapper, step);
+                previous = mapped;
+            }
+            for radiance in [-1.0, -5.0, f32::NAN, f32::INFINITY, 1e30] {
+                let mapped = mapper.apply(gray(radiance), 1.0).x;
+                assert!((0.0..=1.0).contains(&mapped), "{:?} gave {} for {}", mapper, mapped, radiance);
+            }
+        }
+    }
+
+    #[test]
+    fn doubling_exposure_doubles_linear_output_until_it_clips() {
+        for value in [0.01, 0.1, 0.25, 0.4] {
+            let once = ToneMapper::None.apply(gray(value), 1.0);
+            let twice = ToneMapper::None.apply(gray(value), 2.0);
+            assert!((twice - once * 2.0).abs().max() < 1e-6);
+        }
+        assert_eq!(ToneMapper::None.apply(gray(0.8), 2.0), gray(1.0));
+        assert_eq!(ToneMapper::default().apply(Vec3::new(0.2, 0.5, 0.9), 1.0), Vec3::new(0.2, 0.5, 0.9));
+    }
+
+    #[test]
+    fn palette_colors_map_to_themselves_and_others_to_the_nearest() {
+        let palette = [Color::new(0, 0, 0), Color::new(255, 0, 0), Color::new(20, 200, 40), Color::new(255, 255, 255)];
+        for color in palette {
+            assert_eq!(quantize_to_palette(color, &palette), color);
+        }
+        assert_eq!(quantize_to_palette(Color::new(200, 30, 10), &palette), Color::new(255, 0, 0));
+        assert_eq!(quantize_to_palette(Color::new(30, 180, 60), &palette), Color::new(20, 200, 40));
+        assert_eq!(quantize_to_palette(Color::new(60, 50, 50), &palette), Color::new(0, 0, 0));
+        assert_eq!(quantize_to_palette(Color::new(12, 34, 56), &[]), Color::new(12, 34, 56));
+    }
+
+    #[test]
+    fn blackbody_colors_go_from_orange_through_white_to_blue() {
+        let white = Color::from_temperature(6500.0);
+        assert!(white.r() >= 240 && white.g() >= 240 && white.b() >= 240, "{}", white);
+
+        let orange = Color::from_temperature(2000.0);
+        assert!(orange.r() == 255 && orange.g() > orange.b() && orange.g() < 200, "{}", orange);
+
+        let blue = Color::from_temperature(10000.0);
+        assert!(blue.b() == 255 && blue.r() < blue.b(), "{}", blue);
+
+        // Bluer and less red as it heats up
+        let ramp: Vec<Color> = (20..=100).map(|hundreds| Color::from_temperature(hundreds as f32 * 100.0)).collect();
+        assert!(ramp.windows(2).all(|pair| pair[1].b() >= pair[0].b() && pair[1].r() <= pair[0].r()));
+
+        // Clamped to the range of the fit
+        assert_eq!(Color::from_temperature(0.0), Color::from_temperature(1000.0));
+        assert_eq!(Color::from_temperature(1e9), Color::from_temperature(40000.0));
+    }
+
+    #[test]
+    fn premultiplied_soft_edges_do_not_darken() {
+        let (red, clear) = (Color::new_rgba(255, 0, 0, 255), Color::new_rgba(0, 0, 0, 0));
+        // Half way across the edge, e.g. a filter averaging the two pixels
+        let average = |a: Color, b: Color| a.lerp(&b, 0.5);
+
+        let premultiplied = average(red.premultiply(), clear.premultiply());
+        assert_eq!(premultiplied.unpremultiply(), Color::new_rgba(255, 0, 0, premultiplied.a()));
+        // Straight alpha picks up the transparent pixel's black
+        assert!(average(red, clear).r() < 200);
+
+        // Over white the edge is a lighter red, never darker than either side
+        let composited = premultiplied.over(&Color::new(255, 255, 255));
+        assert_eq!(composited.r(), 255);
+        assert!((126..=129).contains(&composited.g()) && composited.g() == composited.b(), "{}", composited);
+    }
+
+    #[test]
+    fn premultiplication_round_trips_and_over_handles_the_extremes() {
+        let opaque = Color::new(12, 200, 99);
+        assert_eq!(opaque.premultiply(), opaque);
+        assert_eq!(opaque.premultiply().unpremultiply(), opaque);
+        let translucent = Color::new_rgba(200, 100, 50, 128);
+        let back = translucent.premultiply().unpremultiply();
+        assert!([back.r().abs_diff(200), back.g().abs_diff(100), back.b().abs_diff(50)].iter().all(|&error| error <= 1), "{}", back);
+        assert_eq!(Color::new_rgba(9, 9, 9, 0).unpremultiply(), Color::new_rgba(0, 0, 0, 0));
+
+        let below = Color::new(10, 20, 30);
+        assert_eq!(opaque.over(&below), opaque);
+        assert_eq!(Color::new_rgba(0, 0, 0, 0).over(&below), below);
+    }
+
+    #[test]
+    fn swapping_red_and_blue_twice_round_trips() {
+        use Channel::{R, G, B, A};
+        let color = Color::new_rgba(10, 120, 250, 77);
+        let swapped = color.swizzle(B, G, R);
+        assert_eq!(swapped, Color::new_rgba(250, 120, 10, 77));
+        assert_eq!(swapped.swizzle(B, G, R), color);
+        assert_eq!(color.swizzle(R, R, R), Color::new_rgba(10, 10, 10, 77));
+        assert_eq!(color.swizzle(A, A, A), Color::new_rgba(77, 77, 77, 77));
+
+        // Usable in constants
+        const SWAPPED: Color = Color::new(1, 2, 3).swizzle(B, G, R);
+        assert_eq!(SWAPPED, Color::new(3, 2, 1));
+    }
+
+    #[test]
+    fn map_applies_to_color_channels_only() {
+        let color = Color::new_rgba(10, 120, 250, 77);
+        assert_eq!(color.map(|channel| 255 - channel), Color::new_rgba(245, 135, 5, 77));
+        assert_eq!(color.map(|channel| channel), color);
+        assert_eq!(color.map(|channel| channel / 2).map(|channel| channel.saturating_mul(2)), Color::new_rgba(10, 120, 250, 77));
+    }
+
+    #[test]
+    fn every_channel_value_round_trips_through_vectors() {
+        for value in 0..=255u8 {
+            let color = Color::new_rgba(value, 255 - value, value / 3, value);
+            assert_eq!(Color::from_vec3(color.to_vec3()), color.with_alpha(255));
+            assert_eq!(Color::from_vec4(color.to_vec4()), color);
+        }
+        assert_eq!(Color::new(255, 0, 51).to_vec3(), Vec3::new(1.0, 0.0, 0.2));
+    }
+
+    #[test]
+    fn vectors_out_of_range_clamp_and_round() {
+        assert_eq!(Color::from_vec3(Vec3::new(-0.5, 1.5, 0.5)), Color::new(0, 255, 128));
+        assert_eq!(Color::from_vec3(Vec3::new(f32::NAN, f32::INFINITY, 0.001)), Color::new(0, 255, 0));
+        assert_eq!(Color::from_vec4(Vec4::new(0.2, 0.4, 0.6, 2.0)), Color::new_rgba(51, 102, 153, 255));
+    }
+
+    #[test]
+    fn srgb_encodes_darks_darker_than_gamma_2_2() {
+        let (srgb, gamma) = (EncodeMode::Srgb, EncodeMode::default());
+        assert_eq!(gamma, EncodeMode::Gamma(2.2));
+        assert!((srgb.encode(0.01) - 0.0999).abs() < 1e-3 && (gamma.encode(0.01) - 0.1233).abs() < 1e-3);
+        for step in 1..100 {
+            let linear = step as f32 / 100.0;
+            let difference = gamma.encode(linear) - srgb.encode(linear);
+            if linear < 0.1 {
+                assert!(difference > 0.0, "{} at {}", difference, linear);
+            } else if linear >= 0.15 {
+                assert!(difference.abs() < 0.01, "{} at {}", difference, linear);
+            }
+        }
+        // Both end points agree
+        for mode in [srgb, gamma, EncodeMode::Gamma(1.8)] {
+            assert!(mode.encode(0.0).abs() < 1e-6 && (mode.encode(1.0) - 1.0).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn decoding_inverts_encoding_and_invalid_gammas_pass_through() {
+        for mode in [EncodeMode::Srgb, EncodeMode::Gamma(2.2), EncodeMode::Gamma(2.6)] {
+            for step in 0..=50 {
+                let linear = step as f32 / 50.0;
+                assert!((mode.decode(mode.encode(linear)) - linear).abs() < 1e-5, "{:?} at {}", mode, linear);
+            }
+        }
+        for gamma in [0.0, -1.0, f32::NAN, f32::INFINITY] {
+            assert_eq!(EncodeMode::Gamma(gamma).encode(0.3), 0.3);
+            assert_eq!(EncodeMode::Gamma(gamma).decode(0.3), 0.3);
+        }
+    }
+
+    #[test]
+    fn near_black_includes_the_threshold_and_nothing_above_it() {
+        assert!(Color::black().is_near_black(0));
+        assert!(!Color::new(1, 0, 0).is_near_black(0));
+        for threshold in [0u8, 4, 254] {
+            for channel in 0..3 {
+                let with = |value: u8| {
+                    let mut channels = [0u8; 3];
+                    channels[channel] = value;
+                    Color::new(channels[0], channels[1], channels[2])
+                };
+                assert!(with(threshold).is_near_black(threshold));
+                assert!(!with(threshold + 1).is_near_black(threshold));
+            }
+        }
+        // All channels at the threshold still count, alpha never does
+        assert!(Color::new(4, 4, 4).is_near_black(4));
+        assert!(Color::new_rgba(0, 0, 0, 255).is_near_black(0) && Color::new_rgba(0, 0, 0, 0).is_near_black(0));
+        assert!(Color::new(255, 255, 255).is_near_black(255));
     }
 }
@@ -0,0 +1,48 @@
+use nalgebra_glm::Vec3;
+use fastnoise_lite::FastNoiseLite;
+use crate::texture::{Texture, spherical_uv};
+use crate::shaders::tangent_basis;
+
+// Where vertex heights come from. Heights are in [-1, 1] for noise and
+// [0, 1] for height maps, multiplied by `scale` model-space units.
+#[derive(Clone, Debug)]
+pub enum DisplacementSource {
+    // The uniforms' noise at the model-space position times `frequency`, which
+    // works like the shaders' zoom (the generator's own frequency is 0.01)
+    Noise { frequency: f32, scale: f32 },
+    // Red channel of an equirectangular height map (looked up with
+    // `spherical_uv`, so it is meant for spheres around the origin)
+    HeightMap { texture: Texture, scale: f32 },
+}
+
+impl DisplacementSource {
+    pub fn height(&self, position: &Vec3, noise: &FastNoiseLite) -> f32 {
+        match self {
+            DisplacementSource::Noise { frequency, scale } => {
+                let p = position * *frequency;
+                noise.get_noise_3d(p.x, p.y, p.z) * scale
+            }
+            DisplacementSource::HeightMap { texture, scale } => {
+                texture.sample_height(spherical_uv(position)) * scale
+            }
+        }
+    }
+}
+
+// Moves a model-space vertex along its normal by the sampled height and
+// rebuilds the normal from two nearby displaced points (finite differences)
+pub fn displace(position: &Vec3, normal: &Vec3, source: &DisplacementSource, noise: &FastNoiseLite) -> (Vec3, Vec3) {
+    let normal = if normal.magnitude() > f32::EPSILON { normal.normalize() } else { Vec3::new(0.0, 1.0, 0.0) };
+    let displaced = |p: Vec3| p + normal * source.height(&p, noise);
+
+    let center = displaced(*position);
+    let (tangent, bitangent) = tangent_basis(&normal);
+    let step = 1e-3;
+    let along_tangent = displaced(position + tangent * step) - center;
+    let along_bitangent = displaced(position + bitangent * step) - center;
+
+    let rebuilt = along_tangent.cross(&along_bitangent);
+    let new_normal = if rebuilt.magnitude() > f32::EPSILON { rebuilt.normalize() } else { normal };
+
+    (center, new_normal)
+}
@@ -0,0 +1,154 @@
+use std::f32::consts::{PI, TAU};
+
+// Easing curves for camera moves and parameter transitions: each maps the
+// progress t of an animation in [0, 1] to how far along the eased value is,
+// with 0 at the start and 1 at the end. Inputs outside [0, 1] are clamped.
+// `in` curves start slowly, `out` curves end slowly, `in_out` do both. Use
+// them as `start + (end - start) * ease_in_out_cubic(t)`.
+
+pub fn linear(t: f32) -> f32 {
+    t.clamp(0.0, 1.0)
+}
+
+pub fn ease_in_quad(t: f32) -> f32 {
+    let t = t.clamp(0.0, 1.0);
+    t * t
+}
+
+pub fn ease_out_quad(t: f32) -> f32 {
+    let t = t.clamp(0.0, 1.0);
+    1.0 - (1.0 - t) * (1.0 - t)
+}
+
+pub fn ease_in_out_quad(t: f32) -> f32 {
+    let t = t.clamp(0.0, 1.0);
+    if t < 0.5 { 2.0 * t * t } else { 1.0 - (-2.0 * t + 2.0).powi(2) / 2.0 }
+}
+
+pub fn ease_in_cubic(t: f32) -> f32 {
+    t.clamp(0.0, 1.0).powi(3)
+}
+
+pub fn ease_out_cubic(t: f32) -> f32 {
+    1.0 - (1.0 - t.clamp(0.0, 1.0)).powi(3)
+}
+
+pub fn ease_in_out_cubic(t: f32) -> f32 {
+    let t = t.clamp(0.0, 1.0);
+    if t < 0.5 { 4.0 * t * t * t } else { 1.0 - (-2.0 * t + 2.0).powi(3) / 2.0 }
+}
+
+pub fn ease_in_out_sine(t: f32) -> f32 {
+    -((PI * t.clamp(0.0, 1.0)).cos() - 1.0) / 2.0
+}
+
+pub fn ease_out_expo(t: f32) -> f32 {
+    let t = t.clamp(0.0, 1.0);
+    if t >= 1.0 { 1.0 } else { 1.0 - 2.0f32.powf(-10.0 * t) }
+}
+
+// How far `ease_in_back` dips below 0 and `ease_out_back` overshoots 1 (about
+// 10% of the move), the usual constant of Penner's back curves
+const BACK_OVERSHOOT: f32 = 1.701_58;
+
+// Pulls back a little before moving; not monotonic
+pub fn ease_in_back(t: f32) -> f32 {
+    let t = t.clamp(0.0, 1.0);
+    (BACK_OVERSHOOT + 1.0) * t * t * t - BACK_OVERSHOOT * t * t
+}
+
+// Overshoots the end a little and settles back; not monotonic
+pub fn ease_out_back(t: f32) -> f32 {
+    let t = t.clamp(0.0, 1.0) - 1.0;
+    1.0 + (BACK_OVERSHOOT + 1.0) * t * t * t + BACK_OVERSHOOT * t * t
+}
+
+// Springs past the end and wobbles into place; not monotonic
+pub fn ease_out_elastic(t: f32) -> f32 {
+    let t = t.clamp(0.0, 1.0);
+    if t <= 0.0 || t >= 1.0 {
+        return t;
+    }
+    2.0f32.powf(-10.0 * t) * ((t * 10.0 - 0.75) * (TAU / 3.0)).sin() + 1.0
+}
+
+// Bounces on the end like a dropped ball; monotonic only between bounces
+pub fn ease_out_bounce(t: f32) -> f32 {
+    let t = t.clamp(0.0, 1.0);
+    let (n, d) = (7.5625, 2.75);
+    if t < 1.0 / d {
+        n * t * t
+    } else if t < 2.0 / d {
+        let t = t - 1.5 / d;
+        n * t * t + 0.75
+    } else if t < 2.5 / d {
+        let t = t - 2.25 / d;
+        n * t * t + 0.9375
+    } else {
+        let t = t - 2.625 / d;
+        n * t * t + 0.984_375
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Curve = (&'static str, fn(f32) -> f32);
+
+    const MONOTONIC: [Curve; 9] = [
+        ("linear", linear),
+        ("ease_in_quad", ease_in_quad),
+        ("ease_out_quad", ease_out_quad),
+        ("ease_in_out_quad", ease_in_out_quad),
+        ("ease_in_cubic", ease_in_cubic),
+        ("ease_out_cubic", ease_out_cubic),
+        ("ease_in_out_cubic", ease_in_out_cubic),
+        ("ease_in_out_sine", ease_in_out_sine),
+        ("ease_out_expo", ease_out_expo),
+    ];
+
+    const OVERSHOOTING: [Curve; 4] = [
+        ("ease_in_back", ease_in_back),
+        ("ease_out_back", ease_out_back),
+        ("ease_out_elastic", ease_out_elastic),
+        ("ease_out_bounce", ease_out_bounce),
+    ];
+
+    #[test]
+    fn every_curve_starts_at_0_ends_at_1_and_clamps_its_input() {
+        for (name, ease) in MONOTONIC.iter().chain(&OVERSHOOTING) {
+            assert!(ease(0.0).abs() < 1e-6, "{} starts at {}", name, ease(0.0));
+            assert!((ease(1.0) - 1.0).abs() < 1e-6, "{} ends at {}", name, ease(1.0));
+            assert_eq!(ease(-3.0), ease(0.0), "{}", name);
+            assert_eq!(ease(7.0), ease(1.0), "{}", name);
+        }
+    }
+
+    #[test]
+    fn plain_curves_never_go_back_and_stay_in_range() {
+        for (name, ease) in MONOTONIC {
+            let mut previous = ease(0.0);
+            for step in 1..=1000 {
+                let value = ease(step as f32 / 1000.0);
+                assert!(value >= previous && value <= 1.0 + 1e-6, "{} goes from {} to {} at step {}", name, previous, value, step);
+                previous = value;
+            }
+        }
+        // In-out curves are symmetric about the midpoint
+        for ease in [ease_in_out_quad, ease_in_out_cubic, ease_in_out_sine] {
+            assert!((ease(0.5) - 0.5).abs() < 1e-6 && (ease(0.2) + ease(0.8) - 1.0).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn back_curves_leave_the_range_by_about_ten_percent() {
+        let samples = |ease: fn(f32) -> f32| (0..=1000).map(move |step| ease(step as f32 / 1000.0));
+        let dip = samples(ease_in_back).fold(f32::INFINITY, f32::min);
+        let peak = samples(ease_out_back).fold(f32::NEG_INFINITY, f32::max);
+        assert!((dip + 0.1).abs() < 0.005 && (peak - 1.1).abs() < 0.005, "{} {}", dip, peak);
+        // Bounces land on 1 without going past it
+        assert!(samples(ease_out_bounce).all(|value| (0.0..=1.0 + 1e-6).contains(&value)));
+        assert!(samples(ease_out_elastic).any(|value| value > 1.0));
+    }
+}
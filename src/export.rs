@@ -0,0 +1,411 @@
+use std::fs::File;
+use std::io::{self, ErrorKind, Write};
+use crate::color::{Color, Color16};
+use crate::postprocess::linearize_depth;
+
+// Minimal PNG encoder: no compression (stored deflate blocks) and no filtering,
+// which keeps it dependency-free at the cost of larger files.
+fn write_png(path: &str, width: usize, height: usize, color_type: u8, bit_depth: u8, rows: &[Vec<u8>]) -> io::Result<()> {
+    let mut file = File::create(path)?;
+    file.write_all(&[0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A])?;
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    header.extend_from_slice(&[bit_depth, color_type, 0, 0, 0]);
+    write_chunk(&mut file, b"IHDR", &header)?;
+
+    // Every scanline starts with filter type 0 (none)
+    let mut raw = Vec::new();
+    for row in rows {
+        raw.push(0);
+        raw.extend_from_slice(row);
+    }
+    write_chunk(&mut file, b"IDAT", &zlib_stored(&raw))?;
+    write_chunk(&mut file, b"IEND", &[])
+}
+
+fn write_chunk(file: &mut File, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    file.write_all(&(data.len() as u32).to_be_bytes())?;
+    file.write_all(kind)?;
+    file.write_all(data)?;
+
+    let mut crc_input = kind.to_vec();
+    crc_input.extend_from_slice(data);
+    file.write_all(&crc32(&crc_input).to_be_bytes())
+}
+
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    let mut blocks = data.chunks(65535).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let len = block.len() as u16;
+        out.push(last as u8);
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+// Every export needs a non-empty image and exactly one value per pixel
+fn check_image_size(width: usize, height: usize, len: usize) -> io::Result<()> {
+    if width == 0 || height == 0 || Some(len) != width.checked_mul(height) {
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("expected {}x{} = {} pixels, found {}", width, height, width.saturating_mul(height), len),
+        ));
+    }
+    Ok(())
+}
+
+// 8-bit RGB PNG of a framebuffer-style 0xRRGGBB buffer
+pub fn save_png(path: &str, width: usize, height: usize, pixels: &[u32]) -> io::Result<()> {
+    check_image_size(width, height, pixels.len())?;
+    let rows: Vec<Vec<u8>> = pixels.chunks(width).take(height)
+        .map(|row| row.iter().flat_map(|&pixel| {
+            let color = Color::from_hex(pixel);
+            [color.r(), color.g(), color.b()]
+        }).collect())
+        .collect();
+
+    write_png(path, width, height, 2, 8, &rows)
+}
+
+// 16-bit-per-channel RGB PNG for compositing tools. It is only as precise as
+// its source: `Framebuffer::to_color16` with a radiance buffer enabled keeps
+// what exposure and coverage blending add below the 8-bit step.
+pub fn save_png16(path: &str, width: usize, height: usize, pixels: &[Color16]) -> io::Result<()> {
+    check_image_size(width, height, pixels.len())?;
+    let rows: Vec<Vec<u8>> = pixels.chunks(width).take(height)
+        .map(|row| row.iter().flat_map(|color| {
+            let mut bytes = [0u8; 6];
+            bytes[0..2].copy_from_slice(&color.r.to_be_bytes());
+            bytes[2..4].copy_from_slice(&color.g.to_be_bytes());
+            bytes[4..6].copy_from_slice(&color.b.to_be_bytes());
+            bytes
+        }).collect())
+        .collect();
+
+    write_png(path, width, height, 2, 16, &rows)
+}
+
+// 8-bit RGBA PNG of straight-alpha colors, e.g. a `RenderTarget` with an atmosphere layer
+pub fn save_png_rgba(path: &str, width: usize, height: usize, pixels: &[Color]) -> io::Result<()> {
+    check_image_size(width, height, pixels.len())?;
+    let rows: Vec<Vec<u8>> = pixels.chunks(width).take(height)
+        .map(|row| row.iter().flat_map(|color| [color.r(), color.g(), color.b(), color.a()]).collect())
+        .collect();
+
+    write_png(path, width, height, 6, 8, &rows)
+}
+
+// Opaque preview of an RGBA image over the usual light/dark gray checkerboard
+// with `checker_size`-pixel squares. Export the real alpha with `save_png_rgba`.
+pub fn save_png_checker_preview(path: &str, width: usize, height: usize, pixels: &[Color], checker_size: usize) -> io::Result<()> {
+    check_image_size(width, height, pixels.len())?;
+    let checker_size = checker_size.max(1);
+    let preview: Vec<u32> = pixels.iter().take(width * height).enumerate()
+        .map(|(i, color)| {
+            let (x, y) = (i % width, i / width);
+            let background = if (x / checker_size + y / checker_size).is_multiple_of(2) {
+                Color::new(204, 204, 204)
+            } else {
+                Color::new(153, 153, 153)
+            };
+            color.premultiply().over(&background).to_hex()
+        })
+        .collect();
+
+    save_png(path, width, height, &preview)
+}
+
+// Depth passes for compositing. Both take the z-buffer (or `RenderTarget::depth`)
+// as stored with `DEFAULT_DEPTH_RANGE`, i.e. NDC depth from a perspective
+// projection with planes `near` and `far` (for other ranges convert with
+// `Uniforms::ndc_depth` first), and store the linear distance from the camera
+// plane along the view axis, in world units. Empty pixels (infinite depth)
+// are stored as `far`. Rows run top to bottom like the color exports. Planes
+// outside 0 < near < far, or not finite, are an `InvalidInput` error.
+
+fn check_depth_range(near: f32, far: f32) -> io::Result<()> {
+    // Written so NaN fails too
+    if !(near > 0.0 && near < far && far.is_finite()) {
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("depth planes must satisfy 0 < near < far, found near {} and far {}", near, far),
+        ));
+    }
+    Ok(())
+}
+
+fn linear_depths(depth: &[f32], width: usize, height: usize, near: f32, far: f32) -> impl Iterator<Item = f32> + '_ {
+    depth.iter().take(width * height).map(move |&depth| {
+        if depth.is_finite() { linearize_depth(depth, near, far).clamp(near, far) } else { far }
+    })
+}
+
+// 16-bit grayscale PNG with the linear depth normalized from `near` (0) to
+// `far` (65535), for tools that only read integer images. Because the range
+// is linear, a far plane of 1000 leaves about 1.5 cm per step.
+pub fn save_depth_png16(path: &str, width: usize, height: usize, depth: &[f32], near: f32, far: f32) -> io::Result<()> {
+    check_image_size(width, height, depth.len())?;
+    check_depth_range(near, far)?;
+    let values: Vec<u16> = linear_depths(depth, width, height, near, far)
+        .map(|distance| ((distance - near) / (far - near) * 65535.0).round() as u16)
+        .collect();
+    let rows: Vec<Vec<u8>> = values.chunks(width)
+        .map(|row| row.iter().flat_map(|value| value.to_be_bytes()).collect())
+        .collect();
+
+    write_png(path, width, height, 0, 16, &rows)
+}
+
+// Uncompressed single-channel OpenEXR image with the linear depth as 32-bit
+// floats in a `Z` channel, the usual layout of a depth pass
+pub fn save_depth_exr(path: &str, width: usize, height: usize, depth: &[f32], near: f32, far: f32) -> io::Result<()> {
+    check_image_size(width, height, depth.len())?;
+    check_depth_range(near, far)?;
+    let values: Vec<f32> = linear_depths(depth, width, height, near, far).collect();
+
+    let mut header = Vec::new();
+    let mut attribute = |name: &str, kind: &str, value: &[u8]| {
+        for text in [name, kind] {
+            header.extend_from_slice(text.as_bytes());
+            header.push(0);
+        }
+        header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+        header.extend_from_slice(value);
+    };
+    let window: Vec<u8> = [0, 0, width as i32 - 1, height as i32 - 1].iter().flat_map(|value| value.to_le_bytes()).collect();
+    // One FLOAT (pixel type 2) channel, linear flag off, no subsampling
+    let mut channels = b"Z\0".to_vec();
+    for value in [2i32, 0, 1, 1] {
+        channels.extend_from_slice(&value.to_le_bytes());
+    }
+    channels.push(0);
+    attribute("channels", "chlist", &channels);
+    attribute("compression", "compression", &[0]);
+    attribute("dataWindow", "box2i", &window);
+    attribute("displayWindow", "box2i", &window);
+    attribute("lineOrder", "lineOrder", &[0]);
+    attribute("pixelAspectRatio", "float", &1.0f32.to_le_bytes());
+    attribute("screenWindowCenter", "v2f", &[0u8; 8]);
+    attribute("screenWindowWidth", "float", &1.0f32.to_le_bytes());
+    header.push(0);
+
+    let mut file = File::create(path)?;
+    // Magic number, then version 2 with no flags (single-part scanline image)
+    file.write_all(&[0x76, 0x2F, 0x31, 0x01, 2, 0, 0, 0])?;
+    file.write_all(&header)?;
+
+    // Offset table: one uncompressed scanline per block
+    let row_bytes = width * 4;
+    let first_block = 8 + header.len() + height * 8;
+    for y in 0..height {
+        file.write_all(&((first_block + y * (8 + row_bytes)) as u64).to_le_bytes())?;
+    }
+    for (y, row) in values.chunks(width).enumerate() {
+        file.write_all(&(y as i32).to_le_bytes())?;
+        file.write_all(&(row_bytes as i32).to_le_bytes())?;
+        let bytes: Vec<u8> = row.iter().flat_map(|value| value.to_le_bytes()).collect();
+        file.write_all(&bytes)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra_glm::Vec3;
+    use std::fs;
+
+    fn temp_path(name: &str) -> String {
+        std::env::temp_dir().join(format!("export_test_{}_{}", std::process::id(), name)).to_string_lossy().into_owned()
+    }
+
+    // Header fields and unfiltered scanlines of a PNG written by `write_png`
+    fn read_png(path: &str) -> (u32, u32, u8, u8, Vec<Vec<u8>>) {
+        let bytes = fs::read(path).unwrap();
+        assert_eq!(&bytes[..8], &[0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A]);
+
+        let (mut header, mut zlib) = (Vec::new(), Vec::new());
+        let mut at = 8;
+        while at < bytes.len() {
+            let length = u32::from_be_bytes(bytes[at..at + 4].try_into().unwrap()) as usize;
+            let (kind, data) = (&bytes[at + 4..at + 8], &bytes[at + 8..at + 8 + length]);
+            let mut crc_input = kind.to_vec();
+            crc_input.extend_from_slice(data);
+            assert_eq!(bytes[at + 8 + length..at + 12 + length], crc32(&crc_input).to_be_bytes());
+            match kind {
+                b"IHDR" => header = data.to_vec(),
+                b"IDAT" => zlib.extend_from_slice(data),
+                _ => {}
+            }
+            at += 12 + length;
+        }
+
+        // Stored deflate blocks only
+        let mut raw = Vec::new();
+        let mut at = 2;
+        loop {
+            let last = zlib[at] & 1 == 1;
+            let length = u16::from_le_bytes([zlib[at + 1], zlib[at + 2]]) as usize;
+            raw.extend_from_slice(&zlib[at + 5..at + 5 + length]);
+            at += 5 + length;
+            if last {
+                break;
+            }
+        }
+        assert_eq!(zlib[at..at + 4], adler32(&raw).to_be_bytes());
+
+        let width = u32::from_be_bytes(header[0..4].try_into().unwrap());
+        let height = u32::from_be_bytes(header[4..8].try_into().unwrap());
+        let (bit_depth, color_type) = (header[8], header[9]);
+        let row_length = raw.len() / height as usize;
+        let rows = raw.chunks(row_length).map(|row| {
+            assert_eq!(row[0], 0, "unfiltered scanline");
+            row[1..].to_vec()
+        }).collect();
+        (width, height, bit_depth, color_type, rows)
+    }
+
+    #[test]
+    fn savers_reject_empty_images_and_wrong_pixel_counts() {
+        let path = temp_path("invalid.png");
+        let checks = [
+            save_png(&path, 0, 2, &[]),
+            save_png(&path, 2, 2, &[0; 3]),
+            save_png(&path, 2, 2, &[0; 5]),
+            save_png16(&path, 2, 0, &[]),
+            save_png16(&path, 2, 2, &[Color16::default(); 3]),
+            save_png_rgba(&path, 0, 0, &[]),
+            save_png_rgba(&path, 3, 1, &[Color::black(); 2]),
+            save_depth_png16(&path, 2, 1, &[1.0], 0.1, 10.0),
+            save_depth_exr(&path, 1, 2, &[1.0; 3], 0.1, 10.0),
+        ];
+        for result in checks {
+            assert_eq!(result.unwrap_err().kind(), ErrorKind::InvalidInput);
+        }
+        assert!(fs::metadata(&path).is_err(), "nothing is written for invalid input");
+    }
+
+    #[test]
+    fn png16_round_trips_radiance() {
+        let radiance = [
+            Vec3::new(0.0, 0.5, 1.0),
+            Vec3::new(0.001, 0.002, 0.003),
+            Vec3::new(0.25 / 255.0, 0.3, 0.999),
+            Vec3::new(2.0, -1.0, 0.75),
+        ];
+        let pixels: Vec<Color16> = radiance.iter().map(|&value| Color16::from_radiance(value)).collect();
+        let path = temp_path("round_trip.png");
+        save_png16(&path, 2, 2, &pixels).unwrap();
+        let (width, height, bit_depth, color_type, rows) = read_png(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!((width, height, bit_depth, color_type), (2, 2, 16, 2));
+
+        let decoded: Vec<Color16> = rows.iter().flat_map(|row| row.chunks(6).map(|pixel| {
+            let channel = |i: usize| u16::from_be_bytes([pixel[i], pixel[i + 1]]);
+            Color16::new(channel(0), channel(2), channel(4))
+        }).collect::<Vec<_>>()).collect();
+        assert_eq!(decoded, pixels);
+
+        // Values between 8-bit steps survive, out-of-range ones clip
+        for (pixel, value) in decoded.iter().zip(&radiance).take(3) {
+            let back = Vec3::new(pixel.r as f32, pixel.g as f32, pixel.b as f32) / 65535.0;
+            assert!((back - value).abs().max() <= 0.5 / 65535.0);
+        }
+        assert_eq!(decoded[0].g, 32768);
+        assert_eq!(decoded[3], Color16::new(65535, 0, 49151));
+    }
+
+    #[test]
+    fn depth_savers_reject_bad_planes() {
+        let path = temp_path("bad_planes");
+        let planes = [(0.0, 10.0), (-1.0, 10.0), (10.0, 10.0), (10.0, 1.0), (f32::NAN, 10.0), (0.1, f32::NAN), (0.1, f32::INFINITY)];
+        for (near, far) in planes {
+            for result in [save_depth_png16(&path, 1, 1, &[0.5], near, far), save_depth_exr(&path, 1, 1, &[0.5], near, far)] {
+                assert_eq!(result.unwrap_err().kind(), ErrorKind::InvalidInput, "near {} far {}", near, far);
+            }
+        }
+        assert!(fs::metadata(&path).is_err(), "nothing is written for invalid planes");
+    }
+
+    #[test]
+    fn exr_depth_holds_the_linear_distances() {
+        let (near, far) = (0.1, 100.0);
+        let ndc = |distance: f32| (far + near - 2.0 * near * far / distance) / (far - near);
+        let depth = [ndc(2.0), ndc(5.0), f32::INFINITY, ndc(0.5), -1.0, ndc(80.0)];
+        let path = temp_path("depth.exr");
+        save_depth_exr(&path, 3, 2, &depth, near, far).unwrap();
+        let bytes = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(bytes[..8], [0x76, 0x2F, 0x31, 0x01, 2, 0, 0, 0]);
+
+        // Attributes are name, type, size and value until an empty name
+        let mut position = 8;
+        let text = |position: &mut usize| {
+            let end = *position + bytes[*position..].iter().position(|&byte| byte == 0).unwrap();
+            let text = String::from_utf8(bytes[*position..end].to_vec()).unwrap();
+            *position = end + 1;
+            text
+        };
+        let read_i32 = |position: usize| i32::from_le_bytes(bytes[position..position + 4].try_into().unwrap());
+        let mut attributes = Vec::new();
+        loop {
+            let name = text(&mut position);
+            if name.is_empty() {
+                break;
+            }
+            let kind = text(&mut position);
+            let size = read_i32(position) as usize;
+            attributes.push((name, kind, bytes[position + 4..position + 4 + size].to_vec()));
+            position += 4 + size;
+        }
+        let attribute = |name: &str| attributes.iter().find(|(found, _, _)| found == name).unwrap_or_else(|| panic!("no {}", name));
+        assert_eq!(attribute("channels").1, "chlist");
+        assert!(attribute("channels").2.starts_with(b"Z\0"));
+        assert_eq!(attribute("compression").2, [0]);
+        let window: Vec<i32> = attribute("dataWindow").2.chunks(4).map(|value| i32::from_le_bytes(value.try_into().unwrap())).collect();
+        assert_eq!(window, [0, 0, 2, 1]);
+
+        // Offset table, then each scanline as its y, byte count and floats
+        let offsets: Vec<usize> = (0..2).map(|y| u64::from_le_bytes(bytes[position + y * 8..position + y * 8 + 8].try_into().unwrap()) as usize).collect();
+        let mut values = Vec::new();
+        for (y, &offset) in offsets.iter().enumerate() {
+            assert_eq!((read_i32(offset), read_i32(offset + 4)), (y as i32, 12));
+            values.extend(bytes[offset + 8..offset + 20].chunks(4).map(|value| f32::from_le_bytes(value.try_into().unwrap())));
+        }
+        assert_eq!(offsets[1] + 20, bytes.len());
+        for (value, expected) in values.iter().zip([2.0, 5.0, far, 0.5, near, 80.0]) {
+            assert!((value - expected).abs() < 1e-3 * expected, "{} instead of {}", value, expected);
+        }
+    }
+}
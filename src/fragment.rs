@@ -1,25 +1,77 @@
 
 use nalgebra_glm::{Vec2, Vec3};
 use crate::color::Color;
+use crate::math::unit_or;
 
+#[derive(Clone, Debug)]
 pub struct Fragment {
+    // Integer pixel coordinates; the attributes below were sampled at the
+    // pixel's center, position + (0.5, 0.5) (see `triangle::PIXEL_CENTER`)
     pub position: Vec2,
     pub color: Color,
+    // z-buffer depth (NDC z mapped onto the viewport's depth range), interpolated
+    // linearly in screen space; only good for z-testing
     pub depth: f32,
+    // Eye-space distance along the view axis (clip-space w), for fog and other
+    // distance-based effects
+    pub view_depth: f32,
     pub normal: Vec3,
     pub intensity: f32,
     pub vertex_position: Vec3,
+    pub world_position: Vec3,
+    pub tex_coords: Vec2,
+    // Weights of the triangle's three vertices at this fragment; a component
+    // near zero means the fragment is close to the opposite edge
+    pub barycentric: Vec3,
+    // Pixels this surface point moved on screen since the previous frame
+    pub velocity: Vec2,
+    // Approximate share of the pixel covered by the triangle, below 1 only
+    // along silhouettes when edge antialiasing is on
+    pub coverage: f32,
+    // `Vertex::flat_id` of the provoking (first) vertex, the same everywhere
+    // on the triangle
+    pub flat_id: u32,
 }
 
 impl Fragment {
+    // False when any interpolated value is NaN or infinite, typically from a
+    // degenerate triangle or normalizing a zero normal. Shaders fed such a
+    // fragment silently produce black, since float-to-u8 casts map NaN to 0.
+    pub fn is_finite(&self) -> bool {
+        self.depth.is_finite()
+            && self.view_depth.is_finite()
+            && self.intensity.is_finite()
+            && self.normal.iter().all(|value| value.is_finite())
+            && self.world_position.iter().all(|value| value.is_finite())
+            && self.vertex_position.iter().all(|value| value.is_finite())
+            && self.tex_coords.iter().all(|value| value.is_finite())
+    }
+
     pub fn new(x: f32, y: f32, color: Color, depth: f32, normal: Vec3, intensity: f32, vertex_position: Vec3,) -> Self {
         Fragment {
             position: Vec2::new(x, y),
             color,
             depth,
+            view_depth: 1.0,
             normal,
             intensity,
-            vertex_position
+            vertex_position,
+            world_position: vertex_position,
+            tex_coords: Vec2::new(0.0, 0.0),
+            barycentric: Vec3::new(1.0 / 3.0, 1.0 / 3.0, 1.0 / 3.0),
+            velocity: Vec2::new(0.0, 0.0),
+            coverage: 1.0,
+            flat_id: 0,
         }
     }
+
+    // Fragment of a unit-sphere-like surface at model position
+    // `vertex_position`, for calling a shader directly (see
+    // `shaders::shade_fragment`): the normal points away from the origin (+Z
+    // at the origin itself), world and model space coincide and the pixel is
+    // (0, 0).
+    pub fn at(vertex_position: Vec3, depth: f32, intensity: f32) -> Self {
+        let normal = unit_or(vertex_position, Vec3::new(0.0, 0.0, 1.0));
+        Fragment::new(0.0, 0.0, Color::black(), depth, normal, intensity, vertex_position)
+    }
 }
\ No newline at end of file
@@ -1,11 +1,110 @@
+use crate::color::{Color, Color16};
+use nalgebra_glm::{Vec2, Vec3};
 
+
+// Comparison a fragment's depth must pass against the stored depth
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DepthFunc {
+    #[default]
+    Less,
+    LessEqual,
+    Always,
+}
+
+impl DepthFunc {
+    pub fn passes(self, depth: f32, stored: f32) -> bool {
+        match self {
+            DepthFunc::Less => depth < stored,
+            DepthFunc::LessEqual => depth <= stored,
+            DepthFunc::Always => true,
+        }
+    }
+}
+
+// What the alpha of a shaded color (times `Uniforms::opacity`) does when it
+// is written
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AlphaMode {
+    // Alpha is ignored and every fragment is drawn solid
+    #[default]
+    Opaque,
+    // Mixed over what is already in the pixel. Depth is still written, so
+    // translucent meshes must be drawn back to front, after the opaque ones.
+    Blend,
+    // Screen-door transparency: the fragment is dropped wherever the pixel's
+    // dither threshold (`Uniforms::dither_pattern`) is above its alpha, so
+    // alpha 0.5 keeps half the pixels (a checkerboard with Bayer). Survivors
+    // are opaque and depth-tested as usual, so nothing needs sorting; the
+    // stipple reads as transparency at a distance or after a blur.
+    Dither,
+}
+
+// Per-pixel state of one depth peeling pass (see `render_scene_peeled`):
+// fragments at or in front of `min_depth` were peeled by an earlier pass and
+// are dropped, and the coverage times alpha of the nearest one left is kept
+// in `alpha` next to its straight color in `buffer`
+#[derive(Clone, Debug)]
+pub struct DepthPeel {
+    pub min_depth: Vec<f32>,
+    pub alpha: Vec<f32>,
+}
+
+// Caller-chosen tag of a drawn object, see `Framebuffer::pick`
+pub type ObjectId = u32;
+
+// Byte layout of `Framebuffer::to_bytes`, named in memory order
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelOrder {
+    Rgba,
+    Bgra,
+    Rgb,
+    Bgr,
+}
+
+impl ChannelOrder {
+    // Stride between consecutive pixels
+    pub const fn bytes_per_pixel(self) -> usize {
+        match self {
+            ChannelOrder::Rgba | ChannelOrder::Bgra => 4,
+            ChannelOrder::Rgb | ChannelOrder::Bgr => 3,
+        }
+    }
+}
+
+// Side length in pixels of the screen tiles tracked for partial redraws
+pub const TILE_SIZE: usize = 32;
+
+#[derive(Clone)]
 pub struct Framebuffer {
     pub width: usize,
     pub height: usize,
     pub buffer: Vec<u32>,
     pub zbuffer: Vec<f32>,
+    // Screen-space motion in pixels of the surface drawn at each pixel, zero where empty
+    pub velocity_buffer: Vec<Vec2>,
+    // Object drawn at each pixel (`Uniforms::object_id` of its draw call)
+    pub object_ids: Vec<Option<ObjectId>>,
+    // Opt-in HDR buffer for unsorted emissive layers (`Uniforms::accumulate`),
+    // linear RGB with 1.0 per fully bright contribution and no upper bound.
+    // Composite it with `postprocess::composite_accumulation`.
+    pub accumulation: Option<Vec<Vec3>>,
+    // Opt-in float copy of the color buffer for `to_color16`: linear RGB as
+    // shaded, after exposure and coverage blending but before the tone curve
+    // and the 8-bit rounding, so dim exposures and soft edges keep their
+    // in-between values. Display-only steps (dither, wireframe overlay,
+    // palette lock) leave it alone. Enable it with `enable_radiance`.
+    pub radiance: Option<Vec<Vec3>>,
+    // Set only while a depth peeling pass is drawn into the framebuffer
+    pub depth_peel: Option<DepthPeel>,
     background_color: u32,
     current_color: u32,
+    current_radiance: Vec3,
+    current_velocity: Vec2,
+    current_object: Option<ObjectId>,
+    tile_columns: usize,
+    tile_rows: usize,
+    dirty_tiles: Vec<bool>,
+    previous_dirty_tiles: Vec<bool>,
 }
 
 impl Framebuffer {
@@ -15,8 +114,21 @@ impl Framebuffer {
             height,
             buffer: vec![0; width * height],
             zbuffer: vec![f32::INFINITY; width * height],
+            velocity_buffer: vec![Vec2::new(0.0, 0.0); width * height],
+            object_ids: vec![None; width * height],
+            accumulation: None,
+            radiance: None,
+            depth_peel: None,
             background_color: 0x000000,
-            current_color: 0xFFFFFF
+            current_color: 0xFFFFFF,
+            current_radiance: Vec3::new(1.0, 1.0, 1.0),
+            current_velocity: Vec2::new(0.0, 0.0),
+            current_object: None,
+            tile_columns: width.div_ceil(TILE_SIZE),
+            tile_rows: height.div_ceil(TILE_SIZE),
+            // Everything starts dirty so the first tiled frame draws the whole screen
+            dirty_tiles: vec![true; width.div_ceil(TILE_SIZE) * height.div_ceil(TILE_SIZE)],
+            previous_dirty_tiles: vec![true; width.div_ceil(TILE_SIZE) * height.div_ceil(TILE_SIZE)],
         }
     }
 
@@ -27,24 +139,237 @@ impl Framebuffer {
         for depth in self.zbuffer.iter_mut() {
             *depth = f32::INFINITY;
         }
+        self.velocity_buffer.fill(Vec2::new(0.0, 0.0));
+        self.object_ids.fill(None);
+        if let Some(accumulation) = &mut self.accumulation {
+            accumulation.fill(Vec3::zeros());
+        }
+        let background = Color::from_hex(self.background_color).to_vec3();
+        if let Some(radiance) = &mut self.radiance {
+            radiance.fill(background);
+        }
+    }
+
+    // Starts the radiance buffer from the current 8-bit colors
+    pub fn enable_radiance(&mut self) {
+        if self.radiance.is_none() {
+            self.radiance = Some(self.buffer.iter().map(|&pixel| Color::from_hex(pixel).to_vec3()).collect());
+        }
+    }
+
+    pub fn enable_accumulation(&mut self) {
+        if self.accumulation.is_none() {
+            self.accumulation = Some(vec![Vec3::zeros(); self.width * self.height]);
+        }
+    }
+
+    // Adds `radiance` to the accumulation buffer if `depth` passes against the
+    // z-buffer, which it never writes, so additive layers need no sorting
+    pub fn accumulate(&mut self, x: usize, y: usize, depth: f32, depth_func: DepthFunc, radiance: Vec3) {
+        if x < self.width && y < self.height {
+            let index = y * self.width + x;
+            if let Some(accumulation) = &mut self.accumulation {
+                if depth_func.passes(depth, self.zbuffer[index]) {
+                    accumulation[index] += radiance;
+                }
+            }
+        }
     }
 
     pub fn point(&mut self, x: usize, y: usize, depth: f32) {
+        self.point_with_depth_state(x, y, depth, DepthFunc::Less, true);
+    }
+
+    // Transparent layers typically test with `Less` but leave the z-buffer untouched
+    pub fn point_with_depth_state(&mut self, x: usize, y: usize, depth: f32, depth_func: DepthFunc, depth_write: bool) {
         if x < self.width && y < self.height {
             let index = y * self.width + x;
 
-            if self.zbuffer[index] > depth {
+            if depth_func.passes(depth, self.zbuffer[index]) {
                 self.buffer[index] = self.current_color;
-                self.zbuffer[index] = depth;
+                if let Some(radiance) = &mut self.radiance {
+                    radiance[index] = self.current_radiance;
+                }
+                self.velocity_buffer[index] = self.current_velocity;
+                self.object_ids[index] = self.current_object;
+                if depth_write {
+                    self.zbuffer[index] = depth;
+                }
             }
         }
     }
 
+    // 16-bit copy of the image for `export::save_png16`, from the radiance
+    // buffer (clipped to [0, 1]). Without one this is only the 8-bit buffer
+    // widened, which adds no precision.
+    pub fn to_color16(&self) -> Vec<Color16> {
+        match &self.radiance {
+            Some(radiance) => radiance.iter().map(|&value| Color16::from_radiance(value)).collect(),
+            None => self.buffer.iter().map(|&pixel| Color16::from(Color::from_hex(pixel))).collect(),
+        }
+    }
+
+    // Packed pixels in the requested channel order, rows top to bottom with no
+    // padding; alpha, when present, is always opaque
+    pub fn to_bytes(&self, order: ChannelOrder) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.buffer.len() * order.bytes_per_pixel());
+        for &pixel in &self.buffer {
+            let color = Color::from_hex(pixel);
+            let (r, g, b) = (color.r(), color.g(), color.b());
+            match order {
+                ChannelOrder::Rgba => bytes.extend_from_slice(&[r, g, b, 255]),
+                ChannelOrder::Bgra => bytes.extend_from_slice(&[b, g, r, 255]),
+                ChannelOrder::Rgb => bytes.extend_from_slice(&[r, g, b]),
+                ChannelOrder::Bgr => bytes.extend_from_slice(&[b, g, r]),
+            }
+        }
+        bytes
+    }
+
     pub fn set_background_color(&mut self, color: u32) {
         self.background_color = color;
     }
 
+    // Also resets the current radiance to the same color
     pub fn set_current_color(&mut self, color: u32) {
         self.current_color = color;
+        self.current_radiance = Color::from_hex(color).to_vec3();
+    }
+
+    // Value written to the radiance buffer along with the current color; set
+    // it after `set_current_color`
+    pub fn set_current_radiance(&mut self, radiance: Vec3) {
+        self.current_radiance = radiance;
+    }
+
+    pub fn set_current_velocity(&mut self, velocity: Vec2) {
+        self.current_velocity = velocity;
+    }
+
+    pub fn set_current_object(&mut self, object: Option<ObjectId>) {
+        self.current_object = object;
+    }
+
+    // Which object is visible at a pixel, e.g. under the mouse; `None` for the
+    // background, untagged objects and coordinates off screen
+    pub fn pick(&self, x: usize, y: usize) -> Option<ObjectId> {
+        if x < self.width && y < self.height {
+            self.object_ids[y * self.width + x]
+        } else {
+            None
+        }
+    }
+
+    // Starts a partial redraw: last frame's dirty tiles must still be cleared,
+    // since whatever was drawn there may have moved away.
+    pub fn begin_tiled_frame(&mut self) {
+        std::mem::swap(&mut self.dirty_tiles, &mut self.previous_dirty_tiles);
+        self.dirty_tiles.iter_mut().for_each(|dirty| *dirty = false);
+    }
+
+    // Marks every tile overlapping the pixel rectangle (inclusive bounds) as dirty
+    pub fn mark_dirty(&mut self, min_x: i32, min_y: i32, max_x: i32, max_y: i32) {
+        // A zero-size framebuffer has no tiles to touch
+        if self.width == 0 || self.height == 0 {
+            return;
+        }
+        if max_x < 0 || max_y < 0 || min_x >= self.width as i32 || min_y >= self.height as i32 {
+            return;
+        }
+
+        let first_column = min_x.max(0) as usize / TILE_SIZE;
+        let first_row = min_y.max(0) as usize / TILE_SIZE;
+        let last_column = (max_x as usize).min(self.width - 1) / TILE_SIZE;
+        let last_row = (max_y as usize).min(self.height - 1) / TILE_SIZE;
+
+        for row in first_row..=last_row {
+            for column in first_column..=last_column {
+                self.dirty_tiles[row * self.tile_columns + column] = true;
+            }
+        }
+    }
+
+    // Whether any tile overlapping the pixel rectangle (inclusive bounds) is
+    // dirty this frame or the previous one
+    pub fn is_rect_dirty(&self, min_x: i32, min_y: i32, max_x: i32, max_y: i32) -> bool {
+        // A zero-size framebuffer has no tiles to touch
+        if self.width == 0 || self.height == 0 {
+            return false;
+        }
+        if max_x < 0 || max_y < 0 || min_x >= self.width as i32 || min_y >= self.height as i32 {
+            return false;
+        }
+
+        let first_column = min_x.max(0) as usize / TILE_SIZE;
+        let first_row = min_y.max(0) as usize / TILE_SIZE;
+        let last_column = (max_x as usize).min(self.width - 1) / TILE_SIZE;
+        let last_row = (max_y as usize).min(self.height - 1) / TILE_SIZE;
+
+        (first_row..=last_row).any(|row| (first_column..=last_column).any(|column| self.is_tile_dirty(column, row)))
+    }
+
+    pub fn mark_all_dirty(&mut self) {
+        self.dirty_tiles.iter_mut().for_each(|dirty| *dirty = true);
+    }
+
+    pub fn is_tile_dirty(&self, column: usize, row: usize) -> bool {
+        let index = row * self.tile_columns + column;
+        self.dirty_tiles[index] || self.previous_dirty_tiles[index]
+    }
+
+    pub fn tile_count(&self) -> (usize, usize) {
+        (self.tile_columns, self.tile_rows)
+    }
+
+    // Clears color, depth, velocity and object ids of the tiles dirty in either the current or previous frame
+    pub fn clear_dirty_tiles(&mut self) {
+        for row in 0..self.tile_rows {
+            for column in 0..self.tile_columns {
+                if !self.is_tile_dirty(column, row) {
+                    continue;
+                }
+
+                let x_end = ((column + 1) * TILE_SIZE).min(self.width);
+                let y_end = ((row + 1) * TILE_SIZE).min(self.height);
+                for y in row * TILE_SIZE..y_end {
+                    let start = y * self.width + column * TILE_SIZE;
+                    let end = y * self.width + x_end;
+                    self.buffer[start..end].fill(self.background_color);
+                    self.zbuffer[start..end].fill(f32::INFINITY);
+                    self.velocity_buffer[start..end].fill(Vec2::new(0.0, 0.0));
+                    self.object_ids[start..end].fill(None);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn to_bytes_lays_out_every_channel_order() {
+        let mut framebuffer = Framebuffer::new(2, 1);
+        framebuffer.buffer = vec![0x102030, 0xa0b0c0];
+        let expected: [(ChannelOrder, &[u8]); 4] = [
+            (ChannelOrder::Rgba, &[0x10, 0x20, 0x30, 255, 0xa0, 0xb0, 0xc0, 255]),
+            (ChannelOrder::Bgra, &[0x30, 0x20, 0x10, 255, 0xc0, 0xb0, 0xa0, 255]),
+            (ChannelOrder::Rgb, &[0x10, 0x20, 0x30, 0xa0, 0xb0, 0xc0]),
+            (ChannelOrder::Bgr, &[0x30, 0x20, 0x10, 0xc0, 0xb0, 0xa0]),
+        ];
+        for (order, bytes) in expected {
+            assert_eq!(framebuffer.to_bytes(order), bytes, "{:?}", order);
+            assert_eq!(bytes.len(), framebuffer.buffer.len() * order.bytes_per_pixel());
+        }
+    }
+
+    #[test]
+    fn dirty_tiles_ignore_zero_size_framebuffers() {
+        for (width, height) in [(0, 0), (0, 4), (4, 0)] {
+            let mut framebuffer = Framebuffer::new(width, height);
+            framebuffer.mark_dirty(-1, -1, 3, 3);
+            assert!(!framebuffer.is_rect_dirty(-1, -1, 3, 3));
+        }
     }
 }
@@ -0,0 +1,404 @@
+use std::collections::HashMap;
+use nalgebra_glm::{Vec2, Vec3};
+use std::f32::consts::PI;
+use crate::mesh::Mesh;
+use crate::vertex::Vertex;
+use crate::texture::spherical_uv;
+use crate::triangle::Winding;
+use crate::math::unit_or;
+
+// Unit icosphere: an icosahedron whose triangles are split in four
+// `subdivisions` times, pushing new vertices onto the sphere. Triangle count
+// is 20 * 4^subdivisions (20, 80, 320, 1280, ...). Faces wind counter-clockwise
+// seen from outside; normals equal positions. UVs are equirectangular per
+// vertex, so triangles across the u seam interpolate badly: enable
+// `Uniforms::spherical_uv` for textured sphere shading.
+pub fn generate_sphere(subdivisions: u32) -> Mesh {
+    let t = (1.0 + 5.0f32.sqrt()) / 2.0;
+    let mut positions: Vec<Vec3> = [
+        (-1.0, t, 0.0), (1.0, t, 0.0), (-1.0, -t, 0.0), (1.0, -t, 0.0),
+        (0.0, -1.0, t), (0.0, 1.0, t), (0.0, -1.0, -t), (0.0, 1.0, -t),
+        (t, 0.0, -1.0), (t, 0.0, 1.0), (-t, 0.0, -1.0), (-t, 0.0, 1.0),
+    ]
+    .iter()
+    .map(|&(x, y, z)| Vec3::new(x, y, z).normalize())
+    .collect();
+
+    let mut faces: Vec<[u32; 3]> = vec![
+        [0, 11, 5], [0, 5, 1], [0, 1, 7], [0, 7, 10], [0, 10, 11],
+        [1, 5, 9], [5, 11, 4], [11, 10, 2], [10, 7, 6], [7, 1, 8],
+        [3, 9, 4], [3, 4, 2], [3, 2, 6], [3, 6, 8], [3, 8, 9],
+        [4, 9, 5], [2, 4, 11], [6, 2, 10], [8, 6, 7], [9, 8, 1],
+    ];
+
+    for _ in 0..subdivisions {
+        // Edges are shared by two faces; reuse their midpoint vertex
+        let mut midpoints: HashMap<(u32, u32), u32> = HashMap::new();
+        let mut midpoint = |a: u32, b: u32, positions: &mut Vec<Vec3>| {
+            let key = (a.min(b), a.max(b));
+            *midpoints.entry(key).or_insert_with(|| {
+                let middle = ((positions[a as usize] + positions[b as usize]) * 0.5).normalize();
+                positions.push(middle);
+                positions.len() as u32 - 1
+            })
+        };
+
+        let mut subdivided = Vec::with_capacity(faces.len() * 4);
+        for [a, b, c] in faces {
+            let ab = midpoint(a, b, &mut positions);
+            let bc = midpoint(b, c, &mut positions);
+            let ca = midpoint(c, a, &mut positions);
+            subdivided.extend_from_slice(&[[a, ab, ca], [b, bc, ab], [c, ca, bc], [ab, bc, ca]]);
+        }
+        faces = subdivided;
+    }
+
+    let vertices = positions.iter()
+        .map(|&position| Vertex::new(position, position, spherical_uv(&position)))
+        .collect();
+    let indices = faces.into_iter().flatten().collect();
+
+    Mesh::new(vertices, indices)
+}
+
+// Flat annulus in the XZ plane between `inner_radius` and `outer_radius`, for
+// planetary rings. Faces wind counter-clockwise seen from +Y, the normal's
+// side; render it without back-face culling to see it from below too. u runs
+// around the ring and v from the inner (0) to the outer (1) edge.
+pub fn generate_ring(inner_radius: f32, outer_radius: f32, segments: u32) -> Mesh {
+    let segments = segments.max(3);
+    let normal = Vec3::new(0.0, 1.0, 0.0);
+
+    let mut vertices = Vec::with_capacity(2 * (segments as usize + 1));
+    for i in 0..=segments {
+        let u = i as f32 / segments as f32;
+        let (sin, cos) = (u * 2.0 * PI).sin_cos();
+        vertices.push(Vertex::new(Vec3::new(cos * inner_radius, 0.0, sin * inner_radius), normal, Vec2::new(u, 0.0)));
+        vertices.push(Vertex::new(Vec3::new(cos * outer_radius, 0.0, sin * outer_radius), normal, Vec2::new(u, 1.0)));
+    }
+
+    let mut indices = Vec::with_capacity(6 * segments as usize);
+    for i in 0..segments {
+        let (inner, outer, next_inner, next_outer) = (2 * i, 2 * i + 1, 2 * i + 2, 2 * i + 3);
+        indices.extend_from_slice(&[inner, next_outer, outer, inner, next_inner, next_outer]);
+    }
+
+    Mesh::new(vertices, indices)
+}
+
+// (outward direction, u axis) of each cube face, ordered +X, -X, +Y, -Y, +Z, -Z
+// like the cubemap faces. The v axis is outward x u.
+const CUBE_FACES: [([f32; 3], [f32; 3]); 6] = [
+    ([1.0, 0.0, 0.0], [0.0, 0.0, -1.0]),
+    ([-1.0, 0.0, 0.0], [0.0, 0.0, 1.0]),
+    ([0.0, 1.0, 0.0], [1.0, 0.0, 0.0]),
+    ([0.0, -1.0, 0.0], [1.0, 0.0, 0.0]),
+    ([0.0, 0.0, 1.0], [1.0, 0.0, 0.0]),
+    ([0.0, 0.0, -1.0], [-1.0, 0.0, 0.0]),
+];
+
+// Unit sphere made of six `resolution` x `resolution` grids, one per face of a
+// cube, pushed out onto the sphere. Grid lines are spaced by equal angles, so
+// triangles stay close in size everywhere and nothing pinches at the poles.
+// Each face has its own vertices and a full [0, 1] UV square, for per-face
+// textures; a mesh has 12 * resolution^2 triangles. Faces wind
+// counter-clockwise seen from outside and normals equal positions.
+pub fn generate_cube_sphere(resolution: u32) -> Mesh {
+    let resolution = resolution.max(1);
+    let row = resolution + 1;
+
+    let mut vertices = Vec::with_capacity(6 * (row * row) as usize);
+    let mut indices = Vec::with_capacity(36 * (resolution * resolution) as usize);
+
+    for (face, &(outward, u_axis)) in CUBE_FACES.iter().enumerate() {
+        let outward = Vec3::from(outward);
+        let u_axis = Vec3::from(u_axis);
+        let v_axis = outward.cross(&u_axis);
+
+        for j in 0..row {
+            for i in 0..row {
+                let (s, t) = (i as f32 / resolution as f32, j as f32 / resolution as f32);
+                // Equal-angle spacing instead of equal steps across the flat face
+                let (a, b) = (((2.0 * s - 1.0) * PI / 4.0).tan(), ((2.0 * t - 1.0) * PI / 4.0).tan());
+                let position = (outward + u_axis * a + v_axis * b).normalize();
+                vertices.push(Vertex::new(position, position, Vec2::new(s, 1.0 - t)));
+            }
+        }
+
+        let base = face as u32 * row * row;
+        for j in 0..resolution {
+            for i in 0..resolution {
+                let corner = base + j * row + i;
+                let (right, up, up_right) = (corner + 1, corner + row, corner + row + 1);
+                indices.extend_from_slice(&[corner, right, up_right, corner, up_right, up]);
+            }
+        }
+    }
+
+    Mesh::new(vertices, indices)
+}
+
+// Torus around the Y axis: a tube of `minor_radius` swept along a circle of
+// `major_radius` in the XZ plane. The seam vertices are duplicated so u (along
+// the ring) and v (around the tube) both run the full [0, 1] without jumping
+// back. Faces wind counter-clockwise seen from outside and normals point away
+// from the tube's center line.
+pub fn generate_torus(major_radius: f32, minor_radius: f32, major_segs: u32, minor_segs: u32) -> Mesh {
+    let (major_segs, minor_segs) = (major_segs.max(3), minor_segs.max(3));
+    let row = minor_segs + 1;
+
+    let mut vertices = Vec::with_capacity(((major_segs + 1) * row) as usize);
+    for i in 0..=major_segs {
+        let u = i as f32 / major_segs as f32;
+        let (sin_u, cos_u) = (u * 2.0 * PI).sin_cos();
+        let radial = Vec3::new(cos_u, 0.0, sin_u);
+
+        for j in 0..=minor_segs {
+            let v = j as f32 / minor_segs as f32;
+            let (sin_v, cos_v) = (v * 2.0 * PI).sin_cos();
+            let normal = radial * cos_v + Vec3::new(0.0, sin_v, 0.0);
+            let position = radial * major_radius + normal * minor_radius;
+            vertices.push(Vertex::new(position, normal, Vec2::new(u, v)));
+        }
+    }
+
+    let mut indices = Vec::with_capacity((6 * major_segs * minor_segs) as usize);
+    for i in 0..major_segs {
+        for j in 0..minor_segs {
+            let corner = i * row + j;
+            let (next_ring, next_tube, both) = (corner + row, corner + 1, corner + row + 1);
+            indices.extend_from_slice(&[corner, next_tube, both, corner, both, next_ring]);
+        }
+    }
+
+    Mesh::new(vertices, indices)
+}
+
+// Replaces the vertex normals with smooth ones rebuilt from the faces: each
+// face adds its area-weighted normal to its corners, and vertices sharing a
+// position (UV seams of imported models) get the same sum. With
+// `orient_outward`, faces and normals pointing towards the mesh centroid are
+// flipped first, which rescues inside-out or inconsistently wound OBJ files
+// that would otherwise render black. That test assumes a roughly convex,
+// star-shaped mesh, which planets are. Vertices no face touches keep their
+// normal.
+pub fn recompute_normals(mesh: &mut Mesh, orient_outward: bool) {
+    let key = |position: &Vec3| (position.x.to_bits(), position.y.to_bits(), position.z.to_bits());
+    let mut sums: HashMap<(u32, u32, u32), Vec3> = HashMap::new();
+
+    let centroid = if mesh.vertices.is_empty() {
+        Vec3::zeros()
+    } else {
+        mesh.vertices.iter().map(|vertex| vertex.position).sum::<Vec3>() / mesh.vertices.len() as f32
+    };
+
+    let vertices = &mesh.vertices;
+    for corners in mesh.indices.chunks_exact_mut(3) {
+        let [a, b, c] = [0, 1, 2].map(|i| vertices[corners[i] as usize].position);
+        // Unnormalized: its length is twice the triangle area
+        let mut face_normal = (b - a).cross(&(c - a));
+        // Oriented per face, so reversed faces don't cancel their neighbours,
+        // and rewound so back-face culling agrees
+        if orient_outward && face_normal.dot(&((a + b + c) / 3.0 - centroid)) < 0.0 {
+            face_normal = -face_normal;
+            corners.swap(1, 2);
+        }
+        for position in [a, b, c] {
+            *sums.entry(key(&position)).or_insert_with(Vec3::zeros) += face_normal;
+        }
+    }
+
+    for vertex in &mut mesh.vertices {
+        let Some(sum) = sums.get(&key(&vertex.position)) else { continue };
+        if sum.norm_squared() <= f32::EPSILON * f32::EPSILON {
+            continue;
+        }
+
+        let mut normal = sum.normalize();
+        if orient_outward && normal.dot(&(vertex.position - centroid)) < 0.0 {
+            normal = -normal;
+        }
+        vertex.normal = normal;
+    }
+}
+
+// Guesses the winding of a mesh's front faces, for `Uniforms::front_face`.
+// Each face votes with its area: its winding normal either agrees with its
+// vertex normals, or, where those are missing, points away from the mesh
+// centroid. Counter-clockwise wins ties and meshes with no votes, so an
+// explicit setting is still needed for open or unusual meshes.
+pub fn detect_winding(mesh: &Mesh) -> Winding {
+    let count = mesh.vertices.len().max(1) as f32;
+    let centroid = mesh.vertices.iter().map(|vertex| vertex.position).sum::<Vec3>() / count;
+
+    let mut vote = 0.0;
+    for corners in mesh.indices.chunks_exact(3) {
+        let [a, b, c] = [0, 1, 2].map(|i| &mesh.vertices[corners[i] as usize]);
+        let face_normal = (b.position - a.position).cross(&(c.position - a.position));
+
+        let vertex_normal = a.normal + b.normal + c.normal;
+        let reference = if vertex_normal.norm_squared() > f32::EPSILON {
+            vertex_normal
+        } else {
+            (a.position + b.position + c.position) / 3.0 - centroid
+        };
+        // A face centered on the centroid has no outward side to vote for
+        let reference = unit_or(reference, Vec3::zeros());
+        if reference == Vec3::zeros() {
+            continue;
+        }
+        vote += face_normal.dot(&reference).signum() * face_normal.magnitude();
+    }
+
+    if vote < 0.0 { Winding::Clockwise } else { Winding::CounterClockwise }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Whether every face's winding normal points away from the origin
+    fn faces_point_outward(mesh: &Mesh) -> bool {
+        mesh.indices.chunks_exact(3).all(|corners| {
+            let [a, b, c] = [0, 1, 2].map(|i| mesh.vertices[corners[i] as usize].position);
+            (b - a).cross(&(c - a)).dot(&(a + b + c)) > 0.0
+        })
+    }
+
+    #[test]
+    fn each_sphere_subdivision_multiplies_the_triangles_by_four() {
+        for subdivisions in 0..5 {
+            let sphere = generate_sphere(subdivisions);
+            assert_eq!(sphere.triangle_count(), 20 * 4usize.pow(subdivisions));
+            // Shared midpoints: V = F / 2 + 2 on a closed genus-0 mesh
+            assert_eq!(sphere.vertices.len(), sphere.triangle_count() / 2 + 2);
+        }
+    }
+
+    #[test]
+    fn sphere_vertices_lie_on_the_unit_sphere_with_outward_normals_and_winding() {
+        let sphere = generate_sphere(3);
+        for vertex in &sphere.vertices {
+            assert!((vertex.position.magnitude() - 1.0).abs() < 1e-5);
+            assert!((vertex.normal - vertex.position).magnitude() < 1e-6);
+            assert!((0.0..=1.0).contains(&vertex.tex_coords.x) && (0.0..=1.0).contains(&vertex.tex_coords.y));
+        }
+        assert!(faces_point_outward(&sphere));
+    }
+
+    #[test]
+    fn recomputed_normals_fix_reversed_normals_and_faces() {
+        let mut sphere = generate_sphere(2);
+        for vertex in &mut sphere.vertices {
+            vertex.normal = -vertex.normal;
+        }
+        // Consistent winding: rebuilt from the faces alone
+        let mut rebuilt = sphere.clone();
+        recompute_normals(&mut rebuilt, false);
+        assert!(rebuilt.vertices.iter().all(|vertex| vertex.normal.dot(&vertex.position) > 0.99));
+
+        // Every other face reversed too: only orienting outward saves it
+        for corners in sphere.indices.chunks_exact_mut(6) {
+            corners.swap(1, 2);
+        }
+        let mut oriented = sphere.clone();
+        recompute_normals(&mut oriented, true);
+        assert!(oriented.vertices.iter().all(|vertex| vertex.normal.dot(&vertex.position) > 0.99));
+        assert!(faces_point_outward(&oriented));
+    }
+
+    #[test]
+    fn detected_winding_follows_the_cube_faces() {
+        // At resolution 1 the cube sphere is a plain cube, wound counter-clockwise
+        let cube = generate_cube_sphere(1);
+        let mut reversed = cube.clone();
+        for corners in reversed.indices.chunks_exact_mut(3) {
+            corners.swap(1, 2);
+        }
+        assert_eq!(detect_winding(&cube), Winding::CounterClockwise);
+        assert_eq!(detect_winding(&reversed), Winding::Clockwise);
+
+        // Without normals the faces are compared to the centroid, wherever it is
+        for mut mesh in [cube, reversed] {
+            let expected = detect_winding(&mesh);
+            for vertex in &mut mesh.vertices {
+                vertex.normal = Vec3::zeros();
+                vertex.position += Vec3::new(10.0, -4.0, 3.0);
+            }
+            assert_eq!(detect_winding(&mesh), expected);
+        }
+    }
+
+    #[test]
+    fn cube_sphere_vertices_lie_on_the_unit_sphere_with_outward_normals_and_winding() {
+        for resolution in [1, 4] {
+            let sphere = generate_cube_sphere(resolution);
+            assert_eq!(sphere.triangle_count(), 12 * (resolution * resolution) as usize);
+            for vertex in &sphere.vertices {
+                assert!((vertex.position.magnitude() - 1.0).abs() < 1e-5);
+                assert!((vertex.normal - vertex.position).magnitude() < 1e-6);
+                assert!((0.0..=1.0).contains(&vertex.tex_coords.x) && (0.0..=1.0).contains(&vertex.tex_coords.y));
+            }
+            assert!(faces_point_outward(&sphere));
+        }
+    }
+
+    #[test]
+    fn torus_normals_point_out_of_the_tube_and_uvs_wrap_seamlessly() {
+        let (major, minor, major_segs, minor_segs) = (2.0, 0.5, 24, 12);
+        let torus = generate_torus(major, minor, major_segs, minor_segs);
+        // The point of the tube's center line closest to a position
+        let center_line = |position: Vec3| Vec3::new(position.x, 0.0, position.z).normalize() * major;
+
+        for vertex in &torus.vertices {
+            let offset = vertex.position - center_line(vertex.position);
+            assert!((offset.magnitude() - minor).abs() < 1e-5);
+            assert!((vertex.normal - offset / minor).magnitude() < 1e-5);
+        }
+        for corners in torus.indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| torus.vertices[corners[i] as usize].position);
+            let centroid = (a + b + c) / 3.0;
+            assert!((b - a).cross(&(c - a)).dot(&(centroid - center_line(centroid))) > 0.0);
+        }
+
+        // Seam vertices repeat the ones at the start of each direction, at the
+        // far end of the UV range, so no triangle spans the jump back to 0
+        let row = minor_segs as usize + 1;
+        let vertex = |i: usize, j: usize| &torus.vertices[i * row + j];
+        for j in 0..row {
+            let (first, last) = (vertex(0, j), vertex(major_segs as usize, j));
+            assert!((first.position - last.position).magnitude() < 1e-5);
+            assert_eq!((first.tex_coords.x, last.tex_coords.x), (0.0, 1.0));
+            assert_eq!(first.tex_coords.y, last.tex_coords.y);
+        }
+        for i in 0..=major_segs as usize {
+            let (first, last) = (vertex(i, 0), vertex(i, minor_segs as usize));
+            assert!((first.position - last.position).magnitude() < 1e-5);
+            assert_eq!((first.tex_coords.y, last.tex_coords.y), (0.0, 1.0));
+        }
+        for corners in torus.indices.chunks_exact(3) {
+            let uvs = [0, 1, 2].map(|i| torus.vertices[corners[i] as usize].tex_coords);
+            for (a, b) in [(uvs[0], uvs[1]), (uvs[1], uvs[2]), (uvs[2], uvs[0])] {
+                assert!((a - b).abs().max() <= 1.0 / minor_segs.min(major_segs) as f32 + 1e-6);
+            }
+        }
+    }
+
+    #[test]
+    fn faces_centered_on_the_centroid_do_not_spoil_the_winding_vote() {
+        // The first face is wound clockwise against its normal; the second has
+        // no normal and its centroid is exactly the mesh's, so it has no side
+        let normal = Vec3::new(0.0, 0.0, 1.0);
+        let positions = [
+            (Vec3::new(0.0, 0.0, 0.0), normal),
+            (Vec3::new(0.0, 1.0, 0.0), normal),
+            (Vec3::new(1.0, 0.0, 0.0), normal),
+            (Vec3::new(1.0, 1.0, 0.0), Vec3::zeros()),
+            (Vec3::new(0.0, 0.0, 1.0), Vec3::zeros()),
+            (Vec3::new(0.0, 0.0, -1.0), Vec3::zeros()),
+        ];
+        let vertices = positions.iter().map(|&(position, normal)| Vertex::new(position, normal, Vec2::zeros())).collect();
+        let mesh = Mesh::new(vertices, vec![0, 1, 2, 3, 4, 5]);
+        assert_eq!(detect_winding(&mesh), Winding::Clockwise);
+    }
+}
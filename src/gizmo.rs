@@ -0,0 +1,107 @@
+use nalgebra_glm::{Vec2, Vec3, Vec4};
+use crate::Uniforms;
+use crate::color::Color;
+use crate::framebuffer::{Framebuffer, DepthFunc};
+
+// Debug overlays in world space: lines are projected with the uniforms'
+// view, projection and viewport (the model matrix is ignored), depth-tested
+// against the z-buffer so scene geometry hides them, and never write depth.
+// Draw them after the scene.
+
+pub const AXIS_X_COLOR: Color = Color::new(230, 60, 60);
+pub const AXIS_Y_COLOR: Color = Color::new(60, 200, 60);
+pub const AXIS_Z_COLOR: Color = Color::new(70, 110, 240);
+
+// The world X, Y and Z axes from the origin to `length`, in red, green and blue
+pub fn draw_axes(framebuffer: &mut Framebuffer, uniforms: &Uniforms, length: f32) {
+    let origin = Vec3::zeros();
+    draw_line(framebuffer, uniforms, origin, Vec3::new(length, 0.0, 0.0), AXIS_X_COLOR);
+    draw_line(framebuffer, uniforms, origin, Vec3::new(0.0, length, 0.0), AXIS_Y_COLOR);
+    draw_line(framebuffer, uniforms, origin, Vec3::new(0.0, 0.0, length), AXIS_Z_COLOR);
+}
+
+// Ground grid on the XZ plane through the origin, from -`half_extent` to
+// `half_extent` on both axes with a line every `spacing` units
+pub fn draw_grid(framebuffer: &mut Framebuffer, uniforms: &Uniforms, half_extent: f32, spacing: f32, color: Color) {
+    if !(spacing > 0.0 && half_extent > 0.0) {
+        return;
+    }
+
+    let lines = (half_extent / spacing).floor() as i32;
+    for i in -lines..=lines {
+        let offset = i as f32 * spacing;
+        draw_line(framebuffer, uniforms, Vec3::new(offset, 0.0, -half_extent), Vec3::new(offset, 0.0, half_extent), color);
+        draw_line(framebuffer, uniforms, Vec3::new(-half_extent, 0.0, offset), Vec3::new(half_extent, 0.0, offset), color);
+    }
+}
+
+// One-pixel-wide world-space segment. It is cut at the near plane, so lines
+// passing beside or behind the camera still draw their visible part, and
+// clipped to the screen before stepping, so long lines cost only their
+// visible length. Depth is interpolated linearly on screen, which is exact
+// for NDC depth, and passes with `LessEqual` offset by `polygon_offset`.
+pub fn draw_line(framebuffer: &mut Framebuffer, uniforms: &Uniforms, from: Vec3, to: Vec3, color: Color) {
+    let view_proj = uniforms.projection_matrix * uniforms.view_matrix;
+    let clip_a = view_proj * Vec4::new(from.x, from.y, from.z, 1.0);
+    let clip_b = view_proj * Vec4::new(to.x, to.y, to.z, 1.0);
+    let Some((clip_a, clip_b)) = clip_near(clip_a, clip_b) else {
+        return;
+    };
+
+    let to_screen = |clip: Vec4| {
+        let screen = uniforms.viewport_matrix * Vec4::new(clip.x / clip.w, clip.y / clip.w, clip.z / clip.w, 1.0);
+        Vec3::new(screen.x, screen.y, screen.z)
+    };
+    let (a, b) = (to_screen(clip_a), to_screen(clip_b));
+    let Some((a, b)) = clip_to_rect(a, b, Vec2::new(framebuffer.width as f32, framebuffer.height as f32)) else {
+        return;
+    };
+
+    framebuffer.set_current_color(color.to_hex());
+    framebuffer.set_current_velocity(Vec2::new(0.0, 0.0));
+    framebuffer.set_current_object(None);
+
+    let steps = (b.x - a.x).abs().max((b.y - a.y).abs()).ceil().max(1.0) as usize;
+    for step in 0..=steps {
+        let point = a.lerp(&b, step as f32 / steps as f32);
+        let (x, y) = (point.x.floor(), point.y.floor());
+        if x >= 0.0 && y >= 0.0 {
+            framebuffer.point_with_depth_state(x as usize, y as usize, point.z + uniforms.polygon_offset, DepthFunc::LessEqual, false);
+        }
+    }
+}
+
+// Part of the clip-space segment in front of the near plane (z >= -w)
+fn clip_near(a: Vec4, b: Vec4) -> Option<(Vec4, Vec4)> {
+    let (distance_a, distance_b) = (a.z + a.w, b.z + b.w);
+    match (distance_a >= 0.0, distance_b >= 0.0) {
+        (true, true) => Some((a, b)),
+        (false, false) => None,
+        (true, false) => Some((a, a.lerp(&b, distance_a / (distance_a - distance_b)))),
+        (false, true) => Some((b.lerp(&a, distance_b / (distance_b - distance_a)), b)),
+    }
+    .filter(|(a, b)| a.w > f32::EPSILON && b.w > f32::EPSILON)
+}
+
+// Liang-Barsky clip of a screen segment (with its depth in z) to [0, size)
+fn clip_to_rect(a: Vec3, b: Vec3, size: Vec2) -> Option<(Vec3, Vec3)> {
+    let delta = b - a;
+    let (mut enter, mut exit) = (0.0f32, 1.0f32);
+    // Keeps the far edge inside the last pixel
+    let max = size - Vec2::new(1e-3, 1e-3);
+    for (p, q) in [(-delta.x, a.x), (delta.x, max.x - a.x), (-delta.y, a.y), (delta.y, max.y - a.y)] {
+        if p == 0.0 {
+            if q < 0.0 {
+                return None;
+            }
+        } else {
+            let t = q / p;
+            if p < 0.0 { enter = enter.max(t) } else { exit = exit.min(t) }
+        }
+    }
+
+    if enter > exit || !a.iter().chain(b.iter()).all(|value| value.is_finite()) {
+        return None;
+    }
+    Some((a + delta * enter, a + delta * exit))
+}
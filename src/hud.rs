@@ -0,0 +1,275 @@
+use minifb::{Key, KeyRepeat, Window};
+use std::fs;
+use std::io::{self, ErrorKind};
+use crate::color::Color;
+use crate::framebuffer::Framebuffer;
+use crate::params::ShaderParams;
+use crate::text::{draw_text, CELL_HEIGHT};
+
+// Distance of the overlay from the framebuffer's top-left corner, in pixels
+const HUD_MARGIN: i32 = 8;
+
+// Keys a bindings file can name, by their `Key` variant
+const BINDABLE_KEYS: &[Key] = &[
+    Key::A, Key::B, Key::C, Key::D, Key::E, Key::F, Key::G, Key::H, Key::I, Key::J, Key::K, Key::L, Key::M,
+    Key::N, Key::O, Key::P, Key::Q, Key::R, Key::S, Key::T, Key::U, Key::V, Key::W, Key::X, Key::Y, Key::Z,
+    Key::Key0, Key::Key1, Key::Key2, Key::Key3, Key::Key4, Key::Key5, Key::Key6, Key::Key7, Key::Key8, Key::Key9,
+    Key::F1, Key::F2, Key::F3, Key::F4, Key::F5, Key::F6, Key::F7, Key::F8, Key::F9, Key::F10, Key::F11, Key::F12,
+    Key::Minus, Key::Equal, Key::LeftBracket, Key::RightBracket, Key::Semicolon, Key::Apostrophe,
+    Key::Slash, Key::Backslash, Key::Insert, Key::Delete, Key::End, Key::PageUp, Key::PageDown,
+    Key::NumPadPlus, Key::NumPadMinus, Key::NumPadAsterisk, Key::NumPadSlash,
+];
+
+// A numeric `ShaderParams` field, by its file key (see `ShaderParams::number`),
+// nudged by `step` per press of `increase` or `decrease`; held keys repeat.
+// The value is kept within `range`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParamBinding {
+    pub param: String,
+    pub increase: Key,
+    pub decrease: Key,
+    pub step: f32,
+    pub range: (f32, f32),
+}
+
+impl ParamBinding {
+    pub fn new(param: &str, decrease: Key, increase: Key, step: f32, range: (f32, f32)) -> Self {
+        ParamBinding { param: param.to_string(), increase, decrease, step, range }
+    }
+}
+
+// Live shader tuning: while shown (toggled with `toggle`), the bindings'
+// keys change their parameters and the current values are listed in the
+// top-left corner. Hidden, it ignores the keys and draws nothing. Default
+// bindings, also in `assets/hud_bindings.toml`:
+//   H      show or hide
+//   Z / X  cloud_threshold -/+ 0.02
+//   C / V  lava_zoom -/+ 50
+//   B / N  spin_speed -/+ 0.005
+// Load a bindings file, replace `bindings` or build it with `new` to tune
+// other parameters.
+#[derive(Debug, Clone)]
+pub struct ParamHud {
+    pub bindings: Vec<ParamBinding>,
+    pub toggle: Key,
+    pub visible: bool,
+    pub color: Color,
+    pub scale: usize,
+}
+
+impl Default for ParamHud {
+    fn default() -> Self {
+        ParamHud::new(vec![
+            ParamBinding::new("cloud_threshold", Key::Z, Key::X, 0.02, (-1.0, 1.0)),
+            ParamBinding::new("lava_zoom", Key::C, Key::V, 50.0, (1.0, 10000.0)),
+            ParamBinding::new("spin_speed", Key::B, Key::N, 0.005, (-0.2, 0.2)),
+        ])
+    }
+}
+
+impl ParamHud {
+    pub fn new(bindings: Vec<ParamBinding>) -> Self {
+        ParamHud { bindings, toggle: Key::H, visible: false, color: Color::new(255, 255, 255), scale: 2 }
+    }
+
+    // A hidden HUD with the bindings of `path` (see `reload`)
+    pub fn load(path: &str) -> io::Result<Self> {
+        let mut hud = ParamHud::new(Vec::new());
+        hud.reload(path)?;
+        Ok(hud)
+    }
+
+    // Replaces the bindings with those of a `key = value` file like
+    // `ShaderParams::load` reads, one binding per numeric parameter:
+    //   cloud_threshold = [Z, X, 0.02, -1.0, 1.0]
+    // for the decrease and increase keys (named as the `Key` variants: A,
+    // Key1, F5, PageUp...), the step and the range, in file order. Unknown
+    // parameters, keys and bad values are reported with their line number;
+    // on error the bindings are left as they were.
+    pub fn reload(&mut self, path: &str) -> io::Result<()> {
+        let contents = fs::read_to_string(path)?;
+        let defaults = ShaderParams::default();
+        let mut bindings = Vec::new();
+
+        for (line_number, line) in contents.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let invalid = |message: String| io::Error::new(
+                ErrorKind::InvalidData,
+                format!("{}:{}: {}", path, line_number + 1, message),
+            );
+
+            let (param, value) = line.split_once('=')
+                .ok_or_else(|| invalid(format!("expected `param = [decrease, increase, step, low, high]`, found `{}`", line)))?;
+            let param = param.trim();
+            if defaults.number(param).is_none() {
+                return Err(invalid(format!("`{}` is not a numeric parameter", param)));
+            }
+            bindings.push(parse_binding(param, value.trim()).map_err(invalid)?);
+        }
+
+        self.bindings = bindings;
+        Ok(())
+    }
+
+    // Reads this frame's key presses into `params`. Returns whether any
+    // parameter changed. Bindings to unknown or non-numeric parameters are skipped.
+    pub fn handle_input(&mut self, window: &Window, params: &mut ShaderParams) -> bool {
+        if window.is_key_pressed(self.toggle, KeyRepeat::No) {
+            self.visible = !self.visible;
+        }
+        if !self.visible {
+            return false;
+        }
+        self.apply_keys(params, |key| window.is_key_pressed(key, KeyRepeat::Yes))
+    }
+
+    // `handle_input` for a visible HUD with the presses given by `is_pressed`
+    pub fn apply_keys(&self, params: &mut ShaderParams, is_pressed: impl Fn(Key) -> bool) -> bool {
+        let mut changed = false;
+        for binding in &self.bindings {
+            let mut delta = 0.0;
+            if is_pressed(binding.increase) {
+                delta += binding.step;
+            }
+            if is_pressed(binding.decrease) {
+                delta -= binding.step;
+            }
+            if delta == 0.0 {
+                continue;
+            }
+
+            if let Some(value) = params.number(&binding.param) {
+                let (low, high) = binding.range;
+                changed |= params.set_number(&binding.param, (value + delta).clamp(low, high)).is_ok();
+            }
+        }
+        changed
+    }
+
+    // One line per binding: its keys, the parameter and its current value
+    pub fn draw(&self, framebuffer: &mut Framebuffer, params: &ShaderParams) {
+        if !self.visible {
+            return;
+        }
+
+        let line_height = (CELL_HEIGHT * self.scale) as i32;
+        for (i, binding) in self.bindings.iter().enumerate() {
+            let value = params.number(&binding.param).map_or("?".to_string(), |value| format!("{:.3}", value));
+            let line = format!("{:?}/{:?} {} {}", binding.decrease, binding.increase, binding.param, value);
+            draw_text(framebuffer, HUD_MARGIN, HUD_MARGIN + i as i32 * line_height, &line, self.color, self.scale);
+        }
+    }
+}
+
+// `[decrease, increase, step, low, high]`
+fn parse_binding(param: &str, value: &str) -> Result<ParamBinding, String> {
+    let fields: Vec<&str> = value.strip_prefix('[')
+        .and_then(|rest| rest.strip_suffix(']'))
+        .ok_or_else(|| format!("`{}` is not a binding, expected [decrease, increase, step, low, high]", value))?
+        .split(',')
+        .map(str::trim)
+        .collect();
+    let [decrease, increase, step, low, high] = fields[..] else {
+        return Err(format!("`{}` must have exactly five fields", value));
+    };
+
+    let key = |name: &str| BINDABLE_KEYS.iter().copied()
+        .find(|key| format!("{:?}", key) == name)
+        .ok_or_else(|| format!("`{}` is not a key name", name));
+    let number = |text: &str| text.parse::<f32>().map_err(|_| format!("`{}` is not a number", text));
+    let (low, high) = (number(low)?, number(high)?);
+    if low.is_nan() || high.is_nan() || low > high {
+        return Err(format!("the range {}..{} is empty", low, high));
+    }
+    Ok(ParamBinding::new(param, key(decrease)?, key(increase)?, number(step)?, (low, high)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ASSET: &str = "assets/hud_bindings.toml";
+
+    fn temp_file(name: &str, contents: &str) -> String {
+        let path = std::env::temp_dir().join(format!("hud_test_{}_{}", std::process::id(), name)).to_string_lossy().into_owned();
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn the_bundled_bindings_are_the_defaults() {
+        assert_eq!(ParamHud::load(ASSET).unwrap().bindings, ParamHud::default().bindings);
+    }
+
+    #[test]
+    fn keys_step_their_parameter_within_its_range() {
+        let hud = ParamHud::default();
+        let mut params = ShaderParams { cloud_threshold: 0.97, ..ShaderParams::default() };
+
+        assert!(hud.apply_keys(&mut params, |key| key == Key::X));
+        assert_eq!(params.cloud_threshold, 0.99);
+        // Clamped to the binding's range, however long the key is held
+        for _ in 0..5 {
+            hud.apply_keys(&mut params, |key| key == Key::X);
+        }
+        assert_eq!(params.cloud_threshold, 1.0);
+        params.spin_speed = -0.19;
+        hud.apply_keys(&mut params, |key| key == Key::B);
+        assert_eq!(params.spin_speed, -0.195);
+        hud.apply_keys(&mut params, |key| key == Key::B);
+        hud.apply_keys(&mut params, |key| key == Key::B);
+        assert_eq!(params.spin_speed, -0.2);
+
+        // Both keys at once cancel out
+        assert!(!hud.apply_keys(&mut params, |key| key == Key::C || key == Key::V));
+        assert_eq!(params.lava_zoom, ShaderParams::default().lava_zoom);
+    }
+
+    #[test]
+    fn bindings_to_unknown_parameters_are_skipped() {
+        let hud = ParamHud::new(vec![
+            ParamBinding::new("no_such_param", Key::Z, Key::X, 1.0, (0.0, 10.0)),
+            ParamBinding::new("fog_color", Key::Z, Key::X, 1.0, (0.0, 10.0)),
+            ParamBinding::new("fog_density", Key::Z, Key::X, 0.5, (0.0, 10.0)),
+        ]);
+        let mut params = ShaderParams::default();
+        assert!(hud.apply_keys(&mut params, |key| key == Key::X));
+        assert_eq!(params.fog_density, 0.5);
+        assert_eq!(params.fog_color, ShaderParams::default().fog_color);
+
+        // Listed with a placeholder value instead of failing to draw
+        let mut hud = hud;
+        hud.visible = true;
+        hud.draw(&mut Framebuffer::new(400, 100), &params);
+    }
+
+    #[test]
+    fn broken_bindings_files_are_reported_and_change_nothing() {
+        let mut hud = ParamHud::default();
+        for (name, contents) in [
+            ("unknown.toml", "no_such_param = [Z, X, 1.0, 0.0, 1.0]\n"),
+            ("color.toml", "fog_color = [Z, X, 1.0, 0.0, 1.0]\n"),
+            ("key.toml", "fog_density = [Z, Enter, 1.0, 0.0, 1.0]\n"),
+            ("fields.toml", "fog_density = [Z, X, 1.0]\n"),
+            ("number.toml", "fog_density = [Z, X, a lot, 0.0, 1.0]\n"),
+            ("range.toml", "fog_density = [Z, X, 1.0, 2.0, 1.0]\n"),
+            ("syntax.toml", "\nfog_density Z X\n"),
+        ] {
+            let path = temp_file(name, contents);
+            let error = hud.reload(&path).unwrap_err();
+            fs::remove_file(&path).ok();
+            assert_eq!(error.kind(), ErrorKind::InvalidData, "{}", name);
+            assert!(error.to_string().contains(&format!("{}:", path)), "{}", error);
+        }
+        assert_eq!(hud.bindings, ParamHud::default().bindings);
+
+        let path = temp_file("custom.toml", "# Fog only\nfog_density = [PageDown, PageUp, 0.1, 0.0, 2.0]\n");
+        hud.reload(&path).unwrap();
+        fs::remove_file(&path).ok();
+        assert_eq!(hud.bindings, vec![ParamBinding::new("fog_density", Key::PageDown, Key::PageUp, 0.1, (0.0, 2.0))]);
+    }
+}
@@ -0,0 +1,1819 @@
+use nalgebra_glm::{Vec2, Vec3, Vec4, Mat4, look_at, ortho, perspective, rotation};
+use std::f32::consts::PI;
+use std::sync::Arc;
+use std::collections::HashMap;
+
+pub mod framebuffer;
+pub mod triangle;
+pub mod vertex;
+pub mod obj;
+pub mod color;
+pub mod fragment;
+pub mod shaders;
+pub mod camera;
+pub mod texture;
+pub mod params;
+pub mod postprocess;
+pub mod export;
+pub mod math;
+pub mod render_target;
+pub mod mesh;
+pub mod vertex_cache;
+pub mod text;
+pub mod geometry;
+pub mod displacement;
+pub mod planet;
+pub mod tiled;
+pub mod adaptive;
+pub mod clock;
+pub mod shadow;
+pub mod lut;
+pub mod supersample;
+pub mod visibility;
+pub mod easing;
+pub mod gizmo;
+pub mod background;
+pub mod taa;
+pub mod raster_math;
+pub mod hud;
+
+use framebuffer::{Framebuffer, DepthFunc, AlphaMode, ObjectId, DepthPeel};
+use texture::{Texture, Cubemap, EquirectEnvironment};
+use params::ShaderParams;
+use color::{Color, ToneMapper, EncodeMode, quantize_to_palette};
+use vertex::Vertex;
+use mesh::Mesh;
+use displacement::DisplacementSource;
+use vertex_cache::VertexCache;
+use shadow::ShadowMap;
+use fragment::Fragment;
+use triangle::{triangle_antialiased, is_front_facing, calculate_bounding_box, Winding};
+use shaders::{vertex_shader, fragment_radiance, ShaderType};
+use math::{smoothstep, DitherPattern};
+use fastnoise_lite::{FastNoiseLite, NoiseType};
+
+// Size of `Uniforms::custom`
+pub const CUSTOM_SLOTS: usize = 16;
+
+// Conventional meaning of the first `Uniforms::custom` slots. Nothing built in
+// reads them; they only keep experiments consistent with each other.
+pub const CUSTOM_INTENSITY: usize = 0;
+pub const CUSTOM_SPEED: usize = 1;
+pub const CUSTOM_SCALE: usize = 2;
+pub const CUSTOM_MIX: usize = 3;
+
+// Default `Uniforms::peel_layers`: the front and back of two nested shells,
+// e.g. an atmosphere around a ring or a cloud layer inside a haze
+pub const DEFAULT_PEEL_LAYERS: u32 = 4;
+
+// Cloneable and `Send + Sync` so frames can be shaded from worker threads.
+// FastNoiseLite is plain data but not `Clone`, so clones share it through an `Arc`.
+#[derive(Clone)]
+pub struct Uniforms {
+    pub model_matrix: Mat4,
+    pub view_matrix: Mat4,
+    pub projection_matrix: Mat4,
+    pub viewport_matrix: Mat4,
+    pub time: u32,
+    pub noise: Arc<FastNoiseLite>,
+    // Winding order of front faces as seen on screen
+    pub front_face: Winding,
+    pub cull_backfaces: bool,
+    pub camera_position: Vec3,
+    // Direction towards the (directional) light, in world space
+    pub light_dir: Vec3,
+    // Inputs of the "textured" shader; parallax is skipped without a height map
+    pub albedo_map: Option<Texture>,
+    pub height_map: Option<Texture>,
+    pub parallax_steps: u32,
+    pub parallax_depth: f32,
+    // Derive UVs from the model-space position, for meshes without texture coordinates
+    pub spherical_uv: bool,
+    // Surroundings seen in reflections
+    pub environment: Option<Cubemap>,
+    // Equirectangular HDR surroundings, used when `environment` is unset
+    pub hdr_environment: Option<Arc<EquirectEnvironment>>,
+    // Algorithm `prepare` rebuilds `noise` with when it differs; `None` keeps
+    // the generator passed to `new` as it is
+    pub noise_kind: Option<NoiseKind>,
+    // Snaps every written pixel to the closest of these colors
+    pub palette_lock: Option<Vec<Color>>,
+    pub params: ShaderParams,
+    pub depth_func: DepthFunc,
+    pub depth_write: bool,
+    // Free-form values for experimental shaders, read by index (see `CUSTOM_*`)
+    pub custom: [f32; CUSTOM_SLOTS],
+    // Real relief: vertices are pushed along their normals before projection
+    pub displacement: Option<DisplacementSource>,
+    // Derived from the matrices above by `prepare`
+    pub inv_view_proj: Mat4,
+    pub inv_viewport: Mat4,
+    // Last frame's projection * view * model, for the velocity buffer. Store
+    // `mvp()` here before changing the matrices each frame; `None` means no motion.
+    pub prev_mvp: Option<Mat4>,
+    // Adds single-scattering sky glow (see `shaders::atmosphere_scatter`) to
+    // whatever shader draws a sphere
+    pub atmosphere: bool,
+    // Analytic silhouette antialiasing (see `triangle_antialiased`). Partly
+    // covered pixels blend over what was drawn before them yet still write
+    // depth, so the result depends on draw order.
+    pub edge_antialiasing: bool,
+    // Tag written to the framebuffer's object-id buffer for picking
+    pub object_id: Option<ObjectId>,
+    // Written instead of shading fragments with NaN/infinite inputs, and
+    // instead of NaN/infinite shader output, so broken geometry and math show
+    // up: magenta (`Color::NON_FINITE`) in debug builds and black in release.
+    // `None` skips the checks and shades them anyway.
+    pub non_finite_color: Option<Color>,
+    // Constant depth bias added to every fragment before the depth test, in
+    // z-buffer units (with `DEFAULT_DEPTH_RANGE`, NDC depth from -1 near to 1
+    // far; scale it along with a narrower range). Negative values pull the
+    // mesh towards the camera, e.g. -1e-4 keeps an atmosphere shell or a
+    // decal in front of coincident surfaces; the biased depth is also stored.
+    pub polygon_offset: f32,
+    // Emissive mode: shaded colors are added to the framebuffer's accumulation
+    // buffer (see `Framebuffer::enable_accumulation`) instead of being drawn,
+    // depth-tested against the scene with `depth_func` but never writing it, so
+    // glows and nebula layers can overlap in any order. Does nothing while the
+    // framebuffer has no accumulation buffer.
+    pub accumulate: bool,
+    // Tints the shading towards this color near triangle edges, drawing the
+    // tessellation over the filled surface in the same pass. Lines are
+    // `wireframe_thickness` wide in barycentric units (0.05 is a twentieth of
+    // the way from an edge to the opposite corner), so they thin out on small
+    // triangles; they are not a constant width in pixels.
+    pub wireframe_overlay: Option<Color>,
+    pub wireframe_thickness: f32,
+    // Shaded colors are multiplied by `exposure` and passed through the tone
+    // curve before anything else touches them, still in float and unclipped.
+    // `ToneMapper::None` at exposure 1 leaves them as they are.
+    pub tone_mapper: ToneMapper,
+    pub exposure: f32,
+    // Display curve of a frame lit in linear light, applied by
+    // `postprocess::encode_display` as its last pass and undone on `is_srgb`
+    // textures and cubemaps as the shaders sample them: gamma 2.2 unless set
+    // (`EncodeMode::Srgb` for the exact sRGB curve)
+    pub encode_mode: EncodeMode,
+    // Directional ambient light as spherical harmonics (see
+    // `shaders::sh_irradiance`, `shaders::sky_ambient_sh`), added to the
+    // diffuse term of the built-in shaders
+    pub ambient_sh: Option<[Vec3; 9]>,
+    // Frame budget of the viewer's adaptive resolution mode (see
+    // `adaptive::AdaptiveResolution`); `None` always renders at full size
+    pub target_frame_ms: Option<f32>,
+    // Directional-light shadows: fragments' direct lighting is scaled by
+    // `ShadowMap::sample_shadow`, ambient light is not
+    pub shadow_map: Option<Arc<ShadowMap>>,
+    // Whether the light is blocked by `shadow_map`; off, it lights every
+    // fragment fully while the map stays attached (e.g. cached between frames)
+    pub light_casts_shadows: bool,
+    // Color the blocked share of the light turns into instead of vanishing,
+    // for stylized shadows: black is plain darkening, white no shadow at all
+    pub shadow_color: Color,
+    // How the shaded color's alpha is applied (see `AlphaMode`). `opacity`
+    // scales it, so an opaque shader can be faded as a whole, e.g. an
+    // atmosphere shell or a ring drawn with `AlphaMode::Dither`.
+    pub alpha_mode: AlphaMode,
+    pub opacity: f32,
+    // Pattern of `AlphaMode::Dither` and of the anti-banding dither below
+    pub dither_pattern: DitherPattern,
+    // Anti-banding dither: the pattern's threshold, centered on zero, is added
+    // to the float color after tone mapping, just before it's rounded to 8
+    // bits, in 8-bit steps peak to peak. 1 breaks up smooth gradients into a
+    // mix of the two nearest steps. 0 is off.
+    pub dither_strength: f32,
+    // Most translucent layers per pixel resolved by `render_scene_peeled`
+    pub peel_layers: u32,
+    // The "ring" shader's flat level-of-detail color, its bands averaged over a
+    // `PlanetBuilder` ring (see `shaders::ring_average_albedo`). Derived from
+    // the noise by `prepare`, so it is sampled once instead of per fragment.
+    pub ring_average_albedo: Color,
+}
+
+impl Uniforms {
+    pub fn new(
+        model_matrix: Mat4,
+        view_matrix: Mat4,
+        projection_matrix: Mat4,
+        viewport_matrix: Mat4,
+        time: u32,
+        noise: FastNoiseLite,
+    ) -> Self {
+        let mut uniforms = Uniforms {
+            model_matrix,
+            view_matrix,
+            projection_matrix,
+            viewport_matrix,
+            time,
+            noise: Arc::new(noise),
+            front_face: Winding::default(),
+            cull_backfaces: false,
+            camera_position: Vec3::new(0.0, 0.0, 0.0),
+            light_dir: Vec3::new(0.0, 0.0, 1.0),
+            albedo_map: None,
+            height_map: None,
+            parallax_steps: 16,
+            parallax_depth: 0.05,
+            spherical_uv: false,
+            environment: None,
+            hdr_environment: None,
+            noise_kind: None,
+            palette_lock: None,
+            params: ShaderParams::default(),
+            depth_func: DepthFunc::default(),
+            depth_write: true,
+            custom: [0.0; CUSTOM_SLOTS],
+            displacement: None,
+            inv_view_proj: Mat4::identity(),
+            inv_viewport: Mat4::identity(),
+            prev_mvp: None,
+            atmosphere: false,
+            edge_antialiasing: false,
+            object_id: None,
+            non_finite_color: Some(if cfg!(debug_assertions) { Color::NON_FINITE } else { Color::black() }),
+            polygon_offset: 0.0,
+            accumulate: false,
+            wireframe_overlay: None,
+            wireframe_thickness: 0.02,
+            tone_mapper: ToneMapper::None,
+            exposure: 1.0,
+            encode_mode: EncodeMode::default(),
+            ambient_sh: None,
+            target_frame_ms: None,
+            shadow_map: None,
+            light_casts_shadows: true,
+            shadow_color: Color::black(),
+            alpha_mode: AlphaMode::Opaque,
+            opacity: 1.0,
+            dither_pattern: DitherPattern::default(),
+            dither_strength: 0.0,
+            peel_layers: DEFAULT_PEEL_LAYERS,
+            ring_average_albedo: Color::black(),
+        };
+        uniforms.prepare();
+        uniforms
+    }
+
+    // Refreshes the cached inverses, the noise generator and the values derived
+    // from it; call it after changing any of the matrices, `noise` or `noise_kind`
+    pub fn prepare(&mut self) {
+        if let Some(kind) = self.noise_kind {
+            if self.noise.noise_type != kind.noise_type() {
+                self.noise = Arc::new(create_noise_of_kind(kind));
+            }
+        }
+        let view_proj = self.projection_matrix * self.view_matrix;
+        self.inv_view_proj = view_proj.try_inverse().unwrap_or(Mat4::identity());
+        self.inv_viewport = self.viewport_matrix.try_inverse().unwrap_or(Mat4::identity());
+        self.ring_average_albedo = shaders::ring_average_albedo(self);
+    }
+
+    pub fn mvp(&self) -> Mat4 {
+        self.projection_matrix * self.view_matrix * self.model_matrix
+    }
+
+    // Screen position and z-buffer depth of a world point, the inverse of
+    // `unproject`. `None` when the point is behind the camera.
+    pub fn project(&self, world: Vec3) -> Option<Vec3> {
+        let clip = self.projection_matrix * self.view_matrix * Vec4::new(world.x, world.y, world.z, 1.0);
+        if clip.w <= 0.0 {
+            return None;
+        }
+
+        let screen = self.viewport_matrix * Vec4::new(clip.x / clip.w, clip.y / clip.w, clip.z / clip.w, 1.0);
+        Some(Vec3::new(screen.x, screen.y, screen.z))
+    }
+
+    // On-screen pixels spanned by one model unit at the model's origin, going
+    // by the largest scale of `model_matrix`, for level-of-detail choices.
+    // Infinite when the origin is on or behind the camera plane.
+    pub fn pixels_per_unit(&self) -> f32 {
+        let clip = self.mvp() * Vec4::new(0.0, 0.0, 0.0, 1.0);
+        if clip.w <= 0.0 {
+            return f32::INFINITY;
+        }
+
+        let scale = (0..3).map(|column| self.model_matrix.fixed_view::<3, 1>(0, column).norm()).fold(0.0, f32::max);
+        scale * self.projection_matrix[(1, 1)].abs() * self.viewport_matrix[(1, 1)].abs() / clip.w
+    }
+
+    // Stored depths of the near and far planes, read back from `viewport_matrix`
+    pub fn depth_range(&self) -> (f32, f32) {
+        let (scale, offset) = (self.viewport_matrix[(2, 2)], self.viewport_matrix[(2, 3)]);
+        (offset - scale, offset + scale)
+    }
+
+    // A z-buffer depth back in NDC [-1, 1], whatever the depth range
+    pub fn ndc_depth(&self, depth: f32) -> f32 {
+        let (near_depth, far_depth) = self.depth_range();
+        (depth - near_depth) / (far_depth - near_depth) * 2.0 - 1.0
+    }
+
+    // World position of a screen point given its depth as stored in the
+    // z-buffer. For pixel (x, y) pass its center, (x + 0.5, y + 0.5), which is
+    // where the rasterizer sampled that depth.
+    pub fn unproject(&self, screen_xy: Vec2, depth: f32) -> Vec3 {
+        let ndc = self.inv_viewport * Vec4::new(screen_xy.x, screen_xy.y, depth, 1.0);
+        let world = self.inv_view_proj * Vec4::new(ndc.x, ndc.y, ndc.z, 1.0);
+        let w = if world.w.abs() > f32::EPSILON { world.w } else { 1.0 };
+
+        Vec3::new(world.x / w, world.y / w, world.z / w)
+    }
+
+    // Switches to a parallel projection of the view-space box (see
+    // `create_orthographic_matrix`) and refreshes the cached inverses
+    pub fn orthographic(&mut self, left: f32, right: f32, bottom: f32, top: f32, near: f32, far: f32) {
+        self.projection_matrix = create_orthographic_matrix(left, right, bottom, top, near, far);
+        self.prepare();
+    }
+
+    // Turntable demo: spins the model by `speed` radians per unit of `time`
+    // about `axis` (in model space, so the object turns in place). Call it once
+    // per frame on freshly built uniforms, since it composes with whatever
+    // `model_matrix` holds; normals follow, as the vertex shader derives the
+    // normal matrix from `model_matrix`. A zero axis leaves the model as it is.
+    pub fn auto_rotate(&mut self, axis: Vec3, speed: f32) {
+        if axis.magnitude() == 0.0 || !axis.magnitude().is_finite() {
+            return;
+        }
+        let angle = (speed * self.time as f32) % (2.0 * PI);
+        self.model_matrix *= rotation(angle, &axis.normalize());
+        self.prepare();
+    }
+
+    // Swaps the noise generator used by every shader, keeping the same seed
+    pub fn set_noise_kind(&mut self, kind: NoiseKind) {
+        self.noise = Arc::new(create_noise_of_kind(kind));
+        self.noise_kind = Some(kind);
+    }
+}
+
+// Fails to compile if a field ever makes the uniforms unsafe to share across threads
+const _: fn() = || {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Uniforms>();
+};
+
+// Coordinate system the scene (and its meshes) are authored in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Handedness {
+    // OpenGL/Blender convention, camera looks down -Z
+    #[default]
+    Right,
+    // DirectX/Maya-style convention, camera looks down +Z
+    Left,
+}
+
+pub fn create_noise() -> FastNoiseLite {
+    create_cloud_noise()
+}
+
+fn create_cloud_noise() -> FastNoiseLite {
+    create_noise_of_kind(NoiseKind::Simplex)
+}
+
+// Noise algorithms the shaders can be driven by
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NoiseKind {
+    // Blocky interpolated lattice values, harsher lava turbulence
+    Value,
+    // Classic gradient noise, with slightly axis-aligned features
+    Perlin,
+    // OpenSimplex2, the softest clouds; what `create_noise` has always used
+    #[default]
+    Simplex,
+}
+
+impl NoiseKind {
+    fn noise_type(self) -> NoiseType {
+        match self {
+            NoiseKind::Value => NoiseType::Value,
+            NoiseKind::Perlin => NoiseType::Perlin,
+            NoiseKind::Simplex => NoiseType::OpenSimplex2,
+        }
+    }
+}
+
+pub fn create_noise_of_kind(kind: NoiseKind) -> FastNoiseLite {
+    let mut noise = FastNoiseLite::with_seed(1337);
+    noise.set_noise_type(Some(kind.noise_type()));
+    noise
+}
+
+pub fn create_model_matrix(translation: Vec3, scale: f32, rotation: Vec3) -> Mat4 {
+    let (sin_x, cos_x) = rotation.x.sin_cos();
+    let (sin_y, cos_y) = rotation.y.sin_cos();
+    let (sin_z, cos_z) = rotation.z.sin_cos();
+
+    let rotation_matrix_x = Mat4::new(
+        1.0,  0.0,    0.0,   0.0,
+        0.0,  cos_x, -sin_x, 0.0,
+        0.0,  sin_x,  cos_x, 0.0,
+        0.0,  0.0,    0.0,   1.0,
+    );
+
+    let rotation_matrix_y = Mat4::new(
+        cos_y,  0.0,  sin_y, 0.0,
+        0.0,    1.0,  0.0,   0.0,
+        -sin_y, 0.0,  cos_y, 0.0,
+        0.0,    0.0,  0.0,   1.0,
+    );
+
+    let rotation_matrix_z = Mat4::new(
+        cos_z, -sin_z, 0.0, 0.0,
+        sin_z,  cos_z, 0.0, 0.0,
+        0.0,    0.0,  1.0, 0.0,
+        0.0,    0.0,  0.0, 1.0,
+    );
+
+    let rotation_matrix = rotation_matrix_z * rotation_matrix_y * rotation_matrix_x;
+
+    let transform_matrix = Mat4::new(
+        scale, 0.0,   0.0,   translation.x,
+        0.0,   scale, 0.0,   translation.y,
+        0.0,   0.0,   scale, translation.z,
+        0.0,   0.0,   0.0,   1.0,
+    );
+
+    transform_matrix * rotation_matrix
+}
+
+// Direction towards the sun for `Uniforms::light_dir`, in a local frame with
+// +Y up, +X east and -Z north, seen from `latitude` radians north.
+// `time_of_day` in [0, 1) is one full day: 0 midnight, 0.25 sunrise (on the
+// equinox), 0.5 noon. `declination` is the sun's angle north of the equator,
+// from `solar_declination`; 0 is the equinox. Below the horizon y is negative.
+pub fn sun_direction(time_of_day: f32, latitude: f32, declination: f32) -> Vec3 {
+    let hour_angle = (time_of_day - 0.5) * 2.0 * PI;
+    let (sin_lat, cos_lat) = latitude.sin_cos();
+    let (sin_dec, cos_dec) = declination.sin_cos();
+
+    let east = -cos_dec * hour_angle.sin();
+    let north = sin_dec * cos_lat - cos_dec * hour_angle.cos() * sin_lat;
+    let up = sin_dec * sin_lat + cos_dec * hour_angle.cos() * cos_lat;
+
+    Vec3::new(east, up, -north)
+}
+
+// Seasonal declination for a planet with `axial_tilt` radians of obliquity
+// (0.409 for Earth): `season` in [0, 1) is the fraction of the year since
+// the spring equinox, so 0.25 is the northern summer solstice.
+pub fn solar_declination(season: f32, axial_tilt: f32) -> f32 {
+    (axial_tilt.sin() * (season * 2.0 * PI).sin()).asin()
+}
+
+// Model matrix of a body revolving around the origin at `radius`, `speed`
+// radians per time unit, starting at angle `phase`. The orbit lies in the XZ
+// plane tilted by `tilt` radians about the X axis. Scale the body on the right:
+// `orbit_transform(...) * Mat4::new_scaling(0.2)`.
+pub fn orbit_transform(radius: f32, speed: f32, phase: f32, time: f32, tilt: f32) -> Mat4 {
+    let angle = phase + speed * time;
+    let position = Vec3::new(radius * angle.cos(), 0.0, radius * angle.sin());
+
+    let tilt_matrix = create_model_matrix(Vec3::new(0.0, 0.0, 0.0), 1.0, Vec3::new(tilt, 0.0, 0.0));
+    tilt_matrix * Mat4::new_translation(&position)
+}
+
+// `count` moons evenly spaced along one shared orbit
+pub fn moon_ring(count: u32, radius: f32, speed: f32, time: f32, tilt: f32) -> Vec<Mat4> {
+    (0..count)
+        .map(|i| {
+            let phase = i as f32 / count as f32 * 2.0 * PI;
+            orbit_transform(radius, speed, phase, time, tilt)
+        })
+        .collect()
+}
+
+pub fn create_view_matrix(eye: Vec3, center: Vec3, up: Vec3) -> Mat4 {
+    look_at(&eye, &center, &up)
+}
+
+// Clip planes of the perspective projection, needed to linearize stored depth
+pub const NEAR_PLANE: f32 = 0.1;
+pub const FAR_PLANE: f32 = 1000.0;
+// Vertical field of view of `create_perspective_matrix`, in radians
+pub const FIELD_OF_VIEW: f32 = 45.0 * PI / 180.0;
+
+// Left-handed scenes are mirrored along Z so they share the right-handed pipeline.
+// Mirroring reverses the screen-space winding, so pair `Handedness::Left` with
+// `Winding::Clockwise` on the uniforms when culling back faces.
+pub fn create_perspective_matrix(window_width: f32, window_height: f32, handedness: Handedness) -> Mat4 {
+    let fov = FIELD_OF_VIEW;
+    let aspect_ratio = window_width / window_height;
+
+    // nalgebra-glm takes the aspect ratio first
+    let projection = perspective(aspect_ratio, fov, NEAR_PLANE, FAR_PLANE);
+
+    match handedness {
+        Handedness::Right => projection,
+        Handedness::Left => projection * Mat4::new_nonuniform_scaling(&Vec3::new(1.0, 1.0, -1.0)),
+    }
+}
+
+// Depth range of `create_viewport_matrix`: the z-buffer stores NDC z as is,
+// -1 at the near plane and 1 at the far plane. Depth-reading passes
+// (`postprocess::linearize_depth` and those built on it, shadow bias,
+// `Uniforms::polygon_offset`) are written for it.
+pub const DEFAULT_DEPTH_RANGE: (f32, f32) = (-1.0, 1.0);
+
+// Parallel projection of the view-space box x in [left, right], y in
+// [bottom, top], looking down -Z from `near` to `far` in front of the eye,
+// onto NDC [-1, 1] on every axis. Clip-space w stays 1, so sizes do not
+// shrink with distance, stored depth is linear in distance (skip
+// `postprocess::linearize_depth`) and `Fragment::view_depth` is always 1.
+pub fn create_orthographic_matrix(left: f32, right: f32, bottom: f32, top: f32, near: f32, far: f32) -> Mat4 {
+    ortho(left, right, bottom, top, near, far)
+}
+
+// Maps NDC [-1, 1] onto continuous screen coordinates [0, width] x [0, height],
+// y down, where pixel (x, y) is the unit square from (x, y) to (x + 1, y + 1)
+// and is sampled at its center (see `triangle::PIXEL_CENTER`). Depth keeps
+// `DEFAULT_DEPTH_RANGE`.
+pub fn create_viewport_matrix(width: f32, height: f32) -> Mat4 {
+    create_viewport_matrix_with_depth_range(width, height, DEFAULT_DEPTH_RANGE)
+}
+
+// Same, with NDC z in [-1, 1] remapped linearly onto `depth_range`: -1 (near
+// plane) lands on `depth_range.0` and 1 (far plane) on `depth_range.1`, which
+// is what the z-buffer then stores, `Uniforms::project` returns and
+// `unproject` expects. E.g. (0, 1) as in Direct3D and Vulkan. Near must stay
+// below far: the test is `DepthFunc::Less` against a buffer cleared to
+// infinity. Convert stored depths with `Uniforms::ndc_depth` before handing
+// them to code that expects NDC.
+pub fn create_viewport_matrix_with_depth_range(width: f32, height: f32, depth_range: (f32, f32)) -> Mat4 {
+    let (near_depth, far_depth) = depth_range;
+    assert!(near_depth < far_depth, "the depth range must increase from near to far");
+
+    Mat4::new(
+        width / 2.0, 0.0, 0.0, width / 2.0,
+        0.0, -height / 2.0, 0.0, height / 2.0,
+        0.0, 0.0, (far_depth - near_depth) / 2.0, (far_depth + near_depth) / 2.0,
+        0.0, 0.0, 0.0, 1.0
+    )
+}
+
+pub fn render(framebuffer: &mut Framebuffer, uniforms: &Uniforms, vertex_array: &[Vertex], shader_selection: u32) {
+    render_with_progress(framebuffer, uniforms, vertex_array, shader_selection, None);
+}
+
+// Same as `render`, reporting the fraction of fragments shaded so far in [0, 1].
+// The callback fires between batches of at least a scanline's worth of
+// fragments, at most about a hundred times per frame, and always ends on 1.
+pub fn render_with_progress(
+    framebuffer: &mut Framebuffer,
+    uniforms: &Uniforms,
+    vertex_array: &[Vertex],
+    shader_selection: u32,
+    progress: Option<&dyn Fn(f32)>,
+) {
+    let triangles = assemble_triangles(uniforms, vertex_array);
+    render_triangles(framebuffer, uniforms, &triangles, shader_selection, progress);
+}
+
+// Indexed counterpart of `render`: each unique vertex goes through the vertex
+// shader once, however many triangles share it. Triangles with a material
+// (see `Mesh::materials`) use its shader instead of `shader_selection`.
+pub fn render_mesh(framebuffer: &mut Framebuffer, uniforms: &Uniforms, mesh: &Mesh, shader_selection: u32) {
+    let transformed_vertices = shade_vertices(uniforms, &mesh.vertices);
+    let groups: Vec<(u32, Vec<[Vertex; 3]>)> = material_groups(mesh, shader_selection).into_iter()
+        .map(|(selection, corners)| (selection, assemble(uniforms, &transformed_vertices, corners.into_iter())))
+        .collect();
+    render_groups(framebuffer, uniforms, &groups);
+}
+
+// One mesh of a `render_scene` call, with its own placement and shader
+#[derive(Clone, Debug)]
+pub struct RenderObject<'a> {
+    pub mesh: &'a Mesh,
+    pub model_matrix: Mat4,
+    pub shader: ShaderType,
+    // Replaces `Uniforms::object_id` while this object is drawn
+    pub object_id: Option<ObjectId>,
+    // Replaces `Uniforms::alpha_mode` while this object is drawn
+    pub alpha_mode: Option<AlphaMode>,
+}
+
+impl<'a> RenderObject<'a> {
+    pub fn new(mesh: &'a Mesh, model_matrix: Mat4, shader: ShaderType) -> Self {
+        RenderObject { mesh, model_matrix, shader, object_id: None, alpha_mode: None }
+    }
+
+    // Distance in front of the camera of the object's bounding-sphere center
+    fn view_distance(&self, uniforms: &Uniforms) -> f32 {
+        let (center, _) = self.mesh.bounding_sphere();
+        let view = uniforms.view_matrix * self.model_matrix * Vec4::new(center.x, center.y, center.z, 1.0);
+        -view.z
+    }
+}
+
+// Draws several objects (the planets of a system, say) into one frame, each
+// with `render_mesh` against the framebuffer's shared z-buffer, so they hide
+// each other by depth whatever their order. The camera, light and options
+// come from `uniforms`; only the model matrix, object id and cached inverses
+// change per object. The framebuffer is not cleared first. Translucent
+// objects (`AlphaMode::Blend`, their own or the uniforms') go last, sorted
+// back to front by the view depth of their bounding-sphere centers, which is
+// right for separate objects but not for ones that interpenetrate (see
+// `render_scene_peeled`). `uniforms.prev_mvp` belongs to a single object, so
+// velocities are not written here.
+pub fn render_scene(framebuffer: &mut Framebuffer, uniforms: &Uniforms, objects: &[RenderObject]) {
+    let mut object_uniforms = uniforms.clone();
+    object_uniforms.prev_mvp = None;
+
+    let alpha_mode = |object: &RenderObject| object.alpha_mode.unwrap_or(uniforms.alpha_mode);
+    let (opaque, mut blended): (Vec<&RenderObject>, Vec<&RenderObject>) = objects.iter()
+        .partition(|object| alpha_mode(object) != AlphaMode::Blend);
+    // Stable, so objects at the same depth keep their order
+    blended.sort_by(|a, b| b.view_distance(uniforms).total_cmp(&a.view_distance(uniforms)));
+
+    for object in opaque.into_iter().chain(blended) {
+        object_uniforms.model_matrix = object.model_matrix;
+        object_uniforms.object_id = object.object_id.or(uniforms.object_id);
+        object_uniforms.alpha_mode = alpha_mode(object);
+        object_uniforms.prepare();
+        render_mesh(framebuffer, &object_uniforms, object.mesh, object.shader.selection());
+    }
+}
+
+// Order-independent transparency by depth peeling: `objects` are drawn over
+// the opaque scene already in the framebuffer, with their shaded alpha (times
+// `Uniforms::opacity`) as in `AlphaMode::Blend`, but in any order. Each pass
+// draws every object again and keeps only the nearest fragment behind the
+// previous pass's layer, so pass n finds the n-th surface from the eye; the
+// layers are composited front to back and then over the opaque pixels. Up to
+// `uniforms.peel_layers` passes run, stopping early once a pass finds
+// nothing, so each layer costs a full draw of `objects` (vertex shading and
+// rasterization) plus one walk over the screen. Surfaces past the last layer
+// are dropped, which the first few layers' opacity usually hides. The opaque
+// depth is kept: translucent objects write neither depth nor velocity.
+pub fn render_scene_peeled(framebuffer: &mut Framebuffer, uniforms: &Uniforms, objects: &[RenderObject]) {
+    let mut layer_uniforms = uniforms.clone();
+    layer_uniforms.depth_func = DepthFunc::Less;
+    layer_uniforms.depth_write = true;
+    layer_uniforms.accumulate = false;
+
+    let pixel_count = framebuffer.width * framebuffer.height;
+    let opaque_depth = framebuffer.zbuffer.clone();
+    let mut min_depth = vec![f32::NEG_INFINITY; pixel_count];
+    // Front-to-back "under" compositing: color so far and its total alpha
+    let mut color = vec![Vec3::zeros(); pixel_count];
+    let mut alpha = vec![0.0f32; pixel_count];
+
+    for _ in 0..uniforms.peel_layers {
+        let mut layer = framebuffer.clone();
+        layer.accumulation = None;
+        layer.radiance = None;
+        layer.depth_peel = Some(DepthPeel { min_depth, alpha: vec![0.0; pixel_count] });
+        render_scene(&mut layer, &layer_uniforms, objects);
+
+        let peel = layer.depth_peel.take().expect("set above");
+        let mut found = false;
+        for index in 0..pixel_count {
+            let layer_alpha = peel.alpha[index];
+            if layer_alpha > 0.0 {
+                found = true;
+                let remaining = 1.0 - alpha[index];
+                color[index] += Color::from_hex(layer.buffer[index]).to_vec3() * (layer_alpha * remaining);
+                alpha[index] += layer_alpha * remaining;
+            }
+        }
+        if !found {
+            break;
+        }
+        // Pixels with no layer this pass have nothing further back either
+        min_depth = layer.zbuffer.iter().zip(&opaque_depth)
+            .map(|(&depth, &opaque)| if depth < opaque { depth } else { opaque })
+            .collect();
+    }
+
+    for index in 0..pixel_count {
+        if alpha[index] > 0.0 {
+            let behind = Color::from_hex(framebuffer.buffer[index]).to_vec3();
+            framebuffer.buffer[index] = Color::from_vec3(color[index] + behind * (1.0 - alpha[index])).to_hex();
+            if let Some(radiance) = &mut framebuffer.radiance {
+                radiance[index] = color[index] + radiance[index] * (1.0 - alpha[index]);
+            }
+        }
+    }
+}
+
+// Variant of `render_mesh` that shades vertices lazily while walking the index
+// buffer, through a post-transform cache of `cache.capacity` entries. Vertices no
+// triangle references are never shaded. Entries are keyed by index only, so
+// the cache is cleared first; its hit/miss counts are those of this call.
+pub fn render_mesh_cached(framebuffer: &mut Framebuffer, uniforms: &Uniforms, mesh: &Mesh, shader_selection: u32, cache: &mut VertexCache) {
+    cache.clear();
+    let mut groups = Vec::new();
+    for (selection, corners) in material_groups(mesh, shader_selection) {
+        let mut triangles = Vec::with_capacity(corners.len());
+        for corners in corners {
+            let [v1, v2, v3] = corners.map(|index| {
+                cache.get_or_shade(index as u32, || vertex_shader(&mesh.vertices[index], uniforms))
+            });
+            if uniforms.cull_backfaces && !is_front_facing(&v1, &v2, &v3, uniforms.front_face) {
+                continue;
+            }
+            triangles.push([v1, v2, v3]);
+        }
+        groups.push((selection, triangles));
+    }
+
+    render_groups(framebuffer, uniforms, &groups);
+}
+
+// Triangle corners grouped by the shader that draws them, in order of first use
+fn material_groups(mesh: &Mesh, shader_selection: u32) -> Vec<(u32, Vec<[usize; 3]>)> {
+    let mut groups: Vec<(u32, Vec<[usize; 3]>)> = Vec::new();
+    for (i, c) in mesh.indices.chunks_exact(3).enumerate() {
+        let selection = mesh.triangle_material(i).map_or(shader_selection, ShaderType::selection);
+        let corners = [c[0] as usize, c[1] as usize, c[2] as usize];
+        match groups.iter_mut().find(|(group, _)| *group == selection) {
+            Some((_, group)) => group.push(corners),
+            None => groups.push((selection, vec![corners])),
+        }
+    }
+
+    groups
+}
+
+fn render_triangles(
+    framebuffer: &mut Framebuffer,
+    uniforms: &Uniforms,
+    triangles: &[[Vertex; 3]],
+    shader_selection: u32,
+    progress: Option<&dyn Fn(f32)>,
+) {
+    let silhouettes = antialiased_edges(uniforms, triangles);
+    let fragments = rasterize(framebuffer, uniforms, triangles, &silhouettes, shader_selection);
+    shade_fragments(framebuffer, uniforms, fragments, shader_selection, progress);
+}
+
+// Draws the material groups of one mesh, each with its own shader. Silhouettes
+// are found over the whole mesh, so an edge between two groups stays interior.
+fn render_groups(framebuffer: &mut Framebuffer, uniforms: &Uniforms, groups: &[(u32, Vec<[Vertex; 3]>)]) {
+    let silhouettes = if uniforms.edge_antialiasing {
+        let triangles: Vec<[Vertex; 3]> = groups.iter().flat_map(|(_, triangles)| triangles.iter().cloned()).collect();
+        silhouette_edges(&triangles, uniforms.front_face)
+    } else {
+        Vec::new()
+    };
+
+    let mut start = 0;
+    for (selection, triangles) in groups {
+        let end = start + triangles.len();
+        let fragments = rasterize(framebuffer, uniforms, triangles, silhouettes.get(start..end).unwrap_or(&[]), *selection);
+        shade_fragments(framebuffer, uniforms, fragments, *selection, None);
+        start = end;
+    }
+}
+
+// `silhouette_edges` when `edge_antialiasing` is on, else nothing
+fn antialiased_edges(uniforms: &Uniforms, triangles: &[[Vertex; 3]]) -> Vec<[bool; 3]> {
+    if uniforms.edge_antialiasing {
+        silhouette_edges(triangles, uniforms.front_face)
+    } else {
+        Vec::new()
+    }
+}
+
+// Rasterization. `silhouettes` holds the antialiased edges of each triangle;
+// missing entries have none.
+fn rasterize(framebuffer: &Framebuffer, uniforms: &Uniforms, triangles: &[[Vertex; 3]], silhouettes: &[[bool; 3]], shader_selection: u32) -> Vec<Fragment> {
+    let mut fragments = Vec::new();
+    for (i, tri) in triangles.iter().enumerate() {
+        let outer = silhouettes.get(i).copied().unwrap_or([false; 3]);
+        fragments.extend(triangle_antialiased(
+            &tri[0], &tri[1], &tri[2], shader_selection, &uniforms.light_dir, (framebuffer.width, framebuffer.height), outer,
+        ));
+    }
+
+    fragments
+}
+
+// For every triangle, which of its edges (opposite vertex 0, 1 and 2) lie on a
+// silhouette: edges that no other front-facing triangle shares. Vertices are
+// matched by exact screen position, which indexed and soup meshes both satisfy.
+fn silhouette_edges(triangles: &[[Vertex; 3]], front_face: Winding) -> Vec<[bool; 3]> {
+    let key = |vertex: &Vertex| vertex.transformed_position.map(f32::to_bits);
+    let edge_key = |a: &Vertex, b: &Vertex| {
+        let (a, b) = (key(a), key(b));
+        if a.as_slice() <= b.as_slice() { (a, b) } else { (b, a) }
+    };
+    let edges = |tri: &[Vertex; 3]| [edge_key(&tri[1], &tri[2]), edge_key(&tri[2], &tri[0]), edge_key(&tri[0], &tri[1])];
+
+    let front: Vec<bool> = triangles.iter().map(|tri| is_front_facing(&tri[0], &tri[1], &tri[2], front_face)).collect();
+    let mut counts = HashMap::new();
+    for tri in triangles.iter().zip(&front).filter(|(_, &front)| front).map(|(tri, _)| tri) {
+        for edge in edges(tri) {
+            *counts.entry(edge).or_insert(0u32) += 1;
+        }
+    }
+
+    triangles.iter().zip(&front)
+        .map(|(tri, &front)| {
+            if !front {
+                return [false; 3];
+            }
+            edges(tri).map(|edge| counts.get(&edge).copied().unwrap_or(0) < 2)
+        })
+        .collect()
+}
+
+fn shade_vertices(uniforms: &Uniforms, vertices: &[Vertex]) -> Vec<Vertex> {
+    vertices.iter().map(|vertex| vertex_shader(vertex, uniforms)).collect()
+}
+
+// Vertex shading followed by primitive assembly of consecutive vertex triples
+fn assemble_triangles(uniforms: &Uniforms, vertex_array: &[Vertex]) -> Vec<[Vertex; 3]> {
+    let transformed_vertices = shade_vertices(uniforms, vertex_array);
+    let corners = (0..transformed_vertices.len() / 3).map(|i| [3 * i, 3 * i + 1, 3 * i + 2]);
+    assemble(uniforms, &transformed_vertices, corners)
+}
+
+// Primitive Assembly (and optional back-face culling)
+fn assemble(uniforms: &Uniforms, transformed_vertices: &[Vertex], corners: impl Iterator<Item = [usize; 3]>) -> Vec<[Vertex; 3]> {
+    let mut triangles = Vec::new();
+    for [i1, i2, i3] in corners {
+        let (v1, v2, v3) = (&transformed_vertices[i1], &transformed_vertices[i2], &transformed_vertices[i3]);
+        if uniforms.cull_backfaces && !is_front_facing(v1, v2, v3, uniforms.front_face) {
+            continue;
+        }
+        triangles.push([v1.clone(), v2.clone(), v3.clone()]);
+    }
+
+    triangles
+}
+
+fn shade_fragments(
+    framebuffer: &mut Framebuffer,
+    uniforms: &Uniforms,
+    fragments: Vec<Fragment>,
+    shader_selection: u32,
+    progress: Option<&dyn Fn(f32)>,
+) {
+    // Fragment Processing
+    let shader = ShaderType::from_selection(shader_selection);
+    let fragment_count = fragments.len();
+    // Progress is reported between batches of about a scanline's worth of
+    // fragments, and never more than about a hundred times
+    let batch_size = framebuffer.width.max(fragment_count / 100).max(1);
+    let mut shaded = 0;
+    for batch in fragments.chunks(batch_size) {
+        for fragment in batch {
+            let x = fragment.position.x as usize;
+            let y = fragment.position.y as usize;
+            write_fragment(framebuffer, uniforms, fragment, shader, x, y);
+        }
+
+        shaded += batch.len();
+        if let Some(progress) = progress {
+            if shaded < fragment_count {
+                progress(shaded as f32 / fragment_count as f32);
+            }
+        }
+    }
+
+    if let Some(progress) = progress {
+        progress(1.0);
+    }
+}
+
+// Shades one fragment and depth-tests it into pixel (x, y) of `framebuffer`,
+// which may be a tile of the screen rather than the fragment's own position
+fn write_fragment(framebuffer: &mut Framebuffer, uniforms: &Uniforms, fragment: &Fragment, shader: ShaderType, x: usize, y: usize) {
+    if x >= framebuffer.width || y >= framebuffer.height {
+        return;
+    }
+
+    if let Some(color) = uniforms.non_finite_color {
+        if !fragment.is_finite() {
+            write_non_finite(framebuffer, uniforms, fragment, color, x, y);
+            return;
+        }
+    }
+
+    if let Some(peel) = &framebuffer.depth_peel {
+        // A NaN depth is unordered and dropped too
+        let depth = fragment.depth + uniforms.polygon_offset;
+        if depth.partial_cmp(&peel.min_depth[y * framebuffer.width + x]) != Some(std::cmp::Ordering::Greater) {
+            return;
+        }
+    }
+
+    let light = match &uniforms.shadow_map {
+        Some(shadow_map) if uniforms.light_casts_shadows => shadow_map.sample_shadow(&fragment.world_position),
+        _ => 1.0,
+    };
+    let unshadowed = fragment;
+    let shadowed;
+    let fragment = if light < 1.0 {
+        let mut lit = fragment.clone();
+        lit.intensity *= light;
+        shadowed = lit;
+        &shadowed
+    } else {
+        fragment
+    };
+
+    let shaded = fragment_radiance(fragment, uniforms, shader.name());
+    let mut linear = shaded.xyz();
+    if light < 1.0 && uniforms.shadow_color != Color::black() {
+        // The light the shadow took away, given back in the shadow's color
+        let blocked = (fragment_radiance(unshadowed, uniforms, shader.name()).xyz() - linear).map(|channel| channel.max(0.0));
+        linear += blocked.component_mul(&uniforms.shadow_color.to_vec3());
+    }
+    // A shader's NaN would otherwise round to a plausible black
+    if let Some(color) = uniforms.non_finite_color {
+        if !(linear.iter().all(|channel| channel.is_finite()) && shaded.w.is_finite()) {
+            write_non_finite(framebuffer, uniforms, fragment, color, x, y);
+            return;
+        }
+    }
+    let alpha = (shaded.w * uniforms.opacity).clamp(0.0, 1.0);
+    // The dither pattern follows the screen, not the tile being drawn
+    let (screen_x, screen_y) = (fragment.position.x as usize, fragment.position.y as usize);
+    if uniforms.alpha_mode == AlphaMode::Dither && alpha < uniforms.dither_pattern.threshold(screen_x, screen_y) {
+        return;
+    }
+    // Peeled layers are always translucent
+    let coverage = if uniforms.alpha_mode == AlphaMode::Blend || framebuffer.depth_peel.is_some() {
+        fragment.coverage * alpha
+    } else {
+        fragment.coverage
+    };
+    // Kept for the framebuffer's radiance buffer, before the tone curve and rounding
+    let mut radiance = linear * uniforms.exposure;
+    let mut shaded_color = Color::from_vec3(linear);
+    if uniforms.tone_mapper != ToneMapper::None || uniforms.exposure != 1.0 || uniforms.dither_strength > 0.0 {
+        let mut mapped = uniforms.tone_mapper.apply(linear, uniforms.exposure);
+        if uniforms.dither_strength > 0.0 {
+            let offset = (uniforms.dither_pattern.threshold(screen_x, screen_y) - 0.5) * uniforms.dither_strength / 255.0;
+            mapped = mapped.add_scalar(offset);
+        }
+        shaded_color = Color::from_vec3(mapped);
+    }
+    if let Some(line_color) = uniforms.wireframe_overlay {
+        let edge_distance = fragment.barycentric.min();
+        let line = 1.0 - smoothstep(0.0, uniforms.wireframe_thickness, edge_distance);
+        shaded_color = shaded_color.lerp(&line_color, line);
+    }
+    if let Some(palette) = &uniforms.palette_lock {
+        shaded_color = quantize_to_palette(shaded_color, palette);
+    }
+    if uniforms.accumulate {
+        framebuffer.accumulate(x, y, fragment.depth + uniforms.polygon_offset, uniforms.depth_func, shaded_color.to_vec3() * coverage);
+        return;
+    }
+    if framebuffer.depth_peel.is_some() {
+        // Kept straight; `render_scene_peeled` does the blending
+        let index = y * framebuffer.width + x;
+        let depth = fragment.depth + uniforms.polygon_offset;
+        if coverage > 0.0 && DepthFunc::Less.passes(depth, framebuffer.zbuffer[index]) {
+            framebuffer.set_current_color(shaded_color.to_hex());
+            framebuffer.point_with_depth_state(x, y, depth, DepthFunc::Less, true);
+            if let Some(peel) = &mut framebuffer.depth_peel {
+                peel.alpha[index] = coverage;
+            }
+        }
+        return;
+    }
+    if coverage < 1.0 {
+        let index = y * framebuffer.width + x;
+        let behind = Color::from_hex(framebuffer.buffer[index]);
+        shaded_color = behind.lerp(&shaded_color, coverage);
+        if let Some(behind_radiance) = framebuffer.radiance.as_ref().map(|buffer| buffer[index]) {
+            radiance = behind_radiance.lerp(&radiance, coverage);
+        }
+    }
+    let color = shaded_color.to_hex();
+    framebuffer.set_current_color(color);
+    framebuffer.set_current_radiance(radiance);
+    framebuffer.set_current_velocity(fragment.velocity);
+    framebuffer.set_current_object(uniforms.object_id);
+    framebuffer.point_with_depth_state(x, y, fragment.depth + uniforms.polygon_offset, uniforms.depth_func, uniforms.depth_write);
+}
+
+// Marks pixel (x, y) with `color` for a fragment whose inputs or shading
+// aren't finite
+fn write_non_finite(framebuffer: &mut Framebuffer, uniforms: &Uniforms, fragment: &Fragment, color: Color, x: usize, y: usize) {
+    // A NaN depth fails every comparison, so draw it regardless
+    framebuffer.set_current_color(color.to_hex());
+    framebuffer.set_current_velocity(Vec2::new(0.0, 0.0));
+    framebuffer.set_current_object(uniforms.object_id);
+    let depth_func = if fragment.depth.is_finite() { uniforms.depth_func } else { DepthFunc::Always };
+    let depth = if fragment.depth.is_finite() { fragment.depth + uniforms.polygon_offset } else { f32::INFINITY };
+    framebuffer.point_with_depth_state(x, y, depth, depth_func, false);
+}
+
+// Headless rendering of an animation: clears and renders `frame_count` frames,
+// advancing `uniforms.time` by one per frame, and returns a copy of each buffer.
+// `progress` gets the fraction of the whole animation done, reported during
+// each frame as by `render_with_progress`.
+pub fn render_frames(
+    framebuffer: &mut Framebuffer,
+    uniforms: &mut Uniforms,
+    vertex_array: &[Vertex],
+    shader_selection: u32,
+    frame_count: u32,
+    progress: Option<&dyn Fn(f32)>,
+) -> Vec<Vec<u32>> {
+    let mut frames = Vec::with_capacity(frame_count as usize);
+
+    for frame in 0..frame_count {
+        let frame_progress = |fraction: f32| {
+            if let Some(progress) = progress {
+                progress((frame as f32 + fraction) / frame_count as f32);
+            }
+        };
+        framebuffer.clear();
+        render_with_progress(framebuffer, uniforms, vertex_array, shader_selection, progress.map(|_| &frame_progress as &dyn Fn(f32)));
+        frames.push(framebuffer.buffer.clone());
+        uniforms.time += 1;
+    }
+
+    frames
+}
+
+// Fixed scene of `render_thumbnail`, so previews are identical from run to run
+pub const THUMBNAIL_TIME: u32 = 120;
+const THUMBNAIL_SUBDIVISIONS: u32 = 3;
+const THUMBNAIL_CAMERA_DISTANCE: f32 = 3.0;
+const THUMBNAIL_BACKGROUND: u32 = 0x1A1A24;
+
+// Square `size` x `size` preview of `shader` on a unit icosphere, for shader
+// pickers and galleries: camera on +Z, light from the upper left, all shader
+// parameters at their defaults and time frozen at `THUMBNAIL_TIME`. Pixels are
+// row-major; `ShaderType::ALL.map(...)` gives the whole catalog.
+pub fn render_thumbnail(shader: ShaderType, size: usize) -> Vec<Color> {
+    let mesh = geometry::generate_sphere(THUMBNAIL_SUBDIVISIONS);
+    let mut framebuffer = Framebuffer::new(size, size);
+    framebuffer.set_background_color(THUMBNAIL_BACKGROUND);
+    framebuffer.clear();
+
+    let mut uniforms = Uniforms::new(
+        create_model_matrix(Vec3::zeros(), 1.0, Vec3::zeros()),
+        create_view_matrix(Vec3::new(0.0, 0.0, THUMBNAIL_CAMERA_DISTANCE), Vec3::zeros(), Vec3::y()),
+        create_perspective_matrix(size as f32, size as f32, Handedness::Right),
+        create_viewport_matrix(size as f32, size as f32),
+        THUMBNAIL_TIME,
+        create_noise(),
+    );
+    uniforms.camera_position = Vec3::new(0.0, 0.0, THUMBNAIL_CAMERA_DISTANCE);
+    uniforms.light_dir = Vec3::new(-0.5, 0.6, 1.0).normalize();
+
+    render_mesh(&mut framebuffer, &uniforms, &mesh, shader.selection());
+    framebuffer.buffer.iter().map(|&pixel| Color::from_hex(pixel)).collect()
+}
+
+// Interactive variant of `render` for a static camera: only the screen tiles
+// touched by a triangle's bounding box, this frame or the previous one, are
+// cleared and redrawn. Every other tile keeps last frame's pixels, so the
+// framebuffer must not be cleared between calls.
+pub fn render_dirty_tiles(framebuffer: &mut Framebuffer, uniforms: &Uniforms, vertex_array: &[Vertex], shader_selection: u32) {
+    let triangles = assemble_triangles(uniforms, vertex_array);
+    // Padded by the one-pixel fringe of antialiased edges, which reaches past
+    // the triangle and must not land on a tile that isn't cleared
+    let bounds: Vec<(i32, i32, i32, i32)> = triangles.iter().map(|tri| {
+        let (min_x, min_y, max_x, max_y) = calculate_bounding_box(
+            &tri[0].transformed_position,
+            &tri[1].transformed_position,
+            &tri[2].transformed_position,
+        );
+        (min_x.saturating_sub(1), min_y.saturating_sub(1), max_x.saturating_add(1), max_y.saturating_add(1))
+    }).collect();
+
+    framebuffer.begin_tiled_frame();
+    for &(min_x, min_y, max_x, max_y) in &bounds {
+        framebuffer.mark_dirty(min_x, min_y, max_x, max_y);
+    }
+    framebuffer.clear_dirty_tiles();
+
+    // Triangles over no dirty tile (off screen, in practice) never reach the rasterizer
+    let triangles: Vec<[Vertex; 3]> = triangles.into_iter().zip(&bounds)
+        .filter(|(_, &(min_x, min_y, max_x, max_y))| framebuffer.is_rect_dirty(min_x, min_y, max_x, max_y))
+        .map(|(tri, _)| tri)
+        .collect();
+    let silhouettes = antialiased_edges(uniforms, &triangles);
+    let fragments = rasterize(framebuffer, uniforms, &triangles, &silhouettes, shader_selection);
+    shade_fragments(framebuffer, uniforms, fragments, shader_selection, None);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Identity model, view and projection: vertices are given in NDC
+    fn ndc_uniforms(width: usize, height: usize) -> Uniforms {
+        let identity = Mat4::identity();
+        Uniforms::new(identity, identity, identity, create_viewport_matrix(width as f32, height as f32), 0, create_noise())
+    }
+
+    // Counter-clockwise in NDC, so front-facing by default
+    fn ndc_triangle(a: (f32, f32), b: (f32, f32), c: (f32, f32), z: f32) -> Vec<Vertex> {
+        [a, b, c].iter()
+            .map(|&(x, y)| Vertex::new(Vec3::new(x, y, z), Vec3::new(0.0, 0.0, 1.0), Vec2::new(x * 0.5 + 0.5, y * 0.5 + 0.5)))
+            .collect()
+    }
+
+    fn camera_uniforms(width: usize, height: usize) -> Uniforms {
+        let view = create_view_matrix(Vec3::new(0.0, 0.0, 5.0), Vec3::zeros(), Vec3::new(0.0, 1.0, 0.0));
+        let projection = create_perspective_matrix(width as f32, height as f32, Handedness::Right);
+        let mut uniforms = Uniforms::new(Mat4::identity(), view, projection, create_viewport_matrix(width as f32, height as f32), 0, create_noise());
+        uniforms.camera_position = Vec3::new(0.0, 0.0, 5.0);
+        uniforms
+    }
+
+    // Square in the XY plane facing +Z, at height z
+    fn square(half_size: f32, z: f32) -> Mesh {
+        let corner = |x: f32, y: f32| Vertex::new(Vec3::new(x, y, z), Vec3::new(0.0, 0.0, 1.0), Vec2::new(x, y));
+        let (a, b, c, d) = (corner(-half_size, -half_size), corner(half_size, -half_size), corner(half_size, half_size), corner(-half_size, half_size));
+        Mesh::from_triangle_soup(vec![a.clone(), b, c.clone(), a, c, d])
+    }
+
+    #[test]
+    fn render_dirty_tiles_matches_a_full_render_and_keeps_clean_tiles() {
+        let mut uniforms = ndc_uniforms(96, 96);
+        uniforms.edge_antialiasing = true;
+        let left = ndc_triangle((-0.9, -0.9), (-0.4, -0.9), (-0.9, -0.4), 0.0);
+        let right = ndc_triangle((-0.5, -0.9), (0.0, -0.9), (-0.5, -0.4), 0.0);
+        // Entirely off screen: culled before rasterization, no visible effect
+        let off_screen = ndc_triangle((3.0, 3.0), (4.0, 3.0), (3.0, 4.0), 0.0);
+
+        let mut partial = Framebuffer::new(96, 96);
+        render_dirty_tiles(&mut partial, &uniforms, &[left, off_screen.clone()].concat(), ShaderType::Lava.selection());
+        // Top-right tile, far from both triangles: never cleared or redrawn
+        let marker = Color::new(1, 2, 3).to_hex();
+        partial.buffer[10 * 96 + 90] = marker;
+        render_dirty_tiles(&mut partial, &uniforms, &[right.clone(), off_screen].concat(), ShaderType::Lava.selection());
+
+        let mut full = Framebuffer::new(96, 96);
+        full.clear();
+        render(&mut full, &uniforms, &right, ShaderType::Lava.selection());
+
+        assert_eq!(partial.buffer[10 * 96 + 90], marker);
+        partial.buffer[10 * 96 + 90] = full.buffer[10 * 96 + 90];
+        assert!(partial.buffer == full.buffer);
+        assert!(!partial.is_rect_dirty(64, 0, 95, 31));
+        assert!(!partial.is_rect_dirty(96, 96, 200, 200));
+    }
+
+    #[test]
+    fn uniforms_can_be_shared_and_moved_across_threads() {
+        fn assert_send_sync<T: Send + Sync + Clone>() {}
+        assert_send_sync::<Uniforms>();
+
+        let mut uniforms = ndc_uniforms(8, 8);
+        uniforms.time = 120;
+        let fragment = Fragment::at(Vec3::new(0.3, -0.2, 0.9), 0.0, 1.0);
+        let expected = shaders::shade_fragment(&fragment, &uniforms, ShaderType::Lava);
+
+        // Borrowed by scoped threads...
+        let borrowed: Vec<Color> = std::thread::scope(|scope| {
+            let workers: Vec<_> = (0..4).map(|_| scope.spawn(|| shaders::shade_fragment(&fragment, &uniforms, ShaderType::Lava))).collect();
+            workers.into_iter().map(|worker| worker.join().unwrap()).collect()
+        });
+        assert!(borrowed.iter().all(|&color| color == expected));
+
+        // ...or cloned into a spawned one, sharing the same noise generator
+        let clone = uniforms.clone();
+        assert!(Arc::ptr_eq(&clone.noise, &uniforms.noise));
+        let moved = std::thread::spawn(move || shaders::shade_fragment(&fragment, &clone, ShaderType::Lava)).join().unwrap();
+        assert_eq!(moved, expected);
+    }
+
+    #[test]
+    fn render_scene_draws_blended_objects_back_to_front_whatever_their_order() {
+        let (backdrop, far, near) = (square(2.0, -1.0), square(1.0, 0.0), square(0.5, 1.0));
+        let mut uniforms = camera_uniforms(32, 32);
+        uniforms.opacity = 0.5;
+        let translucent = |mesh, shader| RenderObject { alpha_mode: Some(AlphaMode::Blend), ..RenderObject::new(mesh, Mat4::identity(), shader) };
+        let objects = [
+            translucent(&near, ShaderType::Ice),
+            translucent(&far, ShaderType::Lava),
+            RenderObject::new(&backdrop, Mat4::identity(), ShaderType::Cloud),
+        ];
+
+        let mut scene = Framebuffer::new(32, 32);
+        render_scene(&mut scene, &uniforms, &objects);
+
+        // Reference: opaque first, then far, then near
+        let mut expected = Framebuffer::new(32, 32);
+        render_mesh(&mut expected, &uniforms, &backdrop, ShaderType::Cloud.selection());
+        uniforms.alpha_mode = AlphaMode::Blend;
+        render_mesh(&mut expected, &uniforms, &far, ShaderType::Lava.selection());
+        render_mesh(&mut expected, &uniforms, &near, ShaderType::Ice.selection());
+        assert_eq!(scene.buffer, expected.buffer);
+
+        // Drawn in the given order, the near square would hide the far one
+        let mut unsorted = Framebuffer::new(32, 32);
+        uniforms.alpha_mode = AlphaMode::Opaque;
+        render_mesh(&mut unsorted, &uniforms, &backdrop, ShaderType::Cloud.selection());
+        uniforms.alpha_mode = AlphaMode::Blend;
+        render_mesh(&mut unsorted, &uniforms, &near, ShaderType::Ice.selection());
+        render_mesh(&mut unsorted, &uniforms, &far, ShaderType::Lava.selection());
+        assert_ne!(scene.buffer[16 * 32 + 16], unsorted.buffer[16 * 32 + 16]);
+    }
+
+    #[test]
+    fn radiance_buffer_keeps_precision_below_the_8_bit_step() {
+        let quad = [ndc_triangle((-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), 0.0), ndc_triangle((-1.0, -1.0), (1.0, 1.0), (-1.0, 1.0), 0.0)].concat();
+        let mut uniforms = ndc_uniforms(16, 16);
+        // Fog mixes the 8-bit albedo into in-between values
+        uniforms.camera_position = Vec3::new(0.0, 0.0, 5.0);
+        uniforms.params.fog_density = 0.1;
+        let mut reference = Framebuffer::new(16, 16);
+        render(&mut reference, &uniforms, &quad, ShaderType::Ice.selection());
+
+        uniforms.exposure = 0.3;
+        let mut framebuffer = Framebuffer::new(16, 16);
+        framebuffer.enable_radiance();
+        render(&mut framebuffer, &uniforms, &quad, ShaderType::Ice.selection());
+
+        let radiance = framebuffer.radiance.as_ref().unwrap();
+        for (&pixel, value) in reference.buffer.iter().zip(radiance) {
+            assert!((Color::from_hex(pixel).to_vec3() * 0.3 - value).abs().max() <= 0.3 * 0.5 / 255.0 + 1e-6);
+        }
+        // Taken from the shader's float output, not from its rounded color
+        let off_grid = |channel: f32| ((channel / 0.3 * 255.0) - (channel / 0.3 * 255.0).round()).abs() > 1e-3;
+        assert!(radiance.iter().any(|value| value.iter().any(|&channel| off_grid(channel))));
+        let exported = framebuffer.to_color16();
+        assert_eq!(exported[0], color::Color16::from_radiance(radiance[0]));
+        // Widening the 8-bit result would only give multiples of 257
+        assert!(exported.iter().any(|pixel| pixel.r % 257 != 0 || pixel.g % 257 != 0 || pixel.b % 257 != 0));
+    }
+
+    fn drawn_pixels(framebuffer: &Framebuffer) -> usize {
+        framebuffer.zbuffer.iter().filter(|depth| depth.is_finite()).count()
+    }
+
+    #[test]
+    fn back_faces_follow_the_configured_front_face_winding() {
+        let counter_clockwise = ndc_triangle((-0.5, -0.5), (0.5, -0.5), (0.0, 0.5), 0.0);
+        let clockwise = ndc_triangle((-0.5, -0.5), (0.0, 0.5), (0.5, -0.5), 0.0);
+        let mut uniforms = ndc_uniforms(16, 16);
+        uniforms.cull_backfaces = true;
+
+        let drawn = |uniforms: &Uniforms, triangle: &[Vertex]| {
+            let mut framebuffer = Framebuffer::new(16, 16);
+            render(&mut framebuffer, uniforms, triangle, ShaderType::Lava.selection());
+            drawn_pixels(&framebuffer)
+        };
+        // Default: counter-clockwise in NDC is the front
+        assert!(drawn(&uniforms, &counter_clockwise) > 0);
+        assert_eq!(drawn(&uniforms, &clockwise), 0);
+
+        // Reversed-winding meshes render once the front face is flipped
+        uniforms.front_face = Winding::Clockwise;
+        assert_eq!(drawn(&uniforms, &counter_clockwise), 0);
+        assert_eq!(drawn(&uniforms, &clockwise), drawn(&ndc_uniforms(16, 16), &counter_clockwise));
+
+        uniforms.cull_backfaces = false;
+        assert!(drawn(&uniforms, &counter_clockwise) > 0);
+    }
+
+    #[test]
+    fn left_handed_projection_sees_down_positive_z() {
+        let uniforms_for = |handedness| Uniforms::new(
+            Mat4::identity(), Mat4::identity(), create_perspective_matrix(16.0, 16.0, handedness),
+            create_viewport_matrix(16.0, 16.0), 0, create_noise(),
+        );
+        let triangle_at = |z: f32| ndc_triangle((-1.0, -1.0), (1.0, -1.0), (0.0, 1.0), z);
+        // Far lava and near ice, drawn in both orders: the near one must win
+        let center_color = |handedness, near: f32, far: f32, near_first: bool| {
+            let uniforms = uniforms_for(handedness);
+            let mut framebuffer = Framebuffer::new(16, 16);
+            let mut draws = [(triangle_at(far), ShaderType::Lava), (triangle_at(near), ShaderType::Ice)];
+            if near_first {
+                draws.reverse();
+            }
+            for (triangle, shader) in &draws {
+                render(&mut framebuffer, &uniforms, triangle, shader.selection());
+            }
+            (framebuffer.buffer[10 * 16 + 8], drawn_pixels(&framebuffer))
+        };
+
+        let right = center_color(Handedness::Right, -3.0, -6.0, false);
+        assert_eq!(center_color(Handedness::Right, -3.0, -6.0, true), right);
+        // The same scene mirrored along Z
+        assert_eq!(center_color(Handedness::Left, 3.0, 6.0, false), right);
+        assert_eq!(center_color(Handedness::Left, 3.0, 6.0, true), right);
+        let mut lava_only = Framebuffer::new(16, 16);
+        render(&mut lava_only, &uniforms_for(Handedness::Right), &triangle_at(-6.0), ShaderType::Lava.selection());
+        assert_ne!(right.0, lava_only.buffer[10 * 16 + 8]);
+    }
+
+    #[test]
+    fn palette_lock_restricts_every_drawn_pixel_to_the_palette() {
+        let palette = vec![Color::new(10, 10, 40), Color::new(200, 80, 20), Color::new(250, 220, 120)];
+        let mut uniforms = ndc_uniforms(16, 16);
+        uniforms.palette_lock = Some(palette.clone());
+        let mut framebuffer = Framebuffer::new(16, 16);
+        render(&mut framebuffer, &uniforms, &ndc_triangle((-1.0, -1.0), (1.0, -1.0), (0.0, 1.0), 0.0), ShaderType::Lava.selection());
+
+        let drawn: Vec<Color> = framebuffer.buffer.iter().zip(&framebuffer.zbuffer)
+            .filter(|(_, depth)| depth.is_finite())
+            .map(|(&pixel, _)| Color::from_hex(pixel))
+            .collect();
+        assert!(!drawn.is_empty());
+        assert!(drawn.iter().all(|color| palette.contains(color)));
+    }
+
+    #[test]
+    fn unproject_inverts_project() {
+        let mut uniforms = camera_uniforms(64, 48);
+        uniforms.view_matrix = create_view_matrix(Vec3::new(2.0, 1.5, 6.0), Vec3::new(0.0, 0.5, 0.0), Vec3::new(0.0, 1.0, 0.0));
+        for depth_range in [DEFAULT_DEPTH_RANGE, (0.0, 1.0)] {
+            uniforms.viewport_matrix = create_viewport_matrix_with_depth_range(64.0, 48.0, depth_range);
+            uniforms.prepare();
+            for world in [Vec3::zeros(), Vec3::new(0.7, -0.4, 1.2), Vec3::new(-3.0, 2.0, -10.0)] {
+                let screen = uniforms.project(world).unwrap();
+                let back = uniforms.unproject(Vec2::new(screen.x, screen.y), screen.z);
+                assert!((back - world).magnitude() < 1e-3 * (1.0 + world.magnitude()), "{:?} came back as {:?}", world, back);
+            }
+        }
+        // Behind the eye
+        assert_eq!(uniforms.project(Vec3::new(4.0, 3.0, 12.0)), None);
+    }
+
+    #[test]
+    fn unprojecting_stored_depth_lands_on_the_drawn_surface() {
+        let uniforms = camera_uniforms(32, 32);
+        let mut framebuffer = Framebuffer::new(32, 32);
+        render_mesh(&mut framebuffer, &uniforms, &square(1.0, 0.25), ShaderType::Lava.selection());
+
+        let index = 13 * 32 + 17;
+        assert!(framebuffer.zbuffer[index].is_finite());
+        let world = uniforms.unproject(Vec2::new(17.5, 13.5), framebuffer.zbuffer[index]);
+        assert!((world.z - 0.25).abs() < 1e-3 && world.x.abs() < 1.0 && world.y.abs() < 1.0, "{:?}", world);
+    }
+
+    #[test]
+    fn cached_vertex_shading_matches_uncached_pixel_for_pixel() {
+        let sphere = geometry::generate_sphere(3);
+        let mut uniforms = camera_uniforms(48, 48);
+        uniforms.cull_backfaces = true;
+        let mut reference = Framebuffer::new(48, 48);
+        render_mesh(&mut reference, &uniforms, &sphere, ShaderType::Gas.selection());
+
+        for capacity in [0, 3, vertex_cache::DEFAULT_VERTEX_CACHE_SIZE] {
+            let mut cache = VertexCache::new(capacity);
+            let mut framebuffer = Framebuffer::new(48, 48);
+            render_mesh_cached(&mut framebuffer, &uniforms, &sphere, ShaderType::Gas.selection(), &mut cache);
+            assert!(framebuffer.buffer == reference.buffer, "capacity {}", capacity);
+            assert!(framebuffer.zbuffer == reference.zbuffer, "capacity {}", capacity);
+            assert_eq!(cache.hits + cache.misses, sphere.indices.len());
+            if capacity == vertex_cache::DEFAULT_VERTEX_CACHE_SIZE {
+                // Each vertex is shared by about six triangles
+                assert!(cache.misses < sphere.indices.len() / 2, "{} misses", cache.misses);
+            }
+        }
+    }
+
+    #[test]
+    fn a_cache_reused_across_frames_never_serves_last_frames_vertices() {
+        let sphere = geometry::generate_sphere(3);
+        let mut uniforms = camera_uniforms(48, 48);
+        let mut cache = VertexCache::new(vertex_cache::DEFAULT_VERTEX_CACHE_SIZE);
+        let mut framebuffer = Framebuffer::new(48, 48);
+        render_mesh_cached(&mut framebuffer, &uniforms, &sphere, ShaderType::Gas.selection(), &mut cache);
+
+        // Next frame the sphere has moved
+        uniforms.model_matrix = create_model_matrix(Vec3::new(0.7, 0.0, 0.0), 1.0, Vec3::zeros());
+        let mut reference = Framebuffer::new(48, 48);
+        render_mesh(&mut reference, &uniforms, &sphere, ShaderType::Gas.selection());
+        framebuffer.clear();
+        render_mesh_cached(&mut framebuffer, &uniforms, &sphere, ShaderType::Gas.selection(), &mut cache);
+        assert!(framebuffer.buffer == reference.buffer);
+        assert!(framebuffer.zbuffer == reference.zbuffer);
+        assert_eq!(cache.hits + cache.misses, sphere.indices.len());
+    }
+
+    #[test]
+    fn steep_triangles_get_depth_and_view_distance_of_the_surface_they_show() {
+        // Floor-like triangle receding from z = 2 to z = -30 in front of the camera
+        let uniforms = camera_uniforms(64, 64);
+        let corners = [Vec3::new(-1.0, -1.0, 2.0), Vec3::new(1.0, -1.0, 2.0), Vec3::new(0.0, 0.0, -30.0)];
+        let [v1, v2, v3] = corners.map(|corner| vertex_shader(&Vertex::new(corner, Vec3::new(0.0, 1.0, 0.0), Vec2::new(0.0, 0.0)), &uniforms));
+        let fragments = triangle::triangle(&v1, &v2, &v3, 1, &uniforms.light_dir, (64, 64));
+        assert!(fragments.len() > 50);
+
+        let plane_normal = (corners[1] - corners[0]).cross(&(corners[2] - corners[0])).normalize();
+        for fragment in &fragments {
+            let center = fragment.position + Vec2::new(triangle::PIXEL_CENTER, triangle::PIXEL_CENTER);
+            // The stored depth unprojects onto the triangle's plane...
+            let surface = uniforms.unproject(center, fragment.depth);
+            assert!((surface - corners[0]).dot(&plane_normal).abs() < 1e-3 * (surface - uniforms.camera_position).magnitude(), "{:?} is off the plane", surface);
+            // ...and the perspective-correct view depth is its distance along the view axis
+            let view = uniforms.view_matrix * Vec4::new(surface.x, surface.y, surface.z, 1.0);
+            assert!((fragment.view_depth - -view.z).abs() < 1e-2 * -view.z, "{} vs {}", fragment.view_depth, -view.z);
+        }
+    }
+
+    #[test]
+    fn picking_returns_the_visible_tagged_object() {
+        let mut uniforms = ndc_uniforms(32, 32);
+        let mut framebuffer = Framebuffer::new(32, 32);
+        framebuffer.clear();
+        let left = ndc_triangle((-1.0, -1.0), (-0.1, -1.0), (-1.0, 1.0), 0.0);
+        let right = ndc_triangle((0.1, -1.0), (1.0, -1.0), (1.0, 1.0), 0.0);
+        uniforms.object_id = Some(1);
+        render(&mut framebuffer, &uniforms, &left, ShaderType::Lava.selection());
+        uniforms.object_id = Some(2);
+        render(&mut framebuffer, &uniforms, &right, ShaderType::Ice.selection());
+
+        assert_eq!(framebuffer.pick(4, 24), Some(1));
+        assert_eq!(framebuffer.pick(28, 24), Some(2));
+        // Between them, and off screen
+        assert_eq!(framebuffer.pick(16, 16), None);
+        assert_eq!(framebuffer.pick(40, 4), None);
+
+        // A nearer object drawn later takes over, a farther one doesn't
+        uniforms.object_id = Some(3);
+        render(&mut framebuffer, &uniforms, &ndc_triangle((-1.0, -1.0), (1.0, -1.0), (0.0, 1.0), -0.5), ShaderType::Cloud.selection());
+        uniforms.object_id = Some(4);
+        render(&mut framebuffer, &uniforms, &ndc_triangle((-1.0, -1.0), (1.0, -1.0), (0.0, 1.0), 0.5), ShaderType::Cloud.selection());
+        assert_eq!(framebuffer.pick(16, 28), Some(3));
+        assert_eq!(framebuffer.pick(1, 8), Some(1));
+
+        // Reset with the rest of the frame
+        framebuffer.clear();
+        assert!(framebuffer.object_ids.iter().all(Option::is_none));
+    }
+
+    #[test]
+    fn viewport_maps_ndc_depth_onto_the_configured_range() {
+        for depth_range in [DEFAULT_DEPTH_RANGE, (0.0, 1.0), (0.25, 4.0)] {
+            let viewport = create_viewport_matrix_with_depth_range(32.0, 32.0, depth_range);
+            for (ndc_z, expected) in [(-1.0, depth_range.0), (1.0, depth_range.1)] {
+                let screen = viewport * Vec4::new(0.3, -0.2, ndc_z, 1.0);
+                assert!((screen.z - expected).abs() < 1e-6, "{} went to {} for {:?}", ndc_z, screen.z, depth_range);
+            }
+
+            // Read back from the uniforms, and stored as such by the rasterizer
+            let mut uniforms = ndc_uniforms(32, 32);
+            uniforms.viewport_matrix = viewport;
+            uniforms.prepare();
+            assert_eq!(uniforms.depth_range(), depth_range);
+            let mut framebuffer = Framebuffer::new(32, 32);
+            render(&mut framebuffer, &uniforms, &ndc_triangle((-1.0, -1.0), (1.0, -1.0), (0.0, 1.0), -0.5), ShaderType::Lava.selection());
+            let stored = framebuffer.zbuffer[24 * 32 + 16];
+            let expected = depth_range.0 + (depth_range.1 - depth_range.0) * 0.25;
+            assert!((stored - expected).abs() < 1e-5 && (uniforms.ndc_depth(stored) + 0.5).abs() < 1e-5, "{} for {:?}", stored, depth_range);
+        }
+    }
+
+    #[test]
+    fn orthographic_projection_puts_points_at_their_box_position() {
+        let mut uniforms = ndc_uniforms(40, 20);
+        uniforms.orthographic(-2.0, 2.0, -1.0, 1.0, 0.5, 10.5);
+        let clip = uniforms.projection_matrix * Vec4::new(1.0, 0.5, -5.5, 1.0);
+        assert!((clip - Vec4::new(0.5, 0.5, 0.0, 1.0)).magnitude() < 1e-6, "{:?}", clip);
+        // Near and far planes at the ends of NDC z
+        assert!(((uniforms.projection_matrix * Vec4::new(0.0, 0.0, -0.5, 1.0)).z + 1.0).abs() < 1e-6);
+        assert!(((uniforms.projection_matrix * Vec4::new(0.0, 0.0, -10.5, 1.0)).z - 1.0).abs() < 1e-6);
+
+        let screen = uniforms.project(Vec3::new(1.0, 0.5, -5.5)).unwrap();
+        assert!((screen - Vec3::new(30.0, 5.0, 0.0)).magnitude() < 1e-4, "{:?}", screen);
+
+        // No shrinking with distance
+        let mut counts = Vec::new();
+        for z in [-1.0, -9.0] {
+            let mut framebuffer = Framebuffer::new(40, 20);
+            render_mesh(&mut framebuffer, &uniforms, &square(0.5, z), ShaderType::Pattern.selection());
+            counts.push(drawn_pixels(&framebuffer));
+        }
+        // Ten pixels per unit on both axes
+        assert_eq!(counts, [100, 100]);
+    }
+
+    #[test]
+    fn dithered_alpha_keeps_the_matching_share_of_pixels() {
+        // Two triangles covering the whole 32x32 screen
+        let mut screen = ndc_triangle((-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), 0.0);
+        screen.extend(ndc_triangle((-1.0, -1.0), (1.0, 1.0), (-1.0, 1.0), 0.0));
+        let mut uniforms = ndc_uniforms(32, 32);
+        uniforms.alpha_mode = AlphaMode::Dither;
+
+        for pattern in [DitherPattern::Bayer, DitherPattern::BlueNoise] {
+            uniforms.dither_pattern = pattern;
+            for (opacity, expected) in [(0.0, 0), (0.25, 256), (0.5, 512), (1.0, 1024)] {
+                uniforms.opacity = opacity;
+                let mut framebuffer = Framebuffer::new(32, 32);
+                render(&mut framebuffer, &uniforms, &screen, ShaderType::Pattern.selection());
+                let drawn = drawn_pixels(&framebuffer) as i32;
+                assert!((drawn - expected).abs() <= 16, "{:?} at {}: {} pixels", pattern, opacity, drawn);
+            }
+        }
+
+        // Half alpha through Bayer is a checkerboard
+        uniforms.dither_pattern = DitherPattern::Bayer;
+        uniforms.opacity = 0.5;
+        let mut framebuffer = Framebuffer::new(32, 32);
+        render(&mut framebuffer, &uniforms, &screen, ShaderType::Pattern.selection());
+        for y in 0..32 {
+            for x in 0..32 {
+                assert_eq!(framebuffer.zbuffer[y * 32 + x].is_finite(), (x + y) % 2 == 0, "pixel ({}, {})", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn lights_without_shadows_reach_occluded_fragments_in_full() {
+        let mut uniforms = camera_uniforms(32, 32);
+        uniforms.light_dir = Vec3::new(0.0, 0.0, 1.0);
+        let receiver = square(1.0, 0.0);
+        let mut reference = Framebuffer::new(32, 32);
+        render_mesh(&mut reference, &uniforms, &receiver, ShaderType::Lava.selection());
+
+        // The blocker only goes into the shadow map, so the camera still sees the receiver
+        let mut shadow_map = ShadowMap::new(128, uniforms.light_dir, Vec3::zeros(), 2.0);
+        shadow_map.render(&square(0.6, 1.0), &uniforms);
+        uniforms.shadow_map = Some(Arc::new(shadow_map));
+        let center = 16 * 32 + 16;
+        let render_with = |uniforms: &Uniforms| {
+            let mut framebuffer = Framebuffer::new(32, 32);
+            render_mesh(&mut framebuffer, uniforms, &receiver, ShaderType::Lava.selection());
+            framebuffer
+        };
+
+        let shadowed = render_with(&uniforms);
+        let brightness = |pixel: u32| Color::from_hex(pixel).to_vec3().sum();
+        assert!(brightness(shadowed.buffer[center]) < brightness(reference.buffer[center]));
+
+        uniforms.light_casts_shadows = false;
+        assert_eq!(render_with(&uniforms).buffer, reference.buffer);
+
+        // A white shadow gives all the blocked light back, a red one only its red
+        uniforms.light_casts_shadows = true;
+        uniforms.shadow_color = Color::new(255, 255, 255);
+        assert_eq!(render_with(&uniforms).buffer, reference.buffer);
+        uniforms.shadow_color = Color::new(255, 0, 0);
+        let (tinted, dark, lit) = (Color::from_hex(render_with(&uniforms).buffer[center]), Color::from_hex(shadowed.buffer[center]), Color::from_hex(reference.buffer[center]));
+        assert!(tinted.r() > dark.r() && tinted.g() == dark.g() && tinted.b() == dark.b(), "{:?} over {:?}", tinted, dark);
+        assert!(tinted.r() <= lit.r());
+    }
+
+    #[test]
+    fn zero_dither_strength_changes_nothing() {
+        let sphere = geometry::generate_sphere(2);
+        let draw = |uniforms: &Uniforms| {
+            let mut framebuffer = Framebuffer::new(32, 32);
+            render_mesh(&mut framebuffer, uniforms, &sphere, ShaderType::Cloud.selection());
+            framebuffer.buffer
+        };
+        let mut uniforms = camera_uniforms(32, 32);
+        let plain = draw(&uniforms);
+        for pattern in [DitherPattern::Bayer, DitherPattern::BlueNoise] {
+            uniforms.dither_pattern = pattern;
+            uniforms.dither_strength = 0.0;
+            assert_eq!(draw(&uniforms), plain, "{:?}", pattern);
+
+            // Some grain, never more than the strength's worth of 8-bit steps
+            uniforms.dither_strength = 3.0;
+            let dithered = draw(&uniforms);
+            assert_ne!(dithered, plain, "{:?}", pattern);
+            for (&a, &b) in dithered.iter().zip(&plain) {
+                let (a, b) = (Color::from_hex(a), Color::from_hex(b));
+                let step = |x: u8, y: u8| (x as i32 - y as i32).abs();
+                assert!(step(a.r(), b.r()).max(step(a.g(), b.g())).max(step(a.b(), b.b())) <= 2, "{:?} against {:?}", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn progress_is_throttled_and_forwarded_by_render_frames() {
+        use std::cell::RefCell;
+        let mut uniforms = ndc_uniforms(64, 64);
+        let mut framebuffer = Framebuffer::new(64, 64);
+        let triangle = ndc_triangle((-1.0, -1.0), (1.0, -1.0), (0.0, 1.0), 0.0);
+
+        let reports = RefCell::new(Vec::new());
+        let record = |fraction: f32| reports.borrow_mut().push(fraction);
+        render_with_progress(&mut framebuffer, &uniforms, &triangle, ShaderType::Lava.selection(), Some(&record));
+        let single = reports.take();
+        // About 2000 fragments, reported once per 64 at most
+        assert!(single.len() > 1 && single.len() <= 2048 / 64 + 1, "{} reports", single.len());
+        assert!(single.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(single.last(), Some(&1.0));
+
+        render_frames(&mut framebuffer, &mut uniforms, &triangle, ShaderType::Lava.selection(), 3, Some(&record));
+        let frames = reports.take();
+        assert_eq!(frames.len(), single.len() * 3);
+        assert!(frames.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!((frames[single.len() - 1] - 1.0 / 3.0).abs() < 1e-6 && frames.last() == Some(&1.0));
+    }
+
+    #[test]
+    fn prepare_rebuilds_the_noise_for_noise_kind() {
+        let mut uniforms = ndc_uniforms(4, 4);
+        assert_eq!(uniforms.noise.noise_type, NoiseType::OpenSimplex2);
+        uniforms.noise_kind = Some(NoiseKind::Perlin);
+        uniforms.prepare();
+        assert_eq!(uniforms.noise.noise_type, NoiseType::Perlin);
+
+        // Without a kind, a custom generator survives `prepare`
+        let mut cellular = create_noise();
+        cellular.set_noise_type(Some(NoiseType::Cellular));
+        uniforms.noise = Arc::new(cellular);
+        uniforms.noise_kind = None;
+        uniforms.prepare();
+        assert_eq!(uniforms.noise.noise_type, NoiseType::Cellular);
+    }
+
+    #[test]
+    fn tone_curve_compresses_shading_past_one_instead_of_clipping_it() {
+        let quad = [ndc_triangle((-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), 0.0), ndc_triangle((-1.0, -1.0), (1.0, 1.0), (-1.0, 1.0), 0.0)].concat();
+        let render_with_ambient = |strength: f32| {
+            let mut uniforms = ndc_uniforms(8, 8);
+            uniforms.ambient_sh = Some(shaders::sky_ambient_sh().map(|coefficient| coefficient * strength));
+            uniforms.tone_mapper = ToneMapper::Reinhard;
+            let mut framebuffer = Framebuffer::new(8, 8);
+            render(&mut framebuffer, &uniforms, &quad, ShaderType::Ice.selection());
+            Color::from_hex(framebuffer.buffer[4 * 8 + 4])
+        };
+
+        // Both lights are far past what 8 bits hold; clipped first, they
+        // would come out the same Reinhard(1) = 0.5 grey
+        let (bright, brighter) = (render_with_ambient(20.0), render_with_ambient(60.0));
+        assert!(bright.b() > 160, "{:?}", bright);
+        assert!(brighter.b() > bright.b(), "{:?} {:?}", bright, brighter);
+    }
+
+    #[test]
+    fn non_finite_shader_output_is_replaced_by_the_debug_color() {
+        let quad = [ndc_triangle((-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), 0.0), ndc_triangle((-1.0, -1.0), (1.0, 1.0), (-1.0, 1.0), 0.0)].concat();
+        let mut uniforms = ndc_uniforms(8, 8);
+        // Finite fragments, but the ambient light turns every shade into NaN
+        uniforms.ambient_sh = Some([Vec3::repeat(f32::NAN); 9]);
+        uniforms.non_finite_color = Some(Color::NON_FINITE);
+        let mut framebuffer = Framebuffer::new(8, 8);
+        render(&mut framebuffer, &uniforms, &quad, ShaderType::Lava.selection());
+        assert!(framebuffer.buffer.iter().all(|&pixel| pixel == Color::NON_FINITE.to_hex()));
+
+        uniforms.non_finite_color = None;
+        let mut unchecked = Framebuffer::new(8, 8);
+        render(&mut unchecked, &uniforms, &quad, ShaderType::Lava.selection());
+        assert_eq!(drawn_pixels(&unchecked), 64);
+        assert!(unchecked.buffer.iter().all(|&pixel| pixel == 0));
+    }
+
+    #[test]
+    fn dither_mixes_the_two_steps_around_a_float_color() {
+        let quad = [ndc_triangle((-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), 0.0), ndc_triangle((-1.0, -1.0), (1.0, 1.0), (-1.0, 1.0), 0.0)].concat();
+        let blues = |strength: f32| {
+            let mut uniforms = ndc_uniforms(16, 16);
+            // The ice shader's full blue at half exposure lands on 127.5
+            uniforms.exposure = 0.5;
+            uniforms.dither_strength = strength;
+            let mut framebuffer = Framebuffer::new(16, 16);
+            render(&mut framebuffer, &uniforms, &quad, ShaderType::Ice.selection());
+            let mut blues: Vec<u8> = framebuffer.buffer.iter().map(|&pixel| Color::from_hex(pixel).b()).collect();
+            blues.sort_unstable();
+            blues.dedup();
+            blues
+        };
+        assert_eq!(blues(0.0), vec![128]);
+        assert_eq!(blues(1.0), vec![127, 128]);
+    }
+
+    #[test]
+    fn edges_between_material_groups_are_not_silhouettes() {
+        let mut two_materials = square(0.5, 0.0);
+        two_materials.materials = vec![ShaderType::Lava, ShaderType::Ice];
+        two_materials.material_ids = vec![0, 1];
+        let mut uniforms = camera_uniforms(48, 48);
+        let draw = |uniforms: &Uniforms, mesh: &Mesh| {
+            let mut framebuffer = Framebuffer::new(48, 48);
+            render_mesh(&mut framebuffer, uniforms, mesh, 0);
+            let mut cached = Framebuffer::new(48, 48);
+            render_mesh_cached(&mut cached, uniforms, mesh, 0, &mut VertexCache::new(vertex_cache::DEFAULT_VERTEX_CACHE_SIZE));
+            let mut tiled = Framebuffer::new(48, 48);
+            tiled::render_mesh_tiled(&mut tiled, uniforms, mesh, 0, 2);
+            assert!(cached.buffer == framebuffer.buffer && tiled.buffer == framebuffer.buffer);
+            framebuffer
+        };
+
+        let aliased = draw(&uniforms, &two_materials);
+        uniforms.edge_antialiasing = true;
+        let antialiased = draw(&uniforms, &two_materials);
+        // Only the square's outline is blended; the diagonal between the two
+        // shaders is drawn as without antialiasing
+        let interior = |x: usize, y: usize| [x - 1, x + 1].iter().all(|&x| [y - 1, y + 1].iter().all(|&y| aliased.zbuffer[y * 48 + x].is_finite()));
+        let mut checked = 0;
+        for y in 1..47 {
+            for x in 1..47 {
+                if interior(x, y) {
+                    assert_eq!(antialiased.buffer[y * 48 + x], aliased.buffer[y * 48 + x], "({}, {})", x, y);
+                    checked += 1;
+                }
+            }
+        }
+        assert!(checked > 0);
+    }
+
+    #[test]
+    fn polygon_offset_resolves_coplanar_triangles_the_same_way_in_any_order() {
+        let quad = [ndc_triangle((-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), 0.3), ndc_triangle((-1.0, -1.0), (1.0, 1.0), (-1.0, 1.0), 0.3)].concat();
+        let uniforms = ndc_uniforms(16, 16);
+        let mut shell_uniforms = uniforms.clone();
+        shell_uniforms.polygon_offset = -1e-4;
+
+        let mut shell_only = Framebuffer::new(16, 16);
+        render(&mut shell_only, &shell_uniforms, &quad, ShaderType::Ice.selection());
+        let mut base_only = Framebuffer::new(16, 16);
+        render(&mut base_only, &uniforms, &quad, ShaderType::Lava.selection());
+        assert!(base_only.buffer != shell_only.buffer);
+        for shell_first in [false, true] {
+            let mut framebuffer = Framebuffer::new(16, 16);
+            if shell_first {
+                render(&mut framebuffer, &shell_uniforms, &quad, ShaderType::Ice.selection());
+                render(&mut framebuffer, &uniforms, &quad, ShaderType::Lava.selection());
+            } else {
+                render(&mut framebuffer, &uniforms, &quad, ShaderType::Lava.selection());
+                render(&mut framebuffer, &shell_uniforms, &quad, ShaderType::Ice.selection());
+            }
+            assert!(framebuffer.buffer == shell_only.buffer, "shell drawn first: {}", shell_first);
+            // The biased depth is what's stored
+            assert!(framebuffer.zbuffer.iter().all(|&depth| (depth - (0.3 - 1e-4)).abs() < 1e-6));
+        }
+    }
+
+    #[test]
+    fn depth_peeling_gives_the_same_pixels_in_any_submission_order() {
+        let (backdrop, far, near) = (square(2.0, -1.0), square(1.0, 0.0), square(0.5, 1.0));
+        let mut uniforms = camera_uniforms(32, 32);
+        let peeled = |uniforms: &Uniforms, objects: &[RenderObject]| {
+            let mut framebuffer = Framebuffer::new(32, 32);
+            render_mesh(&mut framebuffer, uniforms, &backdrop, ShaderType::Cloud.selection());
+            let mut translucent = uniforms.clone();
+            translucent.opacity = 0.5;
+            render_scene_peeled(&mut framebuffer, &translucent, objects);
+            framebuffer.buffer
+        };
+        let (near, far) = (RenderObject::new(&near, Mat4::identity(), ShaderType::Ice), RenderObject::new(&far, Mat4::identity(), ShaderType::Lava));
+
+        let front_first = peeled(&uniforms, &[near.clone(), far.clone()]);
+        assert_eq!(peeled(&uniforms, &[far.clone(), near.clone()]), front_first);
+
+        // Close to blending sorted back to front, up to rounding of the 8-bit layers
+        let mut sorted = Framebuffer::new(32, 32);
+        render_mesh(&mut sorted, &uniforms, &backdrop, ShaderType::Cloud.selection());
+        uniforms.alpha_mode = AlphaMode::Blend;
+        uniforms.opacity = 0.5;
+        render_mesh(&mut sorted, &uniforms, far.mesh, ShaderType::Lava.selection());
+        render_mesh(&mut sorted, &uniforms, near.mesh, ShaderType::Ice.selection());
+        for (&layered, &blended) in front_first.iter().zip(&sorted.buffer) {
+            let (layered, blended) = (Color::from_hex(layered).to_vec3(), Color::from_hex(blended).to_vec3());
+            assert!((layered - blended).abs().max() <= 2.0 / 255.0, "{:?} against {:?}", layered, blended);
+        }
+    }
+
+    #[test]
+    fn surfaces_past_the_last_peeled_layer_are_dropped() {
+        let squares: Vec<Mesh> = (0..6).map(|i| square(1.0, 1.5 - i as f32 * 0.5)).collect();
+        let mut uniforms = camera_uniforms(32, 32);
+        uniforms.opacity = 0.5;
+        uniforms.peel_layers = 2;
+        let peeled = |uniforms: &Uniforms, meshes: &[Mesh]| {
+            let objects: Vec<RenderObject> = meshes.iter().map(|mesh| RenderObject::new(mesh, Mat4::identity(), ShaderType::Lava)).collect();
+            let mut framebuffer = Framebuffer::new(32, 32);
+            render_scene_peeled(&mut framebuffer, uniforms, &objects);
+            framebuffer.buffer
+        };
+
+        // Six surfaces deep, but only the nearest two (drawn first or last) count
+        let deep = peeled(&uniforms, &squares);
+        assert_eq!(deep, peeled(&uniforms, &squares[..2]));
+        let mut reversed = squares.clone();
+        reversed.reverse();
+        assert_eq!(peeled(&uniforms, &reversed), deep);
+        assert_ne!(deep, Framebuffer::new(32, 32).buffer);
+
+        uniforms.peel_layers = 0;
+        assert_eq!(peeled(&uniforms, &squares), Framebuffer::new(32, 32).buffer);
+    }
+
+    #[test]
+    fn pixels_per_unit_matches_the_projected_size_of_a_model_unit() {
+        let mut uniforms = camera_uniforms(64, 48);
+        // Pixels between the world origin and one unit above it
+        let span = |uniforms: &Uniforms| {
+            let (origin, up) = (uniforms.project(Vec3::zeros()).unwrap(), uniforms.project(Vec3::new(0.0, 1.0, 0.0)).unwrap());
+            (up.y - origin.y).abs()
+        };
+        assert!((uniforms.pixels_per_unit() - span(&uniforms)).abs() < 1e-3, "{} against {}", uniforms.pixels_per_unit(), span(&uniforms));
+
+        // Twice the scale spans twice the pixels; moving the model doubles its
+        // distance and halves them
+        uniforms.model_matrix = Mat4::new_scaling(2.0);
+        assert!((uniforms.pixels_per_unit() - 2.0 * span(&uniforms)).abs() < 1e-3);
+        uniforms.model_matrix = Mat4::new_translation(&Vec3::new(0.0, 0.0, -5.0));
+        assert!((uniforms.pixels_per_unit() * 2.0 - span(&uniforms)).abs() < 1e-3);
+
+        // On or behind the camera plane
+        uniforms.model_matrix = Mat4::new_translation(&Vec3::new(0.0, 0.0, 5.0));
+        assert_eq!(uniforms.pixels_per_unit(), f32::INFINITY);
+        uniforms.model_matrix = Mat4::new_translation(&Vec3::new(0.0, 0.0, 9.0));
+        assert_eq!(uniforms.pixels_per_unit(), f32::INFINITY);
+    }
+}
@@ -0,0 +1,202 @@
+use nalgebra_glm::Vec3;
+use std::fs;
+use std::io::{self, ErrorKind};
+use crate::color::Color;
+
+// Color grading lookup table in the Adobe/Resolve `.cube` layout, so a look
+// made in an editing tool can be reproduced here. A 1D table remaps each
+// channel on its own through `size` entries; a 3D table is a `size`^3 lattice
+// over the RGB cube with red varying fastest, then green, then blue. Inputs
+// are the channels as stored in the image, in [0, 1], mapped from the
+// `domain_min`..`domain_max` box.
+#[derive(Clone, Debug)]
+pub struct ColorLut {
+    pub size: usize,
+    pub is_3d: bool,
+    pub domain_min: Vec3,
+    pub domain_max: Vec3,
+    pub data: Vec<Vec3>,
+}
+
+impl ColorLut {
+    pub fn new_1d(data: Vec<Vec3>) -> Self {
+        assert!(data.len() >= 2, "a 1D LUT needs at least two entries");
+        ColorLut { size: data.len(), is_3d: false, domain_min: Vec3::zeros(), domain_max: Vec3::new(1.0, 1.0, 1.0), data }
+    }
+
+    pub fn new_3d(size: usize, data: Vec<Vec3>) -> Self {
+        assert!(size >= 2, "a 3D LUT needs at least two entries per axis");
+        assert_eq!(data.len(), size * size * size, "LUT data does not match its size");
+        ColorLut { size, is_3d: true, domain_min: Vec3::zeros(), domain_max: Vec3::new(1.0, 1.0, 1.0), data }
+    }
+
+    // 3D table that maps every color to itself
+    pub fn identity(size: usize) -> Self {
+        let step = 1.0 / (size - 1) as f32;
+        let mut data = Vec::with_capacity(size * size * size);
+        for b in 0..size {
+            for g in 0..size {
+                for r in 0..size {
+                    data.push(Vec3::new(r as f32, g as f32, b as f32) * step);
+                }
+            }
+        }
+        ColorLut::new_3d(size, data)
+    }
+
+    // Reads a `.cube` file: `LUT_1D_SIZE` or `LUT_3D_SIZE`, optional `TITLE`
+    // and `DOMAIN_MIN`/`DOMAIN_MAX`, then one `r g b` line per entry. `#`
+    // starts a comment. Errors carry the line number like `ShaderParams::load`.
+    pub fn load_cube(path: &str) -> io::Result<Self> {
+        let contents = fs::read_to_string(path)?;
+        let invalid = |line_number: usize, message: String| io::Error::new(
+            ErrorKind::InvalidData,
+            format!("{}:{}: {}", path, line_number, message),
+        );
+        let invalid_file = |message: String| io::Error::new(ErrorKind::InvalidData, format!("{}: {}", path, message));
+
+        let mut shape = None;
+        let mut domain_min = Vec3::zeros();
+        let mut domain_max = Vec3::new(1.0, 1.0, 1.0);
+        let mut data = Vec::new();
+
+        for (index, line) in contents.lines().enumerate() {
+            let line_number = index + 1;
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() || line.starts_with("TITLE") {
+                continue;
+            }
+
+            let mut words = line.split_whitespace();
+            let keyword = words.next().unwrap_or("");
+            let rest: Vec<&str> = words.collect();
+            match keyword {
+                "LUT_1D_SIZE" | "LUT_3D_SIZE" => {
+                    let size = match rest[..] {
+                        [size] => size.parse::<usize>().ok().filter(|&size| size >= 2),
+                        _ => None,
+                    }.ok_or_else(|| invalid(line_number, format!("bad LUT size `{}`", line)))?;
+                    shape = Some((size, keyword == "LUT_3D_SIZE"));
+                }
+                "DOMAIN_MIN" => domain_min = parse_triple(&rest).map_err(|message| invalid(line_number, message))?,
+                "DOMAIN_MAX" => domain_max = parse_triple(&rest).map_err(|message| invalid(line_number, message))?,
+                _ => {
+                    let mut values = vec![keyword];
+                    values.extend(rest);
+                    data.push(parse_triple(&values).map_err(|message| invalid(line_number, message))?);
+                }
+            }
+        }
+
+        let (size, is_3d) = shape.ok_or_else(|| invalid_file("missing LUT_1D_SIZE or LUT_3D_SIZE".to_string()))?;
+        let expected = if is_3d { size * size * size } else { size };
+        if data.len() != expected {
+            return Err(invalid_file(format!("expected {} entries, found {}", expected, data.len())));
+        }
+        if (0..3).any(|axis| domain_max[axis] <= domain_min[axis]) {
+            return Err(invalid_file("DOMAIN_MAX must be above DOMAIN_MIN".to_string()));
+        }
+
+        Ok(ColorLut { size, is_3d, domain_min, domain_max, data })
+    }
+
+    // Grades one color, interpolating linearly between 1D entries or
+    // trilinearly between the eight surrounding 3D lattice points. Alpha is kept.
+    pub fn apply(&self, color: Color) -> Color {
+        let input = color.to_vec3();
+        let graded = self.apply_vec3(input);
+        let graded = Color::from_vec3(graded);
+        Color::new_rgba(graded.r(), graded.g(), graded.b(), color.a())
+    }
+
+    // Same on float channels, without quantizing to 8 bits
+    pub fn apply_vec3(&self, input: Vec3) -> Vec3 {
+        let last = (self.size - 1) as f32;
+        // Lattice coordinate per channel in [0, size - 1]
+        let coordinate = |axis: usize| {
+            let t = (input[axis] - self.domain_min[axis]) / (self.domain_max[axis] - self.domain_min[axis]);
+            if t.is_finite() { t.clamp(0.0, 1.0) * last } else { 0.0 }
+        };
+        let split = |position: f32| {
+            let lower = (position.floor() as usize).min(self.size - 2);
+            (lower, position - lower as f32)
+        };
+
+        if !self.is_3d {
+            let mut output = Vec3::zeros();
+            for axis in 0..3 {
+                let (lower, t) = split(coordinate(axis));
+                output[axis] = self.data[lower][axis] * (1.0 - t) + self.data[lower + 1][axis] * t;
+            }
+            return output;
+        }
+
+        let (r, tr) = split(coordinate(0));
+        let (g, tg) = split(coordinate(1));
+        let (b, tb) = split(coordinate(2));
+        let entry = |r: usize, g: usize, b: usize| self.data[(b * self.size + g) * self.size + r];
+        let lerp = |a: Vec3, b: Vec3, t: f32| a * (1.0 - t) + b * t;
+
+        let back = lerp(
+            lerp(entry(r, g, b), entry(r + 1, g, b), tr),
+            lerp(entry(r, g + 1, b), entry(r + 1, g + 1, b), tr),
+            tg,
+        );
+        let front = lerp(
+            lerp(entry(r, g, b + 1), entry(r + 1, g, b + 1), tr),
+            lerp(entry(r, g + 1, b + 1), entry(r + 1, g + 1, b + 1), tr),
+            tg,
+        );
+        lerp(back, front, tb)
+    }
+}
+
+fn parse_triple(values: &[&str]) -> Result<Vec3, String> {
+    let numbers: Vec<f32> = values.iter()
+        .map(|value| value.parse::<f32>().map_err(|_| format!("`{}` is not a number", value)))
+        .collect::<Result<_, _>>()?;
+
+    match numbers[..] {
+        [r, g, b] => Ok(Vec3::new(r, g, b)),
+        _ => Err(format!("expected three numbers, found `{}`", values.join(" "))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> String {
+        std::env::temp_dir().join(format!("lut_test_{}_{}", std::process::id(), name)).to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn identity_luts_leave_colors_unchanged() {
+        let ramp = ColorLut::new_1d(vec![Vec3::zeros(), Vec3::new(1.0, 1.0, 1.0)]);
+        for lut in [ColorLut::identity(2), ColorLut::identity(17), ramp] {
+            for value in [0u32, 1, 37, 128, 200, 254, 255] {
+                let color = Color::new_rgba(value as u8, (255 - value) as u8, (value * 7 % 256) as u8, 99);
+                assert_eq!(lut.apply(color), color);
+            }
+            let input = Vec3::new(0.123, 0.5, 0.987);
+            assert!((lut.apply_vec3(input) - input).magnitude() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn loaded_identity_cube_is_a_no_op_and_bad_files_are_rejected() {
+        let path = temp_path("identity.cube");
+        fs::write(&path, "TITLE \"identity\"\n# red varies fastest\nLUT_3D_SIZE 2\n\
+            0 0 0\n1 0 0\n0 1 0\n1 1 0\n0 0 1\n1 0 1\n0 1 1\n1 1 1\n").unwrap();
+        let lut = ColorLut::load_cube(&path).unwrap();
+        assert!(lut.is_3d && lut.size == 2);
+        let color = Color::new(10, 140, 250);
+        assert_eq!(lut.apply(color), color);
+
+        fs::write(&path, "LUT_3D_SIZE 2\n0 0 0\n").unwrap();
+        assert_eq!(ColorLut::load_cube(&path).unwrap_err().kind(), ErrorKind::InvalidData);
+        fs::write(&path, "LUT_1D_SIZE 2\n0 0 zero\n1 1 1\n").unwrap();
+        assert!(ColorLut::load_cube(&path).unwrap_err().to_string().contains(":2:"));
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,153 +1,33 @@
-use nalgebra_glm::{Vec3, Mat4, look_at, perspective};
+use nalgebra_glm::Vec3;
 use minifb::{Key, Window, WindowOptions};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::f32::consts::PI;
 
-mod framebuffer;
-mod triangle;
-mod vertex;
-mod obj;
-mod color;
-mod fragment;
-mod shaders;
-mod camera;
-
-use framebuffer::Framebuffer;
-use vertex::Vertex;
-use obj::Obj;
-use camera::Camera;
-use triangle::triangle;
-use shaders::{vertex_shader, fragment_shader};
-use fastnoise_lite::{FastNoiseLite, NoiseType};
-
-pub struct Uniforms {
-    model_matrix: Mat4,
-    view_matrix: Mat4,
-    projection_matrix: Mat4,
-    viewport_matrix: Mat4,
-    time: u32,
-    noise: FastNoiseLite
-}
-
-fn create_noise() -> FastNoiseLite {
-    create_cloud_noise()
-}
-
-fn create_cloud_noise() -> FastNoiseLite {
-    let mut noise = FastNoiseLite::with_seed(1337);
-    noise.set_noise_type(Some(NoiseType::OpenSimplex2));
-    noise
-}
-
-fn create_model_matrix(translation: Vec3, scale: f32, rotation: Vec3) -> Mat4 {
-    let (sin_x, cos_x) = rotation.x.sin_cos();
-    let (sin_y, cos_y) = rotation.y.sin_cos();
-    let (sin_z, cos_z) = rotation.z.sin_cos();
-
-    let rotation_matrix_x = Mat4::new(
-        1.0,  0.0,    0.0,   0.0,
-        0.0,  cos_x, -sin_x, 0.0,
-        0.0,  sin_x,  cos_x, 0.0,
-        0.0,  0.0,    0.0,   1.0,
-    );
-
-    let rotation_matrix_y = Mat4::new(
-        cos_y,  0.0,  sin_y, 0.0,
-        0.0,    1.0,  0.0,   0.0,
-        -sin_y, 0.0,  cos_y, 0.0,
-        0.0,    0.0,  0.0,   1.0,
-    );
-
-    let rotation_matrix_z = Mat4::new(
-        cos_z, -sin_z, 0.0, 0.0,
-        sin_z,  cos_z, 0.0, 0.0,
-        0.0,    0.0,  1.0, 0.0,
-        0.0,    0.0,  0.0, 1.0,
-    );
-
-    let rotation_matrix = rotation_matrix_z * rotation_matrix_y * rotation_matrix_x;
-
-    let transform_matrix = Mat4::new(
-        scale, 0.0,   0.0,   translation.x,
-        0.0,   scale, 0.0,   translation.y,
-        0.0,   0.0,   scale, translation.z,
-        0.0,   0.0,   0.0,   1.0,
-    );
-
-    transform_matrix * rotation_matrix
-}
-
-
-fn create_view_matrix(eye: Vec3, center: Vec3, up: Vec3) -> Mat4 {
-    look_at(&eye, &center, &up)
-}
-
-fn create_perspective_matrix(window_width: f32, window_height: f32) -> Mat4 {
-    let fov = 45.0 * PI / 180.0;
-    let aspect_ratio = window_width / window_height;
-    let near = 0.1;
-    let far = 1000.0;
-
-    perspective(fov, aspect_ratio, near, far)
-}
-
-fn create_viewport_matrix(width: f32, height: f32) -> Mat4 {
-    Mat4::new(
-        width / 2.0, 0.0, 0.0, width / 2.0,
-        0.0, -height / 2.0, 0.0, height / 2.0,
-        0.0, 0.0, 1.0, 0.0,
-        0.0, 0.0, 0.0, 1.0
-    )
-}
-
-fn render(framebuffer: &mut Framebuffer, uniforms: &Uniforms, vertex_array: &[Vertex], shader_selection: u32) {
-    // Vertex Shader
-    let mut transformed_vertices = Vec::with_capacity(vertex_array.len());
-    for vertex in vertex_array {
-        let transformed = vertex_shader(vertex, uniforms);
-        transformed_vertices.push(transformed);
-    }
-
-    // Primitive Assembly
-    let mut triangles = Vec::new();
-    for i in (0..transformed_vertices.len()).step_by(3) {
-        if i + 2 < transformed_vertices.len() {
-            triangles.push([
-                transformed_vertices[i].clone(),
-                transformed_vertices[i + 1].clone(),
-                transformed_vertices[i + 2].clone(),
-            ]);
-        }
-    }
-
-    // Rasterization
-    let mut fragments = Vec::new();
-    for tri in &triangles {
-        fragments.extend(triangle(&tri[0], &tri[1], &tri[2], shader_selection));
-    }
-
-    // Fragment Processing
-    for fragment in fragments {
-        let x = fragment.position.x as usize;
-        let y = fragment.position.y as usize;
-        let mut shaded_color = fragment_shader(&fragment, &uniforms, "lava");
-
-        if x < framebuffer.width && y < framebuffer.height {
-            if shader_selection == 0 {
-                shaded_color = fragment_shader(&fragment, &uniforms, "lava");
-            } else if shader_selection == 1 {
-                shaded_color = fragment_shader(&fragment, &uniforms, "ice");
-            } else if shader_selection == 2 {
-                shaded_color = fragment_shader(&fragment, &uniforms, "cloud");
-            } else if shader_selection == 3 {
-                shaded_color = fragment_shader(&fragment, &uniforms, "jupiter");
-            }
-            let color = shaded_color.to_hex();
-            framebuffer.set_current_color(color);
-            framebuffer.point(x, y, fragment.depth);
-        }
-    }
-}
+use fourth_laboratory_computer_graphics::{
+    Uniforms, Handedness, render_mesh, create_noise, create_model_matrix,
+    create_view_matrix, create_perspective_matrix, create_viewport_matrix,
+};
+use fourth_laboratory_computer_graphics::framebuffer::Framebuffer;
+use fourth_laboratory_computer_graphics::obj::{Obj, DEFAULT_MAX_TRIANGLES};
+use fourth_laboratory_computer_graphics::geometry::detect_winding;
+use fourth_laboratory_computer_graphics::camera::Camera;
+use fourth_laboratory_computer_graphics::params::ShaderParams;
+use fourth_laboratory_computer_graphics::render_target::RenderTarget;
+use fourth_laboratory_computer_graphics::adaptive::AdaptiveResolution;
+use fourth_laboratory_computer_graphics::clock::AnimationClock;
+use fourth_laboratory_computer_graphics::color::Color;
+use fourth_laboratory_computer_graphics::gizmo::{draw_axes, draw_grid};
+use fourth_laboratory_computer_graphics::hud::ParamHud;
+use fourth_laboratory_computer_graphics::background::{BackgroundMode, clear_with_background};
+
+const SHADER_PARAMS_PATH: &str = "assets/shader_params.toml";
+const HUD_BINDINGS_PATH: &str = "assets/hud_bindings.toml";
+// Budget of the adaptive resolution mode, toggled with F
+const ADAPTIVE_FRAME_MS: f32 = 33.0;
+// World axes and ground grid, toggled with G
+const GIZMO_COLOR: Color = Color::new(90, 90, 120);
+// Painted behind the model each frame; see `BackgroundMode` for the others
+const BACKGROUND: BackgroundMode = BackgroundMode::Solid(Color::new(0x33, 0x33, 0x55));
 
 fn main() {
     let window_width = 800;
@@ -158,6 +38,12 @@ fn main() {
     let mut shader_selection = 0;
 
     let mut framebuffer = Framebuffer::new(framebuffer_width, framebuffer_height);
+    // Window-sized copy of a smaller adaptive frame, with the HUD drawn on top
+    let mut display = Framebuffer::new(framebuffer_width, framebuffer_height);
+    let mut adaptive_resolution = AdaptiveResolution::new();
+    let mut target_frame_ms = None;
+    let mut auto_rotate = false;
+    let mut show_gizmos = false;
     let mut window = Window::new(
         "Presiona números del pad numérico para cambiar de shader",
         window_width,
@@ -169,8 +55,6 @@ fn main() {
     window.set_position(500, 500);
     window.update();
 
-    framebuffer.set_background_color(0x333355);
-
     // model position
     let translation = Vec3::new(0.0, 0.0, 0.0);
     let rotation = Vec3::new(0.0, 0.0, 0.0);
@@ -184,41 +68,135 @@ fn main() {
     );
 
     let obj = Obj::load("assets/models/sun.obj").expect("Failed to load obj");
-    let vertex_arrays = obj.get_vertex_array(); 
-    let mut time = 0;
+    // Models exported without UVs get them from the position instead
+    let spherical_uv = !obj.has_texcoords();
+    if obj.triangle_count() > DEFAULT_MAX_TRIANGLES {
+        eprintln!("Decimating {} triangles down to at most {}", obj.triangle_count(), DEFAULT_MAX_TRIANGLES);
+    }
+    let mesh = obj.get_mesh_within_budget(DEFAULT_MAX_TRIANGLES);
+    // Change this to force a winding for meshes the guess gets wrong
+    let front_face = detect_winding(&mesh);
+    // P pauses, period and comma step one frame while inspecting a shader, Home rewinds
+    let mut clock = AnimationClock::new();
+
+    // Press R to re-read both files after editing them, or H for the
+    // on-screen tuning keys (see `ParamHud`)
+    let mut shader_params = ShaderParams::load(SHADER_PARAMS_PATH).unwrap_or_else(|error| {
+        eprintln!("Using default shader parameters: {}", error);
+        ShaderParams::default()
+    });
+    let mut param_hud = ParamHud::load(HUD_BINDINGS_PATH).unwrap_or_else(|error| {
+        eprintln!("Using default HUD bindings: {}", error);
+        ParamHud::default()
+    });
+    // Last frame's transform, for the velocity buffer
+    let mut prev_mvp = None;
 
     while window.is_open() {
         if window.is_key_down(Key::Escape) {
             break;
         }
+        let frame_start = Instant::now();
 
-        time += 1;
+        if window.is_key_pressed(Key::P, minifb::KeyRepeat::No) {
+            clock.toggle_pause();
+        }
+        if window.is_key_pressed(Key::Period, minifb::KeyRepeat::Yes) {
+            clock.step_time(1);
+        }
+        if window.is_key_pressed(Key::Comma, minifb::KeyRepeat::Yes) {
+            clock.step_time(-1);
+        }
+        if window.is_key_pressed(Key::Home, minifb::KeyRepeat::No) {
+            clock.set_time(0);
+        }
+        let time = clock.tick();
 
         shader_selection = handle_input(&window, &mut camera, shader_selection);
 
-        framebuffer.clear();
+        if window.is_key_pressed(Key::R, minifb::KeyRepeat::No) {
+            if let Err(error) = shader_params.reload(SHADER_PARAMS_PATH) {
+                eprintln!("Could not reload shader parameters: {}", error);
+            }
+            if let Err(error) = param_hud.reload(HUD_BINDINGS_PATH) {
+                eprintln!("Could not reload HUD bindings: {}", error);
+            }
+        }
+        param_hud.handle_input(&window, &mut shader_params);
+
+        if window.is_key_pressed(Key::F, minifb::KeyRepeat::No) {
+            target_frame_ms = match target_frame_ms {
+                Some(_) => None,
+                None => Some(ADAPTIVE_FRAME_MS),
+            };
+        }
+
+        if window.is_key_pressed(Key::T, minifb::KeyRepeat::No) {
+            auto_rotate = !auto_rotate;
+        }
+        if window.is_key_pressed(Key::G, minifb::KeyRepeat::No) {
+            show_gizmos = !show_gizmos;
+        }
+
+        // Slow frames are rendered smaller and scaled up to the window
+        let (render_width, render_height) = adaptive_resolution.internal_size(framebuffer_width, framebuffer_height);
+        if (framebuffer.width, framebuffer.height) != (render_width, render_height) {
+            framebuffer = Framebuffer::new(render_width, render_height);
+        }
 
         let noise = create_noise();
         let model_matrix = create_model_matrix(translation, scale, rotation);
         let view_matrix = create_view_matrix(camera.eye, camera.center, camera.up);
-        let projection_matrix = create_perspective_matrix(window_width as f32, window_height as f32);
-        let viewport_matrix = create_viewport_matrix(framebuffer_width as f32, framebuffer_height as f32);
-        let uniforms = Uniforms { 
-            model_matrix, 
-            view_matrix, 
-            projection_matrix, 
+        let projection_matrix = create_perspective_matrix(window_width as f32, window_height as f32, Handedness::Right);
+        let viewport_matrix = create_viewport_matrix(render_width as f32, render_height as f32);
+        let mut uniforms = Uniforms::new(
+            model_matrix,
+            view_matrix,
+            projection_matrix,
             viewport_matrix,
             time,
             noise
-        };
+        );
+        uniforms.camera_position = camera.eye;
+        uniforms.front_face = front_face;
+        uniforms.spherical_uv = spherical_uv;
+        uniforms.params = shader_params.clone();
+        uniforms.target_frame_ms = target_frame_ms;
+        // Turntable mode, toggled with T, at `ShaderParams::spin_speed`
+        if auto_rotate {
+            uniforms.auto_rotate(Vec3::new(0.0, 1.0, 0.0), shader_params.spin_speed);
+        }
+        uniforms.prev_mvp = prev_mvp;
+        prev_mvp = Some(uniforms.mvp());
 
+        clear_with_background(&mut framebuffer, &uniforms, BACKGROUND);
         framebuffer.set_current_color(0xFFDDDD);
-        render(&mut framebuffer, &uniforms, &vertex_arrays, shader_selection);
+        render_mesh(&mut framebuffer, &uniforms, &mesh, shader_selection);
+        if show_gizmos {
+            draw_grid(&mut framebuffer, &uniforms, 5.0, 0.5, GIZMO_COLOR);
+            draw_axes(&mut framebuffer, &uniforms, 2.0);
+        }
 
+        // The HUD goes on after upscaling, so its text stays sharp at any
+        // internal resolution
+        let presented = if (render_width, render_height) == (framebuffer_width, framebuffer_height) {
+            &mut framebuffer
+        } else {
+            RenderTarget::from_framebuffer(&framebuffer)
+                .resized(framebuffer_width, framebuffer_height)
+                .write_to_framebuffer(&mut display);
+            &mut display
+        };
+        param_hud.draw(presented, &shader_params);
         window
-            .update_with_buffer(&framebuffer.buffer, framebuffer_width, framebuffer_height)
+            .update_with_buffer(&presented.buffer, framebuffer_width, framebuffer_height)
             .unwrap();
 
+        match uniforms.target_frame_ms {
+            Some(budget) => adaptive_resolution.update(frame_start.elapsed(), budget),
+            None => adaptive_resolution.reset(),
+        }
+
         std::thread::sleep(frame_delay);
     }
 }
@@ -285,5 +263,21 @@ fn handle_input(window: &Window, camera: &mut Camera, mut shader_selection: u32)
         shader_selection = 3;
     }
 
-    return shader_selection;
+    if window.is_key_down(Key::NumPad4) {
+        shader_selection = 4;
+    }
+
+    if window.is_key_down(Key::NumPad5) {
+        shader_selection = 5;
+    }
+
+    if window.is_key_down(Key::NumPad6) {
+        shader_selection = 6;
+    }
+
+    if window.is_key_down(Key::NumPad7) {
+        shader_selection = 7;
+    }
+
+    shader_selection
 }
@@ -0,0 +1,236 @@
+use nalgebra_glm::Vec3;
+use std::f32::consts::TAU;
+
+// Polynomial smooth minimum (Inigo Quilez). Behaves like `a.min(b)` when the
+// values are more than `k` apart and blends them smoothly otherwise, which is
+// what lets distance fields of separate blobs merge into one surface.
+pub fn smooth_min(a: f32, b: f32, k: f32) -> f32 {
+    if k <= 0.0 {
+        return a.min(b);
+    }
+
+    let h = (0.5 + 0.5 * (b - a) / k).clamp(0.0, 1.0);
+    b + (a - b) * h - k * h * (1.0 - h)
+}
+
+// `v` scaled to unit length, or `fallback` when it has no direction (zero or
+// non-finite). Divides by the largest component first, so huge but finite
+// vectors do not overflow to infinity while squaring.
+pub fn unit_or(v: Vec3, fallback: Vec3) -> Vec3 {
+    let scale = v.amax();
+    if !scale.is_finite() || scale <= 0.0 {
+        return fallback;
+    }
+    (v / scale).normalize()
+}
+
+// Latitude in [-pi/2, pi/2] (+Y is the north pole) and longitude in
+// [-pi, pi] of a point around the origin, with longitude 0 on +X and pi/2 on
+// +Z, the layout of `texture::spherical_uv`. The radius does not matter. At
+// the poles, where longitude is undefined, it is 0; the origin gives (0, 0).
+pub fn to_lat_lon(position: Vec3) -> (f32, f32) {
+    let radius = position.magnitude();
+    if !radius.is_finite() || radius <= f32::EPSILON {
+        return (0.0, 0.0);
+    }
+
+    let latitude = (position.y / radius).clamp(-1.0, 1.0).asin();
+    let longitude = if position.x.abs() <= f32::EPSILON * radius && position.z.abs() <= f32::EPSILON * radius {
+        0.0
+    } else {
+        position.z.atan2(position.x)
+    };
+    (latitude, longitude)
+}
+
+// Unit vector at a latitude and longitude, the inverse of `to_lat_lon`
+pub fn from_lat_lon(latitude: f32, longitude: f32) -> Vec3 {
+    let ring = latitude.cos();
+    Vec3::new(ring * longitude.cos(), latitude.sin(), ring * longitude.sin())
+}
+
+// Classic 4x4 ordered-dither matrix: every rank 0-15 appears once, each
+// step of the rank spread as far as possible from the previous ones
+const BAYER_4X4: [[u8; 4]; 4] = [
+    [0, 8, 2, 10],
+    [12, 4, 14, 6],
+    [3, 11, 1, 9],
+    [15, 7, 13, 5],
+];
+
+// Dither threshold in (0, 1) of screen pixel (x, y), tiling every 4 pixels.
+// A value v passes at a share v of the pixels in any 4x4 block.
+pub fn bayer_threshold(x: usize, y: usize) -> f32 {
+    (BAYER_4X4[y % 4][x % 4] as f32 + 0.5) / 16.0
+}
+
+// 16x16 blue-noise tile (void-and-cluster ranks 0-255): neighbouring ranks
+// land far apart with no repeating grid, so thresholds read as fine grain
+// instead of the cross-hatch of `BAYER_4X4`
+const BLUE_NOISE_16X16: [[u8; 16]; 16] = [
+    [236, 102, 128,  70, 147, 186,  94, 166,  53,   1, 240, 208,  15, 144, 172,  24],
+    [ 54, 191,   6, 210,  48, 121,  12, 215, 110, 145,  73, 127,  97, 189,  85, 119],
+    [156,  90, 249, 171,  82, 238, 194,  68, 246, 178,  28, 198,  58, 252,  39, 222],
+    [ 19,  63, 134,  30, 112, 158,  22, 129,  42,  88, 227, 157, 115,   5, 136, 200],
+    [237, 164, 218, 190,  44, 225,  96, 151, 206,  10, 138,  46, 233, 175,  77, 106],
+    [ 45,  99,  11, 122,  76, 180,  60, 254, 176, 109, 220,  83, 193,  32, 148, 182],
+    [209,  69, 146, 235, 203,   2, 117,  27,  74,  51, 168,  20, 103,  62, 241,  16],
+    [135, 248, 173,  33,  89, 160, 214, 139, 232, 199, 124, 250, 159, 205, 120,  93],
+    [196,  23, 108,  56, 130, 242,  47, 100, 153,   8,  92,  43, 140,   0, 226,  55],
+    [152,  80, 224, 204, 183,  14,  81, 195,  34, 181, 229,  72, 188,  86, 167,  35],
+    [185, 125,   7, 150,  65, 114, 163, 247,  67, 113, 132, 213,  29, 245, 105, 217],
+    [ 71, 255, 101,  37, 239, 216,  26, 137, 211,  13, 170,  57, 155, 123,  61,  18],
+    [165,  49, 207, 174,  84, 126,  52, 179,  87,  41, 253,  98,   9, 231, 202, 143],
+    [228, 116,  17, 141, 197,   4, 234, 104, 154, 223, 142, 192,  78, 177,  40,  95],
+    [187,  79, 243,  59, 107, 161,  75, 201,  21,  64, 118,  31, 219, 111, 133,   3],
+    [149,  36, 169, 221,  25, 251,  38, 131, 230, 184,  91, 162,  50, 244,  66, 212],
+];
+
+// Same as `bayer_threshold` from the blue-noise tile, tiling every 16 pixels
+pub fn blue_noise_threshold(x: usize, y: usize) -> f32 {
+    (BLUE_NOISE_16X16[y % 16][x % 16] as f32 + 0.5) / 256.0
+}
+
+// Threshold pattern of the screen-space dithers (`AlphaMode::Dither`,
+// `Uniforms::dither_strength`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DitherPattern {
+    // Ordered 4x4 Bayer matrix: cheap and even, but its grid shows on smooth gradients
+    #[default]
+    Bayer,
+    // Embedded 16x16 blue-noise tile: less structured, better on skies and clouds
+    BlueNoise,
+}
+
+impl DitherPattern {
+    pub fn threshold(self, x: usize, y: usize) -> f32 {
+        match self {
+            DitherPattern::Bayer => bayer_threshold(x, y),
+            DitherPattern::BlueNoise => blue_noise_threshold(x, y),
+        }
+    }
+}
+
+// Hermite step from 0 at `edge0` to 1 at `edge1`, with zero slope at both ends
+pub fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    if edge1 <= edge0 {
+        return if x < edge0 { 0.0 } else { 1.0 };
+    }
+
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+// Periodic animation curves of `time`. All of them share the shape of
+// `amplitude * sin(time * frequency + phase)`: period 2*pi / frequency, range
+// [-amplitude, amplitude], and a rising zero crossing where the angle is 0, so
+// they are interchangeable in shaders.
+
+pub fn pulse(time: f32, frequency: f32, amplitude: f32, phase: f32) -> f32 {
+    (time * frequency + phase).sin() * amplitude
+}
+
+// Linear ramps up and down, peaking where the sine would
+pub fn triangle_wave(time: f32, frequency: f32, amplitude: f32, phase: f32) -> f32 {
+    let cycles = (time * frequency + phase) / TAU;
+    (4.0 * ((cycles - 0.25).rem_euclid(1.0) - 0.5).abs() - 1.0) * amplitude
+}
+
+// Linear ramp up, then an instant drop back to -amplitude halfway through each cycle
+pub fn sawtooth(time: f32, frequency: f32, amplitude: f32, phase: f32) -> f32 {
+    let cycles = (time * frequency + phase) / TAU;
+    (2.0 * (cycles + 0.5).rem_euclid(1.0) - 1.0) * amplitude
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::{FRAC_PI_2, PI};
+
+    const EPSILON: f32 = 1e-5;
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < EPSILON
+    }
+
+    #[test]
+    fn waveforms_share_the_sine_period_range_and_zero_crossing() {
+        for wave in [pulse, triangle_wave, sawtooth] {
+            assert!(close(wave(0.0, 1.0, 2.0, 0.0), 0.0));
+            // Rising through 0
+            assert!(wave(0.1, 1.0, 2.0, 0.0) > 0.0);
+            for step in 0..200 {
+                let time = step as f32 * 0.037;
+                let value = wave(time, 3.0, 2.0, 0.4);
+                assert!(value.abs() <= 2.0 + EPSILON);
+                assert!(close(value, wave(time + TAU / 3.0, 3.0, 2.0, 0.4)) || close(value.abs(), 2.0));
+                // The phase shifts the curve in its angle
+                assert!(close(wave(time, 1.0, 1.0, 0.5), wave(time + 0.5, 1.0, 1.0, 0.0)));
+            }
+        }
+    }
+
+    #[test]
+    fn triangle_wave_is_linear_between_its_peaks() {
+        assert!(close(triangle_wave(FRAC_PI_2, 1.0, 1.0, 0.0), 1.0));
+        assert!(close(triangle_wave(PI, 1.0, 1.0, 0.0), 0.0));
+        assert!(close(triangle_wave(3.0 * FRAC_PI_2, 1.0, 1.0, 0.0), -1.0));
+        assert!(close(triangle_wave(FRAC_PI_2 / 2.0, 1.0, 1.0, 0.0), 0.5));
+        assert!(close(triangle_wave(FRAC_PI_2 * 1.5, 1.0, 1.0, 0.0), 0.5));
+    }
+
+    #[test]
+    fn sawtooth_ramps_up_then_drops_halfway_through_the_cycle() {
+        assert!(close(sawtooth(FRAC_PI_2, 1.0, 1.0, 0.0), 0.5));
+        assert!(sawtooth(PI - 1e-3, 1.0, 1.0, 0.0) > 0.99);
+        assert!(close(sawtooth(PI, 1.0, 1.0, 0.0), -1.0));
+        assert!(close(sawtooth(3.0 * FRAC_PI_2, 1.0, 1.0, 0.0), -0.5));
+        assert!(close(sawtooth(-FRAC_PI_2, 1.0, 1.0, 0.0), -0.5));
+    }
+
+    #[test]
+    fn lat_lon_round_trips_away_from_the_poles() {
+        for lat_step in -8..=8 {
+            for lon_step in -11..=11 {
+                let (latitude, longitude) = (lat_step as f32 * 0.19, lon_step as f32 * 0.28);
+                let point = from_lat_lon(latitude, longitude);
+                assert!((point.magnitude() - 1.0).abs() < 1e-5);
+                // Any radius gives the same angles
+                let (back_latitude, back_longitude) = to_lat_lon(point * 7.5);
+                assert!((back_latitude - latitude).abs() < 1e-4 && (back_longitude - longitude).abs() < 1e-4,
+                    "({}, {}) came back as ({}, {})", latitude, longitude, back_latitude, back_longitude);
+            }
+        }
+        // Axes of the layout
+        assert!((from_lat_lon(0.0, 0.0) - Vec3::new(1.0, 0.0, 0.0)).magnitude() < 1e-6);
+        assert!((from_lat_lon(0.0, FRAC_PI_2) - Vec3::new(0.0, 0.0, 1.0)).magnitude() < 1e-6);
+        assert!((from_lat_lon(FRAC_PI_2, 1.0) - Vec3::new(0.0, 1.0, 0.0)).magnitude() < 1e-6);
+    }
+
+    #[test]
+    fn poles_and_the_origin_have_zero_longitude() {
+        assert_eq!(to_lat_lon(Vec3::new(0.0, 3.0, 0.0)), (FRAC_PI_2, 0.0));
+        assert_eq!(to_lat_lon(Vec3::new(0.0, -0.5, 0.0)), (-FRAC_PI_2, 0.0));
+        for degenerate in [Vec3::zeros(), Vec3::new(f32::NAN, 1.0, 0.0), Vec3::new(f32::INFINITY, 0.0, 0.0)] {
+            assert_eq!(to_lat_lon(degenerate), (0.0, 0.0));
+        }
+        // Longitude wraps at pi, on -X
+        let (_, west) = to_lat_lon(Vec3::new(-1.0, 0.0, -1e-3));
+        let (_, east) = to_lat_lon(Vec3::new(-1.0, 0.0, 1e-3));
+        assert!((west + PI).abs() < 1e-2 && (east - PI).abs() < 1e-2);
+    }
+
+    #[test]
+    fn dither_tiles_use_every_threshold_once() {
+        for (pattern, tile) in [(DitherPattern::Bayer, 4), (DitherPattern::BlueNoise, 16)] {
+            let mut ranks: Vec<usize> = (0..tile * tile)
+                .map(|i| (pattern.threshold(i % tile, i / tile) * (tile * tile) as f32 - 0.5).round() as usize)
+                .collect();
+            ranks.sort_unstable();
+            assert_eq!(ranks, (0..tile * tile).collect::<Vec<_>>(), "{:?}", pattern);
+            // Tiled across the screen
+            assert_eq!(pattern.threshold(3, 2), pattern.threshold(3 + tile * 5, 2 + tile));
+        }
+        assert_eq!(DitherPattern::default(), DitherPattern::Bayer);
+    }
+}
@@ -0,0 +1,379 @@
+use std::collections::{HashMap, HashSet};
+use nalgebra_glm::{Vec2, Vec3};
+use crate::vertex::Vertex;
+use crate::shaders::ShaderType;
+
+// Indexed triangle list: every three indices into `vertices` form a triangle
+#[derive(Clone, Debug, Default)]
+pub struct Mesh {
+    pub vertices: Vec<Vertex>,
+    pub indices: Vec<u32>,
+    // Optional material of each triangle, an index into `materials`. Triangles
+    // without one (the list is empty or shorter) or whose id has no entry in
+    // `materials` use the shader passed to the render call.
+    pub material_ids: Vec<u16>,
+    pub materials: Vec<ShaderType>,
+}
+
+impl Mesh {
+    pub fn new(vertices: Vec<Vertex>, indices: Vec<u32>) -> Self {
+        Mesh { vertices, indices, material_ids: Vec::new(), materials: Vec::new() }
+    }
+
+    // Wraps a flat list of vertex triples without merging anything
+    pub fn from_triangle_soup(vertices: Vec<Vertex>) -> Self {
+        let indices = (0..vertices.len() as u32).collect();
+        Mesh::new(vertices, indices)
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.indices.len() / 3
+    }
+
+    pub fn triangle_material(&self, triangle: usize) -> Option<ShaderType> {
+        let id = *self.material_ids.get(triangle)?;
+        self.materials.get(id as usize).copied()
+    }
+
+    // Sphere around every vertex position: centered on the bounding box, which
+    // is not the tightest fit but never more than ~1.7x too large. An empty
+    // mesh gives a zero radius at the origin.
+    pub fn bounding_sphere(&self) -> (Vec3, f32) {
+        let Some(first) = self.vertices.first() else {
+            return (Vec3::zeros(), 0.0);
+        };
+
+        let (min, max) = self.vertices.iter().fold((first.position, first.position), |(min, max), vertex| {
+            (min.inf(&vertex.position), max.sup(&vertex.position))
+        });
+        let center = (min + max) * 0.5;
+        let radius = self.vertices.iter()
+            .map(|vertex| (vertex.position - center).magnitude())
+            .fold(0.0, f32::max);
+
+        (center, radius)
+    }
+
+    // Expands back to the flat layout used by `render`
+    pub fn to_vertex_array(&self) -> Vec<Vertex> {
+        self.indices.iter().map(|&index| self.vertices[index as usize].clone()).collect()
+    }
+}
+
+// Largest texture-coordinate difference and smallest normal cosine (about 45
+// degrees apart) for `weld_vertices` to treat two coincident vertices as one.
+// Vertices on a UV seam or a hard edge stay separate.
+pub const WELD_UV_TOLERANCE: f32 = 1e-4;
+pub const WELD_NORMAL_COSINE: f32 = 0.7;
+
+// Smallest grid cell of `weld_vertices`, so tiny tolerances don't push cell
+// coordinates to the ends of the i64 range
+const MIN_WELD_CELL_SIZE: f32 = 1e-12;
+
+// Indexes a flat triangle list, merging vertices that lie within `epsilon` of
+// each other and agree on UVs and roughly on their normal. Merged vertices
+// keep the first one's attributes but the average of all their normals, which
+// smooths the shading across seams the soup duplicated. Triangle order and
+// winding are preserved. An `epsilon` of 0 (or below) only merges exactly
+// equal positions.
+pub fn weld_vertices(vertices: &[Vertex], epsilon: f32) -> Mesh {
+    // Grid of `epsilon`-sized cells, so each vertex only checks its 27
+    // neighbouring cells for candidates. Exact welding keys on the bits of the
+    // position instead (with -0 folded into +0) and checks its own cell only.
+    let exact = epsilon.is_nan() || epsilon <= 0.0;
+    let cell_size = epsilon.max(MIN_WELD_CELL_SIZE);
+    let cell = |position: &Vec3| if exact {
+        position.map(|coordinate| (coordinate + 0.0).to_bits() as i64)
+    } else {
+        position.map(|coordinate| (coordinate / cell_size).floor() as i64)
+    };
+    let (reach, tolerance) = if exact { (0, 0.0) } else { (1, epsilon) };
+    let mut grid: HashMap<(i64, i64, i64), Vec<u32>> = HashMap::new();
+
+    let mut welded: Vec<Vertex> = Vec::new();
+    let mut normal_sums: Vec<Vec3> = Vec::new();
+    let mut indices = Vec::with_capacity(vertices.len());
+
+    for vertex in vertices {
+        let home = cell(&vertex.position);
+        let mut matching = None;
+        'search: for dx in -reach..=reach {
+            for dy in -reach..=reach {
+                for dz in -reach..=reach {
+                    let neighbour = (home.x.saturating_add(dx), home.y.saturating_add(dy), home.z.saturating_add(dz));
+                    let Some(candidates) = grid.get(&neighbour) else { continue };
+                    for &candidate in candidates {
+                        let other = &welded[candidate as usize];
+                        if (other.position - vertex.position).magnitude() <= tolerance
+                            && (other.tex_coords - vertex.tex_coords).magnitude() <= WELD_UV_TOLERANCE
+                            && other.normal.dot(&vertex.normal) >= WELD_NORMAL_COSINE
+                        {
+                            matching = Some(candidate);
+                            break 'search;
+                        }
+                    }
+                }
+            }
+        }
+
+        let index = matching.unwrap_or_else(|| {
+            welded.push(vertex.clone());
+            normal_sums.push(Vec3::zeros());
+            grid.entry((home.x, home.y, home.z)).or_default().push(welded.len() as u32 - 1);
+            welded.len() as u32 - 1
+        });
+        normal_sums[index as usize] += vertex.normal;
+        indices.push(index);
+    }
+
+    for (vertex, sum) in welded.iter_mut().zip(&normal_sums) {
+        if let Some(normal) = sum.try_normalize(f32::EPSILON) {
+            vertex.normal = normal;
+        }
+    }
+
+    Mesh::new(welded, indices)
+}
+
+// Finest clustering grid `decimate` tries, in cells along the longest side
+const MAX_DECIMATION_RESOLUTION: u32 = 4096;
+
+// Simplifies the mesh to at most `target_triangles` triangles by vertex
+// clustering: the bounding box is cut into a grid of cubic cells, every
+// vertex in a cell collapses into one at their average position (normals
+// averaged, the first vertex's other attributes kept), and triangles that
+// collapsed to a line or a point, or onto another triangle, disappear. The
+// finest grid meeting the budget is found by bisection. Fast and robust on
+// any input but blunt: features smaller than a cell are lost, UV seams get
+// averaged across, and the silhouette shrinks by up to about half a cell.
+// A mesh already within the budget is returned as it is.
+pub fn decimate(mesh: &Mesh, target_triangles: usize) -> Mesh {
+    if mesh.triangle_count() <= target_triangles {
+        return mesh.clone();
+    }
+
+    // A single cell leaves no triangles, so there is always a result within
+    // the budget. The count does not grow strictly with the resolution (a
+    // finer grid can cut differently and remove more), so bisection only
+    // steers the search and the candidate closest to the budget is kept.
+    let (mut coarse, mut fine) = (1, MAX_DECIMATION_RESOLUTION);
+    let mut best = cluster_vertices(mesh, coarse);
+    while fine - coarse > 1 {
+        let resolution = coarse + (fine - coarse) / 2;
+        let candidate = cluster_vertices(mesh, resolution);
+        if candidate.triangle_count() <= target_triangles {
+            coarse = resolution;
+            if candidate.triangle_count() > best.triangle_count() {
+                best = candidate;
+            }
+        } else {
+            fine = resolution;
+        }
+    }
+    best
+}
+
+impl Mesh {
+    // The mesh, decimated first if it has more than `max_triangles`, so a huge
+    // model can't stall an interactive viewer
+    pub fn within_triangle_budget(self, max_triangles: usize) -> Mesh {
+        if self.triangle_count() <= max_triangles {
+            self
+        } else {
+            decimate(&self, max_triangles)
+        }
+    }
+}
+
+// One vertex-clustering pass with `resolution` cells along the longest side
+fn cluster_vertices(mesh: &Mesh, resolution: u32) -> Mesh {
+    let Some(first) = mesh.vertices.first() else {
+        return Mesh::default();
+    };
+    let (min, max) = mesh.vertices.iter().fold((first.position, first.position), |(min, max), vertex| {
+        (min.inf(&vertex.position), max.sup(&vertex.position))
+    });
+    let cell_size = ((max - min).max() / resolution as f32).max(f32::MIN_POSITIVE);
+    // The far faces of the bounding box go into the last cell, not past it,
+    // so a resolution of 1 collapses everything into a single cluster
+    let last = resolution.max(1) as i64 - 1;
+    let cell = |position: &Vec3| ((position - min) / cell_size).map(|coordinate| (coordinate.floor() as i64).min(last));
+
+    let mut clusters: HashMap<(i64, i64, i64), u32> = HashMap::new();
+    let mut vertices: Vec<Vertex> = Vec::new();
+    let mut sums: Vec<(Vec3, Vec3, Vec2, f32)> = Vec::new();
+    let remap: Vec<u32> = mesh.vertices.iter().map(|vertex| {
+        let key = cell(&vertex.position);
+        let index = *clusters.entry((key.x, key.y, key.z)).or_insert_with(|| {
+            vertices.push(vertex.clone());
+            sums.push((Vec3::zeros(), Vec3::zeros(), Vec2::zeros(), 0.0));
+            vertices.len() as u32 - 1
+        });
+        let sum = &mut sums[index as usize];
+        sum.0 += vertex.position;
+        sum.1 += vertex.normal;
+        sum.2 += vertex.tex_coords;
+        sum.3 += 1.0;
+        index
+    }).collect();
+
+    for (vertex, (position, normal, tex_coords, count)) in vertices.iter_mut().zip(&sums) {
+        vertex.position = position / *count;
+        vertex.tex_coords = tex_coords / *count;
+        if let Some(normal) = normal.try_normalize(f32::EPSILON) {
+            vertex.normal = normal;
+        }
+    }
+
+    let mut seen = HashSet::new();
+    let mut indices = Vec::new();
+    let mut material_ids = Vec::new();
+    for (triangle, corners) in mesh.indices.chunks_exact(3).enumerate() {
+        let [a, b, c] = [0, 1, 2].map(|i| remap[corners[i] as usize]);
+        if a == b || b == c || a == c {
+            continue;
+        }
+        let mut key = [a, b, c];
+        key.sort_unstable();
+        if !seen.insert(key) {
+            continue;
+        }
+        indices.extend([a, b, c]);
+        if let Some(&id) = mesh.material_ids.get(triangle) {
+            material_ids.push(id);
+        }
+    }
+    // Kept triangles stay in order, so a list that covered only the first
+    // triangles still lines up with the ones that survived
+    Mesh { vertices, indices, material_ids, materials: mesh.materials.clone() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vertex(x: f32, y: f32, normal: Vec3) -> Vertex {
+        Vertex::new(Vec3::new(x, y, 0.0), normal, Vec2::new(x, y))
+    }
+
+    fn quad_soup(normal: Vec3) -> Vec<Vertex> {
+        vec![
+            vertex(0.0, 0.0, normal), vertex(1.0, 0.0, normal), vertex(1.0, 1.0, normal),
+            vertex(0.0, 0.0, normal), vertex(1.0, 1.0, normal), vertex(0.0, 1.0, normal),
+        ]
+    }
+
+    #[test]
+    fn weld_merges_the_shared_corners_of_a_quad() {
+        let mesh = weld_vertices(&quad_soup(Vec3::z()), 1e-5);
+        assert_eq!(mesh.vertices.len(), 4);
+        assert_eq!(mesh.indices, vec![0, 1, 2, 0, 2, 3]);
+    }
+
+    #[test]
+    fn weld_merges_a_seam_within_epsilon_and_averages_its_normals() {
+        let tilted = Vec3::new(0.0, 0.6, 0.8);
+        let mut soup = quad_soup(Vec3::z());
+        // Second triangle's copy of the diagonal, nudged and with its own normal
+        soup[4] = Vertex::new(Vec3::new(1.0 + 5e-5, 1.0, 0.0), tilted, Vec2::new(1.0, 1.0));
+
+        let mesh = weld_vertices(&soup, 1e-4);
+        assert_eq!(mesh.vertices.len(), 4);
+        let expected = (Vec3::z() + tilted).normalize();
+        assert!((mesh.vertices[2].normal - expected).magnitude() < 1e-6);
+
+        // Beyond epsilon it stays a separate vertex
+        assert_eq!(weld_vertices(&soup, 1e-5).vertices.len(), 5);
+    }
+
+    #[test]
+    fn weld_with_zero_epsilon_merges_exact_matches_only() {
+        let mut soup = quad_soup(Vec3::z());
+        soup[5].position.x = -0.0;
+        assert_eq!(weld_vertices(&soup, 0.0).vertices.len(), 4);
+        assert_eq!(weld_vertices(&soup, -1.0).vertices.len(), 4);
+
+        soup[4].position.x += 1e-6;
+        assert_eq!(weld_vertices(&soup, 0.0).vertices.len(), 5);
+    }
+
+    #[test]
+    fn weld_handles_huge_coordinates_with_a_tiny_epsilon() {
+        let soup: Vec<Vertex> = quad_soup(Vec3::z()).into_iter()
+            .map(|mut vertex| { vertex.position *= 1e30; vertex })
+            .collect();
+        assert_eq!(weld_vertices(&soup, f32::MIN_POSITIVE).vertices.len(), 4);
+    }
+
+    fn assert_valid(mesh: &Mesh) {
+        assert_eq!(mesh.indices.len() % 3, 0);
+        assert!(mesh.indices.iter().all(|&index| (index as usize) < mesh.vertices.len()));
+    }
+
+    #[test]
+    fn decimate_never_exceeds_the_budget() {
+        let meshes = [crate::geometry::generate_sphere(3), crate::geometry::generate_torus(1.0, 0.3, 48, 24)];
+        for mesh in &meshes {
+            let count = mesh.triangle_count();
+            for target in [0, 1, 2, 10, 50, 100, 101, 200, 500, 1000, count / 2, count - 1] {
+                let decimated = decimate(mesh, target);
+                assert!(decimated.triangle_count() <= target, "{} triangles for a budget of {}", decimated.triangle_count(), target);
+                assert_valid(&decimated);
+            }
+        }
+    }
+
+    #[test]
+    fn decimate_keeps_the_closest_result_to_the_budget() {
+        // Counts are not monotonic in the grid resolution (see `decimate`)
+        let sphere = crate::geometry::generate_sphere(3);
+        assert_eq!(sphere.triangle_count(), 1280);
+        let decimated = decimate(&sphere, 1279);
+        assert!(decimated.triangle_count() > 1000, "{}", decimated.triangle_count());
+        assert!(decimate(&sphere, 100).triangle_count() >= 48);
+    }
+
+    #[test]
+    fn decimate_to_zero_leaves_an_empty_mesh() {
+        let sphere = crate::geometry::generate_sphere(2);
+        assert_eq!(decimate(&sphere, 0).triangle_count(), 0);
+        assert_eq!(decimate(&Mesh::default(), 0).triangle_count(), 0);
+    }
+
+    #[test]
+    fn decimate_returns_meshes_within_budget_unchanged() {
+        let sphere = crate::geometry::generate_sphere(1);
+        let kept = decimate(&sphere, sphere.triangle_count());
+        assert_eq!(kept.indices, sphere.indices);
+        assert_eq!(kept.vertices.len(), sphere.vertices.len());
+    }
+
+    #[test]
+    fn bounding_sphere_encloses_every_vertex() {
+        let mut sphere = crate::geometry::generate_sphere(2);
+        for vertex in &mut sphere.vertices {
+            vertex.position = vertex.position * 3.0 + Vec3::new(1.0, -2.0, 5.0);
+        }
+        let (center, radius) = sphere.bounding_sphere();
+        assert!((center - Vec3::new(1.0, -2.0, 5.0)).magnitude() < 1e-4);
+        assert!((radius - 3.0).abs() < 1e-4);
+        assert_eq!(Mesh::default().bounding_sphere(), (Vec3::zeros(), 0.0));
+    }
+
+    #[test]
+    fn decimate_keeps_the_bounding_sphere() {
+        let mut sphere = crate::geometry::generate_sphere(3);
+        for vertex in &mut sphere.vertices {
+            vertex.position = vertex.position * 2.0 + Vec3::new(-1.0, 4.0, 0.5);
+        }
+        let (center, radius) = sphere.bounding_sphere();
+        for target in [1000, 500, 200, 100] {
+            let decimated = decimate(&sphere, target);
+            let (decimated_center, decimated_radius) = decimated.bounding_sphere();
+            // Clusters average their vertices, so they never leave the original sphere
+            assert!(decimated.vertices.iter().all(|vertex| (vertex.position - center).magnitude() <= radius + 1e-4));
+            assert!((decimated_center - center).magnitude() < 0.1 * radius, "{:?} moved from {:?} at {}", decimated_center, center, target);
+            assert!(decimated_radius > 0.85 * radius, "radius {} of {} at {}", decimated_radius, radius, target);
+        }
+    }
+}
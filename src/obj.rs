@@ -1,21 +1,28 @@
-use tobj;
 use nalgebra_glm::{Vec2, Vec3};
 use crate::vertex::Vertex;
+use crate::mesh::Mesh;
+
+// Triangle budget of `Obj::get_mesh_within_budget` in the viewer: bigger
+// models are decimated on load
+pub const DEFAULT_MAX_TRIANGLES: usize = 200_000;
 
 pub struct Obj {
-    meshes: Vec<Mesh>,
+    meshes: Vec<ObjMesh>,
+    material_names: Vec<String>,
 }
 
-struct Mesh {
+struct ObjMesh {
     vertices: Vec<Vec3>,
     normals: Vec<Vec3>,
     texcoords: Vec<Vec2>,
     indices: Vec<u32>,
+    // Index of the `usemtl` material in the .mtl file, if it could be read
+    material_id: Option<usize>,
 }
 
 impl Obj {
     pub fn load(filename: &str) -> Result<Self, tobj::LoadError> {
-        let (models, _) = tobj::load_obj(filename, &tobj::LoadOptions {
+        let (models, materials) = tobj::load_obj(filename, &tobj::LoadOptions {
             single_index: true,
             triangulate: true,
             ..Default::default()
@@ -23,7 +30,7 @@ impl Obj {
 
         let meshes = models.into_iter().map(|model| {
             let mesh = model.mesh;
-            Mesh {
+            ObjMesh {
                 vertices: mesh.positions.chunks(3)
                     .map(|v| Vec3::new(v[0], v[1], v[2]))
                     .collect(),
@@ -34,10 +41,28 @@ impl Obj {
                     .map(|t| Vec2::new(t[0], 1.0 - t[1]))
                     .collect(),
                 indices: mesh.indices,
+                material_id: mesh.material_id,
             }
         }).collect();
 
-        Ok(Obj { meshes })
+        // A missing .mtl only loses the material assignment, not the geometry
+        let material_names = materials
+            .map(|materials| materials.into_iter().map(|material| material.name).collect())
+            .unwrap_or_default();
+
+        Ok(Obj { meshes, material_names })
+    }
+
+    // Names of the .mtl materials, in the order `Mesh::material_ids` refer to
+    // them; map each to a shader in `Mesh::materials`
+    pub fn material_names(&self) -> &[String] {
+        &self.material_names
+    }
+
+    // Whether the file has any `vt` coordinates; without them every vertex
+    // gets (0, 0), so set `Uniforms::spherical_uv` to texture the model
+    pub fn has_texcoords(&self) -> bool {
+        self.meshes.iter().any(|mesh| !mesh.texcoords.is_empty())
     }
 
     pub fn get_vertex_array(&self) -> Vec<Vertex> {
@@ -59,4 +84,79 @@ impl Obj {
 
         vertices
     }
+
+    // Indexed version of `get_vertex_array`, keeping the vertices tobj shares
+    // between faces. All models of the file are merged into one mesh, and each
+    // triangle keeps the material id its `usemtl` group had (triangles with
+    // no material get an id past the end of `material_names`).
+    pub fn get_mesh(&self) -> Mesh {
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+        let mut material_ids = Vec::new();
+
+        for mesh in &self.meshes {
+            let base = vertices.len() as u32;
+            for (i, &position) in mesh.vertices.iter().enumerate() {
+                let normal = mesh.normals.get(i)
+                    .cloned()
+                    .unwrap_or(Vec3::new(0.0, 1.0, 0.0));
+                let tex_coords = mesh.texcoords.get(i)
+                    .cloned()
+                    .unwrap_or(Vec2::new(0.0, 0.0));
+
+                vertices.push(Vertex::new(position, normal, tex_coords));
+            }
+            indices.extend(mesh.indices.iter().map(|&index| base + index));
+
+            let material_id = mesh.material_id.unwrap_or(self.material_names.len()).min(u16::MAX as usize) as u16;
+            material_ids.extend(std::iter::repeat_n(material_id, mesh.indices.len() / 3));
+        }
+
+        let mut mesh = Mesh::new(vertices, indices);
+        if !self.material_names.is_empty() {
+            mesh.material_ids = material_ids;
+        }
+        mesh
+    }
+
+    // Triangles in all models of the file, before any decimation
+    pub fn triangle_count(&self) -> usize {
+        self.meshes.iter().map(|mesh| mesh.indices.len() / 3).sum()
+    }
+
+    // `get_mesh`, decimated if the file has more than `max_triangles` (see
+    // `Mesh::within_triangle_budget`); `DEFAULT_MAX_TRIANGLES` keeps the viewer
+    // interactive
+    pub fn get_mesh_within_budget(&self, max_triangles: usize) -> Mesh {
+        self.get_mesh().within_triangle_budget(max_triangles)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn texcoords_are_detected_per_file() {
+        assert!(Obj::load("assets/models/cube.obj").unwrap().has_texcoords());
+
+        let path = std::env::temp_dir().join(format!("obj_test_{}_no_uv.obj", std::process::id()));
+        std::fs::write(&path, "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n").unwrap();
+        let obj = Obj::load(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(!obj.has_texcoords());
+        assert!(obj.get_mesh().vertices.iter().all(|vertex| vertex.tex_coords == Vec2::zeros()));
+    }
+
+    #[test]
+    fn meshes_over_the_triangle_budget_are_decimated_on_load() {
+        let obj = Obj::load("assets/models/cube.obj").unwrap();
+        assert_eq!(obj.triangle_count(), 12);
+        assert_eq!(obj.get_mesh_within_budget(12).indices, obj.get_mesh().indices);
+        assert_eq!(obj.get_mesh_within_budget(DEFAULT_MAX_TRIANGLES).indices, obj.get_mesh().indices);
+
+        let decimated = obj.get_mesh_within_budget(6);
+        assert!(decimated.triangle_count() <= 6, "{}", decimated.triangle_count());
+        assert_eq!(obj.get_mesh_within_budget(0).triangle_count(), 0);
+    }
 }
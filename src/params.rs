@@ -0,0 +1,312 @@
+use std::fs;
+use std::io::{self, ErrorKind};
+use crate::color::Color;
+
+// Tunable constants read by the built-in shaders. Defaults reproduce the
+// original hardcoded look, so an untouched `ShaderParams` changes nothing.
+#[derive(Clone, Debug)]
+pub struct ShaderParams {
+    // Angular frequency of the "pattern" shader's sine waves, in radians per
+    // model-space unit
+    pub pattern_frequency: f32,
+    // Exponential height fog: density at world height 0, how fast it thins
+    // out per unit of height, and its color. A density of 0 disables it.
+    pub fog_density: f32,
+    pub fog_falloff: f32,
+    pub fog_color: Color,
+    // Share of the environment reflection in the "reflective" shader
+    pub reflectivity: f32,
+    // Roughness of the stretched sheen on the gas bands, in (0, 1]
+    pub band_roughness: f32,
+    // Single-scattering atmosphere, in model units around the planet center.
+    // Coefficients are extinction per unit length at sea level; the Rayleigh one
+    // applies to blue and is scaled down by wavelength^-4 for green and red.
+    pub planet_radius: f32,
+    pub atmosphere_radius: f32,
+    pub rayleigh_coefficient: f32,
+    pub mie_coefficient: f32,
+    // Forward-scattering asymmetry of the Mie sunset glow, in [0, 1)
+    pub mie_anisotropy: f32,
+    // Latitude in degrees past which spherically mapped textures fade into
+    // the flat average of their pole row, hiding the pinch where every column
+    // converges. The fade takes `polar_blend_width` degrees (ending at the pole
+    // at the latest); 90 disables it.
+    pub polar_blend_latitude: f32,
+    pub polar_blend_width: f32,
+    // Scale applied to model-space positions before sampling noise in the
+    // lava and cloud shaders. The noise has a base frequency of 0.01, so one
+    // blob spans about 100 / zoom model units: 1000 gives ~10 lava cells per
+    // unit, 100 about one cloud per unit. Scale them with the mesh size.
+    pub lava_zoom: f32,
+    pub cloud_zoom: f32,
+    // Noise value, in about [-1, 1], above which the "cloud" shader draws
+    // cloud; lower it for more overcast skies
+    pub cloud_threshold: f32,
+    // Of the "refractive" shader's material; 1.31 is ice, 1.5 glass
+    pub index_of_refraction: f32,
+    // Terrain of the "biome" shader. Elevation is fractal noise in about
+    // [-1, 1] sampled at `biome_zoom` (same scale as `lava_zoom`); below
+    // `biome_sea_level` is ocean, above `biome_highland_level` mountains.
+    // Temperature falls from the equator to the poles and with altitude.
+    // `biome_colors[elevation][temperature]` is the color of each biome, rows
+    // ocean, lowland, highland and columns polar, cold, temperate, hot (keys
+    // `biome_lowland_hot` and so on). `biome_blend` is the share of each cell,
+    // in [0, 1], spent fading into its neighbours; 0 gives hard borders.
+    pub biome_zoom: f32,
+    pub biome_sea_level: f32,
+    pub biome_highland_level: f32,
+    pub biome_blend: f32,
+    pub biome_colors: [[Color; 4]; 3],
+    // Level of detail of the "ring" shader, by `Uniforms::pixels_per_unit`
+    // (about the planet's radius in pixels for `PlanetBuilder` rings). At
+    // `ring_lod_detail_size` and above every band is drawn; from there it
+    // fades to a flat disk of the bands' average color, reached at
+    // `ring_lod_solid_size`. The finest bands alias below ~60 pixels per
+    // unit seen face-on, and much sooner at grazing angles. Both 0 disables it.
+    pub ring_lod_detail_size: f32,
+    pub ring_lod_solid_size: f32,
+    // Radians per frame of the viewer's turntable mode (T)
+    pub spin_speed: f32,
+}
+
+// Row and column names of `ShaderParams::biome_colors` in the file keys
+const BIOME_ELEVATIONS: [&str; 3] = ["ocean", "lowland", "highland"];
+const BIOME_TEMPERATURES: [&str; 4] = ["polar", "cold", "temperate", "hot"];
+
+impl Default for ShaderParams {
+    fn default() -> Self {
+        ShaderParams {
+            pattern_frequency: 10.0,
+            fog_density: 0.0,
+            fog_falloff: 1.0,
+            fog_color: Color::new(200, 210, 220),
+            reflectivity: 0.6,
+            band_roughness: 0.35,
+            planet_radius: 1.0,
+            atmosphere_radius: 1.1,
+            rayleigh_coefficient: 10.0,
+            mie_coefficient: 2.0,
+            mie_anisotropy: 0.76,
+            polar_blend_latitude: 90.0,
+            polar_blend_width: 10.0,
+            lava_zoom: 1000.0,
+            cloud_zoom: 100.0,
+            cloud_threshold: 0.5,
+            index_of_refraction: 1.31,
+            biome_zoom: 150.0,
+            biome_sea_level: 0.0,
+            biome_highland_level: 0.45,
+            biome_blend: 0.3,
+            biome_colors: [
+                // Sea ice, cold sea, open ocean, tropical shallows
+                [Color::new(215, 225, 235), Color::new(30, 55, 100), Color::new(20, 65, 135), Color::new(25, 110, 150)],
+                // Ice sheet, tundra, forest, desert
+                [Color::new(240, 245, 250), Color::new(125, 120, 90), Color::new(40, 100, 35), Color::new(210, 180, 110)],
+                // Snow caps, bare rock, scrubland, red rock
+                [Color::new(250, 250, 255), Color::new(110, 105, 100), Color::new(95, 90, 65), Color::new(150, 90, 55)],
+            ],
+            ring_lod_detail_size: 300.0,
+            ring_lod_solid_size: 80.0,
+            spin_speed: 0.01,
+        }
+    }
+}
+
+// The numeric parameters, each read and written under its field name as the
+// file key. Colors have their own keys in `ShaderParams::set`.
+macro_rules! numeric_params {
+    ($($field:ident),* $(,)?) => {
+        impl ShaderParams {
+            // File keys of every numeric parameter, in declaration order
+            pub const NUMBER_KEYS: &'static [&'static str] = &[$(stringify!($field)),*];
+
+            fn number_field(&self, key: &str) -> Option<f32> {
+                match key {
+                    $(stringify!($field) => Some(self.$field),)*
+                    _ => None,
+                }
+            }
+
+            fn number_field_mut(&mut self, key: &str) -> Option<&mut f32> {
+                match key {
+                    $(stringify!($field) => Some(&mut self.$field),)*
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+numeric_params!(
+    pattern_frequency,
+    fog_density,
+    fog_falloff,
+    reflectivity,
+    band_roughness,
+    planet_radius,
+    atmosphere_radius,
+    rayleigh_coefficient,
+    mie_coefficient,
+    mie_anisotropy,
+    polar_blend_latitude,
+    polar_blend_width,
+    lava_zoom,
+    cloud_zoom,
+    cloud_threshold,
+    index_of_refraction,
+    biome_zoom,
+    biome_sea_level,
+    biome_highland_level,
+    biome_blend,
+    ring_lod_detail_size,
+    ring_lod_solid_size,
+    spin_speed,
+);
+
+impl ShaderParams {
+    // Reads a `key = value` file (the flat subset of TOML) over the defaults.
+    // Numbers are plain floats and colors are `[r, g, b]` arrays of 0-255;
+    // `#` starts a comment. Unknown keys and bad values are reported with
+    // their line number.
+    pub fn load(path: &str) -> io::Result<Self> {
+        let mut params = ShaderParams::default();
+        params.reload(path)?;
+        Ok(params)
+    }
+
+    // Re-reads `path` in place, so external edits show up without recompiling.
+    // Keys missing from the file keep their current value. On error nothing
+    // is changed.
+    pub fn reload(&mut self, path: &str) -> io::Result<()> {
+        let contents = fs::read_to_string(path)?;
+        let mut updated = self.clone();
+
+        for (line_number, line) in contents.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let invalid = |message: String| io::Error::new(
+                ErrorKind::InvalidData,
+                format!("{}:{}: {}", path, line_number + 1, message),
+            );
+
+            let (key, value) = line.split_once('=')
+                .ok_or_else(|| invalid(format!("expected `key = value`, found `{}`", line)))?;
+            updated.set(key.trim(), value.trim()).map_err(invalid)?;
+        }
+
+        *self = updated;
+        Ok(())
+    }
+
+    // Current value of a numeric parameter by its file key, e.g. for
+    // `hud::ParamHud`; `None` for colors and unknown keys
+    pub fn number(&self, key: &str) -> Option<f32> {
+        self.number_field(key)
+    }
+
+    // Sets a numeric parameter by its file key, as if read from the file
+    pub fn set_number(&mut self, key: &str, value: f32) -> Result<(), String> {
+        if self.number(key).is_none() {
+            return Err(format!("`{}` is not a numeric parameter", key));
+        }
+        self.set(key, &value.to_string())
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        if let Some(field) = self.number_field_mut(key) {
+            *field = parse_number(value)?;
+            return Ok(());
+        }
+
+        match key {
+            "fog_color" => self.fog_color = parse_color(value)?,
+            _ => match biome_cell(key) {
+                Some((elevation, temperature)) => self.biome_colors[elevation][temperature] = parse_color(value)?,
+                None => return Err(format!("unknown parameter `{}`", key)),
+            },
+        }
+        Ok(())
+    }
+}
+
+// `biome_<elevation>_<temperature>` to its `biome_colors` indices
+fn biome_cell(key: &str) -> Option<(usize, usize)> {
+    let (elevation, temperature) = key.strip_prefix("biome_")?.split_once('_')?;
+    Some((
+        BIOME_ELEVATIONS.iter().position(|&name| name == elevation)?,
+        BIOME_TEMPERATURES.iter().position(|&name| name == temperature)?,
+    ))
+}
+
+fn parse_number(value: &str) -> Result<f32, String> {
+    value.parse().map_err(|_| format!("`{}` is not a number", value))
+}
+
+fn parse_color(value: &str) -> Result<Color, String> {
+    let channels: Vec<u8> = value.strip_prefix('[')
+        .and_then(|rest| rest.strip_suffix(']'))
+        .ok_or_else(|| format!("`{}` is not a color, expected [r, g, b]", value))?
+        .split(',')
+        .map(|channel| channel.trim().parse::<u8>().map_err(|_| format!("`{}` is not a channel value in 0-255", channel.trim())))
+        .collect::<Result<_, _>>()?;
+
+    match channels[..] {
+        [r, g, b] => Ok(Color::new(r, g, b)),
+        _ => Err(format!("`{}` must have exactly three channels", value)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ASSET: &str = "assets/shader_params.toml";
+
+    #[test]
+    fn the_bundled_file_sets_every_numeric_key_to_its_default() {
+        let contents = fs::read_to_string(ASSET).unwrap();
+        let file_keys: Vec<&str> = contents.lines()
+            .filter_map(|line| line.split('#').next()?.split_once('='))
+            .map(|(key, _)| key.trim())
+            .collect();
+        for key in ShaderParams::NUMBER_KEYS {
+            assert!(file_keys.contains(key), "`{}` is missing from {}", key, ASSET);
+        }
+
+        let (loaded, defaults) = (ShaderParams::load(ASSET).unwrap(), ShaderParams::default());
+        for key in ShaderParams::NUMBER_KEYS {
+            assert_eq!(loaded.number(key), defaults.number(key), "{}", key);
+        }
+        assert_eq!(loaded.biome_colors, defaults.biome_colors);
+        assert_eq!(loaded.fog_color, defaults.fog_color);
+    }
+
+    #[test]
+    fn every_numeric_key_round_trips_through_set_number() {
+        let mut params = ShaderParams::load(ASSET).unwrap();
+        for (i, key) in ShaderParams::NUMBER_KEYS.iter().enumerate() {
+            let value = params.number(key).unwrap() + 0.25 + i as f32;
+            params.set_number(key, value).unwrap();
+            assert_eq!(params.number(key), Some(value), "{}", key);
+        }
+        // Every key writes its own field
+        for (i, key) in ShaderParams::NUMBER_KEYS.iter().enumerate() {
+            assert_eq!(params.number(key), Some(ShaderParams::default().number(key).unwrap() + 0.25 + i as f32), "{}", key);
+        }
+    }
+
+    #[test]
+    fn colors_and_unknown_keys_are_not_numbers() {
+        let mut params = ShaderParams::default();
+        for key in ["fog_color", "biome_ocean_polar", "no_such_key"] {
+            assert_eq!(params.number(key), None);
+            assert!(params.set_number(key, 1.0).is_err());
+        }
+        assert!(params.set("fog_density", "dense").is_err());
+        assert!(params.set("biome_lowland_hot", "[1, 2, 3]").is_ok());
+        assert_eq!(params.biome_colors[1][3], Color::new(1, 2, 3));
+    }
+}
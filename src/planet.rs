@@ -0,0 +1,184 @@
+use std::sync::Arc;
+use nalgebra_glm::{Mat4, Vec3};
+use crate::{Uniforms, render_mesh, create_model_matrix, create_noise};
+use crate::framebuffer::Framebuffer;
+use crate::geometry::{generate_sphere, generate_ring};
+use crate::mesh::Mesh;
+use crate::shaders::{ShaderType, bake_shader_to_texture};
+use crate::texture::Texture;
+use fastnoise_lite::FastNoiseLite;
+
+// Sphere detail used by `PlanetBuilder` unless overridden (1280 triangles)
+pub const DEFAULT_PLANET_SUBDIVISIONS: u32 = 3;
+// Extent of `PlanetBuilder::with_rings`, in planet radii
+pub const RING_INNER_RADIUS: f32 = 1.4;
+pub const RING_OUTER_RADIUS: f32 = 2.3;
+
+// A ready-to-render planet: body mesh and shader, optional ring, and how it
+// spins. Build one with `PlanetBuilder` and draw it with `render`.
+#[derive(Clone)]
+pub struct Planet {
+    pub mesh: Mesh,
+    pub shader: ShaderType,
+    pub ring: Option<Mesh>,
+    pub atmosphere: bool,
+    // Radians per unit of `Uniforms::time`, about the planet's own (tilted) axis
+    pub rotation_speed: f32,
+    // Tilt of the spin axis (and the ring plane) in radians, about Z
+    pub axial_tilt: f32,
+    pub position: Vec3,
+    pub scale: f32,
+    // Drives the noise behind the procedural colors, so two planets with the
+    // same shader still look different
+    pub noise: Arc<FastNoiseLite>,
+    // Precomputed surface (see `PlanetBuilder::with_baked_surface`), drawn with
+    // the textured shader in place of `shader`
+    pub baked_surface: Option<Texture>,
+}
+
+impl Planet {
+    // Body placement at `time`: scaled, spun about Y, then tilted and moved.
+    // The ring uses the same transform, so it stays in the equatorial plane.
+    pub fn model_matrix(&self, time: f32) -> Mat4 {
+        let tilt = create_model_matrix(self.position, self.scale, Vec3::new(0.0, 0.0, self.axial_tilt));
+        let spin = create_model_matrix(Vec3::new(0.0, 0.0, 0.0), 1.0, Vec3::new(0.0, self.rotation_speed * time, 0.0));
+        tilt * spin
+    }
+
+    // Draws the body and its ring with the camera, light and options of
+    // `uniforms`; only the model matrix, noise and atmosphere flag are replaced
+    pub fn render(&self, framebuffer: &mut Framebuffer, uniforms: &Uniforms) {
+        let mut planet_uniforms = uniforms.clone();
+        planet_uniforms.model_matrix = self.model_matrix(uniforms.time as f32);
+        planet_uniforms.noise = Arc::clone(&self.noise);
+        planet_uniforms.atmosphere = self.atmosphere;
+        planet_uniforms.prepare();
+
+        match &self.baked_surface {
+            Some(surface) => {
+                let mut baked_uniforms = planet_uniforms.clone();
+                baked_uniforms.albedo_map = Some(surface.clone());
+                baked_uniforms.spherical_uv = true;
+                render_mesh(framebuffer, &baked_uniforms, &self.mesh, ShaderType::Textured.selection());
+            }
+            None => render_mesh(framebuffer, &planet_uniforms, &self.mesh, self.shader.selection()),
+        }
+
+        if let Some(ring) = &self.ring {
+            // Both faces of the ring are visible
+            planet_uniforms.cull_backfaces = false;
+            planet_uniforms.atmosphere = false;
+            render_mesh(framebuffer, &planet_uniforms, ring, ShaderType::Ring.selection());
+        }
+    }
+}
+
+// Fluent entry point that wires the sphere generator, shaders, ring mesh,
+// atmosphere and spin together:
+// `PlanetBuilder::new(ShaderType::Gas).with_rings().with_atmosphere().build()`
+#[derive(Debug, Clone)]
+pub struct PlanetBuilder {
+    shader: ShaderType,
+    palette_seed: i32,
+    atmosphere: bool,
+    rings: bool,
+    rotation_speed: f32,
+    axial_tilt: f32,
+    position: Vec3,
+    scale: f32,
+    subdivisions: u32,
+    bake_resolution: Option<usize>,
+}
+
+impl PlanetBuilder {
+    pub fn new(shader: ShaderType) -> Self {
+        PlanetBuilder {
+            shader,
+            palette_seed: 1337,
+            atmosphere: false,
+            rings: false,
+            rotation_speed: 0.01,
+            axial_tilt: 0.0,
+            position: Vec3::new(0.0, 0.0, 0.0),
+            scale: 1.0,
+            subdivisions: DEFAULT_PLANET_SUBDIVISIONS,
+            bake_resolution: None,
+        }
+    }
+
+    // Seed of the noise that the procedural shaders turn into colors
+    pub fn with_palette_seed(mut self, seed: i32) -> Self {
+        self.palette_seed = seed;
+        self
+    }
+
+    pub fn with_atmosphere(mut self) -> Self {
+        self.atmosphere = true;
+        self
+    }
+
+    // Ring from 1.4 to 2.3 planet radii
+    pub fn with_rings(mut self) -> Self {
+        self.rings = true;
+        self
+    }
+
+    pub fn with_rotation_speed(mut self, speed: f32) -> Self {
+        self.rotation_speed = speed;
+        self
+    }
+
+    pub fn with_axial_tilt(mut self, tilt: f32) -> Self {
+        self.axial_tilt = tilt;
+        self
+    }
+
+    pub fn with_position(mut self, position: Vec3) -> Self {
+        self.position = position;
+        self
+    }
+
+    pub fn with_scale(mut self, scale: f32) -> Self {
+        self.scale = scale;
+        self
+    }
+
+    pub fn with_subdivisions(mut self, subdivisions: u32) -> Self {
+        self.subdivisions = subdivisions;
+        self
+    }
+
+    // Evaluates the shader once into a `2 * resolution` x `resolution` texture
+    // at build time and samples that every frame instead. Ignored for animated
+    // shaders (`ShaderType::is_animated`), which keep being shaded live.
+    pub fn with_baked_surface(mut self, resolution: usize) -> Self {
+        self.bake_resolution = Some(resolution);
+        self
+    }
+
+    pub fn build(self) -> Planet {
+        let mut noise = create_noise();
+        noise.set_seed(Some(self.palette_seed));
+        let noise = Arc::new(noise);
+
+        let baked_surface = self.bake_resolution.and_then(|resolution| {
+            let identity = Mat4::identity();
+            let mut uniforms = Uniforms::new(identity, identity, identity, identity, 0, create_noise());
+            uniforms.noise = Arc::clone(&noise);
+            bake_shader_to_texture(self.shader, resolution, &uniforms)
+        });
+
+        Planet {
+            mesh: generate_sphere(self.subdivisions),
+            shader: self.shader,
+            ring: self.rings.then(|| generate_ring(RING_INNER_RADIUS, RING_OUTER_RADIUS, 128)),
+            atmosphere: self.atmosphere,
+            rotation_speed: self.rotation_speed,
+            axial_tilt: self.axial_tilt,
+            position: self.position,
+            scale: self.scale,
+            noise,
+            baked_surface,
+        }
+    }
+}
@@ -0,0 +1,564 @@
+use crate::render_target::RenderTarget;
+use crate::Uniforms;
+use crate::lut::ColorLut;
+use crate::color::Color;
+use crate::math::smoothstep;
+use nalgebra_glm::{Vec2, Vec3};
+
+// Converts NDC depth in [-1, 1], what the z-buffer holds with
+// `DEFAULT_DEPTH_RANGE`, back to eye-space distance. For other ranges pass
+// `Uniforms::ndc_depth(depth)`.
+pub fn linearize_depth(depth: f32, near: f32, far: f32) -> f32 {
+    2.0 * near * far / (far + near - depth * (far - near))
+}
+
+// Screen-space ambient occlusion. Each covered pixel looks at neighbours in a
+// `radius`-pixel disk; neighbours noticeably closer to the camera occlude it.
+// `strength` scales how dark a fully occluded pixel gets (0 = no effect).
+// Runs on the color and depth produced by the main pass, whose depth range is
+// read from `uniforms`.
+pub fn ssao(target: &mut RenderTarget, uniforms: &Uniforms, near: f32, far: f32, radius: f32, strength: f32) {
+    if strength <= 0.0 || radius <= 0.0 {
+        return;
+    }
+
+    let width = target.width;
+    let height = target.height;
+    let linear_depth: Vec<f32> = target.depth.iter()
+        .map(|&depth| if depth.is_finite() { linearize_depth(uniforms.ndc_depth(depth), near, far) } else { f32::INFINITY })
+        .collect();
+
+    // Two rings of eight directions, rotated against each other
+    let mut kernel = Vec::with_capacity(16);
+    for i in 0..16 {
+        let angle = i as f32 * std::f32::consts::PI / 8.0 + if i % 2 == 0 { 0.0 } else { 0.2 };
+        let distance = if i % 2 == 0 { radius } else { radius * 0.5 };
+        kernel.push(((angle.cos() * distance) as i32, (angle.sin() * distance) as i32));
+    }
+
+    let bias = 0.02;
+    let mut occluded = target.color.clone();
+
+    for y in 0..height {
+        for x in 0..width {
+            let center = linear_depth[y * width + x];
+            if !center.is_finite() {
+                continue;
+            }
+
+            let mut occlusion = 0.0;
+            for &(dx, dy) in &kernel {
+                let sx = x as i32 + dx;
+                let sy = y as i32 + dy;
+                if sx < 0 || sy < 0 || sx >= width as i32 || sy >= height as i32 {
+                    continue;
+                }
+
+                let sample = linear_depth[sy as usize * width + sx as usize];
+                let difference = center - sample;
+                if difference > bias * center {
+                    // Fade out occluders far in front, like a silhouette against the background
+                    occlusion += 1.0 / (1.0 + difference * difference);
+                }
+            }
+
+            let ambient = 1.0 - strength * occlusion / kernel.len() as f32;
+            occluded[y * width + x] = target.get(x, y) * ambient.clamp(0.0, 1.0);
+        }
+    }
+
+    target.color = occluded;
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct CrtParams {
+    // How much darker every other scanline gets, in [0, 1]
+    pub scanline_strength: f32,
+    // Horizontal red/blue split in pixels at the screen corners
+    pub aberration: f32,
+    // Barrel distortion coefficient; 0 keeps the image flat
+    pub distortion: f32,
+}
+
+impl Default for CrtParams {
+    fn default() -> Self {
+        CrtParams {
+            scanline_strength: 0.3,
+            aberration: 2.0,
+            distortion: 0.1,
+        }
+    }
+}
+
+// Old CRT look: barrel-warped image, color fringes growing towards the edges
+// and darkened odd scanlines. Pixels warped off the tube are black.
+pub fn crt_postprocess(target: &mut RenderTarget, params: &CrtParams) {
+    let width = target.width as f32;
+    let height = target.height as f32;
+    let mut output = target.color.clone();
+
+    for y in 0..target.height {
+        for x in 0..target.width {
+            // Centered coordinates in [-1, 1]
+            let cx = (x as f32 + 0.5) / width * 2.0 - 1.0;
+            let cy = (y as f32 + 0.5) / height * 2.0 - 1.0;
+            let r2 = cx * cx + cy * cy;
+            let warp = 1.0 + params.distortion * r2;
+            let (wx, wy) = (cx * warp, cy * warp);
+
+            if wx.abs() > 1.0 || wy.abs() > 1.0 {
+                output[y * target.width + x] = Color::black();
+                continue;
+            }
+
+            let sx = (wx + 1.0) * 0.5 * width - 0.5;
+            let sy = ((wy + 1.0) * 0.5 * height - 0.5).round() as i32;
+            let shift = params.aberration * r2 * 0.5;
+
+            let red = target.get_clamped((sx - shift).round() as i32, sy).r();
+            let green = target.get_clamped(sx.round() as i32, sy).g();
+            let blue = target.get_clamped((sx + shift).round() as i32, sy).b();
+
+            let scanline = if y % 2 == 1 { 1.0 - params.scanline_strength } else { 1.0 };
+            output[y * target.width + x] = Color::new(red, green, blue) * scanline;
+        }
+    }
+
+    target.color = output;
+}
+
+// Bends the background around a black hole at `center` (pixels). Light passing
+// at distance r is deflected by roughly strength * radius^2 / r, so background
+// pixels sample from a point pulled towards the hole; inside `radius` (the
+// event horizon) everything is black. Geometry in front (finite depth) is kept.
+pub fn gravitational_lens(target: &mut RenderTarget, center: Vec2, radius: f32, strength: f32) {
+    if radius <= 0.0 {
+        return;
+    }
+
+    let source = target.color.clone();
+    let radius_squared = radius * radius;
+
+    for y in 0..target.height {
+        for x in 0..target.width {
+            let index = y * target.width + x;
+            if target.depth[index].is_finite() {
+                continue;
+            }
+
+            let offset = Vec2::new(x as f32 + 0.5, y as f32 + 0.5) - center;
+            let distance = offset.magnitude();
+            if distance < radius {
+                target.color[index] = Color::black();
+                continue;
+            }
+
+            let deflection = strength * radius_squared / distance;
+            let sample = center + offset * ((distance - deflection).max(0.0) / distance);
+            let sx = (sample.x as i32).clamp(0, target.width as i32 - 1) as usize;
+            let sy = (sample.y as i32).clamp(0, target.height as i32 - 1) as usize;
+            target.color[index] = source[sy * target.width + sx];
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ShockwaveParams {
+    // Pixels the ring's radius grows per frame
+    pub speed: f32,
+    // Thickness of the distorted ring, in pixels
+    pub width: f32,
+    // Largest displacement at the ring's crest, in pixels, at the moment of
+    // the blast; it fades linearly to zero by the time the ring leaves the screen
+    pub amplitude: f32,
+}
+
+impl Default for ShockwaveParams {
+    fn default() -> Self {
+        ShockwaveParams {
+            speed: 8.0,
+            width: 40.0,
+            amplitude: 12.0,
+        }
+    }
+}
+
+// Expanding blast ring around `center` (pixels), `time` frames after the
+// blast (e.g. `uniforms.time - start`). Pixels inside the ring sample the
+// image pushed along the radius by one sine period, compressing it at the
+// leading half and stretching it at the trailing one, like a refracting
+// pressure wave. Does nothing before the blast and once the ring is past the
+// farthest corner.
+pub fn shockwave(target: &mut RenderTarget, center: Vec2, time: f32, params: &ShockwaveParams) {
+    let half_width = params.width * 0.5;
+    if time < 0.0 || half_width <= 0.0 || params.amplitude == 0.0 {
+        return;
+    }
+
+    let (width, height) = (target.width as f32, target.height as f32);
+    let farthest = [Vec2::new(0.0, 0.0), Vec2::new(width, 0.0), Vec2::new(0.0, height), Vec2::new(width, height)]
+        .iter()
+        .map(|corner| (corner - center).magnitude())
+        .fold(0.0, f32::max);
+    let radius = params.speed * time;
+    if radius - half_width >= farthest {
+        return;
+    }
+    let amplitude = params.amplitude * (1.0 - radius / farthest.max(f32::EPSILON)).max(0.0);
+
+    let source = target.clone();
+    for y in 0..target.height {
+        for x in 0..target.width {
+            let offset = Vec2::new(x as f32 + 0.5, y as f32 + 0.5) - center;
+            let distance = offset.magnitude();
+            // Position across the ring, -1 at its inner edge to 1 at the outer
+            let across = (distance - radius) / half_width;
+            if across.abs() >= 1.0 || distance <= f32::EPSILON {
+                continue;
+            }
+
+            let displacement = amplitude * (across * std::f32::consts::PI).sin();
+            let sample = center + offset * ((distance + displacement) / distance);
+            let color = source.get_clamped((sample.x - 0.5).round() as i32, (sample.y - 0.5).round() as i32);
+            target.set(x, y, color);
+        }
+    }
+}
+
+// Smears each pixel along its velocity-buffer motion, centered on the pixel so
+// moving edges blur both ways. `strength` scales the streak relative to one
+// frame of motion (1.0 = the distance moved since the last frame, 0 = off).
+// Background pixels have zero velocity and stay sharp.
+pub fn motion_blur(target: &mut RenderTarget, strength: f32, samples: u32) {
+    if strength <= 0.0 || samples < 2 {
+        return;
+    }
+
+    let source = target.clone();
+
+    for y in 0..target.height {
+        for x in 0..target.width {
+            let streak = source.velocity_at(x, y) * strength;
+            if streak.magnitude() < 0.5 {
+                continue;
+            }
+
+            let (mut r, mut g, mut b) = (0u32, 0u32, 0u32);
+            for i in 0..samples {
+                let t = i as f32 / (samples - 1) as f32 - 0.5;
+                let sample = source.get_clamped(
+                    (x as f32 + streak.x * t).round() as i32,
+                    (y as f32 + streak.y * t).round() as i32,
+                );
+                r += sample.r() as u32;
+                g += sample.g() as u32;
+                b += sample.b() as u32;
+            }
+
+            target.set(x, y, Color::new((r / samples) as u8, (g / samples) as u8, (b / samples) as u8));
+        }
+    }
+}
+
+// Ghost discs along the flare axis: position on the axis (0 = sun, 1 = screen
+// center, 2 = mirrored across the center), radius as a fraction of the screen
+// height, and tint
+const FLARE_GHOSTS: [(f32, f32, Color); 5] = [
+    (0.5, 0.04, Color::new(255, 220, 160)),
+    (1.2, 0.07, Color::new(160, 200, 255)),
+    (1.5, 0.03, Color::new(255, 180, 220)),
+    (1.8, 0.11, Color::new(180, 255, 200)),
+    (2.1, 0.05, Color::new(255, 240, 200)),
+];
+
+// Camera glare for a bright light at `sun_screen_pos` (pixels plus the point's
+// z-buffer depth, as returned by `Uniforms::project`): tinted ghosts and a
+// streak along the line from the sun through the screen center. Nothing is
+// drawn when the sun is off screen or something closer covers its pixel.
+pub fn lens_flare(target: &mut RenderTarget, sun_screen_pos: Vec3, intensity: f32) {
+    if intensity <= 0.0 {
+        return;
+    }
+
+    let (sx, sy) = (sun_screen_pos.x.floor(), sun_screen_pos.y.floor());
+    if sx < 0.0 || sy < 0.0 || sx >= target.width as f32 || sy >= target.height as f32 {
+        return;
+    }
+    // The sun's own surface may have written this pixel; only nearer geometry occludes
+    let depth_bias = 1e-3;
+    if target.depth_at(sx as usize, sy as usize) < sun_screen_pos.z - depth_bias {
+        return;
+    }
+
+    let sun = Vec2::new(sun_screen_pos.x, sun_screen_pos.y);
+    let center = Vec2::new(target.width as f32 * 0.5, target.height as f32 * 0.5);
+    let axis = center - sun;
+    let axis_length = axis.magnitude();
+    let direction = if axis_length > f32::EPSILON { axis / axis_length } else { Vec2::new(1.0, 0.0) };
+    let size = target.height as f32;
+    let streak_width = size * 0.004;
+
+    for y in 0..target.height {
+        for x in 0..target.width {
+            let pixel = Vec2::new(x as f32 + 0.5, y as f32 + 0.5);
+            let mut glow = target.get(x, y);
+
+            for &(position, radius, tint) in &FLARE_GHOSTS {
+                let ghost = sun + axis * position;
+                let falloff = 1.0 - (pixel - ghost).magnitude() / (radius * size);
+                if falloff > 0.0 {
+                    glow = glow + tint * (falloff * falloff * 0.35 * intensity);
+                }
+            }
+
+            let offset = pixel - sun;
+            let across = offset.x * direction.y - offset.y * direction.x;
+            let along = offset.dot(&direction).abs();
+            let streak = (-(across * across) / (streak_width * streak_width)).exp() * (-along / (size * 0.5)).exp();
+            if streak > 1e-3 {
+                glow = glow + Color::new(255, 245, 230) * (streak * 0.6 * intensity);
+            }
+
+            target.set(x, y, glow);
+        }
+    }
+}
+
+// Final color grade, meant to run last (after any tone mapping), per pixel on
+// channels in [0, 1]: `brightness` is added, `contrast` scales around mid
+// gray 0.5 and `saturation` moves between the pixel's luminance gray (0) and
+// its color (1), overshooting past 1. (0, 1, 1) leaves the image unchanged.
+pub fn grade(target: &mut RenderTarget, brightness: f32, contrast: f32, saturation: f32) {
+    if brightness == 0.0 && contrast == 1.0 && saturation == 1.0 {
+        return;
+    }
+
+    for color in target.color.iter_mut() {
+        let gray = color.luminance();
+        let channel = |value: u8| {
+            let value = value as f32 / 255.0;
+            let value = gray + (value - gray) * saturation;
+            let value = (value - 0.5) * contrast + 0.5 + brightness;
+            (value.clamp(0.0, 1.0) * 255.0).round() as u8
+        };
+        *color = Color::new_rgba(channel(color.r()), channel(color.g()), channel(color.b()), color.a());
+    }
+}
+
+// Remaps every pixel through `lut`, e.g. a `.cube` look exported from a
+// grading tool. Like `grade` it belongs at the end, on display-ready colors.
+pub fn apply_lut(target: &mut RenderTarget, lut: &ColorLut) {
+    for color in target.color.iter_mut() {
+        *color = lut.apply(*color);
+    }
+}
+
+// Largest circle of confusion, in pixels, so a wide aperture stays affordable
+const MAX_BLUR_RADIUS: f32 = 12.0;
+
+// Thin-lens depth of field. A pixel at eye distance d is spread over a circle
+// of confusion of `aperture * |d - focus_dist| / d` pixels: sharp on the focus
+// plane and growing up to `aperture` towards infinity (the empty background is
+// fully defocused). Implemented as scatter-as-gather: each pixel averages the
+// neighbours within its own circle whose circles reach back to it, so sharp
+// foreground objects do not bleed into a blurred background behind them.
+// Depths are converted with `uniforms`' depth range like in `ssao`.
+pub fn depth_of_field(target: &mut RenderTarget, uniforms: &Uniforms, focus_dist: f32, aperture: f32, near: f32, far: f32) {
+    if aperture <= 0.0 {
+        return;
+    }
+
+    let (width, height) = (target.width, target.height);
+    let focus_dist = focus_dist.max(near);
+    let circle_of_confusion: Vec<f32> = target.depth.iter()
+        .map(|&depth| {
+            let blur = if depth.is_finite() {
+                let distance = linearize_depth(uniforms.ndc_depth(depth), near, far);
+                aperture * (distance - focus_dist).abs() / distance
+            } else {
+                aperture
+            };
+            blur.min(MAX_BLUR_RADIUS)
+        })
+        .collect();
+
+    let source = target.color.clone();
+    for y in 0..height {
+        for x in 0..width {
+            let radius = circle_of_confusion[y * width + x];
+            if radius < 0.5 {
+                continue;
+            }
+
+            let reach = radius.ceil() as i32;
+            let (mut r, mut g, mut b, mut weight) = (0.0, 0.0, 0.0, 0.0);
+            for dy in -reach..=reach {
+                for dx in -reach..=reach {
+                    let distance = ((dx * dx + dy * dy) as f32).sqrt();
+                    let (sx, sy) = (x as i32 + dx, y as i32 + dy);
+                    if distance > radius || sx < 0 || sy < 0 || sx >= width as i32 || sy >= height as i32 {
+                        continue;
+                    }
+
+                    let index = sy as usize * width + sx as usize;
+                    if circle_of_confusion[index] < distance {
+                        continue;
+                    }
+                    let sample = source[index];
+                    r += sample.r() as f32;
+                    g += sample.g() as f32;
+                    b += sample.b() as f32;
+                    weight += 1.0;
+                }
+            }
+
+            // The pixel itself always contributes, so weight is at least 1
+            target.set(x, y, Color::new((r / weight) as u8, (g / weight) as u8, (b / weight) as u8));
+        }
+    }
+}
+
+// Adds an emissive accumulation buffer (`Framebuffer::accumulation`, same size
+// as the target) on top of the lit scene. The HDR sum is scaled by `exposure`
+// and rolled off with 1 - e^-x, so a few stacked layers brighten smoothly
+// instead of clipping to white at the first overlap.
+pub fn composite_accumulation(target: &mut RenderTarget, accumulation: &[Vec3], exposure: f32) {
+    assert!(accumulation.len() == target.color.len(), "accumulation buffer size mismatch");
+
+    for (color, radiance) in target.color.iter_mut().zip(accumulation) {
+        if *radiance == Vec3::zeros() {
+            continue;
+        }
+        let glow = radiance.map(|channel| 1.0 - (-channel * exposure).exp()) * 255.0;
+        let add = |value: u8, glow: f32| (value as f32 + glow).round().min(255.0) as u8;
+        *color = Color::new_rgba(add(color.r(), glow.x), add(color.g(), glow.y), add(color.b(), glow.z), color.a());
+    }
+}
+
+// Encodes a target holding linear light (e.g. lit with `is_srgb` textures)
+// with `uniforms.encode_mode` for display, as the very last pass
+pub fn encode_display(target: &mut RenderTarget, uniforms: &Uniforms) {
+    let mode = uniforms.encode_mode;
+    for color in target.color.iter_mut() {
+        let alpha = color.a();
+        *color = Color::from_vec3(color.to_vec3().map(|channel| mode.encode(channel))).with_alpha(alpha);
+    }
+}
+
+// Draws a star as a camera-facing disk instead of (or over) its mesh, so it
+// stays a bright glowing point however far away it is. `sun_screen_pos` comes
+// from `Uniforms::project`; `angular_radius` is the disk's apparent radius in
+// radians (the Sun seen from Earth is about 0.0047), converted to pixels with
+// the vertical `fov` of the projection, but never under one pixel. The disk
+// fades out at its rim into a halo four times wider, colored by the star's
+// `temperature` (see `Color::from_temperature`), and is added to the image
+// wherever nothing nearer than the star was drawn.
+pub fn sun_disk(target: &mut RenderTarget, sun_screen_pos: Vec3, angular_radius: f32, fov: f32, temperature: f32, intensity: f32) {
+    if intensity <= 0.0 {
+        return;
+    }
+
+    let pixels_per_radian = target.height as f32 * 0.5 / (fov * 0.5).tan();
+    let radius = (angular_radius.tan() * pixels_per_radian).max(1.0);
+    let halo = radius * 4.0;
+    let tint = Color::from_temperature(temperature).to_vec3();
+    let sun = Vec2::new(sun_screen_pos.x, sun_screen_pos.y);
+
+    let (width, height) = (target.width, target.height);
+    let clamp = |value: f32, size: usize| (value.max(0.0) as usize).min(size);
+    for y in clamp(sun.y - halo, height)..clamp(sun.y + halo + 1.0, height) {
+        for x in clamp(sun.x - halo, width)..clamp(sun.x + halo + 1.0, width) {
+            if target.depth_at(x, y) < sun_screen_pos.z - 1e-3 {
+                continue;
+            }
+
+            let distance = (Vec2::new(x as f32 + 0.5, y as f32 + 0.5) - sun).magnitude();
+            let core = 1.0 - smoothstep(radius * 0.7, radius, distance);
+            let glow = (1.0 - distance / halo).max(0.0).powi(3) * 0.5;
+            let light = (core + glow) * intensity;
+            if light <= 0.0 {
+                continue;
+            }
+
+            let base = target.get(x, y);
+            let lit = base.to_vec3() + tint * light;
+            target.set(x, y, Color::from_vec3(lit).with_alpha(base.a()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra_glm::Mat4;
+    use crate::color::EncodeMode;
+    use crate::{create_noise, create_view_matrix, create_viewport_matrix_with_depth_range, DEFAULT_DEPTH_RANGE};
+
+    const NEAR: f32 = 0.1;
+    const FAR: f32 = 100.0;
+
+    fn uniforms(size: usize, depth_range: (f32, f32)) -> Uniforms {
+        let view = create_view_matrix(Vec3::new(0.0, 0.0, 5.0), Vec3::zeros(), Vec3::new(0.0, 1.0, 0.0));
+        let viewport = create_viewport_matrix_with_depth_range(size as f32, size as f32, depth_range);
+        let mut uniforms = Uniforms::new(Mat4::identity(), view, Mat4::identity(), viewport, 0, create_noise());
+        uniforms.camera_position = Vec3::new(0.0, 0.0, 5.0);
+        uniforms.orthographic(-2.0, 2.0, -2.0, 2.0, NEAR, FAR);
+        uniforms
+    }
+
+    // Gray target whose left half is `near_distance` from the camera, the
+    // right half `far_distance` and the top rows empty, stored in `depth_range`
+    fn stepped_target(size: usize, depth_range: (f32, f32), near_distance: f32, far_distance: f32) -> RenderTarget {
+        let stored = |distance: f32| {
+            let ndc = (FAR + NEAR - 2.0 * NEAR * FAR / distance) / (FAR - NEAR);
+            depth_range.0 + (ndc + 1.0) * 0.5 * (depth_range.1 - depth_range.0)
+        };
+        let mut target = RenderTarget::new(size, size);
+        for y in 0..size {
+            for x in 0..size {
+                target.set(x, y, Color::new(200, (x * 8) as u8, (y * 8) as u8));
+                target.depth[y * size + x] = match (y < 4, x < size / 2) {
+                    (true, _) => f32::INFINITY,
+                    (false, true) => stored(near_distance),
+                    (false, false) => stored(far_distance),
+                };
+            }
+        }
+        target
+    }
+
+    #[test]
+    fn depth_passes_read_any_depth_range() {
+        let check = |pass: &dyn Fn(&mut RenderTarget, &Uniforms)| {
+            let mut results = [(-1.0, 1.0), (0.0, 1.0), (0.25, 4.0)].map(|depth_range| {
+                let mut target = stepped_target(24, depth_range, 2.0, 6.0);
+                pass(&mut target, &uniforms(24, depth_range));
+                target.color
+            });
+            let default = std::mem::take(&mut results[0]);
+            assert_ne!(default, stepped_target(24, DEFAULT_DEPTH_RANGE, 2.0, 6.0).color, "the pass did nothing");
+            for other in &results[1..] {
+                assert_eq!(*other, default);
+            }
+        };
+        check(&|target, uniforms| ssao(target, uniforms, NEAR, FAR, 4.0, 1.0));
+        check(&|target, uniforms| depth_of_field(target, uniforms, 2.0, 6.0, NEAR, FAR));
+    }
+
+    #[test]
+    fn encode_display_uses_the_frames_display_curve() {
+        let mut uniforms = uniforms(1, DEFAULT_DEPTH_RANGE);
+        let encoded = |uniforms: &Uniforms| {
+            let mut target = RenderTarget::new(1, 1);
+            target.set(0, 0, Color::new_rgba(10, 64, 200, 90));
+            encode_display(&mut target, uniforms);
+            target.get(0, 0)
+        };
+        for mode in [EncodeMode::Srgb, EncodeMode::Gamma(2.2), EncodeMode::Gamma(1.0)] {
+            uniforms.encode_mode = mode;
+            let expected = Color::from_vec3(Color::new(10, 64, 200).to_vec3().map(|channel| mode.encode(channel))).with_alpha(90);
+            assert_eq!(encoded(&uniforms), expected, "{:?}", mode);
+        }
+        assert_eq!(encoded(&uniforms), Color::new_rgba(10, 64, 200, 90));
+    }
+}
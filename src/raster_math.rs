@@ -0,0 +1,252 @@
+use nalgebra_glm::Vec3;
+use crate::triangle::MIN_TRIANGLE_AREA;
+
+// Shared edge-function math of the rasterizer, in screen space (Y down, only
+// x and y are read). One convention throughout: the edge function of a, b and
+// a point is positive on the left of a -> b as seen on screen, so triangles
+// wound counter-clockwise on screen (and in NDC, before the viewport's Y flip)
+// have a positive area. That is the front-face orientation of
+// `Winding::CounterClockwise`.
+
+// Twice the signed area of the triangle (a, b, p): the cross product of
+// a -> p and a -> b. Zero when p is on the line through a and b.
+pub fn edge_function(a: &Vec3, b: &Vec3, p: &Vec3) -> f32 {
+    (p.x - a.x) * (b.y - a.y) - (p.y - a.y) * (b.x - a.x)
+}
+
+// Signed area of the triangle in square pixels, positive when it winds
+// counter-clockwise on screen
+pub fn triangle_area_signed(a: &Vec3, b: &Vec3, c: &Vec3) -> f32 {
+    edge_function(a, b, c) * 0.5
+}
+
+// Weights of a, b and c at `p`, summing to 1, whatever the winding. All
+// three are in [0, 1] exactly when p is inside or on an edge; a weight is 0
+// on the edge opposite its vertex and negative past it. `None` for
+// degenerate triangles (below `MIN_TRIANGLE_AREA`) and NaN positions.
+pub fn barycentric_coords(a: &Vec3, b: &Vec3, c: &Vec3, p: &Vec3) -> Option<Vec3> {
+    let area = edge_function(a, b, c);
+    if area.is_nan() || area.abs() < MIN_TRIANGLE_AREA {
+        return None;
+    }
+
+    Some(Vec3::new(edge_function(b, c, p), edge_function(c, a, p), edge_function(a, b, p)) / area)
+}
+
+// Fractional bits of `FixedPoint`: positions snap to 1/256 of a pixel
+pub const SUBPIXEL_BITS: u32 = 8;
+// One pixel in `FixedPoint` units
+pub const FIXED_ONE: i64 = 1 << SUBPIXEL_BITS;
+// One square pixel in `edge_function_fixed` units
+pub const FIXED_AREA_UNIT: f64 = (FIXED_ONE * FIXED_ONE) as f64;
+// Largest coordinate magnitude kept, in `FixedPoint` units (2^52 pixels): far
+// past anything on screen, and small enough that differences of coordinates
+// and their products never overflow
+const MAX_FIXED_COORDINATE: f32 = (1u64 << 60) as f32;
+
+// Screen position snapped to the subpixel grid, for watertight rasterization:
+// edge functions of snapped points are exact integers, so two triangles
+// sharing an edge get exactly opposite values along it and every sample on it
+// goes to one of them by the fill rule, never both or neither. The
+// rasterizer (`triangle::triangle_in_rect`) decides coverage this way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FixedPoint {
+    pub x: i64,
+    pub y: i64,
+}
+
+impl FixedPoint {
+    // Rounds to the nearest subpixel. Drop non-finite positions first: NaN
+    // becomes 0 and infinities are clamped like huge coordinates.
+    pub fn from_screen(position: &Vec3) -> Self {
+        let snap = |value: f32| (value * FIXED_ONE as f32).round().clamp(-MAX_FIXED_COORDINATE, MAX_FIXED_COORDINATE) as i64;
+        FixedPoint { x: snap(position.x), y: snap(position.y) }
+    }
+
+    // Sample point at the center of pixel (x, y) (see `triangle::PIXEL_CENTER`)
+    pub fn pixel_center(x: i32, y: i32) -> Self {
+        let half = FIXED_ONE / 2;
+        FixedPoint { x: ((x as i64) << SUBPIXEL_BITS) + half, y: ((y as i64) << SUBPIXEL_BITS) + half }
+    }
+}
+
+// `edge_function` on snapped points, in units of 1 / 2^(2 * SUBPIXEL_BITS)
+// square pixels (`FIXED_AREA_UNIT` per square pixel). Always exact: products
+// are taken in 128 bits.
+pub fn edge_function_fixed(a: FixedPoint, b: FixedPoint, p: FixedPoint) -> i128 {
+    (p.x - a.x) as i128 * (b.y - a.y) as i128 - (p.y - a.y) as i128 * (b.x - a.x) as i128
+}
+
+// Top-left fill rule for a positive-area triangle in screen space (Y down),
+// whose interior lies on the side where the edge function grows. A left edge
+// has the interior to its right (the edge goes down the screen); a top edge
+// is horizontal with the interior below. Samples exactly on an edge belong to
+// the triangle only for top and left edges.
+pub fn is_top_left(from: FixedPoint, to: FixedPoint) -> bool {
+    to.y > from.y || (to.y == from.y && to.x < from.x)
+}
+
+// Whether a sample with this edge value is on the inner side of the edge
+pub fn covers(edge_value: i128, top_left: bool) -> bool {
+    edge_value > 0 || (edge_value == 0 && top_left)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::triangle::triangle;
+    use crate::vertex::Vertex;
+    use nalgebra_glm::Vec2;
+
+    fn point(x: f32, y: f32) -> Vec3 {
+        Vec3::new(x, y, 0.0)
+    }
+
+    // Counter-clockwise on screen (Y down): down the left side, then up and right
+    fn ccw() -> (Vec3, Vec3, Vec3) {
+        (point(0.0, 0.0), point(0.0, 4.0), point(4.0, 0.0))
+    }
+
+    #[test]
+    fn signed_area_is_positive_counter_clockwise_and_negative_clockwise() {
+        let (a, b, c) = ccw();
+        assert_eq!(triangle_area_signed(&a, &b, &c), 8.0);
+        assert_eq!(triangle_area_signed(&a, &c, &b), -8.0);
+        assert_eq!(triangle_area_signed(&a, &b, &point(0.0, 9.0)), 0.0);
+    }
+
+    #[test]
+    fn edge_function_is_zero_on_the_edge_and_changes_sign_across_it() {
+        let (a, b, c) = ccw();
+        // Inside of a counter-clockwise triangle is on the positive side of every edge
+        assert!(edge_function(&a, &b, &point(1.0, 1.0)) > 0.0);
+        assert_eq!(edge_function(&a, &b, &point(0.0, 2.0)), 0.0);
+        assert!(edge_function(&a, &b, &point(-1.0, 2.0)) < 0.0);
+        assert_eq!(edge_function(&a, &b, &c), -edge_function(&b, &a, &c));
+    }
+
+    #[test]
+    fn barycentric_coords_agree_for_both_windings() {
+        let (a, b, c) = ccw();
+        let check = |p: Vec3, expected: Vec3| {
+            let forward = barycentric_coords(&a, &b, &c, &p).unwrap();
+            let reversed = barycentric_coords(&a, &c, &b, &p).unwrap();
+            assert!((forward - expected).abs().max() < 1e-6, "{:?}", forward);
+            assert!((Vec3::new(reversed.x, reversed.z, reversed.y) - expected).abs().max() < 1e-6);
+        };
+        // Inside
+        check(point(1.0, 1.0), Vec3::new(0.5, 0.25, 0.25));
+        // On the edge a-b, opposite c
+        check(point(0.0, 2.0), Vec3::new(0.5, 0.5, 0.0));
+        // At a vertex
+        check(b, Vec3::new(0.0, 1.0, 0.0));
+        // Outside, past the edge b-c
+        check(point(4.0, 4.0), Vec3::new(-1.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn barycentric_coords_reject_degenerate_triangles() {
+        let (a, b, _) = ccw();
+        assert_eq!(barycentric_coords(&a, &b, &point(0.0, 8.0), &point(1.0, 1.0)), None);
+        assert_eq!(barycentric_coords(&a, &a, &a, &a), None);
+        assert_eq!(barycentric_coords(&a, &b, &point(f32::NAN, 0.0), &a), None);
+    }
+
+    #[test]
+    fn fixed_and_float_edge_functions_agree_on_interior_points() {
+        let (a, b, c) = (point(1.25, 0.5), point(3.75, 20.125), point(17.5, 6.0));
+        let [fa, fb, fc] = [a, b, c].map(|corner| FixedPoint::from_screen(&corner));
+        assert_eq!(edge_function_fixed(fa, fb, fc) as f64 / FIXED_AREA_UNIT, edge_function(&a, &b, &c) as f64);
+
+        for y in 0..24 {
+            for x in 0..20 {
+                let p = FixedPoint::pixel_center(x, y);
+                let sample = point(x as f32 + 0.5, y as f32 + 0.5);
+                let weights = barycentric_coords(&a, &b, &c, &sample).unwrap();
+                if weights.min() <= 0.0 {
+                    continue;
+                }
+                let fixed = [(fb, fc), (fc, fa), (fa, fb)].map(|(from, to)| edge_function_fixed(from, to, p));
+                let float = [(b, c), (c, a), (a, b)].map(|(from, to)| edge_function(&from, &to, &sample));
+                for (fixed, float) in fixed.iter().zip(&float) {
+                    assert!(*fixed > 0);
+                    assert!((*fixed as f64 / FIXED_AREA_UNIT - *float as f64).abs() < 1e-3);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn huge_coordinates_do_not_overflow() {
+        let far = FixedPoint::from_screen(&point(1e30, -1e30));
+        let infinite = FixedPoint::from_screen(&point(f32::INFINITY, f32::NEG_INFINITY));
+        let origin = FixedPoint::pixel_center(0, 0);
+        // Both clamp to the same corner; products of the clamped extremes
+        // would panic in debug builds if they overflowed
+        assert_eq!(far, infinite);
+        assert_eq!(edge_function_fixed(origin, far, far), 0);
+        let opposite = FixedPoint::from_screen(&point(-1e30, 1e30));
+        assert!(edge_function_fixed(opposite, far, FixedPoint::from_screen(&point(1e30, 1e30))) != 0);
+    }
+
+    // Pixels hit by each triangle of `triangles`, counted per pixel
+    fn coverage_counts(triangles: &[[Vec3; 3]], size: usize) -> Vec<u32> {
+        let mut counts = vec![0; size * size];
+        for corners in triangles {
+            let [v1, v2, v3] = corners.map(|position| Vertex::new(position, Vec3::new(0.0, 0.0, 1.0), Vec2::new(0.0, 0.0)));
+            for fragment in triangle(&v1, &v2, &v3, 1, &Vec3::new(0.0, 0.0, 1.0), (size, size)) {
+                counts[fragment.position.y as usize * size + fragment.position.x as usize] += 1;
+            }
+        }
+        counts
+    }
+
+    #[test]
+    fn triangles_sharing_an_edge_through_pixel_centers_cover_each_pixel_once() {
+        // Square split along its diagonal, which passes through every pixel center on it
+        let triangles = [
+            [point(0.0, 0.0), point(8.0, 8.0), point(8.0, 0.0)],
+            [point(0.0, 0.0), point(0.0, 8.0), point(8.0, 8.0)],
+        ];
+        let counts = coverage_counts(&triangles, 8);
+        assert!(counts.iter().all(|&count| count == 1), "{:?}", counts);
+    }
+
+    #[test]
+    fn a_triangle_fan_has_no_gaps_or_double_hits() {
+        // Fan around an off-grid center to a ring of fractional corners, in
+        // both windings; edges cross pixel centers at many subpixel offsets
+        let center = point(16.3, 15.7);
+        let ring: Vec<Vec3> = (0..13).map(|i| {
+            let angle = i as f32 / 13.0 * std::f32::consts::TAU + 0.1;
+            center + point(angle.cos(), angle.sin()) * (11.0 + (i % 3) as f32 * 1.37)
+        }).collect();
+        let triangles: Vec<[Vec3; 3]> = (0..ring.len()).map(|i| {
+            let (from, to) = (ring[i], ring[(i + 1) % ring.len()]);
+            if i % 2 == 0 { [center, from, to] } else { [center, to, from] }
+        }).collect();
+        let counts = coverage_counts(&triangles, 32);
+
+        let snapped: Vec<FixedPoint> = ring.iter().map(FixedPoint::from_screen).collect();
+        let center = FixedPoint::from_screen(&center);
+        for y in 0..32 {
+            for x in 0..32 {
+                let sample = FixedPoint::pixel_center(x, y);
+                // Strictly inside one of the fan's triangles, or on a spoke
+                let inside = (0..snapped.len()).any(|i| {
+                    let (from, to) = (snapped[i], snapped[(i + 1) % snapped.len()]);
+                    let outer = edge_function_fixed(from, to, sample);
+                    let sides = [edge_function_fixed(center, from, sample), edge_function_fixed(to, center, sample)];
+                    outer.signum() == edge_function_fixed(from, to, center).signum()
+                        && outer != 0
+                        && sides.iter().all(|side| side.signum() * outer.signum() >= 0)
+                });
+                let count = counts[y as usize * 32 + x as usize];
+                assert!(count <= 1, "pixel ({}, {}) drawn {} times", x, y, count);
+                if inside {
+                    assert_eq!(count, 1, "gap at pixel ({}, {})", x, y);
+                }
+            }
+        }
+    }
+}
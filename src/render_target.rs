@@ -0,0 +1,109 @@
+use crate::color::Color;
+use crate::framebuffer::Framebuffer;
+use nalgebra_glm::Vec2;
+
+// Intermediate color + depth image shared between render passes. The main
+// pass produces one from the framebuffer and every post pass reads and
+// writes these instead of passing raw buffers around.
+#[derive(Clone, Debug)]
+pub struct RenderTarget {
+    pub width: usize,
+    pub height: usize,
+    // Straight alpha, fully opaque when read from the framebuffer. Passes that
+    // layer translucent colors premultiply them before `Color::over`.
+    pub color: Vec<Color>,
+    // Same convention as the framebuffer z-buffer: NDC depth, infinity where empty
+    pub depth: Vec<f32>,
+    // Per-pixel screen-space motion since the previous frame, in pixels
+    pub velocity: Vec<Vec2>,
+}
+
+impl RenderTarget {
+    pub fn new(width: usize, height: usize) -> Self {
+        RenderTarget {
+            width,
+            height,
+            color: vec![Color::black(); width * height],
+            depth: vec![f32::INFINITY; width * height],
+            velocity: vec![Vec2::new(0.0, 0.0); width * height],
+        }
+    }
+
+    pub fn from_framebuffer(framebuffer: &Framebuffer) -> Self {
+        RenderTarget {
+            width: framebuffer.width,
+            height: framebuffer.height,
+            color: framebuffer.buffer.iter().map(|&pixel| Color::from_hex(pixel)).collect(),
+            depth: framebuffer.zbuffer.clone(),
+            velocity: framebuffer.velocity_buffer.clone(),
+        }
+    }
+
+    // Copies color and depth back so the result can be presented
+    pub fn write_to_framebuffer(&self, framebuffer: &mut Framebuffer) {
+        assert!(self.width == framebuffer.width && self.height == framebuffer.height, "render target size mismatch");
+        for (pixel, color) in framebuffer.buffer.iter_mut().zip(&self.color) {
+            *pixel = color.to_hex();
+        }
+        framebuffer.zbuffer.copy_from_slice(&self.depth);
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> Color {
+        self.color[y * self.width + x]
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, color: Color) {
+        self.color[y * self.width + x] = color;
+    }
+
+    // Color at integer coordinates clamped to the image, for blur-style kernels
+    pub fn get_clamped(&self, x: i32, y: i32) -> Color {
+        let x = x.clamp(0, self.width as i32 - 1) as usize;
+        let y = y.clamp(0, self.height as i32 - 1) as usize;
+        self.get(x, y)
+    }
+
+    pub fn depth_at(&self, x: usize, y: usize) -> f32 {
+        self.depth[y * self.width + x]
+    }
+
+    pub fn velocity_at(&self, x: usize, y: usize) -> Vec2 {
+        self.velocity[y * self.width + x]
+    }
+
+    // Scaled copy of the image, e.g. to present a frame rendered at a lower
+    // internal resolution. Colors are filtered bilinearly between pixel
+    // centers (clamping at the borders); depth and velocity, which must not be
+    // blended across silhouettes, take the nearest pixel. Velocities are
+    // rescaled to the new pixel size.
+    pub fn resized(&self, width: usize, height: usize) -> RenderTarget {
+        let mut resized = RenderTarget::new(width, height);
+        if self.width == 0 || self.height == 0 {
+            return resized;
+        }
+
+        let (scale_x, scale_y) = (self.width as f32 / width as f32, self.height as f32 / height as f32);
+        for y in 0..height {
+            for x in 0..width {
+                let source_x = (x as f32 + 0.5) * scale_x - 0.5;
+                let source_y = (y as f32 + 0.5) * scale_y - 0.5;
+                let (x0, y0) = (source_x.floor(), source_y.floor());
+                let (tx, ty) = (source_x - x0, source_y - y0);
+                let (x0, y0) = (x0 as i32, y0 as i32);
+
+                let top = self.get_clamped(x0, y0).lerp(&self.get_clamped(x0 + 1, y0), tx);
+                let bottom = self.get_clamped(x0, y0 + 1).lerp(&self.get_clamped(x0 + 1, y0 + 1), tx);
+                resized.set(x, y, top.lerp(&bottom, ty));
+
+                let nearest_x = (source_x.round() as i32).clamp(0, self.width as i32 - 1) as usize;
+                let nearest_y = (source_y.round() as i32).clamp(0, self.height as i32 - 1) as usize;
+                let index = y * width + x;
+                resized.depth[index] = self.depth_at(nearest_x, nearest_y);
+                let velocity = self.velocity_at(nearest_x, nearest_y);
+                resized.velocity[index] = Vec2::new(velocity.x / scale_x, velocity.y / scale_y);
+            }
+        }
+
+        resized
+    }
+}
@@ -4,18 +4,32 @@ use crate::vertex::Vertex;
 use crate::Uniforms;
 use crate::fragment::Fragment;
 use crate::color::Color;
+use crate::texture::{Texture, parallax_uv, spherical_uv};
+use crate::params::ShaderParams;
+use crate::math::{smooth_min, smoothstep, pulse, unit_or, to_lat_lon, from_lat_lon};
+use crate::displacement::displace;
+use crate::planet::{RING_INNER_RADIUS, RING_OUTER_RADIUS};
+use std::f32::consts::{PI, FRAC_PI_2};
 
 pub fn vertex_shader(vertex: &Vertex, uniforms: &Uniforms) -> Vertex {
+    let (model_position, model_normal) = match &uniforms.displacement {
+        Some(source) => displace(&vertex.position, &vertex.normal, source, &uniforms.noise),
+        None => (vertex.position, vertex.normal),
+    };
+
     let position = Vec4::new(
-        vertex.position.x,
-        vertex.position.y,
-        vertex.position.z,
+        model_position.x,
+        model_position.y,
+        model_position.z,
         1.0
     );
 
-    let transformed = uniforms.projection_matrix * uniforms.view_matrix * uniforms.model_matrix * position;
+    let world_position = uniforms.model_matrix * position;
+    let transformed = uniforms.projection_matrix * uniforms.view_matrix * world_position;
 
-    let w = transformed.w;
+    // 1 under an orthographic projection; a vertex exactly on the eye plane
+    // (w = 0) is left undivided instead of sent to infinity
+    let w = if transformed.w.abs() > f32::EPSILON { transformed.w } else { 1.0 };
     let transformed_position = Vec4::new(
         transformed.x / w,
         transformed.y / w,
@@ -28,42 +42,474 @@ pub fn vertex_shader(vertex: &Vertex, uniforms: &Uniforms) -> Vertex {
     let model_mat3 = mat4_to_mat3(&uniforms.model_matrix);
     let normal_matrix = model_mat3.transpose().try_inverse().unwrap_or(Mat3::identity());
 
-    let transformed_normal = normal_matrix * vertex.normal;
+    let transformed_normal = normal_matrix * model_normal;
+
+    // Where the same model-space point landed last frame
+    let velocity = match uniforms.prev_mvp {
+        Some(prev_mvp) => {
+            let previous = prev_mvp * position;
+            let previous_w = if previous.w.abs() > f32::EPSILON { previous.w } else { 1.0 };
+            let previous_screen = uniforms.viewport_matrix * Vec4::new(
+                previous.x / previous_w,
+                previous.y / previous_w,
+                previous.z / previous_w,
+                1.0
+            );
+            Vec2::new(screen_position.x - previous_screen.x, screen_position.y - previous_screen.y)
+        }
+        None => Vec2::new(0.0, 0.0),
+    };
 
     Vertex {
-        position: vertex.position,
-        normal: vertex.normal,
+        position: model_position,
+        normal: model_normal,
         tex_coords: vertex.tex_coords,
         color: vertex.color,
         transformed_position: Vec3::new(screen_position.x, screen_position.y, screen_position.z),
-        transformed_normal: transformed_normal
+        transformed_normal,
+        world_position: Vec3::new(world_position.x, world_position.y, world_position.z),
+        inv_w: 1.0 / w,
+        velocity,
+        flat_id: vertex.flat_id,
+        interpolation: vertex.interpolation,
     }
 }
 
+// The built-in fragment shaders. The numbering is the `shader_selection` the
+// render functions take (the viewer's number pad picks 0-7).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ShaderType {
+  Lava,
+  Ice,
+  Cloud,
+  // Banded gas giant ("jupiter")
+  Gas,
+  Iridescent,
+  Pattern,
+  Reflective,
+  Metaball,
+  Textured,
+  Atmosphere,
+  Ring,
+  Refractive,
+  Combined,
+  Biome,
+}
+
+impl ShaderType {
+  pub const ALL: [ShaderType; 14] = [
+    ShaderType::Lava, ShaderType::Ice, ShaderType::Cloud, ShaderType::Gas,
+    ShaderType::Iridescent, ShaderType::Pattern, ShaderType::Reflective, ShaderType::Metaball,
+    ShaderType::Textured, ShaderType::Atmosphere, ShaderType::Ring, ShaderType::Refractive,
+    ShaderType::Combined, ShaderType::Biome,
+  ];
+
+  // Unknown selections have always fallen back to lava
+  pub fn from_selection(shader_selection: u32) -> ShaderType {
+    ShaderType::ALL.get(shader_selection as usize).copied().unwrap_or(ShaderType::Lava)
+  }
+
+  pub fn selection(self) -> u32 {
+    ShaderType::ALL.iter().position(|&shader| shader == self).unwrap_or(0) as u32
+  }
+
+  // Name understood by `fragment_shader`
+  pub fn name(self) -> &'static str {
+    match self {
+      ShaderType::Lava => "lava",
+      ShaderType::Ice => "ice",
+      ShaderType::Cloud => "cloud",
+      ShaderType::Gas => "jupiter",
+      ShaderType::Iridescent => "iridescent",
+      ShaderType::Pattern => "pattern",
+      ShaderType::Reflective => "reflective",
+      ShaderType::Metaball => "metaball",
+      ShaderType::Textured => "textured",
+      ShaderType::Atmosphere => "atmosphere",
+      ShaderType::Ring => "ring",
+      ShaderType::Refractive => "refractive",
+      ShaderType::Combined => "combined",
+      ShaderType::Biome => "biome",
+    }
+  }
+
+  // Whether the shader reads `Uniforms::time`, so its output changes from frame
+  // to frame even for a still surface and cannot be baked
+  pub fn is_animated(self) -> bool {
+    matches!(
+      self,
+      ShaderType::Lava | ShaderType::Ice | ShaderType::Cloud | ShaderType::Iridescent
+        | ShaderType::Metaball | ShaderType::Combined
+    )
+  }
+}
+
+// Evaluates a static shader once over a unit sphere into an equirectangular
+// texture (2 * resolution by resolution texels, addressed like `spherical_uv`).
+// Render with `albedo_map` set to it, `spherical_uv` on and the textured shader
+// to skip the noise every frame. The bake is unlit (intensity 1) and without
+// fog or sky glow, which the textured pass adds back, but view-dependent
+// terms (the gas sheen, reflections) are frozen as seen from
+// `uniforms.camera_position`. Animated shaders return `None`.
+pub fn bake_shader_to_texture(shader: ShaderType, resolution: usize, uniforms: &Uniforms) -> Option<Texture> {
+  if shader.is_animated() {
+    return None;
+  }
+
+  let mut bake_uniforms = uniforms.clone();
+  bake_uniforms.atmosphere = false;
+  bake_uniforms.params.fog_density = 0.0;
+
+  Some(Texture::from_fn(resolution * 2, resolution, |uv| {
+    // Inverse of `spherical_uv`
+    let position = from_lat_lon((0.5 - uv.y) * PI, (uv.x - 0.5) * 2.0 * PI);
+
+    let mut fragment = Fragment::new(0.0, 0.0, Color::black(), 0.0, position, 1.0, position);
+    fragment.tex_coords = uv;
+    fragment_shader(&fragment, &bake_uniforms, shader.name())
+  }))
+}
+
+// Color of a single fragment under `shader`, with no rasterization or
+// framebuffer involved, e.g. `shade_fragment(&Fragment::at(position, 0.0, 1.0),
+// &uniforms, ShaderType::Lava)` to check a shader at a known point and time.
+// The pipeline's own steps (shadows, alpha, tone mapping) are not applied.
+pub fn shade_fragment(fragment: &Fragment, uniforms: &Uniforms, shader: ShaderType) -> Color {
+  fragment_shader(fragment, uniforms, shader.name())
+}
+
+// `shade_fragment` before rounding to 8 bits (see `fragment_radiance`)
+pub fn shade_fragment_radiance(fragment: &Fragment, uniforms: &Uniforms, shader: ShaderType) -> Vec4 {
+  fragment_radiance(fragment, uniforms, shader.name())
+}
+
+// Two built-in shaders combined by any blend, e.g. lava seen through clouds:
+// `blend_shaders(ShaderType::Lava, ShaderType::Cloud, |lava, clouds| lava.blend_screen(&clouds), fragment, uniforms)`.
+// `combined_shader` is the fixed version of this. Both shaders run in full
+// for every fragment, so it costs as much as the two of them together.
+pub fn blend_shaders(a: ShaderType, b: ShaderType, blend: fn(Color, Color) -> Color, fragment: &Fragment, uniforms: &Uniforms) -> Color {
+  blend(shade_fragment(fragment, uniforms, a), shade_fragment(fragment, uniforms, b))
+}
+
+// Shades one fragment with the shader named `shader_type`. Every built-in
+// shader must stay panic-free and NaN-free for any finite fragment and
+// uniforms, including zero and degenerate values and magnitudes up to about
+// 1e30 (past that products overflow f32 itself): normalize with
+// `unit_or` and a fallback, guard divisions and clamp parameters that have a
+// valid range instead of trusting them.
 pub fn fragment_shader(fragment: &Fragment, uniforms: &Uniforms, shader_type: &str) -> Color {
-  match shader_type {
-      "cloud" => cloud_shader(fragment, uniforms),
-      "lava" => lava_shader(fragment, uniforms),
-      "ice" => ice_shader(fragment, uniforms),
-      "jupiter" => jupiter_shader(fragment, uniforms),
-      _ => combined_shader(fragment, uniforms), // Default shader
+  Color::from_vec4(fragment_radiance(fragment, uniforms, shader_type))
+}
+
+// What `fragment_shader` rounds to 8 bits: the linear color in xyz, which
+// may go past 1 where lighting adds up, and alpha in w. The pipeline keeps it
+// in float through exposure and tone mapping.
+pub fn fragment_radiance(fragment: &Fragment, uniforms: &Uniforms, shader_type: &str) -> Vec4 {
+  let opaque = |color: Vec3| color.push(1.0);
+  let color = match shader_type {
+      "cloud" => opaque(cloud_shader(fragment, uniforms)),
+      "lava" => opaque(lava_shader(fragment, uniforms)),
+      "ice" => opaque(ice_shader(fragment, uniforms)),
+      "jupiter" => opaque(jupiter_shader(fragment, uniforms)),
+      "iridescent" => opaque(iridescent_shader(fragment, uniforms)),
+      "textured" => textured_shader(fragment, uniforms),
+      "reflective" => opaque(reflective_shader(fragment, uniforms)),
+      "metaball" => opaque(metaball_shader(fragment, uniforms)),
+      "atmosphere" => opaque(atmosphere_shader(fragment, uniforms)),
+      "ring" => opaque(ring_shader(fragment, uniforms)),
+      "refractive" => opaque(refractive_shader(fragment, uniforms)),
+      "biome" => opaque(biome_shader(fragment, uniforms)),
+      "pattern" => opaque(diffuse(static_pattern_shader(fragment, uniforms), fragment, uniforms)),
+      _ => opaque(combined_shader(fragment, uniforms)), // Default shader
+  };
+
+  // Sky glow over any sphere shader; the atmosphere shader already has its own
+  let (mut rgb, alpha) = (color.xyz(), color.w);
+  if uniforms.atmosphere && shader_type != "atmosphere" && shader_type != "ring" {
+    rgb += atmosphere_scatter(&planet_surface(fragment, &uniforms.params), &(fragment.world_position - uniforms.camera_position), &uniforms.light_dir, &uniforms.params);
   }
+
+  height_fog(rgb, &fragment.world_position, &uniforms.camera_position, &uniforms.params).push(alpha)
 }
 
-fn static_pattern_shader(fragment: &Fragment) -> Color {
-    let x = fragment.vertex_position.x;
-    let y = fragment.vertex_position.y;
-  
-    let pattern = ((x * 10.0).sin() * (y * 10.0).sin()).abs();
-  
-    let r = (pattern * 255.0) as u8;
-    let g = ((1.0 - pattern) * 255.0) as u8;
-    let b = 128;
+// Noise coordinates are wrapped into +-NOISE_COORDINATE_RANGE. Far beyond it
+// FastNoiseLite overflows its lattice indices (a panic in debug builds) or
+// returns NaN, and f32 has no precision left for detail there anyway.
+const NOISE_COORDINATE_RANGE: f32 = 1.0e9;
+
+// `uniforms.noise` lookups for the shaders, safe for any coordinates;
+// anything non-finite reads as 0, the noise's mean
+fn noise_3d(uniforms: &Uniforms, x: f32, y: f32, z: f32) -> f32 {
+  let wrap = |coordinate: f32| coordinate % NOISE_COORDINATE_RANGE;
+  let value = uniforms.noise.get_noise_3d(wrap(x), wrap(y), wrap(z));
+  if value.is_finite() { value } else { 0.0 }
+}
+
+fn noise_2d(uniforms: &Uniforms, x: f32, y: f32) -> f32 {
+  let wrap = |coordinate: f32| coordinate % NOISE_COORDINATE_RANGE;
+  let value = uniforms.noise.get_noise_2d(wrap(x), wrap(y));
+  if value.is_finite() { value } else { 0.0 }
+}
+
+// Direct light (`fragment.intensity`) plus, when `Uniforms::ambient_sh` is
+// set, the sky light reaching a Lambertian surface facing the normal, so the
+// night side reads as sky-lit instead of black
+fn diffuse(albedo: Color, fragment: &Fragment, uniforms: &Uniforms) -> Vec3 {
+  let direct = albedo.to_vec3() * fragment.intensity;
+  match &uniforms.ambient_sh {
+    Some(coefficients) => {
+      let ambient = sh_irradiance(unit_or(fragment.normal, Vec3::new(0.0, 1.0, 0.0)), coefficients) / PI;
+      direct + albedo.to_vec3().component_mul(&ambient)
+    }
+    None => direct,
+  }
+}
+
+// Irradiance arriving at a surface with unit `normal` from an environment
+// given by its first nine real spherical-harmonic coefficients, ordered
+// L00, L1-1, L10, L11, L2-2, L2-1, L20, L21, L22 over the basis (y, z, x for
+// band 1) of Ramamoorthi and Hanrahan's "An Efficient Representation for
+// Irradiance Environment Maps". Divide by pi for the outgoing radiance of a
+// white diffuse surface.
+pub fn sh_irradiance(normal: Vec3, coeffs: &[Vec3; 9]) -> Vec3 {
+  let (c1, c2, c3, c4, c5) = (0.429_043, 0.511_664, 0.743_125, 0.886_227, 0.247_708);
+  let (x, y, z) = (normal.x, normal.y, normal.z);
+
+  coeffs[8] * (c1 * (x * x - y * y))
+    + coeffs[6] * (c3 * z * z)
+    + coeffs[0] * c4
+    - coeffs[6] * c5
+    + (coeffs[4] * (x * y) + coeffs[7] * (x * z) + coeffs[5] * (y * z)) * (2.0 * c1)
+    + (coeffs[3] * x + coeffs[1] * y + coeffs[2] * z) * (2.0 * c2)
+}
+
+// Coefficients for `sh_irradiance` of a dim blue sky above +Y over a dark
+// green-brown ground, blending linearly across the horizon: a soft default
+// for `Uniforms::ambient_sh`
+pub fn sky_ambient_sh() -> [Vec3; 9] {
+  let sky = Vec3::new(0.10, 0.14, 0.22);
+  let ground = Vec3::new(0.05, 0.06, 0.03);
+  // Projections of a constant (sqrt(4 pi) Y00) and of the y gradient
+  // (sqrt(4 pi / 3) Y1-1) onto the basis
+  let mean = (sky + ground) * 0.5 * (4.0 * PI).sqrt();
+  let gradient = (sky - ground) * 0.5 * (4.0 * PI / 3.0).sqrt();
+
+  let mut coeffs = [Vec3::zeros(); 9];
+  coeffs[0] = mean;
+  coeffs[1] = gradient;
+  coeffs
+}
+
+// Fog whose density decays exponentially with height, d(h) = density * e^(-falloff * h),
+// integrated in closed form along the segment from the camera to the fragment.
+pub fn height_fog(color: Vec3, world_pos: &Vec3, camera_pos: &Vec3, params: &ShaderParams) -> Vec3 {
+  if params.fog_density <= 0.0 {
+    return color;
+  }
+
+  let distance = (world_pos - camera_pos).magnitude();
+  let height_delta = world_pos.y - camera_pos.y;
+  let falloff = params.fog_falloff.max(1e-4);
+
+  // Average density along the ray; the ratio tends to 1 for horizontal rays
+  let slope = falloff * height_delta;
+  let ratio = if slope.abs() > 1e-4 { (1.0 - (-slope).exp()) / slope } else { 1.0 };
+  // inf * 0 at extreme heights gives NaN, which `max` turns into clear air
+  let optical_depth = (params.fog_density * (-falloff * camera_pos.y).exp() * ratio * distance).max(0.0);
+
+  let fog_amount = 1.0 - (-optical_depth).exp();
+  color.lerp(&params.fog_color.to_vec3(), fog_amount)
+}
+
+// Relative Rayleigh extinction of red, green and blue light (~ wavelength^-4 for 680/550/440 nm)
+const RAYLEIGH_WAVELENGTH_RATIO: [f32; 3] = [0.175, 0.408, 1.0];
+const SCATTER_VIEW_SAMPLES: u32 = 8;
+const SCATTER_LIGHT_SAMPLES: u32 = 4;
+const SUN_RADIANCE: f32 = 20.0;
+
+// Light the atmosphere scatters towards the eye along a view ray, Nishita-style:
+// the ray is marched through the shell between `planet_radius` and
+// `atmosphere_radius` and, at each sample, sunlight attenuated on its way in
+// (and on its way out to the eye) is scattered by air molecules (Rayleigh,
+// blue sky) and haze (Mie, glow around the sun). Positions are relative to the
+// planet center; `view_dir` points away from the eye and `world_pos` is any
+// point on the ray, usually the shaded fragment.
+pub fn atmosphere_scatter(world_pos: &Vec3, view_dir: &Vec3, sun_dir: &Vec3, params: &ShaderParams) -> Vec3 {
+  let view_dir = unit_or(*view_dir, Vec3::new(0.0, 0.0, -1.0));
+  let sun_dir = unit_or(*sun_dir, Vec3::new(0.0, 0.0, 1.0));
+  let planet_radius = params.planet_radius.max(f32::EPSILON);
+  let thickness = (params.atmosphere_radius - planet_radius).max(f32::EPSILON);
+
+  let (entry, exit) = match ray_sphere(world_pos, &view_dir, params.atmosphere_radius) {
+    Some(span) => span,
+    None => return Vec3::zeros(),
+  };
+  // Stop at the ground when the ray hits the planet
+  let exit = match ray_sphere(world_pos, &view_dir, planet_radius) {
+    Some((ground, _)) if ground > entry => exit.min(ground),
+    _ => exit,
+  };
+  if exit <= entry {
+    return Vec3::zeros();
+  }
+
+  // Scale heights as a share of the shell, so thin and thick atmospheres look alike
+  let (rayleigh_height, mie_height) = (thickness * 0.25, thickness * 0.1);
+  // Negative extinction would amplify light exponentially along the ray
+  let beta_rayleigh = Vec3::from(RAYLEIGH_WAVELENGTH_RATIO) * params.rayleigh_coefficient.max(0.0);
+  let beta_mie = Vec3::new(1.0, 1.0, 1.0) * params.mie_coefficient.max(0.0);
+  let densities = |point: &Vec3| {
+    let height = (point.magnitude() - planet_radius).max(0.0);
+    ((-height / rayleigh_height).exp(), (-height / mie_height).exp())
+  };
+
+  let step = (exit - entry) / SCATTER_VIEW_SAMPLES as f32;
+  let (mut view_rayleigh, mut view_mie) = (0.0, 0.0);
+  let mut sum_rayleigh = Vec3::new(0.0, 0.0, 0.0);
+  let mut sum_mie = Vec3::new(0.0, 0.0, 0.0);
+
+  for i in 0..SCATTER_VIEW_SAMPLES {
+    let sample = world_pos + view_dir * (entry + (i as f32 + 0.5) * step);
+    let (density_rayleigh, density_mie) = densities(&sample);
+    view_rayleigh += density_rayleigh * step;
+    view_mie += density_mie * step;
+
+    // Samples in the planet's shadow receive no sunlight
+    if ray_sphere(&sample, &sun_dir, planet_radius).is_some_and(|(ground, _)| ground > 0.0) {
+      continue;
+    }
+    let (_, light_exit) = match ray_sphere(&sample, &sun_dir, params.atmosphere_radius) {
+      Some(span) => span,
+      None => continue,
+    };
+    let light_step = light_exit.max(0.0) / SCATTER_LIGHT_SAMPLES as f32;
+    let (mut light_rayleigh, mut light_mie) = (0.0, 0.0);
+    for j in 0..SCATTER_LIGHT_SAMPLES {
+      let (r, m) = densities(&(sample + sun_dir * ((j as f32 + 0.5) * light_step)));
+      light_rayleigh += r * light_step;
+      light_mie += m * light_step;
+    }
+
+    // Mie extinction is slightly larger than its scattering (some light is absorbed)
+    let optical_depth = beta_rayleigh * (view_rayleigh + light_rayleigh) + beta_mie * 1.1 * (view_mie + light_mie);
+    let attenuation = optical_depth.map(|tau| (-tau).exp());
+    sum_rayleigh += attenuation * density_rayleigh * step;
+    sum_mie += attenuation * density_mie * step;
+  }
+
+  let mu = view_dir.dot(&sun_dir);
+  let phase_rayleigh = 3.0 / (16.0 * PI) * (1.0 + mu * mu);
+  let g = params.mie_anisotropy.clamp(0.0, 0.99);
+  let phase_mie = 3.0 / (8.0 * PI) * ((1.0 - g * g) * (1.0 + mu * mu))
+    / ((2.0 + g * g) * (1.0 + g * g - 2.0 * g * mu).powf(1.5));
+
+  let radiance = (sum_rayleigh.component_mul(&beta_rayleigh) * phase_rayleigh
+    + sum_mie.component_mul(&beta_mie) * phase_mie) * SUN_RADIANCE;
+  // Exponential tone mapping keeps the bright glow near the sun from clipping
+  radiance.map(|channel| 1.0 - (-channel).exp())
+}
+
+// Distances along the ray to where it enters and leaves a sphere at the origin
+fn ray_sphere(origin: &Vec3, direction: &Vec3, radius: f32) -> Option<(f32, f32)> {
+  let b = origin.dot(direction);
+  let c = origin.dot(origin) - radius * radius;
+  let discriminant = b * b - c;
+  // NaN when the terms overflow for huge distances, treated as a miss
+  if discriminant.is_nan() || discriminant < 0.0 {
+    return None;
+  }
+
+  let root = discriminant.sqrt();
+  Some((-b - root, -b + root))
+}
+
+// Planet seen through its atmosphere. Assumes the model is neither rotated nor
+// scaled, so model-space positions and world-space directions line up.
+fn atmosphere_shader(fragment: &Fragment, uniforms: &Uniforms) -> Vec3 {
+  let params = &uniforms.params;
+  let view_dir = fragment.world_position - uniforms.camera_position;
+
+  let ground = diffuse(Color::new(40, 60, 30), fragment, uniforms);
+  ground + atmosphere_scatter(&planet_surface(fragment, params), &view_dir, &uniforms.light_dir, params)
+}
+
+// The fragment's model-space direction pushed onto the planet's surface
+fn planet_surface(fragment: &Fragment, params: &ShaderParams) -> Vec3 {
+  unit_or(fragment.vertex_position, Vec3::new(0.0, 1.0, 0.0)) * params.planet_radius
+}
+
+// Concentric dusty bands for a flat ring in the model's XZ plane (see
+// `generate_ring`). Gaps come from noise along the radius only, so the bands
+// stay circular. Lit from both sides, since thin rings scatter light through.
+// Small on screen it fades to the bands' average (`ShaderParams::ring_lod_*`).
+fn ring_shader(fragment: &Fragment, uniforms: &Uniforms) -> Vec3 {
+  let params = &uniforms.params;
+  let radius = fragment.vertex_position.x.hypot(fragment.vertex_position.z);
+  // 1 draws every band, 0 only their average
+  let detail = smoothstep(params.ring_lod_solid_size, params.ring_lod_detail_size, uniforms.pixels_per_unit());
+  let albedo = if detail >= 1.0 {
+    ring_albedo(uniforms, radius)
+  } else if detail <= 0.0 {
+    uniforms.ring_average_albedo
+  } else {
+    uniforms.ring_average_albedo.lerp(&ring_albedo(uniforms, radius), detail)
+  };
+
+  let normal = unit_or(fragment.normal, Vec3::new(0.0, 1.0, 0.0));
+  let light = 0.35 + 0.65 * normal.dot(&unit_or(uniforms.light_dir, normal)).abs();
+  albedo.to_vec3() * light
+}
+
+// Unlit color of the ring at `radius`, dimmed where the dust thins out
+fn ring_albedo(uniforms: &Uniforms, radius: f32) -> Color {
+  let bands = noise_2d(uniforms, radius * 400.0, 0.0) * 0.5 + 0.5;
+  let fine = (radius * 180.0).sin() * 0.5 + 0.5;
+  let density = (bands * 0.8 + fine * 0.2).clamp(0.0, 1.0);
+
+  let dust = Color::new(110, 95, 75).lerp(&Color::new(225, 205, 170), density);
+  dust * density.sqrt()
+}
+
+// Samples across the `PlanetBuilder` ring for its flat LOD color
+const RING_LOD_SAMPLES: u32 = 64;
+
+// `ring_albedo` averaged over the area of a `PlanetBuilder` ring: each sample
+// radius is weighted by its circumference, so the wide outer bands count for
+// more than the inner ones. `Uniforms::prepare` keeps the result in
+// `Uniforms::ring_average_albedo`.
+pub fn ring_average_albedo(uniforms: &Uniforms) -> Color {
+  let (sum, weight) = (0..RING_LOD_SAMPLES).fold((Vec3::zeros(), 0.0), |(sum, weight), i| {
+    let t = (i as f32 + 0.5) / RING_LOD_SAMPLES as f32;
+    let radius = RING_INNER_RADIUS + (RING_OUTER_RADIUS - RING_INNER_RADIUS) * t;
+    (sum + ring_albedo(uniforms, radius).to_vec3() * radius, weight + radius)
+  });
+  Color::from_vec3(sum / weight)
+}
+
+// Uses model-space coordinates, so the pattern stays attached to the surface
+// as the model rotates instead of sliding across it. Each face is patterned in
+// the model plane it faces most (triplanar), so sides facing x or y aren't
+// smeared out by a pattern that only varies in x and y.
+fn static_pattern_shader(fragment: &Fragment, uniforms: &Uniforms) -> Color {
+    let position = fragment.vertex_position;
+    let frequency = uniforms.params.pattern_frequency;
+    let planar = |u: f32, v: f32| ((u * frequency).sin() * (v * frequency).sin()).abs();
+
+    // Back to model space; the transpose undoes the rotation and a uniform scale
+    // only changes the length, which the weights divide out
+    let model_normal = mat4_to_mat3(&uniforms.model_matrix).transpose() * fragment.normal;
+    let weights = model_normal.abs();
+    let total = weights.x + weights.y + weights.z;
+    let weights = if total > 0.0 && total.is_finite() { weights / total } else { Vec3::new(0.0, 0.0, 1.0) };
+
+    let pattern = weights.z * planar(position.x, position.y)
+        + weights.x * planar(position.y, position.z)
+        + weights.y * planar(position.x, position.z);
   
-    Color::new(r, g, b)
+    Color::from_vec3(Vec3::new(pattern, 1.0 - pattern, 128.0 / 255.0))
 }
 
-fn lava_shader(fragment: &Fragment, uniforms: &Uniforms) -> Color {
+fn lava_shader(fragment: &Fragment, uniforms: &Uniforms) -> Vec3 {
   // Base colors for the lava effect
   let bright_color = Color::new(255, 240, 0); // Bright orange (lava-like)
   let dark_color = Color::new(130, 20, 0);   // Darker red-orange  
@@ -81,16 +527,16 @@ fn lava_shader(fragment: &Fragment, uniforms: &Uniforms) -> Color {
   let t = uniforms.time as f32 * 0.01;
 
   // Pulsate on the z-axis to change spot size
-  let pulsate = (t * base_frequency).sin() * pulsate_amplitude;
+  let pulsate = pulse(t, base_frequency, pulsate_amplitude, 0.0);
 
   // Apply noise to coordinates with subtle pulsating on z-axis
-  let zoom = 1000.0; // Constant zoom factor
-  let noise_value1 = uniforms.noise.get_noise_3d(
+  let zoom = uniforms.params.lava_zoom;
+  let noise_value1 = noise_3d(uniforms, 
     position.x * zoom,
     position.y * zoom,
     (position.z + pulsate) * zoom
   );
-  let noise_value2 = uniforms.noise.get_noise_3d(
+  let noise_value2 = noise_3d(uniforms, 
     (position.x + 1000.0) * zoom,
     (position.y + 1000.0) * zoom,
     (position.z + 1000.0 + pulsate) * zoom
@@ -100,17 +546,81 @@ fn lava_shader(fragment: &Fragment, uniforms: &Uniforms) -> Color {
   // Use lerp for color blending based on noise value
   let color = dark_color.lerp(&bright_color, noise_value);
 
-  color * fragment.intensity
+  diffuse(color, fragment, uniforms)
+}
+
+// How much colder the top of the elevation range is than sea level, in the
+// [0, 1] temperature scale of the biome table
+const BIOME_LAPSE_RATE: f32 = 0.6;
+
+// Planet surface colored from `params.biome_colors` by elevation and
+// temperature: a few octaves of noise give the elevation and the latitude
+// the temperature (1 at the equator, 0 at the poles, less going uphill), so
+// ice caps, tundra, forest and desert fall into latitude belts broken up by
+// the terrain. Both axes are blended with smoothstep between table cells.
+fn biome_shader(fragment: &Fragment, uniforms: &Uniforms) -> Vec3 {
+  let params = &uniforms.params;
+  let position = fragment.vertex_position;
+
+  let mut elevation = 0.0;
+  let mut amplitude = 0.5;
+  let mut total_amplitude = 0.0;
+  let mut zoom = params.biome_zoom;
+  for octave in 0..4 {
+    let offset = octave as f32 * 173.0;
+    elevation += noise_3d(uniforms, position.x * zoom + offset, position.y * zoom + offset, position.z * zoom + offset) * amplitude;
+    total_amplitude += amplitude;
+    amplitude *= 0.5;
+    zoom *= 2.0;
+  }
+  let elevation = (elevation / total_amplitude).clamp(-1.0, 1.0);
+
+  // Piecewise-linear row coordinate: each elevation band takes a third
+  let sea_level = params.biome_sea_level.clamp(-1.0, 1.0);
+  let highland_level = params.biome_highland_level.clamp(sea_level, 1.0);
+  let band = |value: f32, low: f32, high: f32| ((value - low) / (high - low).max(1e-3)).clamp(0.0, 1.0);
+  let row = if elevation < sea_level {
+    band(elevation, -1.0, sea_level)
+  } else if elevation < highland_level {
+    1.0 + band(elevation, sea_level, highland_level)
+  } else {
+    2.0 + band(elevation, highland_level, 1.0)
+  };
+
+  let (latitude, _) = to_lat_lon(position);
+  let altitude = band(elevation, sea_level, 1.0);
+  let temperature = (latitude.cos() - BIOME_LAPSE_RATE * altitude).clamp(0.0, 1.0);
+
+  let color = biome_lookup(&params.biome_colors, row / 3.0, temperature, params.biome_blend);
+  diffuse(color, fragment, uniforms)
 }
 
-fn ice_shader(fragment: &Fragment, uniforms: &Uniforms) -> Color {
+// Bilinear lookup in a biome table with coordinates in [0, 1] over its rows
+// and columns. Each cell keeps its flat color except for the `blend` share
+// around its borders, where it smoothsteps into the next cell.
+fn biome_lookup(table: &[[Color; 4]; 3], row: f32, column: f32, blend: f32) -> Color {
+  let half_width = blend.clamp(0.0, 1.0) * 0.5;
+  let cell = |value: f32, count: usize| {
+    let position = (value * count as f32 - 0.5).clamp(0.0, (count - 1) as f32);
+    let lower = (position.floor() as usize).min(count - 2);
+    let t = smoothstep(0.5 - half_width, 0.5 + half_width, position - lower as f32);
+    (lower, t)
+  };
+
+  let (r, tr) = cell(row, table.len());
+  let (c, tc) = cell(column, table[0].len());
+  let low = table[r][c].lerp(&table[r][c + 1], tc);
+  let high = table[r + 1][c].lerp(&table[r + 1][c + 1], tc);
+  low.lerp(&high, tr)
+}
+
+fn ice_shader(fragment: &Fragment, uniforms: &Uniforms) -> Vec3 {
   let ripple_pattern = (fragment.vertex_position.x * 8.0 + uniforms.time as f32 * 0.1).sin().abs();
-  let intensity = (ripple_pattern * 255.0) as u8;
-  Color::new(0, intensity, 255) * fragment.intensity // Azul agua
+  diffuse(Color::from_vec3(Vec3::new(0.0, ripple_pattern, 1.0)), fragment, uniforms) // Azul agua
 }
 
-fn cloud_shader(fragment: &Fragment, uniforms: &Uniforms) -> Color {
-  let zoom = 100.0;  // Escala del mapa de ruido
+fn cloud_shader(fragment: &Fragment, uniforms: &Uniforms) -> Vec3 {
+  let zoom = uniforms.params.cloud_zoom;  // Escala del mapa de ruido
   let ox = 100.0; // Offset en el eje x
   let oy = 100.0; // Offset en el eje y
 
@@ -122,12 +632,27 @@ fn cloud_shader(fragment: &Fragment, uniforms: &Uniforms) -> Color {
   let cloud_time = uniforms.time as f32 * 0.5;  // Las nubes se mueven a un ritmo
   let land_time = uniforms.time as f32 * 0.2;   // El terreno se mueve a otro ritmo
 
+  // El viento depende de la latitud: las nubes del ecuador avanzan más rápido
+  let (latitude, _) = to_lat_lon(fragment.vertex_position);
+  let wind_offset = wind_field(latitude) * cloud_time;
+
+  // Two samples whose evolution phases are half a cycle apart, cross-faded so
+  // each one is invisible at the moment it wraps around: the shapes morph
+  // continuously instead of just sliding
+  let morph_phase = (uniforms.time as f32 * 0.002).fract();
+  let morph_weight = (1.0 - 2.0 * morph_phase).abs();
+  let morph_depth = 50.0;
+  let cloud_x = x * zoom + ox + wind_offset;
+  let cloud_y = y * zoom + oy;
+  let cloud_a = noise_3d(uniforms, cloud_x, cloud_y, morph_phase * morph_depth);
+  let cloud_b = noise_3d(uniforms, cloud_x, cloud_y, (morph_phase + 0.5).fract() * morph_depth + 37.0);
+
   // Obtener el valor de ruido para las nubes y el terreno con sus respectivos tiempos
-  let cloud_noise = uniforms.noise.get_noise_2d(x * zoom + ox + cloud_time, y * zoom + oy);
-  let land_noise = uniforms.noise.get_noise_2d(x * zoom + ox + land_time, y * zoom + oy);
+  let cloud_noise = cloud_a * (1.0 - morph_weight) + cloud_b * morph_weight;
+  let land_noise = noise_2d(uniforms, x * zoom + ox + land_time, y * zoom + oy);
 
   // Umbrales de nubes y tierra
-  let cloud_threshold = 0.5;
+  let cloud_threshold = uniforms.params.cloud_threshold;
   let land_threshold = 0.1;
 
   // Colores para nubes, cielo y tierra
@@ -144,15 +669,21 @@ fn cloud_shader(fragment: &Fragment, uniforms: &Uniforms) -> Color {
       sky_color    // Color del cielo
   };
 
-  final_color * fragment.intensity
+  diffuse(final_color, fragment, uniforms)
+}
+
+// Relative wind speed at a latitude in radians: 1 at the equator, calm at the poles
+pub fn wind_field(latitude: f32) -> f32 {
+  let c = latitude.cos();
+  0.2 + 0.8 * c * c
 }
 
-fn jupiter_shader(fragment: &Fragment, uniforms: &Uniforms) -> Color {
+fn jupiter_shader(fragment: &Fragment, uniforms: &Uniforms) -> Vec3 {
   let zoom = 100.0;  // to move our values 
   let x = fragment.vertex_position.x;
   let y = fragment.vertex_position.y;
 
-  let band_noise = uniforms.noise.get_noise_2d(y * zoom, 0.0);// Desplazamiento para el movimiento de bandas
+  let band_noise = noise_2d(uniforms, y * zoom, 0.0);// Desplazamiento para el movimiento de bandas
 
   // Definir colores para las diferentes bandas de gas
   let dark_brown = Color::new(139, 69, 19);
@@ -183,42 +714,450 @@ fn jupiter_shader(fragment: &Fragment, uniforms: &Uniforms) -> Color {
       band_color // Colores de bandas para el resto
   };
 
-  final_color * fragment.intensity
+  // Brillo anisotrópico: the tangent runs north-south, so the highlight is
+  // stretched along the bands
+  let view_dir = unit_or(uniforms.camera_position - fragment.world_position, fragment.normal);
+  let (_, north) = tangent_basis(&fragment.normal);
+  let sheen = anisotropic_specular(&fragment.normal, &north, &uniforms.light_dir, &view_dir, uniforms.params.band_roughness);
+
+  diffuse(final_color, fragment, uniforms) + Color::new(255, 240, 220).to_vec3() * (sheen * 0.25)
+}
+
+// Kajiya-Kay specular: light scattered by fibres (or flow lines) along `tangent`.
+// The highlight is a band perpendicular to the tangent rather than a round spot.
+pub fn anisotropic_specular(normal: &Vec3, tangent: &Vec3, light_dir: &Vec3, view_dir: &Vec3, roughness: f32) -> f32 {
+  let normal = unit_or(*normal, Vec3::new(0.0, 0.0, 1.0));
+  let light_dir = unit_or(*light_dir, normal);
+  let n_dot_l = normal.dot(&light_dir);
+  if n_dot_l <= 0.0 {
+    return 0.0;
+  }
+
+  let half_vector = unit_or(light_dir + unit_or(*view_dir, normal), normal);
+  let t_dot_h = unit_or(*tangent, Vec3::zeros()).dot(&half_vector);
+  let sin_t_h = (1.0 - t_dot_h * t_dot_h).max(0.0).sqrt();
+
+  // Same roughness-to-exponent mapping as Blinn-Phong
+  let roughness = roughness.clamp(0.01, 1.0);
+  let exponent = 2.0 / (roughness * roughness) - 2.0;
+
+  sin_t_h.powf(exponent) * n_dot_l
+}
+
+fn iridescent_shader(fragment: &Fragment, uniforms: &Uniforms) -> Vec3 {
+  // Thin-film interference: the hue depends on how obliquely the surface is seen
+  let view_dir = unit_or(uniforms.camera_position - fragment.world_position, fragment.normal);
+  let facing = fragment.normal.dot(&view_dir).abs();
+  let fresnel = (1.0 - facing).powi(3);
+
+  // Film thickness varies slowly over the surface so the bands are not perfectly concentric
+  let thickness = noise_3d(uniforms, 
+    fragment.vertex_position.x * 50.0,
+    fragment.vertex_position.y * 50.0,
+    fragment.vertex_position.z * 50.0 + uniforms.time as f32 * 0.2
+  );
+
+  let band_count = 2.0;
+  let hue = ((1.0 - facing) * band_count + thickness * 0.3) * 360.0;
+  let sheen = Color::from_hsv(hue, 0.7, 1.0);
+
+  let base_color = Color::new(40, 30, 60); // Morado oscuro aceitoso
+  let color = base_color.lerp(&sheen, 0.35 + 0.65 * fresnel);
+
+  diffuse(color, fragment, uniforms)
+}
+
+// Orthonormal tangent and bitangent for a normal, following lines of longitude
+// on a sphere. Falls back to the X axis near the poles (and for a zero normal).
+pub fn tangent_basis(normal: &Vec3) -> (Vec3, Vec3) {
+  let reference = if normal.y.abs() < 0.999 { Vec3::new(0.0, 1.0, 0.0) } else { Vec3::new(1.0, 0.0, 0.0) };
+  let tangent = unit_or(reference.cross(normal), Vec3::new(1.0, 0.0, 0.0));
+  let bitangent = normal.cross(&tangent);
+  (tangent, bitangent)
+}
+
+// The albedo map's alpha comes out in w
+fn textured_shader(fragment: &Fragment, uniforms: &Uniforms) -> Vec4 {
+  let mut uv = if uniforms.spherical_uv {
+    spherical_uv(&fragment.vertex_position)
+  } else {
+    fragment.tex_coords
+  };
+
+  if let Some(height_map) = &uniforms.height_map {
+    let view_dir = unit_or(uniforms.camera_position - fragment.world_position, fragment.normal);
+    let (tangent, bitangent) = tangent_basis(&fragment.normal);
+    let view_ts = Vec3::new(view_dir.dot(&tangent), view_dir.dot(&bitangent), view_dir.dot(&fragment.normal));
+    uv = parallax_uv(height_map, uv, view_ts, uniforms.parallax_steps, uniforms.parallax_depth);
+  }
+
+  let albedo = match &uniforms.albedo_map {
+    Some(texture) if uniforms.spherical_uv => polar_blend(texture, uv, &fragment.vertex_position, uniforms),
+    Some(texture) => texture.sample_decoded(uv, uniforms.encode_mode),
+    None => Color::new(180, 180, 180),
+  };
+
+  diffuse(albedo, fragment, uniforms).push(albedo.a() as f32 / 255.0)
 }
 
+// Glass or ice: the background seen through the surface, bent by Snell's law,
+// mixed with the mirrored surroundings by Schlick's fresnel approximation, so
+// the surface turns into a mirror at grazing angles. Rays leaving the denser
+// medium past the critical angle are totally reflected.
+fn refractive_shader(fragment: &Fragment, uniforms: &Uniforms) -> Vec3 {
+  let tint = Color::new(215, 235, 255); // Hielo ligeramente azul
+  // Real materials stay far below 10 (diamond is 2.42); the cap keeps eta^2 finite
+  let ior = uniforms.params.index_of_refraction.clamp(1.0, 10.0);
+  let incident = unit_or(fragment.world_position - uniforms.camera_position, Vec3::new(0.0, 0.0, -1.0));
+
+  let surface_normal = unit_or(fragment.normal, -incident);
+
+  // Seen from inside, the normal faces away from the eye and the ratio inverts
+  let entering = surface_normal.dot(&incident) < 0.0;
+  let (normal, eta) = if entering { (surface_normal, 1.0 / ior) } else { (-surface_normal, ior) };
+
+  let cos_incident = -normal.dot(&incident);
+  let reflected = incident + normal * 2.0 * cos_incident;
+  let reflection = environment_radiance(uniforms, &reflected);
+
+  let k = 1.0 - eta * eta * (1.0 - cos_incident * cos_incident);
+  if k < 0.0 {
+    return reflection;
+  }
+  let refracted = incident * eta + normal * (eta * cos_incident - k.sqrt());
+  let refraction = environment_radiance(uniforms, &refracted).component_mul(&tint.to_vec3());
+
+  let r0 = ((1.0 - ior) / (1.0 + ior)).powi(2);
+  // Schlick uses the angle on the less dense side
+  let cos_fresnel = if entering { cos_incident } else { k.sqrt() };
+  let fresnel = r0 + (1.0 - r0) * (1.0 - cos_fresnel).powi(5);
+
+  refraction.lerp(&reflection, fresnel.clamp(0.0, 1.0))
+}
+
+// Equirectangular sample that fades into the pole's average color above
+// `params.polar_blend_latitude`, so the pinched texels at the poles never show
+fn polar_blend(texture: &Texture, uv: Vec2, position: &Vec3, uniforms: &Uniforms) -> Color {
+  let params = &uniforms.params;
+  let color = texture.sample_decoded(uv, uniforms.encode_mode);
+  let radius = position.magnitude();
+  if params.polar_blend_latitude >= 90.0 || radius <= f32::EPSILON {
+    return color;
+  }
+
+  let latitude = to_lat_lon(*position).0.abs().to_degrees();
+  let threshold = params.polar_blend_latitude;
+  let blend = smoothstep(threshold, (threshold + params.polar_blend_width.max(0.0)).min(90.0), latitude);
+  if blend <= 0.0 {
+    return color;
+  }
+
+  // v is 0 at the north pole and 1 at the south pole; sample half a texel in
+  let pole_v = if position.y > 0.0 { 0.5 / texture.height as f32 } else { 1.0 - 0.5 / texture.height as f32 };
+  color.lerp(&texture.row_average(pole_v, 16, uniforms.encode_mode), blend)
+}
+
+// `environment_radiance` clipped to displayable values
+pub fn environment_color(uniforms: &Uniforms, direction: &Vec3) -> Color {
+  Color::from_vec3(environment_radiance(uniforms, direction))
+}
+
+// The cubemap, else the HDR panorama, else a deep space gradient
+pub fn environment_radiance(uniforms: &Uniforms, direction: &Vec3) -> Vec3 {
+  match (&uniforms.environment, &uniforms.hdr_environment) {
+    (Some(cubemap), _) => cubemap.sample_decoded(direction, uniforms.encode_mode).to_vec3(),
+    (None, Some(panorama)) => panorama.sample_env(direction),
+    (None, None) => {
+      let up = unit_or(*direction, Vec3::zeros()).y;
+      Color::new(10, 10, 30).lerp(&Color::new(60, 60, 110), up * 0.5 + 0.5).to_vec3()
+    }
+  }
+}
+
+fn reflective_shader(fragment: &Fragment, uniforms: &Uniforms) -> Vec3 {
+  let base_color = Color::new(170, 190, 210); // Metal azulado
+  let incident = unit_or(fragment.world_position - uniforms.camera_position, Vec3::new(0.0, 0.0, -1.0));
+  let normal = unit_or(fragment.normal, -incident);
+  let reflected = incident - normal * 2.0 * incident.dot(&normal);
+
+  let reflection = environment_radiance(uniforms, &reflected);
+  // Reflections are not shadowed by the diffuse term
+  diffuse(base_color, fragment, uniforms).lerp(&reflection, uniforms.params.reflectivity.clamp(0.0, 1.0))
+}
 
 fn moving_circles_shader(fragment: &Fragment, uniforms: &Uniforms) -> Color {
+    Color::from_vec3(Vec3::repeat(moving_circles_coverage(fragment, uniforms, 0.0)))
+}
+
+// How much of the two drifting circles covers the fragment, in [0, 1]. The
+// rim fades out over `edge_width` model units; 0 gives hard edges.
+fn moving_circles_coverage(fragment: &Fragment, uniforms: &Uniforms, edge_width: f32) -> f32 {
     let x = fragment.vertex_position.x;
     let y = fragment.vertex_position.y;
   
     let time = uniforms.time as f32 * 0.05;
-    let circle1_x = (time.sin() * 0.4 + 0.5) % 1.0;
-    let circle2_x = (time.cos() * 0.4 + 0.5) % 1.0;
+    let circle1_x = (pulse(time, 1.0, 0.4, 0.0) + 0.5) % 1.0;
+    let circle2_x = (pulse(time, 1.0, 0.4, FRAC_PI_2) + 0.5) % 1.0;
   
     let dist1 = ((x - circle1_x).powi(2) + (y - 0.3).powi(2)).sqrt();
     let dist2 = ((x - circle2_x).powi(2) + (y - 0.7).powi(2)).sqrt();
   
     let circle_size = 0.1;
-    let circle1 = if dist1 < circle_size { 1.0f32 } else { 0.0f32 };
-    let circle2 = if dist2 < circle_size { 1.0f32 } else { 0.0f32 };
+    let circle1 = 1.0 - smoothstep(circle_size - edge_width, circle_size, dist1);
+    let circle2 = 1.0 - smoothstep(circle_size - edge_width, circle_size, dist2);
   
-    let circle_intensity = (circle1 + circle2).min(1.0f32);
-  
-    Color::new(
-      (circle_intensity * 255.0) as u8,
-      (circle_intensity * 255.0) as u8,
-      (circle_intensity * 255.0) as u8
-    )
+    (circle1 + circle2).min(1.0f32)
+}
+
+// Lava-lamp blobs: sphere distance fields drifting through the model, merged
+// with a smooth minimum so they melt into each other instead of overlapping
+fn metaball_shader(fragment: &Fragment, uniforms: &Uniforms) -> Vec3 {
+  let p = fragment.vertex_position;
+  let time = uniforms.time as f32 * 0.03;
+
+  let blob_count = 4;
+  let blob_radius = 0.35;
+  let mut field = f32::MAX;
+  for i in 0..blob_count {
+    let phase = i as f32 * 1.7;
+    let center = Vec3::new(
+      pulse(time, 1.0, 0.6, phase),
+      pulse(time, 0.8, 0.6, phase * 2.0 + FRAC_PI_2),
+      pulse(time, 1.3, 0.4, phase)
+    );
+    field = smooth_min(field, (p - center).magnitude() - blob_radius, 0.3);
+  }
+
+  let glow_color = Color::new(255, 120, 30);  // Naranja brillante
+  let fluid_color = Color::new(60, 0, 60);    // Morado del fondo
+
+  // Inside the merged surface is fully lit, with a short glowing falloff outside
+  let glow = (1.0 - field.max(0.0) / 0.15).clamp(0.0, 1.0);
+  diffuse(fluid_color.lerp(&glow_color, glow), fragment, uniforms)
 }
 
-pub fn combined_shader(fragment: &Fragment, uniforms: &Uniforms) -> Color {
-    let base_color = static_pattern_shader(fragment);
+// Channels at or below this count as black when `combined_shader` decides
+// whether the circles cover a fragment
+pub const NEAR_BLACK_THRESHOLD: u8 = 4;
+
+// The hard branch switches color from one fragment to the next, so the circle
+// rims alias (stair steps that crawl as they move); `combined_shader_blended`
+// is the antialiased version.
+pub fn combined_shader(fragment: &Fragment, uniforms: &Uniforms) -> Vec3 {
+    let base_color = static_pattern_shader(fragment, uniforms);
     let circle_color = moving_circles_shader(fragment, uniforms);
   
     // Combine shaders: use circle color if it's not black, otherwise use base color
-    if !circle_color.is_black() {
-      circle_color * fragment.intensity
+    if !circle_color.is_near_black(NEAR_BLACK_THRESHOLD) {
+      diffuse(circle_color, fragment, uniforms)
     } else {
-      base_color * fragment.intensity
+      diffuse(base_color, fragment, uniforms)
+    }
+}
+
+// Width of the circle rims of `combined_shader_blended`, in model units
+const CIRCLE_EDGE_WIDTH: f32 = 0.02;
+
+// `combined_shader` with the circles mixed over the pattern by their coverage,
+// which fades across a thin rim, instead of replacing it past a cutoff
+pub fn combined_shader_blended(fragment: &Fragment, uniforms: &Uniforms) -> Vec3 {
+    let base_color = static_pattern_shader(fragment, uniforms);
+    let coverage = moving_circles_coverage(fragment, uniforms, CIRCLE_EDGE_WIDTH);
+    let circle_color = Color::from_vec3(Vec3::repeat(1.0));
+    diffuse(base_color.lerp(&circle_color, coverage), fragment, uniforms)
+}
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{create_noise, create_viewport_matrix};
+  use crate::texture::{Cubemap, EquirectEnvironment};
+  use nalgebra_glm::Mat4;
+  use std::sync::Arc;
+  use rand::rngs::StdRng;
+  use rand::{Rng, SeedableRng};
+
+  // Magnitudes the shaders promise to handle (see `fragment_shader`)
+  const EXTREMES: [f32; 9] = [0.0, -0.0, 1e-30, -1e-30, 1.0, -1.0, 1e6, 1e30, -1e30];
+
+  fn extreme_or_random(rng: &mut StdRng) -> f32 {
+    if rng.gen_bool(0.5) { EXTREMES[rng.gen_range(0..EXTREMES.len())] } else { rng.gen_range(-3.0..3.0) }
+  }
+
+  fn random_vec3(rng: &mut StdRng) -> Vec3 {
+    Vec3::new(extreme_or_random(rng), extreme_or_random(rng), extreme_or_random(rng))
+  }
+
+  fn random_fragment(rng: &mut StdRng) -> Fragment {
+    let mut fragment = Fragment::new(
+      rng.gen_range(0.0..64.0),
+      rng.gen_range(0.0..64.0),
+      Color::black(),
+      extreme_or_random(rng),
+      random_vec3(rng),
+      extreme_or_random(rng),
+      random_vec3(rng),
+    );
+    fragment.world_position = random_vec3(rng);
+    fragment.tex_coords = Vec2::new(extreme_or_random(rng), extreme_or_random(rng));
+    fragment.view_depth = extreme_or_random(rng);
+    fragment.barycentric = random_vec3(rng);
+    fragment.velocity = Vec2::new(extreme_or_random(rng), extreme_or_random(rng));
+    fragment
+  }
+
+  fn random_color(rng: &mut StdRng) -> Color {
+    Color::new_rgba(rng.gen(), rng.gen(), rng.gen(), rng.gen())
+  }
+
+  fn random_texture(rng: &mut StdRng) -> Texture {
+    let (width, height) = (rng.gen_range(1..5), rng.gen_range(1..5));
+    Texture::new(width, height, (0..width * height).map(|_| random_color(rng)).collect())
+  }
+
+  fn random_matrix(rng: &mut StdRng) -> Mat4 {
+    if rng.gen_bool(0.5) { Mat4::identity() } else { Mat4::from_fn(|_, _| extreme_or_random(rng)) }
+  }
+
+  fn random_params(rng: &mut StdRng) -> ShaderParams {
+    let mut params = ShaderParams::default();
+    for key in ShaderParams::NUMBER_KEYS {
+      if rng.gen_bool(0.3) {
+        params.set_number(key, extreme_or_random(rng)).unwrap();
+      }
+    }
+    params.fog_color = random_color(rng);
+    params.biome_colors = [[(); 4]; 3].map(|row| row.map(|_| random_color(rng)));
+    params
+  }
+
+  fn random_uniforms(rng: &mut StdRng) -> Uniforms {
+    let time = if rng.gen_bool(0.2) { u32::MAX } else { rng.gen() };
+    let (model, view, projection) = (random_matrix(rng), random_matrix(rng), random_matrix(rng));
+    let mut uniforms = Uniforms::new(model, view, projection, create_viewport_matrix(64.0, 64.0), time, create_noise());
+    uniforms.camera_position = random_vec3(rng);
+    uniforms.light_dir = random_vec3(rng);
+    uniforms.atmosphere = rng.gen_bool(0.5);
+    if rng.gen_bool(0.5) {
+      uniforms.ambient_sh = Some(sky_ambient_sh());
+    }
+    uniforms.params = random_params(rng);
+    uniforms.spherical_uv = rng.gen_bool(0.5);
+    uniforms.albedo_map = rng.gen_bool(0.5).then(|| random_texture(rng));
+    uniforms.height_map = rng.gen_bool(0.3).then(|| random_texture(rng));
+    uniforms.parallax_steps = rng.gen_range(0..40);
+    uniforms.parallax_depth = extreme_or_random(rng);
+    uniforms.environment = rng.gen_bool(0.3).then(|| Cubemap::new([(); 6].map(|_| random_texture(rng))));
+    if rng.gen_bool(0.3) {
+      let data = (0..8).map(|_| random_vec3(rng).abs()).collect();
+      uniforms.hdr_environment = Some(Arc::new(EquirectEnvironment::new(4, 2, data)));
+    }
+    uniforms
+  }
+
+  // The float result must be finite with alpha in [0, 1]; its rounding to
+  // 8 bits then only clamps
+  fn assert_finite_shading(fragment: &Fragment, uniforms: &Uniforms, shader: ShaderType) {
+    let shaded = shade_fragment_radiance(fragment, uniforms, shader);
+    assert!(shaded.iter().all(|channel| channel.is_finite()) && (0.0..=1.0).contains(&shaded.w),
+      "{} gave {:?} for {:?}", shader.name(), shaded, fragment);
+    assert_eq!(shade_fragment(fragment, uniforms, shader), Color::from_vec4(shaded));
+  }
+
+  #[test]
+  fn every_shader_survives_random_and_degenerate_fragments() {
+    let mut rng = StdRng::seed_from_u64(183);
+    for _ in 0..300 {
+      let uniforms = random_uniforms(&mut rng);
+      let fragment = random_fragment(&mut rng);
+      for shader in ShaderType::ALL {
+        assert_finite_shading(&fragment, &uniforms, shader);
+      }
+    }
+  }
+
+  #[test]
+  fn every_shader_survives_all_zero_and_nan_fragments() {
+    let mut rng = StdRng::seed_from_u64(1830);
+    let zero = Fragment::new(0.0, 0.0, Color::black(), 0.0, Vec3::zeros(), 0.0, Vec3::zeros());
+    let mut nan = Fragment::new(0.0, 0.0, Color::black(), f32::NAN, Vec3::repeat(f32::NAN), f32::NAN, Vec3::repeat(f32::NAN));
+    nan.world_position = Vec3::repeat(f32::NAN);
+    nan.tex_coords = Vec2::repeat(f32::NAN);
+    nan.view_depth = f32::NAN;
+    nan.barycentric = Vec3::repeat(f32::NAN);
+
+    for _ in 0..20 {
+      let uniforms = random_uniforms(&mut rng);
+      for shader in ShaderType::ALL {
+        // Non-finite input has no defined color, only the no-panic promise
+        shade_fragment(&nan, &uniforms, shader);
+        assert_finite_shading(&zero, &uniforms, shader);
+      }
+    }
+    // Unknown names fall back to the combined shader
+    fragment_shader(&zero, &random_uniforms(&mut rng), "no such shader");
+    fragment_shader(&nan, &random_uniforms(&mut rng), "no such shader");
+  }
+
+  #[test]
+  fn pattern_follows_the_model_plane_each_face_points_along() {
+    let uniforms = Uniforms::new(Mat4::identity(), Mat4::identity(), Mat4::identity(), Mat4::identity(), 0, create_noise());
+    let shade = |normal: Vec3, position: Vec3| {
+      static_pattern_shader(&Fragment::new(0.0, 0.0, Color::black(), 0.0, normal, 1.0, position), &uniforms)
+    };
+
+    // Facing z: the original pattern in x and y
+    let front = Vec3::new(0.0, 0.0, 1.0);
+    let expected = ((0.1f32 * 10.0).sin() * (0.2f32 * 10.0).sin()).abs();
+    assert_eq!(shade(front, Vec3::new(0.1, 0.2, 0.7)), Color::from_vec3(Vec3::new(expected, 1.0 - expected, 128.0 / 255.0)));
+
+    // Facing x the pattern must change along z, not stay constant like a
+    // pattern projected along z would
+    let side = Vec3::new(1.0, 0.0, 0.0);
+    assert_ne!(shade(side, Vec3::new(1.0, 0.2, 0.0)), shade(side, Vec3::new(1.0, 0.2, 0.15)));
+    assert_eq!(shade(side, Vec3::new(1.0, 0.2, 0.15)), shade(side, Vec3::new(-3.0, 0.2, 0.15)));
+  }
+
+  #[test]
+  fn lava_matches_its_reference_colors_at_a_fixed_point_and_time() {
+    // Pinned on the default noise and parameters: a change here means every
+    // lava planet looks different
+    let mut uniforms = Uniforms::new(Mat4::identity(), Mat4::identity(), Mat4::identity(), Mat4::identity(), 200, create_noise());
+    let fragment = |intensity: f32| Fragment::new(10.0, 10.0, Color::black(), 0.5, Vec3::new(0.0, 0.0, 1.0), intensity, Vec3::new(0.5, 0.5, 0.0));
+    assert_eq!(shade_fragment(&fragment(1.0), &uniforms, ShaderType::Lava), Color::new(163, 78, 0));
+
+    // Light scales the float color before it is rounded
+    let lit = shade_fragment_radiance(&fragment(1.0), &uniforms, ShaderType::Lava);
+    let half = shade_fragment_radiance(&fragment(0.5), &uniforms, ShaderType::Lava);
+    assert!((half.xyz() - lit.xyz() * 0.5).norm() < 1e-6 && half.w == 1.0);
+
+    // The spots move: a hundred frames earlier the same point is in a dark patch
+    uniforms.time = 100;
+    assert_eq!(shade_fragment(&fragment(1.0), &uniforms, ShaderType::Lava), Color::new(130, 20, 0));
+  }
+
+  #[test]
+  fn ring_lod_draws_every_band_above_the_detail_size_and_the_average_below_the_solid_size() {
+    // Identity matrices put one model unit at one pixel
+    let mut uniforms = Uniforms::new(Mat4::identity(), Mat4::identity(), Mat4::identity(), Mat4::identity(), 0, create_noise());
+    assert_eq!(uniforms.pixels_per_unit(), 1.0);
+    assert_eq!(uniforms.ring_average_albedo, ring_average_albedo(&uniforms));
+    let shade = |uniforms: &Uniforms, radius: f32| {
+      let position = Vec3::new(radius, 0.0, 0.0);
+      shade_fragment(&Fragment::new(0.0, 0.0, Color::black(), 0.0, Vec3::new(0.0, 1.0, 0.0), 1.0, position), uniforms, ShaderType::Ring)
+    };
+    let radii = [1.5, 1.72, 1.9, 2.2];
+    // Light along +Z is perpendicular to the ring's normal
+    let lit = |albedo: Color| Color::from_vec3(albedo.to_vec3() * 0.35);
+
+    (uniforms.params.ring_lod_solid_size, uniforms.params.ring_lod_detail_size) = (0.25, 1.0);
+    for radius in radii {
+      assert_eq!(shade(&uniforms, radius), lit(ring_albedo(&uniforms, radius)));
     }
-}
\ No newline at end of file
+    assert!(radii.iter().any(|&radius| shade(&uniforms, radius) != shade(&uniforms, radii[0])));
+
+    (uniforms.params.ring_lod_solid_size, uniforms.params.ring_lod_detail_size) = (1.0, 4.0);
+    for radius in radii {
+      assert_eq!(shade(&uniforms, radius), lit(uniforms.ring_average_albedo));
+    }
+  }
+}
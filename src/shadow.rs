@@ -0,0 +1,185 @@
+use nalgebra_glm::{Mat4, Vec3, Vec4, look_at, ortho};
+use crate::{Uniforms, create_viewport_matrix};
+use crate::mesh::Mesh;
+use crate::shaders::vertex_shader;
+use crate::triangle::triangle;
+
+// Largest PCF kernel radius in texels, reached by receivers far behind their
+// blocker; the contact end of a shadow uses a single ring of neighbours
+pub const MAX_PCF_RADIUS: i32 = 4;
+
+// Depth of the scene as seen from a directional light, for self-shadowing.
+// The light looks along `-light_dir` through an orthographic box around a
+// bounding sphere. Render the casters with `render`, put the map on
+// `Uniforms::shadow_map` and the shaders' direct light is scaled by
+// `sample_shadow` at each fragment.
+#[derive(Clone, Debug)]
+pub struct ShadowMap {
+    pub size: usize,
+    // NDC depth from the light per texel, infinity where nothing was drawn
+    pub depth: Vec<f32>,
+    // World space to the light's clip space
+    pub light_view_proj: Mat4,
+    // Subtracted from the receiver depth before comparing, in light NDC depth
+    // (which spans the box's depth of 4 * radius over 2 units). Raise it if
+    // lit surfaces show shadow acne stripes, lower it if shadows detach.
+    pub bias: f32,
+    // Apparent size of the light, in texels of penumbra per unit of NDC depth
+    // between blocker and receiver: 0 gives hard edges, larger values soften
+    // shadows the farther they fall from their caster
+    pub light_size: f32,
+    viewport_matrix: Mat4,
+}
+
+impl ShadowMap {
+    // A `size` x `size` map covering the sphere at `center` with `radius`,
+    // which should enclose every caster and receiver
+    pub fn new(size: usize, light_dir: Vec3, center: Vec3, radius: f32) -> Self {
+        let light_dir = light_dir.try_normalize(f32::EPSILON).unwrap_or(Vec3::new(0.0, 1.0, 0.0));
+        let up = if light_dir.y.abs() < 0.99 { Vec3::new(0.0, 1.0, 0.0) } else { Vec3::new(1.0, 0.0, 0.0) };
+        let eye = center + light_dir * (radius * 2.0);
+
+        let view = look_at(&eye, &center, &up);
+        let projection = ortho(-radius, radius, -radius, radius, radius * 0.5, radius * 4.0);
+
+        ShadowMap {
+            size,
+            depth: vec![f32::INFINITY; size * size],
+            light_view_proj: projection * view,
+            bias: 0.005,
+            light_size: 40.0,
+            viewport_matrix: create_viewport_matrix(size as f32, size as f32),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.depth.fill(f32::INFINITY);
+    }
+
+    // Adds `mesh`, placed by `uniforms.model_matrix` (and displaced like the
+    // main pass), to the map. Both faces cast shadows.
+    pub fn render(&mut self, mesh: &Mesh, uniforms: &Uniforms) {
+        let mut light_uniforms = uniforms.clone();
+        light_uniforms.view_matrix = Mat4::identity();
+        light_uniforms.projection_matrix = self.light_view_proj;
+        light_uniforms.viewport_matrix = self.viewport_matrix;
+        light_uniforms.prev_mvp = None;
+
+        let vertices: Vec<_> = mesh.vertices.iter().map(|vertex| vertex_shader(vertex, &light_uniforms)).collect();
+        for corners in mesh.indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| &vertices[corners[i] as usize]);
+            for fragment in triangle(a, b, c, 1, &uniforms.light_dir, (self.size, self.size)) {
+                let index = fragment.position.y as usize * self.size + fragment.position.x as usize;
+                self.depth[index] = self.depth[index].min(fragment.depth);
+            }
+        }
+    }
+
+    // Share of the light reaching `world_pos`: 1 lit, 0 fully shadowed, in
+    // between along soft edges. Percentage-closer soft shadows: the average
+    // depth of nearby blockers sets the filter radius, so shadows are sharp
+    // where they touch their caster and blur with distance. Points outside the
+    // map are lit.
+    pub fn sample_shadow(&self, world_pos: &Vec3) -> f32 {
+        let clip = self.light_view_proj * Vec4::new(world_pos.x, world_pos.y, world_pos.z, 1.0);
+        let screen = self.viewport_matrix * Vec4::new(clip.x / clip.w, clip.y / clip.w, clip.z / clip.w, 1.0);
+        let (x, y) = (screen.x.floor() as i32, screen.y.floor() as i32);
+        let receiver = screen.z - self.bias;
+        if x < 0 || y < 0 || x >= self.size as i32 || y >= self.size as i32 {
+            return 1.0;
+        }
+
+        // Blocker search over the widest kernel
+        let (mut blocker_sum, mut blockers) = (0.0, 0);
+        for depth in self.neighbourhood(x, y, MAX_PCF_RADIUS) {
+            if depth < receiver {
+                blocker_sum += depth;
+                blockers += 1;
+            }
+        }
+        if blockers == 0 {
+            return 1.0;
+        }
+
+        let blocker = blocker_sum / blockers as f32;
+        let radius = ((receiver - blocker) * self.light_size).round().clamp(1.0, MAX_PCF_RADIUS as f32) as i32;
+
+        let (mut lit, mut samples) = (0, 0);
+        for depth in self.neighbourhood(x, y, radius) {
+            if depth >= receiver {
+                lit += 1;
+            }
+            samples += 1;
+        }
+        lit as f32 / samples.max(1) as f32
+    }
+
+    // Stored depths within `radius` texels of (x, y), clamped to the map
+    fn neighbourhood(&self, x: i32, y: i32, radius: i32) -> impl Iterator<Item = f32> + '_ {
+        let last = self.size as i32 - 1;
+        (-radius..=radius).flat_map(move |dy| (-radius..=radius).map(move |dx| {
+            let (sx, sy) = ((x + dx).clamp(0, last), (y + dy).clamp(0, last));
+            self.depth[sy as usize * self.size + sx as usize]
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra_glm::Vec2;
+    use crate::create_noise;
+    use crate::vertex::Vertex;
+
+    // The light pass replaces view, projection and viewport, so only the model matters
+    fn uniforms() -> Uniforms {
+        let mut uniforms = Uniforms::new(Mat4::identity(), Mat4::identity(), Mat4::identity(), Mat4::identity(), 0, create_noise());
+        uniforms.light_dir = Vec3::new(0.0, 0.0, 1.0);
+        uniforms
+    }
+
+    // Square of `half_size` around the origin at height z, rising by `slope` along y
+    fn plane(half_size: f32, z: f32, slope: f32) -> Mesh {
+        let corner = |x: f32, y: f32| Vertex::new(Vec3::new(x, y, z + y * slope), Vec3::new(0.0, -slope, 1.0).normalize(), Vec2::zeros());
+        let (a, b, c, d) = (corner(-half_size, -half_size), corner(half_size, -half_size), corner(half_size, half_size), corner(-half_size, half_size));
+        Mesh::from_triangle_soup(vec![a.clone(), b, c.clone(), a, c, d])
+    }
+
+    #[test]
+    fn blockers_darken_the_receivers_behind_them() {
+        let uniforms = uniforms();
+        let mut shadow_map = ShadowMap::new(128, uniforms.light_dir, Vec3::zeros(), 2.0);
+        shadow_map.render(&plane(1.0, 0.0, 0.0), &uniforms);
+        shadow_map.render(&plane(0.5, 1.0, 0.0), &uniforms);
+
+        assert_eq!(shadow_map.sample_shadow(&Vec3::zeros()), 0.0);
+        assert_eq!(shadow_map.sample_shadow(&Vec3::new(0.9, 0.9, 0.0)), 1.0);
+        // The blocker itself faces the light
+        assert_eq!(shadow_map.sample_shadow(&Vec3::new(0.0, 0.0, 1.0)), 1.0);
+        // Along the edge of the shadow the filter mixes both
+        let edge = shadow_map.sample_shadow(&Vec3::new(0.5, 0.0, 0.0));
+        assert!(edge > 0.0 && edge < 1.0, "{}", edge);
+
+        shadow_map.clear();
+        assert_eq!(shadow_map.sample_shadow(&Vec3::zeros()), 1.0);
+    }
+
+    #[test]
+    fn the_bias_keeps_lit_surfaces_from_shadowing_themselves() {
+        let uniforms = uniforms();
+        let points: Vec<Vec3> = (0..15)
+            .flat_map(|i| (0..15).map(move |j| (i as f32 / 10.0 - 0.7, j as f32 / 10.0 - 0.7)))
+            .map(|(x, y)| Vec3::new(x, y, y * 0.25))
+            .collect();
+
+        // Tilted away from the light, so depth changes from one texel to the next
+        let mut shadow_map = ShadowMap::new(128, uniforms.light_dir, Vec3::zeros(), 2.0);
+        shadow_map.render(&plane(1.0, 0.0, 0.25), &uniforms);
+        for point in &points {
+            assert_eq!(shadow_map.sample_shadow(point), 1.0, "acne at {:?}", point);
+        }
+
+        shadow_map.bias = 0.0;
+        assert!(points.iter().any(|point| shadow_map.sample_shadow(point) < 1.0));
+    }
+}
@@ -0,0 +1,124 @@
+use nalgebra_glm::{Mat4, Vec2, Vec3};
+use crate::{Uniforms, render_mesh};
+use crate::framebuffer::Framebuffer;
+use crate::mesh::Mesh;
+
+// Where the sub-samples of a pixel are taken, as offsets from its center in
+// pixels. `Grid` is the plain 2x2 box; its samples share two columns and two
+// rows, so an edge close to horizontal or vertical only ever produces three
+// coverage levels. `RotatedGrid` (RGSS) turns the square by about 26.6
+// degrees so all four samples land on distinct rows and columns, giving such
+// edges five levels for the same cost.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SamplePattern {
+    #[default]
+    Grid,
+    RotatedGrid,
+}
+
+impl SamplePattern {
+    pub fn offsets(self) -> [Vec2; 4] {
+        match self {
+            SamplePattern::Grid => [
+                Vec2::new(-0.25, -0.25),
+                Vec2::new(0.25, -0.25),
+                Vec2::new(-0.25, 0.25),
+                Vec2::new(0.25, 0.25),
+            ],
+            SamplePattern::RotatedGrid => [
+                Vec2::new(-0.125, -0.375),
+                Vec2::new(0.375, -0.125),
+                Vec2::new(-0.375, 0.125),
+                Vec2::new(0.125, 0.375),
+            ],
+        }
+    }
+}
+
+// Supersampled `render_mesh`: the mesh is drawn once per sample of `pattern`
+// with the image shifted so each pixel center lands on that sample, and the
+// pixel colors are box-averaged. Every pass starts from the framebuffer as
+// given, so clear it (or draw the background) first. Depth keeps the nearest
+// sample; velocities and object ids come from the first pass.
+pub fn render_mesh_supersampled(
+    framebuffer: &mut Framebuffer,
+    uniforms: &Uniforms,
+    mesh: &Mesh,
+    shader_selection: u32,
+    pattern: SamplePattern,
+) {
+    let offsets = pattern.offsets();
+    let mut sums = vec![[0u32; 3]; framebuffer.buffer.len()];
+    let mut resolved: Option<Framebuffer> = None;
+
+    for offset in offsets {
+        let mut sample = framebuffer.clone();
+        let mut sample_uniforms = uniforms.clone();
+        // Sampling at center + offset is the same as moving the image by -offset
+        sample_uniforms.viewport_matrix = Mat4::new_translation(&Vec3::new(-offset.x, -offset.y, 0.0)) * uniforms.viewport_matrix;
+        sample_uniforms.prepare();
+        render_mesh(&mut sample, &sample_uniforms, mesh, shader_selection);
+
+        for (sum, &pixel) in sums.iter_mut().zip(&sample.buffer) {
+            sum[0] += (pixel >> 16) & 0xFF;
+            sum[1] += (pixel >> 8) & 0xFF;
+            sum[2] += pixel & 0xFF;
+        }
+        match &mut resolved {
+            Some(first) => {
+                for (depth, &sample_depth) in first.zbuffer.iter_mut().zip(&sample.zbuffer) {
+                    *depth = depth.min(sample_depth);
+                }
+            }
+            None => resolved = Some(sample),
+        }
+    }
+
+    let mut resolved = resolved.expect("every pattern has samples");
+    let count = offsets.len() as u32;
+    for (pixel, sum) in resolved.buffer.iter_mut().zip(&sums) {
+        let channel = |total: u32| (total + count / 2) / count;
+        *pixel = (channel(sum[0]) << 16) | (channel(sum[1]) << 8) | channel(sum[2]);
+    }
+    *framebuffer = resolved;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sample_patterns_have_the_expected_offsets() {
+        assert_eq!(SamplePattern::default(), SamplePattern::Grid);
+        let grid = SamplePattern::Grid.offsets();
+        let rotated = SamplePattern::RotatedGrid.offsets();
+        assert_eq!(grid, [Vec2::new(-0.25, -0.25), Vec2::new(0.25, -0.25), Vec2::new(-0.25, 0.25), Vec2::new(0.25, 0.25)]);
+        assert_eq!(rotated, [Vec2::new(-0.125, -0.375), Vec2::new(0.375, -0.125), Vec2::new(-0.375, 0.125), Vec2::new(0.125, 0.375)]);
+
+        for offsets in [grid, rotated] {
+            // Centered on the pixel center and inside the pixel
+            assert_eq!(offsets.iter().sum::<Vec2>(), Vec2::zeros());
+            assert!(offsets.iter().all(|offset| offset.x.abs() < 0.5 && offset.y.abs() < 0.5));
+        }
+        // Each rotated sample is its grid sample turned by atan(1/2), about 26.6 degrees
+        for (g, r) in grid.iter().zip(&rotated) {
+            let angle = (g.x * r.y - g.y * r.x).atan2(g.dot(r));
+            assert!((angle - 0.5f32.atan()).abs() < 1e-6, "{:?} to {:?}", g, r);
+        }
+    }
+
+    #[test]
+    fn rotated_grid_gives_axis_aligned_edges_more_coverage_levels() {
+        // Coverage levels a horizontal or vertical edge sweeping across a pixel can produce
+        let levels = |pattern: SamplePattern, axis: usize| {
+            let mut rows: Vec<f32> = pattern.offsets().iter().map(|offset| offset[axis]).collect();
+            rows.sort_by(f32::total_cmp);
+            rows.dedup();
+            rows.len() + 1
+        };
+        for axis in 0..2 {
+            assert_eq!(levels(SamplePattern::Grid, axis), 3);
+            assert_eq!(levels(SamplePattern::RotatedGrid, axis), 5);
+        }
+    }
+}
@@ -0,0 +1,211 @@
+use nalgebra_glm::{Mat4, Vec2, Vec3};
+use crate::color::Color;
+use crate::render_target::RenderTarget;
+
+// Weight of the new frame in `TaaState::resolve` unless changed: each pixel
+// converges over roughly the last ten frames
+pub const DEFAULT_TAA_BLEND: f32 = 0.1;
+// Length of the jitter sequence before it repeats
+pub const TAA_JITTER_SAMPLES: u32 = 8;
+
+// Temporal antialiasing: every frame is rendered with the image shifted by a
+// different sub-pixel offset (`jittered_viewport`), then `resolve` mixes it
+// with the accumulated history, fetched where each pixel was last frame
+// according to the velocity buffer. Over a few frames every pixel averages
+// many sample positions, like supersampling at the cost of one render. To
+// keep moving or newly revealed surfaces from leaving trails, the history is
+// clamped to the range of colors around the pixel in the new frame.
+//
+// Per frame: render with `jittered_viewport(viewport)` (and `prev_mvp` set so
+// moving objects have velocities), `resolve` the frame, then `advance`.
+#[derive(Clone, Debug)]
+pub struct TaaState {
+    // Last resolved frame row by row, `None` until the first `resolve` or after
+    // `reset`. Kept in float: rounded to 8 bits, a pixel stops moving once its
+    // step (`blend` times the distance left) falls under half a level, leaving
+    // it stuck short of the new color with a small `blend`.
+    pub history: Option<Vec<Vec3>>,
+    // Share of the new frame in the result, in (0, 1]: lower is smoother but
+    // slower to follow changes, 1 turns TAA off
+    pub blend: f32,
+    history_size: (usize, usize),
+    frame: u32,
+}
+
+impl Default for TaaState {
+    fn default() -> Self {
+        TaaState { history: None, blend: DEFAULT_TAA_BLEND, history_size: (0, 0), frame: 0 }
+    }
+}
+
+impl TaaState {
+    pub fn new() -> Self {
+        TaaState::default()
+    }
+
+    // Drops the history, e.g. after a camera cut or a change of resolution
+    pub fn reset(&mut self) {
+        self.history = None;
+    }
+
+    // Offset of this frame's samples from the pixel centers, in pixels within
+    // [-0.5, 0.5): a Halton (2, 3) sequence, which covers the pixel evenly in
+    // any run of consecutive frames
+    pub fn jitter(&self) -> Vec2 {
+        let index = self.frame % TAA_JITTER_SAMPLES + 1;
+        Vec2::new(halton(index, 2) - 0.5, halton(index, 3) - 0.5)
+    }
+
+    // `viewport_matrix` with the image moved by `-jitter()`, so each pixel
+    // center samples the scene at center + jitter
+    pub fn jittered_viewport(&self, viewport_matrix: Mat4) -> Mat4 {
+        let jitter = self.jitter();
+        Mat4::new_translation(&Vec3::new(-jitter.x, -jitter.y, 0.0)) * viewport_matrix
+    }
+
+    // Moves on to the next jitter offset
+    pub fn advance(&mut self) {
+        self.frame = self.frame.wrapping_add(1);
+    }
+
+    // Blends `target` with the reprojected history in place and keeps the
+    // result as the new history. Pixels whose previous position falls off
+    // screen, and every pixel of the first frame, take the new frame as is.
+    pub fn resolve(&mut self, target: &mut RenderTarget) {
+        let blend = if self.blend.is_finite() { self.blend.clamp(0.0, 1.0) } else { 1.0 };
+        let size = (target.width, target.height);
+        let mut resolved_history: Vec<Vec3> = target.color.iter().map(|color| color.to_vec3()).collect();
+        let history = match self.history.take() {
+            Some(history) if self.history_size == size && history.len() == resolved_history.len() => history,
+            _ => {
+                self.history = Some(resolved_history);
+                self.history_size = size;
+                return;
+            }
+        };
+
+        let current = target.clone();
+        for y in 0..target.height {
+            for x in 0..target.width {
+                let color = current.get(x, y);
+                let previous = Vec2::new(x as f32, y as f32) - current.velocity_at(x, y);
+                let Some(past) = sample_bilinear(&history, size, previous) else {
+                    continue;
+                };
+
+                // Neighborhood clamp: history outside the colors present here
+                // now belongs to something that moved away
+                let (mut low, mut high) = (Vec3::repeat(f32::INFINITY), Vec3::repeat(f32::NEG_INFINITY));
+                for dy in -1..=1 {
+                    for dx in -1..=1 {
+                        let neighbour = current.get_clamped(x as i32 + dx, y as i32 + dy).to_vec3();
+                        low = low.inf(&neighbour);
+                        high = high.sup(&neighbour);
+                    }
+                }
+                let past = past.sup(&low).inf(&high);
+
+                let resolved = past.lerp(&color.to_vec3(), blend);
+                resolved_history[y * target.width + x] = resolved;
+                target.set(x, y, Color::from_vec3(resolved).with_alpha(color.a()));
+            }
+        }
+
+        self.history = Some(resolved_history);
+    }
+}
+
+// Radical inverse of `index` in `base`, in [0, 1)
+fn halton(mut index: u32, base: u32) -> f32 {
+    let (mut result, mut fraction) = (0.0, 1.0);
+    while index > 0 {
+        fraction /= base as f32;
+        result += fraction * (index % base) as f32;
+        index /= base;
+    }
+    result
+}
+
+// Color of a `width` x `height` history at a point in pixel index coordinates,
+// with pixel (x, y) at exactly (x, y) and bilinear weights in between; `None`
+// off screen
+fn sample_bilinear(history: &[Vec3], (width, height): (usize, usize), point: Vec2) -> Option<Vec3> {
+    if !(point.x >= -0.5 && point.y >= -0.5 && point.x <= width as f32 - 0.5 && point.y <= height as f32 - 0.5) {
+        return None;
+    }
+
+    let (x0, y0) = (point.x.floor(), point.y.floor());
+    let (tx, ty) = (point.x - x0, point.y - y0);
+    let (x0, y0) = (x0 as i32, y0 as i32);
+    let texel = |x: i32, y: i32| {
+        let (x, y) = (x.clamp(0, width as i32 - 1) as usize, y.clamp(0, height as i32 - 1) as usize);
+        history[y * width + x]
+    };
+    let top = texel(x0, y0).lerp(&texel(x0 + 1, y0), tx);
+    let bottom = texel(x0, y0 + 1).lerp(&texel(x0 + 1, y0 + 1), tx);
+    Some(top.lerp(&bottom, ty))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Three columns: black, `center` and white, so every neighbourhood of the
+    // middle column spans the whole range and the clamp leaves its history alone
+    fn columns(center: u8) -> RenderTarget {
+        let mut target = RenderTarget::new(3, 3);
+        for y in 0..3 {
+            target.set(0, y, Color::black());
+            target.set(1, y, Color::new(center, center, center));
+            target.set(2, y, Color::new(255, 255, 255));
+        }
+        target
+    }
+
+    #[test]
+    fn history_converges_to_a_still_image_with_a_small_blend() {
+        let mut taa = TaaState { blend: 0.02, ..TaaState::new() };
+        taa.resolve(&mut columns(0));
+
+        // 8-bit history would stall about 25 levels short, where each step
+        // of 2% rounds away
+        let mut frame = columns(100);
+        for _ in 0..600 {
+            frame = columns(100);
+            taa.resolve(&mut frame);
+        }
+        assert_eq!(frame.get(1, 1), Color::new(100, 100, 100));
+        let history = taa.history.as_ref().unwrap();
+        assert!((history[4].x - 100.0 / 255.0).abs() < 1e-3, "{:?}", history[4]);
+    }
+
+    #[test]
+    fn moving_objects_leave_no_trail() {
+        let dot = |x: usize| {
+            let mut target = RenderTarget::new(5, 5);
+            target.set(x, 2, Color::new(255, 255, 255));
+            target
+        };
+        let mut taa = TaaState::new();
+        for _ in 0..10 {
+            taa.resolve(&mut dot(1));
+        }
+
+        // The dot jumps two pixels right; its new pixels know where it was
+        let mut moved = dot(3);
+        moved.velocity[2 * 5 + 3] = Vec2::new(2.0, 0.0);
+        let expected = moved.color.clone();
+        taa.resolve(&mut moved);
+        // The old spot now sees only black around it, so its white history is
+        // clamped away instead of fading out over several frames
+        assert_eq!(moved.color, expected);
+
+        // A new resolution starts over from the new frame
+        let mut smaller = RenderTarget::new(4, 4);
+        smaller.set(0, 0, Color::new(10, 20, 30));
+        let expected = smaller.color.clone();
+        taa.resolve(&mut smaller);
+        assert_eq!(smaller.color, expected);
+        assert_eq!(taa.history.as_ref().map(Vec::len), Some(16));
+    }
+}
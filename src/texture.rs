@@ -0,0 +1,639 @@
+use nalgebra_glm::{Vec2, Vec3};
+use std::f32::consts::PI;
+use std::fs;
+use std::io::{self, ErrorKind};
+use crate::color::{Color, EncodeMode};
+
+// How texture coordinates outside [0, 1] (and texel indices outside the
+// image) are brought back in. `Wrap` repeats the image, `Clamp` stretches its
+// border texels and `Mirror` repeats it flipped every other tile, so tiles
+// meet without a seam.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WrapMode {
+    #[default]
+    Wrap,
+    Clamp,
+    Mirror,
+}
+
+impl WrapMode {
+    // A texture coordinate in [0, 1]: with `Wrap` -0.1 reads 0.9 and 2.5 reads
+    // 0.5, with `Clamp` they read 0 and 1, with `Mirror` 0.1 and 0.5
+    pub fn apply(self, coordinate: f32) -> f32 {
+        match self {
+            WrapMode::Wrap => coordinate.rem_euclid(1.0),
+            WrapMode::Clamp => coordinate.clamp(0.0, 1.0),
+            WrapMode::Mirror => {
+                let t = coordinate.rem_euclid(2.0);
+                if t > 1.0 { 2.0 - t } else { t }
+            }
+        }
+    }
+
+    // A texel index in [0, size), for the texels around a filtered sample
+    pub fn texel_index(self, index: i32, size: usize) -> usize {
+        let size = size as i32;
+        match self {
+            WrapMode::Wrap => index.rem_euclid(size) as usize,
+            WrapMode::Clamp => index.clamp(0, size - 1) as usize,
+            WrapMode::Mirror => {
+                let t = index.rem_euclid(2 * size);
+                (if t < size { t } else { 2 * size - 1 - t }) as usize
+            }
+        }
+    }
+
+    // Same coordinate after any whole number of periods, so huge UVs can't
+    // overflow the texel indices: a tile for `Wrap`, two for `Mirror`
+    fn reduce(self, coordinate: f32) -> f32 {
+        match self {
+            WrapMode::Wrap => coordinate.rem_euclid(1.0),
+            WrapMode::Clamp => coordinate.clamp(0.0, 1.0),
+            WrapMode::Mirror => coordinate.rem_euclid(2.0),
+        }
+    }
+}
+
+// CPU-side texture stored row by row, (0, 0) being the top-left texel
+#[derive(Clone, Debug)]
+pub struct Texture {
+    pub width: usize,
+    pub height: usize,
+    pub data: Vec<Color>,
+    // Texels hold gamma-encoded sRGB colors (photos, painted albedo maps), which
+    // `sample` decodes to linear light before filtering so lighting math is
+    // right. Leave it off for data: height, normal and baked shader maps. The
+    // `_decoded` samplers take the curve to undo, usually `Uniforms::encode_mode`.
+    pub is_srgb: bool,
+    // Addressing of both axes in `texel`, `sample` and `sample_linear`
+    pub wrap_mode: WrapMode,
+}
+
+impl Texture {
+    pub fn new(width: usize, height: usize, data: Vec<Color>) -> Self {
+        assert_eq!(data.len(), width * height, "texture data does not match its size");
+        Texture { width, height, data, is_srgb: false, wrap_mode: WrapMode::default() }
+    }
+
+    pub fn with_srgb(mut self, is_srgb: bool) -> Self {
+        self.is_srgb = is_srgb;
+        self
+    }
+
+    pub fn with_wrap_mode(mut self, wrap_mode: WrapMode) -> Self {
+        self.wrap_mode = wrap_mode;
+        self
+    }
+
+    // Builds a texture by evaluating `f` at the UV of every texel center
+    pub fn from_fn<F: Fn(Vec2) -> Color>(width: usize, height: usize, f: F) -> Self {
+        let mut data = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                let uv = Vec2::new(
+                    (x as f32 + 0.5) / width as f32,
+                    (y as f32 + 0.5) / height as f32,
+                );
+                data.push(f(uv));
+            }
+        }
+        Texture { width, height, data, is_srgb: false, wrap_mode: WrapMode::default() }
+    }
+
+    // Addressed by `wrap_mode`; an empty texture reads as black
+    pub fn texel(&self, x: i32, y: i32) -> Color {
+        self.texel_wrapped(x, y, self.wrap_mode)
+    }
+
+    // Bilinear sample addressed by `wrap_mode`, in linear light when `is_srgb`
+    pub fn sample(&self, uv: Vec2) -> Color {
+        self.sample_decoded(uv, EncodeMode::Srgb)
+    }
+
+    // `sample` as floats in [0, 1], without rounding the decoded sRGB values
+    // back to 8 bits (which crushes dark tones)
+    pub fn sample_linear(&self, uv: Vec2) -> Vec3 {
+        self.sample_linear_decoded(uv, EncodeMode::Srgb)
+    }
+
+    // Same two, with `is_srgb` texels decoded by `decode` instead of the exact
+    // sRGB curve. Matching the frame's display curve makes an unlit texel come
+    // back out of `postprocess::encode_display` as it went in.
+    pub fn sample_decoded(&self, uv: Vec2, decode: EncodeMode) -> Color {
+        self.sample_wrapped(uv, self.wrap_mode, decode)
+    }
+
+    pub fn sample_linear_decoded(&self, uv: Vec2, decode: EncodeMode) -> Vec3 {
+        self.sample_linear_wrapped(uv, self.wrap_mode, decode)
+    }
+
+    // The samplers above with `wrap_mode` overridden, for callers such as
+    // `Cubemap` whose addressing does not depend on the texture
+    fn texel_wrapped(&self, x: i32, y: i32, wrap_mode: WrapMode) -> Color {
+        if self.data.is_empty() {
+            return Color::black();
+        }
+        let x = wrap_mode.texel_index(x, self.width);
+        let y = wrap_mode.texel_index(y, self.height);
+        self.data[y * self.width + x]
+    }
+
+    fn sample_wrapped(&self, uv: Vec2, wrap_mode: WrapMode, decode: EncodeMode) -> Color {
+        if self.data.is_empty() {
+            return Color::black();
+        }
+        if self.is_srgb {
+            return Color::from_vec3(self.sample_linear_wrapped(uv, wrap_mode, decode));
+        }
+
+        // Reducing first keeps huge UVs from overflowing the texel indices
+        let uv = uv.map(|coordinate| wrap_mode.reduce(coordinate));
+        let x = uv.x * self.width as f32 - 0.5;
+        let y = uv.y * self.height as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (tx, ty) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i32, y0 as i32);
+
+        let texel = |x: i32, y: i32| self.texel_wrapped(x, y, wrap_mode);
+        let top = texel(x0, y0).lerp(&texel(x0 + 1, y0), tx);
+        let bottom = texel(x0, y0 + 1).lerp(&texel(x0 + 1, y0 + 1), tx);
+        top.lerp(&bottom, ty)
+    }
+
+    fn sample_linear_wrapped(&self, uv: Vec2, wrap_mode: WrapMode, decode: EncodeMode) -> Vec3 {
+        if self.data.is_empty() {
+            return Vec3::zeros();
+        }
+        let uv = uv.map(|coordinate| wrap_mode.reduce(coordinate));
+        let x = uv.x * self.width as f32 - 0.5;
+        let y = uv.y * self.height as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (tx, ty) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i32, y0 as i32);
+
+        let texel = |x: i32, y: i32| {
+            let color = self.texel_wrapped(x, y, wrap_mode).to_vec3();
+            if self.is_srgb { color.map(|channel| decode.decode(channel)) } else { color }
+        };
+        let top = texel(x0, y0).lerp(&texel(x0 + 1, y0), tx);
+        let bottom = texel(x0, y0 + 1).lerp(&texel(x0 + 1, y0 + 1), tx);
+        top.lerp(&bottom, ty)
+    }
+
+    // Average of `samples` texels spread along the row at `v`, e.g. the color an
+    // equirectangular map should have at a pole, where the whole row meets.
+    // `decode` as in `sample_decoded`.
+    pub fn row_average(&self, v: f32, samples: u32, decode: EncodeMode) -> Color {
+        let samples = samples.max(1);
+        let (mut r, mut g, mut b) = (0u32, 0u32, 0u32);
+        for i in 0..samples {
+            let color = self.sample_decoded(Vec2::new((i as f32 + 0.5) / samples as f32, v), decode);
+            r += color.r() as u32;
+            g += color.g() as u32;
+            b += color.b() as u32;
+        }
+
+        Color::new((r / samples) as u8, (g / samples) as u8, (b / samples) as u8)
+    }
+
+    // Height maps are grayscale; the red channel is read as a height in [0, 1]
+    pub fn sample_height(&self, uv: Vec2) -> f32 {
+        self.sample(uv).r() as f32 / 255.0
+    }
+}
+
+// Six square faces ordered +X, -X, +Y, -Y, +Z, -Z, laid out as in OpenGL
+#[derive(Clone, Debug)]
+pub struct Cubemap {
+    pub faces: [Texture; 6],
+}
+
+impl Cubemap {
+    pub fn new(faces: [Texture; 6]) -> Self {
+        Cubemap { faces }
+    }
+
+    // Picks the face of the direction's major axis and projects onto it. Faces
+    // are always clamped, whatever their `wrap_mode`: filtering across an edge
+    // must not pull in the opposite side of the same face.
+    pub fn sample(&self, direction: &Vec3) -> Color {
+        self.sample_decoded(direction, EncodeMode::Srgb)
+    }
+
+    // `sample` with `is_srgb` faces decoded by `decode`, as in `Texture::sample_decoded`
+    pub fn sample_decoded(&self, direction: &Vec3, decode: EncodeMode) -> Color {
+        let (x, y, z) = (direction.x, direction.y, direction.z);
+        let (ax, ay, az) = (x.abs(), y.abs(), z.abs());
+
+        let (face, u, v, major) = if ax >= ay && ax >= az {
+            if x > 0.0 { (0, -z, -y, ax) } else { (1, z, -y, ax) }
+        } else if ay >= az {
+            if y > 0.0 { (2, x, z, ay) } else { (3, x, -z, ay) }
+        } else if z > 0.0 {
+            (4, x, -y, az)
+        } else {
+            (5, -x, -y, az)
+        };
+
+        if major <= f32::EPSILON {
+            return self.faces[4].sample_wrapped(Vec2::new(0.5, 0.5), WrapMode::Clamp, decode);
+        }
+
+        let uv = Vec2::new(0.5 * (u / major + 1.0), 0.5 * (v / major + 1.0));
+        self.faces[face].sample_wrapped(uv, WrapMode::Clamp, decode)
+    }
+}
+
+// Floating-point latitude-longitude image of the surroundings, such as a
+// space HDRI: u is the longitude and v runs from the +Y pole (0) to -Y (1),
+// the layout `spherical_uv` produces. Values are linear radiance, often far
+// above 1 for stars and suns.
+#[derive(Clone, Debug)]
+pub struct EquirectEnvironment {
+    pub width: usize,
+    pub height: usize,
+    pub data: Vec<Vec3>,
+}
+
+impl EquirectEnvironment {
+    pub fn new(width: usize, height: usize, data: Vec<Vec3>) -> Self {
+        assert_eq!(data.len(), width * height, "environment data does not match its size");
+        EquirectEnvironment { width, height, data }
+    }
+
+    // Reads a Radiance RGBE `.hdr` file, flat or run-length encoded, in the
+    // usual `-Y height +X width` orientation
+    pub fn load_hdr(path: &str) -> io::Result<Self> {
+        let bytes = fs::read(path)?;
+        let invalid = |message: &str| io::Error::new(ErrorKind::InvalidData, format!("{}: {}", path, message));
+
+        // Text header up to a blank line, then the resolution line
+        let mut position = 0;
+        let next_line = |position: &mut usize| -> Option<String> {
+            let end = bytes[*position..].iter().position(|&byte| byte == b'\n')? + *position;
+            let line = String::from_utf8_lossy(&bytes[*position..end]).trim().to_string();
+            *position = end + 1;
+            Some(line)
+        };
+
+        let magic = next_line(&mut position).ok_or_else(|| invalid("empty file"))?;
+        if !magic.starts_with("#?") {
+            return Err(invalid("not a Radiance HDR file"));
+        }
+        loop {
+            let line = next_line(&mut position).ok_or_else(|| invalid("truncated header"))?;
+            if line.is_empty() {
+                break;
+            }
+            if let Some(format) = line.strip_prefix("FORMAT=") {
+                if format != "32-bit_rle_rgbe" {
+                    return Err(invalid("only the RGBE pixel format is supported"));
+                }
+            }
+        }
+
+        let resolution = next_line(&mut position).ok_or_else(|| invalid("missing resolution"))?;
+        let (height, width) = match resolution.split_whitespace().collect::<Vec<_>>()[..] {
+            ["-Y", height, "+X", width] => (
+                height.parse::<usize>().map_err(|_| invalid("bad height"))?,
+                width.parse::<usize>().map_err(|_| invalid("bad width"))?,
+            ),
+            _ => return Err(invalid("unsupported orientation, expected -Y height +X width")),
+        };
+
+        let mut data = Vec::with_capacity(width * height);
+        let mut scanline = vec![[0u8; 4]; width];
+        for _ in 0..height {
+            position = read_rgbe_scanline(&bytes, position, &mut scanline).ok_or_else(|| invalid("truncated pixel data"))?;
+            data.extend(scanline.iter().map(|&rgbe| decode_rgbe(rgbe)));
+        }
+
+        Ok(EquirectEnvironment { width, height, data })
+    }
+
+    // Bilinear radiance seen along `direction`. Longitude wraps across the
+    // seam; latitude clamps at the poles, where every texel of the first and
+    // last rows sits at the same point anyway.
+    pub fn sample_env(&self, direction: &Vec3) -> Vec3 {
+        if self.width == 0 || self.height == 0 {
+            return Vec3::zeros();
+        }
+
+        let uv = spherical_uv(direction);
+        let x = uv.x * self.width as f32 - 0.5;
+        let y = uv.y * self.height as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (tx, ty) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i32, y0 as i32);
+
+        let texel = |x: i32, y: i32| {
+            let x = WrapMode::Wrap.texel_index(x, self.width);
+            let y = WrapMode::Clamp.texel_index(y, self.height);
+            self.data[y * self.width + x]
+        };
+        let top = texel(x0, y0).lerp(&texel(x0 + 1, y0), tx);
+        let bottom = texel(x0, y0 + 1).lerp(&texel(x0 + 1, y0 + 1), tx);
+        top.lerp(&bottom, ty)
+    }
+}
+
+// Shared exponent: each channel is mantissa / 256 * 2^(exponent - 128)
+fn decode_rgbe([r, g, b, e]: [u8; 4]) -> Vec3 {
+    if e == 0 {
+        return Vec3::zeros();
+    }
+    let scale = 2f32.powi(e as i32 - 136);
+    Vec3::new(r as f32, g as f32, b as f32) * scale
+}
+
+// Fills `scanline` from `bytes` at `position`, returning the position after it.
+// Run-length encoded lines start with 2, 2 and the width; anything else is
+// stored flat.
+fn read_rgbe_scanline(bytes: &[u8], mut position: usize, scanline: &mut [[u8; 4]]) -> Option<usize> {
+    let width = scanline.len();
+    let header = bytes.get(position..position + 4)?;
+    let encoded = (8..0x8000).contains(&width)
+        && header[0] == 2 && header[1] == 2
+        && ((header[2] as usize) << 8 | header[3] as usize) == width;
+
+    if !encoded {
+        for pixel in scanline.iter_mut() {
+            pixel.copy_from_slice(bytes.get(position..position + 4)?);
+            position += 4;
+        }
+        return Some(position);
+    }
+
+    position += 4;
+    // Each channel is encoded separately: counts above 128 repeat the next
+    // byte (count - 128) times, smaller counts are followed by literal bytes
+    for channel in 0..4 {
+        let mut x = 0;
+        while x < width {
+            let count = *bytes.get(position)? as usize;
+            position += 1;
+            if count > 128 {
+                let run = count - 128;
+                let value = *bytes.get(position)?;
+                position += 1;
+                for pixel in scanline.get_mut(x..x + run)? {
+                    pixel[channel] = value;
+                }
+                x += run;
+            } else {
+                if count == 0 {
+                    return None;
+                }
+                for (pixel, &value) in scanline.get_mut(x..x + count)?.iter_mut().zip(bytes.get(position..position + count)?) {
+                    pixel[channel] = value;
+                }
+                position += count;
+                x += count;
+            }
+        }
+    }
+    Some(position)
+}
+
+// Beyond this many layers parallax gets no sharper, only slower
+const MAX_PARALLAX_STEPS: u32 = 1024;
+
+// Parallax occlusion mapping: marches the UV along the tangent-space view ray
+// until it dips below the height field, then refines between the last two layers.
+// Heights are treated as depth below the surface (1.0 = deepest).
+pub fn parallax_uv(height_tex: &Texture, uv: Vec2, view_ts: Vec3, steps: u32, depth_scale: f32) -> Vec2 {
+    if steps == 0 || depth_scale <= 0.0 || view_ts.z <= 0.0 {
+        return uv;
+    }
+    let steps = steps.min(MAX_PARALLAX_STEPS);
+
+    let layer_depth = 1.0 / steps as f32;
+    // Shift in UV for the whole depth range, longer at grazing angles
+    let max_offset = Vec2::new(view_ts.x, view_ts.y) / view_ts.z * depth_scale;
+    if !max_offset.iter().all(|offset| offset.is_finite()) {
+        return uv;
+    }
+    let delta_uv = max_offset * layer_depth;
+
+    let mut current_uv = uv;
+    let mut current_layer = 0.0;
+    let mut current_depth = 1.0 - height_tex.sample_height(current_uv);
+
+    while current_layer < current_depth && current_layer < 1.0 {
+        current_uv -= delta_uv;
+        current_layer += layer_depth;
+        current_depth = 1.0 - height_tex.sample_height(current_uv);
+    }
+
+    // Linear interpolation between the layer before and after the intersection
+    let previous_uv = current_uv + delta_uv;
+    let after = current_depth - current_layer;
+    let before = (1.0 - height_tex.sample_height(previous_uv)) - (current_layer - layer_depth);
+    let denom = after - before;
+    let weight = if denom.abs() > f32::EPSILON { after / denom } else { 0.0 };
+
+    previous_uv * weight + current_uv * (1.0 - weight)
+}
+
+// Equirectangular UVs for a point on (or around) a sphere centered at the origin.
+// Evaluate it per fragment rather than per vertex: interpolating per-vertex UVs
+// across the atan2 seam would sweep the whole texture through the seam triangles.
+// At the poles (and the origin) longitude is undefined and u falls back to 0.5.
+pub fn spherical_uv(pos: &Vec3) -> Vec2 {
+    let radius = pos.magnitude();
+    if radius <= f32::EPSILON {
+        return Vec2::new(0.5, 0.5);
+    }
+
+    let u = if pos.x.abs() <= f32::EPSILON && pos.z.abs() <= f32::EPSILON {
+        0.5
+    } else {
+        pos.z.atan2(pos.x) / (2.0 * PI) + 0.5
+    };
+    let v = (pos.y / radius).clamp(-1.0, 1.0).acos() / PI;
+
+    Vec2::new(u, v)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn srgb_texels_decode_to_linear_light() {
+        let gray = Texture::new(1, 1, vec![Color::new(128, 128, 128)]);
+        assert_eq!(gray.sample(Vec2::new(0.5, 0.5)), Color::new(128, 128, 128));
+
+        let gray = gray.with_srgb(true);
+        let linear = gray.sample_linear(Vec2::new(0.5, 0.5));
+        assert!((linear.x - 0.216).abs() < 0.002, "{}", linear.x);
+        assert_eq!(gray.sample(Vec2::new(0.5, 0.5)), Color::from_vec3(linear));
+
+        // Filtering happens in linear light: half way between black and white
+        // is 0.5 linear, not the sRGB midpoint
+        let ramp = Texture::new(2, 1, vec![Color::black(), Color::new(255, 255, 255)]).with_srgb(true);
+        assert!((ramp.sample_linear(Vec2::new(0.5, 0.5)).x - 0.5).abs() < 1e-5);
+    }
+
+    const MODES: [WrapMode; 3] = [WrapMode::Wrap, WrapMode::Clamp, WrapMode::Mirror];
+
+    #[test]
+    fn wrap_modes_fold_coordinates_outside_the_unit_range() {
+        let expected = [
+            (WrapMode::Wrap, [0.9, 0.1, 0.5]),
+            (WrapMode::Clamp, [0.0, 1.0, 1.0]),
+            (WrapMode::Mirror, [0.1, 0.9, 0.5]),
+        ];
+        for (mode, values) in expected {
+            for (coordinate, value) in [-0.1, 1.1, 2.5].into_iter().zip(values) {
+                assert!((mode.apply(coordinate) - value).abs() < 1e-5, "{:?} maps {} to {}", mode, coordinate, mode.apply(coordinate));
+            }
+            // Inside the range nothing changes
+            assert_eq!(mode.apply(0.25), 0.25);
+        }
+    }
+
+    #[test]
+    fn wrap_modes_fold_texel_indices_the_same_way() {
+        let indices: Vec<Vec<usize>> = MODES.iter().map(|mode| (-5..9).map(|index| mode.texel_index(index, 4)).collect()).collect();
+        assert_eq!(indices[0], [3, 0, 1, 2, 3, 0, 1, 2, 3, 0, 1, 2, 3, 0]);
+        assert_eq!(indices[1], [0, 0, 0, 0, 0, 0, 1, 2, 3, 3, 3, 3, 3, 3]);
+        assert_eq!(indices[2], [3, 3, 2, 1, 0, 0, 1, 2, 3, 3, 2, 1, 0, 0]);
+    }
+
+    #[test]
+    fn samples_outside_the_texture_follow_the_wrap_mode() {
+        let ramp: Vec<Color> = [0, 85, 170, 255].iter().map(|&value| Color::new(value, value, value)).collect();
+        for mode in MODES {
+            let texture = Texture { wrap_mode: mode, ..Texture::new(4, 1, ramp.clone()) };
+            for u in [-0.1, 1.1, 2.5, 1e9, -1e9] {
+                assert_eq!(texture.sample(Vec2::new(u, 0.5)), texture.sample(Vec2::new(mode.apply(u), 0.5)), "{:?} at {}", mode, u);
+            }
+        }
+        // Clamped samples stretch the border texels
+        let clamped = Texture { wrap_mode: WrapMode::Clamp, ..Texture::new(4, 1, ramp) };
+        assert_eq!(clamped.sample(Vec2::new(2.5, 0.5)), Color::new(255, 255, 255));
+        assert_eq!(clamped.sample(Vec2::new(-0.1, 0.5)), Color::black());
+    }
+
+    fn write_hdr(name: &str, header: &str, pixels: &[u8]) -> String {
+        let path = std::env::temp_dir().join(format!("hdr_test_{}_{}", std::process::id(), name)).to_string_lossy().into_owned();
+        let mut bytes = header.as_bytes().to_vec();
+        bytes.extend_from_slice(pixels);
+        fs::write(&path, bytes).unwrap();
+        path
+    }
+
+    #[test]
+    fn rgbe_pixels_share_one_exponent() {
+        assert_eq!(decode_rgbe([128, 64, 0, 129]), Vec3::new(1.0, 0.5, 0.0));
+        assert_eq!(decode_rgbe([128, 128, 128, 136]), Vec3::new(128.0, 128.0, 128.0));
+        // A zero exponent is black whatever the mantissas say
+        assert_eq!(decode_rgbe([255, 255, 255, 0]), Vec3::zeros());
+    }
+
+    #[test]
+    fn flat_hdr_files_load_row_by_row() {
+        let header = "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 2 +X 2\n";
+        let pixels = [
+            128, 0, 0, 129, 0, 128, 0, 129,
+            0, 0, 128, 129, 128, 128, 128, 130,
+        ];
+        let path = write_hdr("flat.hdr", header, &pixels);
+        let environment = EquirectEnvironment::load_hdr(&path).unwrap();
+        fs::remove_file(&path).ok();
+
+        assert_eq!((environment.width, environment.height), (2, 2));
+        assert_eq!(environment.data, vec![
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            Vec3::new(2.0, 2.0, 2.0),
+        ]);
+    }
+
+    #[test]
+    fn run_length_encoded_scanlines_decode_runs_and_literals() {
+        // One 8 pixel line: red is a single run, green all literals, blue a
+        // run followed by literals, and the exponent another run
+        let mut pixels = vec![2, 2, 0, 8];
+        pixels.extend_from_slice(&[136, 128]);
+        pixels.extend_from_slice(&[8, 0, 16, 32, 48, 64, 80, 96, 112]);
+        pixels.extend_from_slice(&[130, 255, 6, 1, 2, 3, 4, 5, 6]);
+        pixels.extend_from_slice(&[136, 136]);
+
+        let mut scanline = vec![[0u8; 4]; 8];
+        assert_eq!(read_rgbe_scanline(&pixels, 0, &mut scanline), Some(pixels.len()));
+        let expected_blue = [255, 255, 1, 2, 3, 4, 5, 6];
+        for (x, pixel) in scanline.iter().enumerate() {
+            assert_eq!(*pixel, [128, 16 * x as u8, expected_blue[x], 136]);
+        }
+
+        let path = write_hdr("rle.hdr", "#?RGBE\n\n-Y 1 +X 8\n", &pixels);
+        let environment = EquirectEnvironment::load_hdr(&path).unwrap();
+        fs::remove_file(&path).ok();
+        assert_eq!(environment.data.len(), 8);
+        assert_eq!(environment.data[3], Vec3::new(128.0, 48.0, 2.0));
+
+        // A run past the end of the line is rejected, not written out of bounds
+        let mut overflowing = vec![2, 2, 0, 8, 137, 128];
+        overflowing.extend_from_slice(&[0; 16]);
+        assert_eq!(read_rgbe_scanline(&overflowing, 0, &mut scanline), None);
+    }
+
+    #[test]
+    fn broken_hdr_files_are_errors() {
+        let cases = [
+            ("magic.hdr", "P6\n\n-Y 1 +X 1\n", vec![0, 0, 0, 0]),
+            ("header.hdr", "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n", vec![]),
+            ("format.hdr", "#?RADIANCE\nFORMAT=32-bit_rle_xyze\n\n-Y 1 +X 1\n", vec![0, 0, 0, 0]),
+            ("orientation.hdr", "#?RADIANCE\n\n+Y 1 +X 1\n", vec![0, 0, 0, 0]),
+            ("size.hdr", "#?RADIANCE\n\n-Y one +X 1\n", vec![0, 0, 0, 0]),
+            ("pixels.hdr", "#?RADIANCE\n\n-Y 2 +X 2\n", vec![128, 0, 0, 129, 0, 128]),
+        ];
+        for (name, header, pixels) in cases {
+            let path = write_hdr(name, header, &pixels);
+            let result = EquirectEnvironment::load_hdr(&path);
+            fs::remove_file(&path).ok();
+            let error = result.expect_err(name);
+            assert_eq!(error.kind(), ErrorKind::InvalidData, "{}", name);
+        }
+        assert!(EquirectEnvironment::load_hdr("no/such/environment.hdr").is_err());
+    }
+
+    #[test]
+    fn cubemap_faces_clamp_at_their_edges() {
+        // +Z is red on the left and blue on the right; wrapping would blend
+        // the right edge with the red column
+        let (red, blue) = (Color::new(255, 0, 0), Color::new(0, 0, 255));
+        let black = || Texture::new(1, 1, vec![Color::black()]);
+        let front = Texture::new(2, 1, vec![red, blue]).with_wrap_mode(WrapMode::Wrap);
+        let cubemap = Cubemap::new([black(), black(), black(), black(), front, black()]);
+
+        assert_eq!(cubemap.sample(&Vec3::new(0.98, 0.0, 1.0)), blue);
+        assert_eq!(cubemap.sample(&Vec3::new(-0.98, 0.0, 1.0)), red);
+        // The face's own sampling still wraps
+        assert_ne!(cubemap.faces[4].sample(Vec2::new(0.99, 0.5)), blue);
+    }
+
+    #[test]
+    fn srgb_texels_decode_with_the_requested_curve() {
+        let dark = Texture::new(1, 1, vec![Color::new(20, 20, 20)]).with_srgb(true);
+        let center = Vec2::new(0.5, 0.5);
+        assert_eq!(dark.sample_linear(center), dark.sample_linear_decoded(center, EncodeMode::Srgb));
+        // The sRGB toe reads dark tones brighter than a pure 2.2 power
+        assert!(dark.sample_linear_decoded(center, EncodeMode::Srgb).x > dark.sample_linear_decoded(center, EncodeMode::Gamma(2.2)).x);
+
+        // Encoding with the same curve gives the texel back
+        for mode in [EncodeMode::Srgb, EncodeMode::Gamma(2.2), EncodeMode::Gamma(1.8)] {
+            for value in [20, 128, 230] {
+                let texture = Texture::new(1, 1, vec![Color::new(value, value, value)]).with_srgb(true);
+                let linear = texture.sample_linear_decoded(center, mode);
+                assert_eq!(Color::from_vec3(linear.map(|channel| mode.encode(channel))), texture.data[0], "{:?}", mode);
+            }
+        }
+        // Data textures are never decoded
+        let data = Texture::new(1, 1, vec![Color::new(20, 20, 20)]);
+        assert_eq!(data.sample_decoded(center, EncodeMode::Gamma(2.2)), data.data[0]);
+    }
+}
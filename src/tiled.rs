@@ -0,0 +1,140 @@
+use std::thread;
+use nalgebra_glm::Vec3;
+use crate::{Uniforms, shade_vertices, assemble, material_groups, silhouette_edges, write_fragment};
+use crate::framebuffer::{Framebuffer, TILE_SIZE};
+use crate::mesh::Mesh;
+use crate::shaders::ShaderType;
+use crate::triangle::{triangle_in_rect, calculate_bounding_box, clamp_bounding_box};
+use crate::vertex::Vertex;
+
+// Parallel `render_mesh`: the screen is cut into TILE_SIZE tiles and `threads`
+// workers each take every `threads`-th tile. A worker rasterizes only the
+// triangles whose bounding box overlaps its tile, into a private copy of the
+// tile (color, depth and the other per-pixel buffers), and the finished tiles
+// are copied back once every worker is done. No pixel is ever shared between
+// threads, so nothing needs locking, and the image matches the serial path.
+// Vertex shading and triangle setup stay on the calling thread.
+pub fn render_mesh_tiled(framebuffer: &mut Framebuffer, uniforms: &Uniforms, mesh: &Mesh, shader_selection: u32, threads: usize) {
+    let transformed_vertices = shade_vertices(uniforms, &mesh.vertices);
+    let mut triangles: Vec<[Vertex; 3]> = Vec::new();
+    let mut shaders: Vec<ShaderType> = Vec::new();
+    for (selection, corners) in material_groups(mesh, shader_selection) {
+        let assembled = assemble(uniforms, &transformed_vertices, corners.into_iter());
+        shaders.extend(std::iter::repeat_n(ShaderType::from_selection(selection), assembled.len()));
+        triangles.extend(assembled);
+    }
+
+    let silhouettes = if uniforms.edge_antialiasing {
+        silhouette_edges(&triangles, uniforms.front_face)
+    } else {
+        vec![[false; 3]; triangles.len()]
+    };
+
+    // Binning: the triangles overlapping each tile, in draw order
+    let (columns, rows) = framebuffer.tile_count();
+    let mut bins: Vec<Vec<usize>> = vec![Vec::new(); columns * rows];
+    for (i, tri) in triangles.iter().enumerate() {
+        let bounds = calculate_bounding_box(&tri[0].transformed_position, &tri[1].transformed_position, &tri[2].transformed_position);
+        // Antialiased edges grow the triangle by a one pixel fringe
+        let margin = if silhouettes[i].contains(&true) { 1 } else { 0 };
+        let bounds = (bounds.0 - margin, bounds.1 - margin, bounds.2 + margin, bounds.3 + margin);
+        if let Some((min_x, min_y, max_x, max_y)) = clamp_bounding_box(bounds, (framebuffer.width, framebuffer.height)) {
+            for row in min_y as usize / TILE_SIZE..=max_y as usize / TILE_SIZE {
+                for column in min_x as usize / TILE_SIZE..=max_x as usize / TILE_SIZE {
+                    bins[row * columns + column].push(i);
+                }
+            }
+        }
+    }
+
+    let threads = threads.max(1);
+    let target: &Framebuffer = framebuffer;
+    let finished: Vec<(usize, Framebuffer)> = thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads)
+            .map(|worker| {
+                let (bins, triangles, shaders, silhouettes) = (&bins, &triangles, &shaders, &silhouettes);
+                scope.spawn(move || {
+                    let mut tiles = Vec::new();
+                    for tile in (worker..bins.len()).step_by(threads) {
+                        if bins[tile].is_empty() {
+                            continue;
+                        }
+
+                        let origin = ((tile % columns) * TILE_SIZE, (tile / columns) * TILE_SIZE);
+                        let mut local = copy_tile(target, origin);
+                        let rect = (
+                            origin.0 as i32,
+                            origin.1 as i32,
+                            (origin.0 + local.width) as i32 - 1,
+                            (origin.1 + local.height) as i32 - 1,
+                        );
+
+                        for &i in &bins[tile] {
+                            let tri = &triangles[i];
+                            let shader = shaders[i];
+                            let fragments = triangle_in_rect(
+                                &tri[0], &tri[1], &tri[2], shader.selection(), &uniforms.light_dir, rect, silhouettes[i],
+                            );
+                            for fragment in &fragments {
+                                let x = fragment.position.x as usize - origin.0;
+                                let y = fragment.position.y as usize - origin.1;
+                                write_fragment(&mut local, uniforms, fragment, shader, x, y);
+                            }
+                        }
+
+                        tiles.push((tile, local));
+                    }
+                    tiles
+                })
+            })
+            .collect();
+
+        workers.into_iter().flat_map(|worker| worker.join().expect("tile worker panicked")).collect()
+    });
+
+    for (tile, local) in finished {
+        let origin = ((tile % columns) * TILE_SIZE, (tile / columns) * TILE_SIZE);
+        paste_tile(framebuffer, &local, origin);
+    }
+}
+
+// The tile at `origin` as a small framebuffer of its own, holding the current contents
+fn copy_tile(framebuffer: &Framebuffer, origin: (usize, usize)) -> Framebuffer {
+    let width = TILE_SIZE.min(framebuffer.width - origin.0);
+    let height = TILE_SIZE.min(framebuffer.height - origin.1);
+    let mut tile = Framebuffer::new(width, height);
+
+    for y in 0..height {
+        let source = (origin.1 + y) * framebuffer.width + origin.0;
+        let (source, local) = (source..source + width, y * width..(y + 1) * width);
+        tile.buffer[local.clone()].copy_from_slice(&framebuffer.buffer[source.clone()]);
+        tile.zbuffer[local.clone()].copy_from_slice(&framebuffer.zbuffer[source.clone()]);
+        tile.velocity_buffer[local.clone()].copy_from_slice(&framebuffer.velocity_buffer[source.clone()]);
+        tile.object_ids[local.clone()].copy_from_slice(&framebuffer.object_ids[source.clone()]);
+        if let Some(accumulation) = &framebuffer.accumulation {
+            tile.accumulation.get_or_insert_with(|| vec![Vec3::zeros(); width * height])[local.clone()].copy_from_slice(&accumulation[source.clone()]);
+        }
+        if let Some(radiance) = &framebuffer.radiance {
+            tile.radiance.get_or_insert_with(|| vec![Vec3::zeros(); width * height])[local].copy_from_slice(&radiance[source]);
+        }
+    }
+
+    tile
+}
+
+fn paste_tile(framebuffer: &mut Framebuffer, tile: &Framebuffer, origin: (usize, usize)) {
+    for y in 0..tile.height {
+        let target = (origin.1 + y) * framebuffer.width + origin.0;
+        let (target, local) = (target..target + tile.width, y * tile.width..(y + 1) * tile.width);
+        framebuffer.buffer[target.clone()].copy_from_slice(&tile.buffer[local.clone()]);
+        framebuffer.zbuffer[target.clone()].copy_from_slice(&tile.zbuffer[local.clone()]);
+        framebuffer.velocity_buffer[target.clone()].copy_from_slice(&tile.velocity_buffer[local.clone()]);
+        framebuffer.object_ids[target.clone()].copy_from_slice(&tile.object_ids[local.clone()]);
+        if let (Some(accumulation), Some(tile_accumulation)) = (&mut framebuffer.accumulation, &tile.accumulation) {
+            accumulation[target.clone()].copy_from_slice(&tile_accumulation[local.clone()]);
+        }
+        if let (Some(radiance), Some(tile_radiance)) = (&mut framebuffer.radiance, &tile.radiance) {
+            radiance[target].copy_from_slice(&tile_radiance[local]);
+        }
+    }
+}
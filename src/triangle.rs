@@ -1,55 +1,217 @@
 use nalgebra_glm::{Vec3, dot};
 use crate::fragment::Fragment;
-use crate::vertex::Vertex;
+use crate::vertex::{Interpolation, Vertex};
 use crate::color::Color;
+use crate::raster_math::{edge_function, edge_function_fixed, is_top_left, covers, FixedPoint, FIXED_ONE, FIXED_AREA_UNIT};
 
-pub fn triangle(v1: &Vertex, v2: &Vertex, v3: &Vertex, shader_selection: u32) -> Vec<Fragment> {
+// Offset of the sample point inside each pixel. Pixel (x, y) covers the
+// screen square [x, x + 1) x [y, y + 1) and is tested and interpolated at
+// (x + 0.5, y + 0.5), as in Direct3D 10+, OpenGL and Vulkan, so a triangle
+// with pixel-aligned corners covers exactly the pixels inside it, with no
+// half-pixel shift against textures or post passes using the same centers.
+pub const PIXEL_CENTER: f32 = 0.5;
+
+// Triangles whose screen-space edge function (twice their area, in square
+// pixels) is smaller than this produce no fragments. Far below the area of a
+// pixel, so only degenerate slivers are affected.
+pub const MIN_TRIANGLE_AREA: f32 = 1e-6;
+
+// Front faces are identified by their winding once projected to the screen.
+// The default matches OBJ/Blender exports (counter-clockwise in NDC).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Winding {
+  #[default]
+  CounterClockwise,
+  Clockwise,
+}
+
+// The viewport matrix flips Y, so a counter-clockwise triangle in NDC ends up
+// with a positive edge function in screen space.
+pub fn is_front_facing(v1: &Vertex, v2: &Vertex, v3: &Vertex, front_face: Winding) -> bool {
+  let area = edge_function(&v1.transformed_position, &v2.transformed_position, &v3.transformed_position);
+
+  match front_face {
+    Winding::CounterClockwise => area > 0.0,
+    Winding::Clockwise => area < 0.0,
+  }
+}
+
+// Only pixels inside `viewport` (width, height) are produced, so geometry
+// partially off screen neither wastes time nor yields out-of-range fragments.
+pub fn triangle(v1: &Vertex, v2: &Vertex, v3: &Vertex, shader_selection: u32, light_dir: &Vec3, viewport: (usize, usize)) -> Vec<Fragment> {
+  triangle_antialiased(v1, v2, v3, shader_selection, light_dir, viewport, [false; 3])
+}
+
+// Analytic edge antialiasing for the edges flagged in `outer_edges` (opposite
+// v1, v2 and v3), meant for silhouettes. Pixels within half a pixel of such an
+// edge get a `coverage` below 1 from their distance to it, including a fringe
+// of pixels just outside the triangle, which the fill rule alone would skip.
+// Much cheaper than supersampling, but unlike MSAA it never smooths interior
+// edges or where two surfaces intersect.
+pub fn triangle_antialiased(
+  v1: &Vertex,
+  v2: &Vertex,
+  v3: &Vertex,
+  shader_selection: u32,
+  light_dir: &Vec3,
+  viewport: (usize, usize),
+  outer_edges: [bool; 3],
+) -> Vec<Fragment> {
+  let rect = (0, 0, viewport.0 as i32 - 1, viewport.1 as i32 - 1);
+  triangle_in_rect(v1, v2, v3, shader_selection, light_dir, rect, outer_edges)
+}
+
+// `triangle_antialiased` restricted to the pixels of `rect` (inclusive min_x,
+// min_y, max_x, max_y), e.g. one screen tile. Pixels are sampled in the same
+// global coordinates, so splitting the screen into rects gives exactly the
+// fragments of one full-screen call.
+pub fn triangle_in_rect(
+  v1: &Vertex,
+  v2: &Vertex,
+  v3: &Vertex,
+  shader_selection: u32,
+  light_dir: &Vec3,
+  rect: (i32, i32, i32, i32),
+  outer_edges: [bool; 3],
+) -> Vec<Fragment> {
   let mut fragments = Vec::new();
+
+  // Slivers from collinear vertices or near-plane clipping would divide by a
+  // vanishing area below; NaN positions are dropped by the same test
+  let signed_area = edge_function(&v1.transformed_position, &v2.transformed_position, &v3.transformed_position);
+  if signed_area.is_nan() || signed_area.abs() < MIN_TRIANGLE_AREA {
+    return fragments;
+  }
+
+  // Coverage is decided on positions snapped to the subpixel grid, where edge
+  // functions are exact: triangles sharing an edge see exactly opposite
+  // values along it, so the fill rule gives each sample to one of them
+  let snapped = [v1, v2, v3].map(|vertex| FixedPoint::from_screen(&vertex.transformed_position));
+  let fixed_area = edge_function_fixed(snapped[0], snapped[1], snapped[2]);
+  if fixed_area == 0 {
+    return fragments;
+  }
+
+  // Rasterize every triangle with a positive area so the fill rule below
+  // only has to handle one orientation
+  let swapped = fixed_area < 0;
+  let (v2, v3) = if swapped { (v3, v2) } else { (v2, v3) };
+  let (fa, fb, fc) = if swapped { (snapped[0], snapped[2], snapped[1]) } else { (snapped[0], snapped[1], snapped[2]) };
+  let outer = if swapped { [outer_edges[0], outer_edges[2], outer_edges[1]] } else { outer_edges };
   let (a, b, c) = (v1.transformed_position, v2.transformed_position, v3.transformed_position);
 
+  // The fringe outside antialiased edges reaches one pixel past the triangle
+  let margin = if outer.contains(&true) { 1 } else { 0 };
   let (min_x, min_y, max_x, max_y) = calculate_bounding_box(&a, &b, &c);
+  let bounds = (min_x - margin, min_y - margin, max_x + margin, max_y + margin);
+  let (min_x, min_y, max_x, max_y) = match clamp_to_rect(bounds, rect) {
+    Some(bounds) => bounds,
+    None => return fragments,
+  };
 
-  let light_dir = Vec3::new(0.0, 0.0, 1.0);
+  let triangle_area = fixed_area.unsigned_abs() as f64;
 
-  let triangle_area = edge_function(&a, &b, &c);
+  // Top-left fill rule: a sample exactly on an edge belongs to the triangle only
+  // if that is a top or left edge, so pixels on shared edges are drawn once
+  let edges = [(fb, fc), (fc, fa), (fa, fb)];
+  let top_left = edges.map(|(from, to)| is_top_left(from, to));
+  // Dividing an edge function by its edge's length gives the pixel distance to that edge
+  let edge_lengths = edges.map(|(from, to)| ((to.x - from.x) as f64).hypot((to.y - from.y) as f64) as f32 / FIXED_ONE as f32);
+  // Edge functions are linear, so stepping one pixel right adds a constant
+  let steps = edges.map(|(from, to)| (to.y - from.y) as i128 * FIXED_ONE as i128);
 
   for y in min_y..=max_y {
+    let row_start = FixedPoint::pixel_center(min_x, y);
+    let mut row = edges.map(|(from, to)| edge_function_fixed(from, to, row_start));
     for x in min_x..=max_x {
-      let point = Vec3::new(x as f32 + 0.5, y as f32 + 0.5, 0.0);
+      let fixed = row;
+      for (edge, step) in row.iter_mut().zip(&steps) {
+        *edge += step;
+      }
+
+      let inside = (0..3).all(|i| covers(fixed[i], top_left[i]));
+      // In square pixels, for the antialiasing distances and the weights
+      let [e1, e2, e3] = fixed.map(|edge| (edge as f64 / FIXED_AREA_UNIT) as f32);
+
+      let mut coverage = 1.0f32;
+      let mut in_fringe = !inside;
+      for (i, &edge) in [e1, e2, e3].iter().enumerate() {
+        if outer[i] {
+          let distance = edge / edge_lengths[i].max(f32::EPSILON);
+          coverage = coverage.min((distance + 0.5).clamp(0.0, 1.0));
+        } else if !covers(fixed[i], top_left[i]) {
+          // Outside an interior edge (or on it, when the fill rule gives it
+          // away) the neighbour triangle owns the pixel
+          in_fringe = false;
+        }
+      }
 
-      let (w1, w2, w3) = barycentric_coordinates(&point, &a, &b, &c, triangle_area);
+      if inside || (in_fringe && coverage > 0.0) {
+        // Fringe pixels take the attributes of the closest point inside
+        let (w1, w2, w3) = if inside {
+          let weight = |edge: i128| (edge as f64 / triangle_area) as f32;
+          (weight(fixed[0]), weight(fixed[1]), weight(fixed[2]))
+        } else {
+          let (e1, e2, e3) = (e1.max(0.0), e2.max(0.0), e3.max(0.0));
+          let area = (e1 + e2 + e3).max(f32::EPSILON);
+          (e1 / area, e2 / area, e3 / area)
+        };
 
-      if w1 >= 0.0 && w1 <= 1.0 && 
-         w2 >= 0.0 && w2 <= 1.0 &&
-         w3 >= 0.0 && w3 <= 1.0 {
+        // Screen-space weights are right for NDC depth, which is linear on screen,
+        // but attributes must be interpolated as attr/w and renormalized by 1/w
+        let inv_w = w1 * v1.inv_w + w2 * v2.inv_w + w3 * v3.inv_w;
+        let perspective = (w1 * v1.inv_w / inv_w, w2 * v2.inv_w / inv_w, w3 * v3.inv_w / inv_w);
+        // Flat attributes come from the provoking vertex, v1
+        let weights = |mode: Interpolation| match mode {
+          Interpolation::Flat => (1.0, 0.0, 0.0),
+          Interpolation::Perspective => perspective,
+          Interpolation::Linear => (w1, w2, w3),
+        };
+        let qualifiers = v1.interpolation;
 
-        let normal = v1.transformed_normal * w1 + v2.transformed_normal * w2 + v3.transformed_normal * w3;
+        let (p1, p2, p3) = weights(qualifiers.normal);
+        let normal = v1.transformed_normal * p1 + v2.transformed_normal * p2 + v3.transformed_normal * p3;
         let normal = normal.normalize();
 
-        let mut intensity = dot(&normal, &light_dir).max(0.0);
+        let mut intensity = dot(&normal, light_dir).max(0.0);
 
         let base_color = Color::new(100, 100, 100);
         let lit_color = base_color * intensity;
 
         let depth = a.z * w1 + b.z * w2 + c.z * w3;
 
-        let vertex_position = v1.position * w1 + v2.position * w2 + v3.position * w3;
+        let (p1, p2, p3) = weights(qualifiers.position);
+        let vertex_position = v1.position * p1 + v2.position * p2 + v3.position * p3;
+        let world_position = v1.world_position * p1 + v2.world_position * p2 + v3.world_position * p3;
+        let (p1, p2, p3) = weights(qualifiers.tex_coords);
+        let tex_coords = v1.tex_coords * p1 + v2.tex_coords * p2 + v3.tex_coords * p3;
+        let (p1, p2, p3) = weights(qualifiers.velocity);
+        let velocity = v1.velocity * p1 + v2.velocity * p2 + v3.velocity * p3;
 
         if shader_selection == 0 {
-          intensity = 0.7 * (dot(&normal, &light_dir).abs() + 0.3);
+          intensity = 0.7 * (dot(&normal, light_dir).abs() + 0.3);
         }
 
-        fragments.push(
-            Fragment::new(
-                x as f32,
-                y as f32,
-                lit_color,
-                depth,
-                normal,
-                intensity,
-                vertex_position,
-            )
+        let mut fragment = Fragment::new(
+            x as f32,
+            y as f32,
+            lit_color,
+            depth,
+            normal,
+            intensity,
+            vertex_position,
         );
+        fragment.world_position = world_position;
+        fragment.tex_coords = tex_coords;
+        fragment.view_depth = 1.0 / inv_w;
+        fragment.velocity = velocity;
+        fragment.coverage = coverage;
+        // Flat: v1 is the provoking vertex and is never swapped above
+        fragment.flat_id = v1.flat_id;
+        // Reported in the caller's vertex order, whatever order was rasterized
+        fragment.barycentric = if swapped { Vec3::new(w1, w3, w2) } else { Vec3::new(w1, w2, w3) };
+
+        fragments.push(fragment);
       }
     }
   }
@@ -57,7 +219,7 @@ pub fn triangle(v1: &Vertex, v2: &Vertex, v3: &Vertex, shader_selection: u32) ->
   fragments
 }
 
-fn calculate_bounding_box(v1: &Vec3, v2: &Vec3, v3: &Vec3) -> (i32, i32, i32, i32) {
+pub fn calculate_bounding_box(v1: &Vec3, v2: &Vec3, v3: &Vec3) -> (i32, i32, i32, i32) {
     let min_x = v1.x.min(v2.x).min(v3.x).floor() as i32;
     let min_y = v1.y.min(v2.y).min(v3.y).floor() as i32;
     let max_x = v1.x.max(v2.x).max(v3.x).ceil() as i32;
@@ -66,16 +228,173 @@ fn calculate_bounding_box(v1: &Vec3, v2: &Vec3, v3: &Vec3) -> (i32, i32, i32, i3
     (min_x, min_y, max_x, max_y)
 }
 
-fn barycentric_coordinates(p: &Vec3, a: &Vec3, b: &Vec3, c: &Vec3, area: f32) -> (f32, f32, f32) {
-    let w1 = edge_function(b, c, p) / area;
-    let w2 = edge_function(c, a, p) / area;
-    let w3 = edge_function(a, b, p) / area;
-
-    (w1, w2, w3)
+// Intersects an inclusive pixel rectangle with [0, width) x [0, height);
+// `None` when nothing of it is on screen
+pub fn clamp_bounding_box(bounds: (i32, i32, i32, i32), viewport: (usize, usize)) -> Option<(i32, i32, i32, i32)> {
+    clamp_to_rect(bounds, (0, 0, viewport.0 as i32 - 1, viewport.1 as i32 - 1))
 }
 
-fn edge_function(a: &Vec3, b: &Vec3, c: &Vec3) -> f32 {
-    (c.x - a.x) * (b.y - a.y) - (c.y - a.y) * (b.x - a.x)
+// Intersection of two inclusive pixel rectangles
+pub fn clamp_to_rect(bounds: (i32, i32, i32, i32), rect: (i32, i32, i32, i32)) -> Option<(i32, i32, i32, i32)> {
+    let clamped = (bounds.0.max(rect.0), bounds.1.max(rect.1), bounds.2.min(rect.2), bounds.3.min(rect.3));
+    if clamped.0 > clamped.2 || clamped.1 > clamped.3 {
+        return None;
+    }
+
+    Some(clamped)
 }
 
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use nalgebra_glm::Vec2;
+
+  fn vertex(x: f32, y: f32) -> Vertex {
+    Vertex::new(Vec3::new(x, y, 0.0), Vec3::new(0.0, 0.0, 1.0), Vec2::new(0.0, 0.0))
+  }
+
+  // Times each pixel is produced by the triangles, given in screen space
+  fn coverage_counts(triangles: &[[(f32, f32); 3]], viewport: (usize, usize)) -> Vec<u32> {
+    let mut counts = vec![0; viewport.0 * viewport.1];
+    for &[a, b, c] in triangles {
+      let (v1, v2, v3) = (vertex(a.0, a.1), vertex(b.0, b.1), vertex(c.0, c.1));
+      for fragment in triangle(&v1, &v2, &v3, 1, &Vec3::new(0.0, 0.0, 1.0), viewport) {
+        counts[fragment.position.y as usize * viewport.0 + fragment.position.x as usize] += 1;
+      }
+    }
+    counts
+  }
+
+  #[test]
+  fn shared_edges_through_pixel_centers_are_drawn_exactly_once() {
+    // A 6x6 block split along a horizontal and a vertical line through pixel
+    // centers (y = 3.5, x = 2.5) and along both halves' diagonals
+    let triangles = [
+      [(0.0, 0.0), (0.0, 3.5), (2.5, 0.0)],
+      [(2.5, 0.0), (0.0, 3.5), (2.5, 3.5)],
+      [(2.5, 0.0), (2.5, 3.5), (6.0, 0.0)],
+      [(6.0, 0.0), (2.5, 3.5), (6.0, 3.5)],
+      [(0.0, 3.5), (0.0, 6.0), (2.5, 3.5)],
+      [(2.5, 3.5), (0.0, 6.0), (2.5, 6.0)],
+      [(2.5, 3.5), (2.5, 6.0), (6.0, 3.5)],
+      [(6.0, 3.5), (2.5, 6.0), (6.0, 6.0)],
+    ];
+    let counts = coverage_counts(&triangles, (6, 6));
+    assert!(counts.iter().all(|&count| count == 1), "{:?}", counts);
+
+    // The same with every triangle wound the other way
+    let reversed: Vec<[(f32, f32); 3]> = triangles.iter().map(|&[a, b, c]| [a, c, b]).collect();
+    assert_eq!(coverage_counts(&reversed, (6, 6)), counts);
+  }
+
+  #[test]
+  fn samples_on_top_and_left_edges_belong_to_the_triangle() {
+    // Right triangle with its left and top edges through pixel centers
+    // (x = 1.5 and y = 1.5): those samples are inside, while the centers on
+    // its hypotenuse (bottom right) are left to the neighbour
+    let counts = coverage_counts(&[[(1.5, 1.5), (1.5, 5.5), (5.5, 1.5)]], (8, 8));
+    let hit = |x: usize, y: usize| counts[y * 8 + x] == 1;
+    assert!(hit(1, 1) && hit(1, 4) && hit(4, 1));
+    assert!(!hit(1, 5) && !hit(5, 1) && !hit(3, 3) && !hit(2, 4));
+    assert!(hit(2, 3) && hit(3, 2));
+    assert_eq!(counts.iter().sum::<u32>(), 10);
+  }
 
+  #[test]
+  fn triangles_off_the_left_edge_are_clipped_to_the_viewport() {
+    let viewport = (16, 12);
+    // Right triangle whose left half hangs off screen
+    let counts = coverage_counts(&[[(-8.0, 0.0), (-8.0, 8.0), (8.0, 0.0)]], viewport);
+    for y in 0..12 {
+      for x in 0..16 {
+        // Inside when the pixel center is left of the hypotenuse x + 2y = 8
+        let inside = y < 8 && (x as f32 + 0.5) + 2.0 * (y as f32 + 0.5) < 8.0;
+        assert_eq!(counts[y * 16 + x], inside as u32, "pixel ({}, {})", x, y);
+      }
+    }
+
+    // Entirely off screen, or absurdly large: nothing out of range, no panic
+    assert!(coverage_counts(&[[(-30.0, 0.0), (-30.0, 8.0), (-20.0, 0.0)]], viewport).iter().all(|&count| count == 0));
+    let huge = coverage_counts(&[[(-1e9, -1e9), (-1e9, 1e9), (1e9, 0.0)]], viewport);
+    assert!(huge.iter().all(|&count| count == 1));
+  }
+
+  #[test]
+  fn pixel_aligned_shapes_cover_exactly_their_pixels_sampled_at_centers() {
+    // Square from (2, 1) to (6, 5), split on its diagonal
+    let counts = coverage_counts(&[[(2.0, 1.0), (2.0, 5.0), (6.0, 5.0)], [(2.0, 1.0), (6.0, 5.0), (6.0, 1.0)]], (8, 8));
+    for y in 0..8 {
+      for x in 0..8 {
+        let inside = (2..6).contains(&x) && (1..5).contains(&y);
+        assert_eq!(counts[y * 8 + x], inside as u32, "pixel ({}, {})", x, y);
+      }
+    }
+
+    // Attributes are interpolated at the center of each pixel too
+    let corner = |x: f32, y: f32| Vertex::new(Vec3::new(x, y, 0.0), Vec3::new(0.0, 0.0, 1.0), Vec2::new(x / 8.0, y / 8.0));
+    let fragments = triangle(&corner(0.0, 0.0), &corner(0.0, 8.0), &corner(8.0, 0.0), 1, &Vec3::new(0.0, 0.0, 1.0), (8, 8));
+    assert_eq!(fragments.len(), 28);
+    for fragment in &fragments {
+      let center = (fragment.position + Vec2::new(PIXEL_CENTER, PIXEL_CENTER)) / 8.0;
+      assert!((fragment.tex_coords - center).magnitude() < 1e-5, "{:?} at {:?}", fragment.tex_coords, fragment.position);
+    }
+  }
+
+  #[test]
+  fn flat_attributes_come_from_the_first_vertex_whatever_the_winding() {
+    let corner = |x: f32, y: f32, id: u32| Vertex { flat_id: id, ..Vertex::new(Vec3::new(x, y, 0.0), Vec3::new(0.0, 0.0, 1.0), Vec2::new(x, y)) };
+    let (a, b, c) = (corner(1.0, 1.0, 7), corner(1.0, 9.0, 8), corner(9.0, 1.0, 9));
+    for (v1, v2, v3) in [(&a, &b, &c), (&a, &c, &b)] {
+      let fragments = triangle_antialiased(v1, v2, v3, 1, &Vec3::new(0.0, 0.0, 1.0), (12, 12), [true; 3]);
+      assert!(fragments.len() > 20);
+      assert!(fragments.iter().all(|fragment| fragment.flat_id == 7));
+      // Smooth attributes do vary
+      assert!(fragments.iter().any(|fragment| (fragment.tex_coords - fragments[0].tex_coords).magnitude() > 1.0));
+    }
+  }
+
+  #[test]
+  fn degenerate_triangles_produce_no_fragments() {
+    let viewport = (16, 16);
+    let degenerate = [
+      // Collinear, coincident and vanishingly thin
+      [(1.0, 1.0), (5.0, 5.0), (9.0, 9.0)],
+      [(3.0, 3.0), (3.0, 3.0), (3.0, 3.0)],
+      [(1.0, 1.0), (15.0, 1.0), (8.0, 1.0 + 1e-8)],
+      // Non-finite positions
+      [(f32::NAN, 1.0), (5.0, 5.0), (9.0, 1.0)],
+      [(f32::INFINITY, 1.0), (5.0, 5.0), (9.0, 1.0)],
+    ];
+    for corners in degenerate {
+      assert!(coverage_counts(&[corners], viewport).iter().all(|&count| count == 0), "{:?}", corners);
+      let [a, b, c] = corners.map(|(x, y)| vertex(x, y));
+      assert!(triangle_antialiased(&a, &b, &c, 1, &Vec3::new(0.0, 0.0, 1.0), viewport, [true; 3]).is_empty(), "{:?}", corners);
+    }
+  }
+
+  #[test]
+  fn each_attribute_follows_its_interpolation_qualifier() {
+    let corner = |x: f32, y: f32, inv_w: f32| Vertex { inv_w, ..Vertex::new(Vec3::new(x, y, 0.0), Vec3::new(x, y, 1.0), Vec2::new(x, y)) };
+    let (mut a, b, c) = (corner(1.0, 1.0, 1.0), corner(1.0, 9.0, 0.25), corner(9.0, 1.0, 0.5));
+    a.interpolation.tex_coords = Interpolation::Flat;
+    a.interpolation.position = Interpolation::Linear;
+    let fragments = triangle(&a, &b, &c, 1, &Vec3::new(0.0, 0.0, 1.0), (12, 12));
+    assert!(fragments.len() > 20);
+
+    let mut perspective_differs = false;
+    for fragment in &fragments {
+      let [w1, w2, w3] = [fragment.barycentric.x, fragment.barycentric.y, fragment.barycentric.z];
+      assert_eq!(fragment.tex_coords, a.tex_coords);
+      // Linear: the screen-space weights as they are
+      let linear = a.position * w1 + b.position * w2 + c.position * w3;
+      assert!((fragment.vertex_position - linear).magnitude() < 1e-4);
+      // The normal keeps the perspective-correct default
+      let inv_w = w1 * a.inv_w + w2 * b.inv_w + w3 * c.inv_w;
+      let normal = (a.normal * w1 * a.inv_w + b.normal * w2 * b.inv_w + c.normal * w3 * c.inv_w) / inv_w;
+      assert!((fragment.normal - normal.normalize()).magnitude() < 1e-4);
+      let perspective = (a.position * w1 * a.inv_w + b.position * w2 * b.inv_w + c.position * w3 * c.inv_w) / inv_w;
+      perspective_differs |= (perspective - linear).magnitude() > 0.1;
+    }
+    assert!(perspective_differs);
+  }
+}
@@ -1,6 +1,29 @@
 use nalgebra_glm::{Vec2, Vec3};
 use crate::color::Color;
 
+// How the rasterizer fills an attribute in between a triangle's corners
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Interpolation {
+  // The provoking vertex's value over the whole triangle
+  Flat,
+  // Perspective-correct: interpolated as attribute / w and renormalized by
+  // 1 / w, so it stays attached to the surface
+  #[default]
+  Perspective,
+  // Straight across the screen, ignoring depth (GLSL's noperspective)
+  Linear,
+}
+
+// Qualifier of each interpolated attribute. `position` covers both the model
+// and the world position.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Interpolations {
+  pub normal: Interpolation,
+  pub position: Interpolation,
+  pub tex_coords: Interpolation,
+  pub velocity: Interpolation,
+}
+
 #[derive(Clone, Debug)]
 pub struct Vertex {
   pub position: Vec3,
@@ -9,6 +32,19 @@ pub struct Vertex {
   pub color: Color,
   pub transformed_position: Vec3,
   pub transformed_normal: Vec3,
+  pub world_position: Vec3,
+  // 1 / clip-space w, for perspective-correct interpolation (1 under orthographic)
+  pub inv_w: f32,
+  // Screen-space motion in pixels since the previous frame (see `Uniforms::prev_mvp`)
+  pub velocity: Vec2,
+  // Flat (never interpolated) attribute such as a face index or material id.
+  // Fragments take it from the triangle's provoking vertex, its first one in
+  // index order; give a face's first vertex its own copy when vertices are
+  // shared between faces with different values.
+  pub flat_id: u32,
+  // How the attributes above are interpolated; read from the provoking
+  // vertex too, so `Interpolation::Flat` gives faceted normals or per-face UVs
+  pub interpolation: Interpolations,
 }
 
 impl Vertex {
@@ -20,6 +56,11 @@ impl Vertex {
       color: Color::black(),
       transformed_position: position,
       transformed_normal: normal,
+      world_position: position,
+      inv_w: 1.0,
+      velocity: Vec2::new(0.0, 0.0),
+      flat_id: 0,
+      interpolation: Interpolations::default(),
     }
   }
 
@@ -31,6 +72,11 @@ impl Vertex {
       color,
       transformed_position: Vec3::new(0.0, 0.0, 0.0),
       transformed_normal: Vec3::new(0.0, 0.0, 0.0),
+      world_position: position,
+      inv_w: 1.0,
+      velocity: Vec2::new(0.0, 0.0),
+      flat_id: 0,
+      interpolation: Interpolations::default(),
     }
   }
 
@@ -49,6 +95,11 @@ impl Default for Vertex {
       color: Color::black(),
       transformed_position: Vec3::new(0.0, 0.0, 0.0),
       transformed_normal: Vec3::new(0.0, 1.0, 0.0),
+      world_position: Vec3::new(0.0, 0.0, 0.0),
+      inv_w: 1.0,
+      velocity: Vec2::new(0.0, 0.0),
+      flat_id: 0,
+      interpolation: Interpolations::default(),
     }
   }
 }
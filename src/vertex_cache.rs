@@ -0,0 +1,87 @@
+use std::collections::VecDeque;
+use crate::vertex::Vertex;
+
+// FIFO cache of vertex shader outputs keyed by vertex index, like the
+// post-transform cache of GPUs. With triangles ordered for locality, most
+// shared vertices are found here instead of being transformed again.
+pub struct VertexCache {
+    capacity: usize,
+    entries: VecDeque<(u32, Vertex)>,
+    pub hits: usize,
+    pub misses: usize,
+}
+
+// Typical hardware cache size; enough for the fans of a subdivided sphere
+pub const DEFAULT_VERTEX_CACHE_SIZE: usize = 32;
+
+impl VertexCache {
+    pub fn new(capacity: usize) -> Self {
+        VertexCache {
+            capacity,
+            entries: VecDeque::with_capacity(capacity),
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    // Returns the cached output for `index`, running `shade` on a miss
+    pub fn get_or_shade<F: FnOnce() -> Vertex>(&mut self, index: u32, shade: F) -> Vertex {
+        if let Some((_, vertex)) = self.entries.iter().find(|(cached, _)| *cached == index) {
+            self.hits += 1;
+            return vertex.clone();
+        }
+
+        self.misses += 1;
+        let vertex = shade();
+        if self.capacity > 0 {
+            if self.entries.len() == self.capacity {
+                self.entries.pop_front();
+            }
+            self.entries.push_back((index, vertex.clone()));
+        }
+        vertex
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.hits = 0;
+        self.misses = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra_glm::{Vec2, Vec3};
+
+    fn shaded(index: u32) -> Vertex {
+        Vertex::new(Vec3::new(index as f32, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0), Vec2::new(0.0, 0.0))
+    }
+
+    #[test]
+    fn hits_return_the_cached_output_and_the_oldest_entry_is_evicted() {
+        let mut cache = VertexCache::new(2);
+        for index in [0, 1, 0, 1, 2, 0, 2] {
+            let vertex = cache.get_or_shade(index, || shaded(index));
+            assert_eq!(vertex.position.x, index as f32);
+        }
+        // 0 and 1 miss, then hit; 2 evicts 0, which misses again and evicts 1
+        assert_eq!((cache.hits, cache.misses), (3, 4));
+
+        // A hit never runs the shader
+        cache.get_or_shade(2, || panic!("2 should be cached"));
+        cache.clear();
+        assert_eq!((cache.hits, cache.misses), (0, 0));
+        cache.get_or_shade(2, || shaded(2));
+        assert_eq!(cache.misses, 1);
+    }
+
+    #[test]
+    fn a_zero_capacity_cache_shades_every_time() {
+        let mut cache = VertexCache::new(0);
+        for _ in 0..3 {
+            cache.get_or_shade(7, || shaded(7));
+        }
+        assert_eq!((cache.hits, cache.misses), (0, 3));
+    }
+}
@@ -0,0 +1,190 @@
+use nalgebra_glm::{Mat4, Vec3, Vec4};
+use crate::{Uniforms, RenderObject};
+use crate::displacement::DisplacementSource;
+
+// Scene-level culling for `render_scene`: whole objects are dropped before any
+// of their vertices are shaded, by testing the bounding sphere of the mesh
+// (`Mesh::bounding_sphere`, grown by the displacement if any) against the
+// view frustum, and optionally against the depth of what was drawn already.
+// Triangle culling happens later, per triangle, in the pipeline itself.
+
+// Indices of the objects whose bounding sphere touches the view frustum, in
+// order. Conservative: an object may be kept and still draw nothing, but one
+// that would draw a pixel is never dropped.
+pub fn cull_objects(objects: &[RenderObject], uniforms: &Uniforms) -> Vec<usize> {
+    let planes = frustum_planes(&(uniforms.projection_matrix * uniforms.view_matrix));
+    (0..objects.len())
+        .filter(|&i| {
+            let (center, radius) = world_bounds(&objects[i], uniforms);
+            planes.iter().all(|plane| plane.dot(&Vec4::new(center.x, center.y, center.z, 1.0)) >= -radius)
+        })
+        .collect()
+}
+
+// `cull_objects`, also dropping objects hidden behind `occluders`
+pub fn cull_objects_occluded(objects: &[RenderObject], uniforms: &Uniforms, occluders: &OcclusionBuffer) -> Vec<usize> {
+    cull_objects(objects, uniforms)
+        .into_iter()
+        .filter(|&i| {
+            let (center, radius) = world_bounds(&objects[i], uniforms);
+            !occluders.hides(uniforms, center, radius)
+        })
+        .collect()
+}
+
+// Low-resolution copy of a depth buffer for occlusion tests. Each cell keeps
+// the farthest depth of its `cell_size` x `cell_size` block of pixels, so an
+// object nearer than that somewhere in the block may show and is kept.
+// Build it from the z-buffer after drawing the big occluders (planets), then
+// cull the small objects (an asteroid field) against it.
+#[derive(Clone, Debug)]
+pub struct OcclusionBuffer {
+    pub width: usize,
+    pub height: usize,
+    pub cell_size: usize,
+    pub depth: Vec<f32>,
+}
+
+impl OcclusionBuffer {
+    pub fn from_depth(zbuffer: &[f32], width: usize, height: usize, cell_size: usize) -> Self {
+        assert_eq!(zbuffer.len(), width * height, "depth buffer does not match its size");
+        let cell_size = cell_size.max(1);
+        let (columns, rows) = (width.div_ceil(cell_size), height.div_ceil(cell_size));
+        let mut depth = vec![f32::NEG_INFINITY; columns * rows];
+        for y in 0..height {
+            for x in 0..width {
+                let cell = &mut depth[(y / cell_size) * columns + x / cell_size];
+                *cell = cell.max(zbuffer[y * width + x]);
+            }
+        }
+        OcclusionBuffer { width: columns, height: rows, cell_size, depth }
+    }
+
+    // Whether the sphere is behind the stored depth everywhere it covers. The
+    // sphere's world box is projected: its nearest corner bounds the nearest
+    // depth of the sphere, its corners together bound its screen rectangle.
+    // Spheres reaching behind the camera are never hidden.
+    pub fn hides(&self, uniforms: &Uniforms, center: Vec3, radius: f32) -> bool {
+        let (mut min_x, mut min_y, mut max_x, mut max_y) = (f32::INFINITY, f32::INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY);
+        let mut nearest = f32::INFINITY;
+        for corner in 0..8 {
+            let sign = |bit: usize| if corner & bit == 0 { -1.0 } else { 1.0 };
+            let point = center + Vec3::new(sign(1), sign(2), sign(4)) * radius;
+            let Some(screen) = uniforms.project(point) else {
+                return false;
+            };
+            min_x = min_x.min(screen.x);
+            min_y = min_y.min(screen.y);
+            max_x = max_x.max(screen.x);
+            max_y = max_y.max(screen.y);
+            nearest = nearest.min(screen.z);
+        }
+        if !(nearest.is_finite() && min_x.is_finite() && min_y.is_finite() && max_x.is_finite() && max_y.is_finite()) {
+            return false;
+        }
+
+        if max_x < 0.0 || max_y < 0.0 || self.depth.is_empty() {
+            return false;
+        }
+
+        // Cells overlapping the rectangle, clamped to the screen
+        let cell = |value: f32, cells: usize| ((value / self.cell_size as f32).floor().max(0.0) as usize).min(cells - 1);
+        let (first_column, last_column) = (cell(min_x, self.width), cell(max_x, self.width));
+        let (first_row, last_row) = (cell(min_y, self.height), cell(max_y, self.height));
+
+        (first_row..=last_row).all(|row| {
+            (first_column..=last_column).all(|column| nearest > self.depth[row * self.width + column])
+        })
+    }
+}
+
+// World-space bounding sphere of the object as drawn
+fn world_bounds(object: &RenderObject, uniforms: &Uniforms) -> (Vec3, f32) {
+    let (center, mut radius) = object.mesh.bounding_sphere();
+    radius += match &uniforms.displacement {
+        Some(DisplacementSource::Noise { scale, .. }) | Some(DisplacementSource::HeightMap { scale, .. }) => scale.abs(),
+        None => 0.0,
+    };
+
+    let model = &object.model_matrix;
+    let world_center = model * Vec4::new(center.x, center.y, center.z, 1.0);
+    // The longest transformed axis bounds how much the matrix stretches the sphere
+    let stretch = (0..3)
+        .map(|column| Vec3::new(model[(0, column)], model[(1, column)], model[(2, column)]).magnitude())
+        .fold(0.0, f32::max);
+
+    (world_center.xyz(), radius * stretch)
+}
+
+// Left, right, bottom, top, near and far planes of a view-projection matrix
+// (Gribb and Hartmann), normalized so `dot(plane, point)` is a signed distance
+// in world units, positive inside
+fn frustum_planes(view_proj: &Mat4) -> [Vec4; 6] {
+    let row = |i: usize| Vec4::new(view_proj[(i, 0)], view_proj[(i, 1)], view_proj[(i, 2)], view_proj[(i, 3)]);
+    let (x, y, z, w) = (row(0), row(1), row(2), row(3));
+    [w + x, w - x, w + y, w - y, w + z, w - z].map(|plane| {
+        let length = plane.xyz().magnitude();
+        if length > f32::EPSILON { plane / length } else { plane }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::framebuffer::Framebuffer;
+    use crate::geometry::generate_sphere;
+    use crate::shaders::ShaderType;
+    use crate::{create_noise, create_perspective_matrix, create_view_matrix, create_viewport_matrix, render_mesh, Handedness, FAR_PLANE};
+
+    // Looking down -Z from (0, 0, 5)
+    fn camera_uniforms() -> Uniforms {
+        let view = create_view_matrix(Vec3::new(0.0, 0.0, 5.0), Vec3::zeros(), Vec3::new(0.0, 1.0, 0.0));
+        let projection = create_perspective_matrix(64.0, 64.0, Handedness::Right);
+        Uniforms::new(Mat4::identity(), view, projection, create_viewport_matrix(64.0, 64.0), 0, create_noise())
+    }
+
+    #[test]
+    fn objects_behind_the_camera_or_outside_the_frustum_are_culled() {
+        let uniforms = camera_uniforms();
+        let sphere = generate_sphere(1);
+        let at = |x: f32, y: f32, z: f32| RenderObject::new(&sphere, Mat4::new_translation(&Vec3::new(x, y, z)), ShaderType::Lava);
+        let objects = [
+            at(0.0, 0.0, 0.0),
+            // Behind the eye
+            at(0.0, 0.0, 10.0),
+            // Far off to the side, and past the far plane
+            at(50.0, 0.0, 0.0),
+            at(0.0, 0.0, -FAR_PLANE - 10.0),
+            // Straddling the left edge of the view
+            at(-2.5, 0.0, 0.0),
+            // Around the eye itself
+            at(0.0, 0.0, 5.5),
+        ];
+        assert_eq!(cull_objects(&objects, &uniforms), [0, 4, 5]);
+
+        // Scaled up, the one behind reaches back into view
+        let mut grown = at(0.0, 0.0, 10.0);
+        grown.model_matrix *= Mat4::new_scaling(6.0);
+        assert_eq!(cull_objects(&[grown], &uniforms), [0]);
+    }
+
+    #[test]
+    fn objects_behind_drawn_depth_are_culled_and_nearer_ones_kept() {
+        let uniforms = camera_uniforms();
+        let mut framebuffer = Framebuffer::new(64, 64);
+        let mut wall = generate_sphere(3);
+        for vertex in &mut wall.vertices {
+            vertex.position *= 3.0;
+        }
+        render_mesh(&mut framebuffer, &uniforms, &wall, ShaderType::Lava.selection());
+        let occluders = OcclusionBuffer::from_depth(&framebuffer.zbuffer, 64, 64, 8);
+        assert_eq!((occluders.width, occluders.height), (8, 8));
+
+        let small = generate_sphere(1);
+        let at = |z: f32| RenderObject::new(&small, Mat4::new_translation(&Vec3::new(0.0, 0.0, z)) * Mat4::new_scaling(0.2), ShaderType::Ice);
+        // Inside the big sphere, in front of it, and behind the camera
+        let objects = [at(-1.0), at(4.0), at(8.0)];
+        assert_eq!(cull_objects(&objects, &uniforms), [0, 1]);
+        assert_eq!(cull_objects_occluded(&objects, &uniforms, &occluders), [1]);
+    }
+}